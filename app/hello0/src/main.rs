@@ -3,9 +3,12 @@
 
 use noli::prelude::*;
 
-fn main() {
+fn app_main(args: &[&str]) {
     Api::write_string("**** Hello from an app!\n");
+    for (i, arg) in args.iter().enumerate() {
+        println!("args[{i}] = {arg:?}");
+    }
     Api::exit(42);
 }
 
-entry_point!(main);
+entry_point!(app_main, args);
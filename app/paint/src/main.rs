@@ -14,7 +14,10 @@ fn main() -> Result<()> {
         if let Some('q') = Api::read_key() {
             break;
         }
-        if let Some(MouseEvent { button, position }) = Api::get_mouse_cursor_info() {
+        if let Some(MouseEvent {
+            button, position, ..
+        }) = Api::get_mouse_cursor_info()
+        {
             println!("{button:?} {position:?}");
             if button.l() || button.c() || button.r() {
                 let color = ((button.l() as u32) * 0xff0000)
@@ -23,7 +23,10 @@ fn main() {
     let _ = bitmap_draw_rect(bitmap, 0xff0000, 0, 0, 20, 20);
 
     loop {
-        if let Some(MouseEvent { button, position }) = Api::get_mouse_cursor_info() {
+        if let Some(MouseEvent {
+            button, position, ..
+        }) = Api::get_mouse_cursor_info()
+        {
             window.flush_area(cursor.rect());
             cursor.set_position(position.x, position.y);
             window.flush_area(cursor.rect());
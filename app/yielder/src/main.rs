@@ -0,0 +1,21 @@
+#![no_std]
+#![cfg_attr(not(target_os = "linux"), no_main)]
+
+use noli::prelude::*;
+
+// Runs forever like the loop app, but yields to the OS on every iteration
+// so that the tasks in the OS keep running.
+fn main() {
+    println!("yielder: started");
+    let mut last_report_ms = Api::uptime_ms();
+    loop {
+        Api::yield_now();
+        let now_ms = Api::uptime_ms();
+        if now_ms - last_report_ms >= 1000 {
+            println!("yielder: uptime {now_ms} ms");
+            last_report_ms = now_ms;
+        }
+    }
+}
+
+entry_point!(main);
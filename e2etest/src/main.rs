@@ -102,6 +102,32 @@ mod test {
         check_app_is_working("hello1", "hello from sys_print!").await
     }
     #[tokio::test]
+    async fn os_tasks_run_while_app_yields() -> Result<()> {
+        // cargo test -p e2etest -- --nocapture yields
+        const APP_NAME: &str = "yielder";
+        let dev_env = DevEnv::new()?;
+        let app_bin_path = dev_env.build_builtin_app(APP_NAME)?;
+        let mut qemu = Qemu::new(dev_env.ovmf_path())?;
+        let _rootfs = qemu
+            .launch_with_wasabi_os_and_files(dev_env.wasabi_efi_path(), &[app_bin_path.as_str()])?;
+        qemu.wait_until_serial_output_contains("usb_hid_keyboard is ready")?;
+        qemu.send_monitor_cmd("sendkey ret").await?;
+        qemu.wait_until_serial_output_contains("Welcome to WasabiOS!")?;
+        qemu.wait_until_serial_output_contains(
+            "net: rx: DHCP: SERVER -> CLIENT yiaddr = 10.0.2.15 chaddr = 52:54:00:12:34:56",
+        )?;
+        for c in APP_NAME.chars() {
+            qemu.send_monitor_cmd(&format!("sendkey {c}")).await?;
+        }
+        qemu.send_monitor_cmd("sendkey ret").await?;
+        qemu.wait_until_serial_output_contains("yielder: started")?;
+        // The app never exits, so the tcp_echo_task in the OS can only respond if the app yields.
+        assert_tcp_echo_client_is_working("hello_while_yielding")?;
+        qemu.wait_until_serial_output_contains("yielder: uptime")?;
+        qemu.kill().await?;
+        Ok(())
+    }
+    #[tokio::test]
     async fn app_rev_is_working() -> Result<()> {
         const APP_NAME: &str = "rev";
         const INPUT_STRING: &str = "wasabios";
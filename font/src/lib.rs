@@ -3,39 +3,96 @@ extern crate proc_macro;
 use proc_macro::TokenStream;
 use std::fs::File;
 use std::io::Read;
+use syn::parse::Parser;
+use syn::punctuated::Punctuated;
+use syn::Expr;
+use syn::Lit;
+use syn::MetaNameValue;
+use syn::Token;
 
+const DEFAULT_FONT_WIDTH: usize = 8;
+const DEFAULT_FONT_HEIGHT: usize = 16;
+
+struct FontConfig {
+    file_name: String,
+    width: usize,
+    height: usize,
+}
+impl FontConfig {
+    /// Parses optional `key = value` pairs: `path` to the font file relative to the font crate
+    /// (default: "font.txt"), `width` of a glyph up to 8 (default: 8) and `height` of a glyph
+    /// (default: 16).
+    fn parse(input: TokenStream) -> Self {
+        let mut config = Self {
+            file_name: "font.txt".to_string(),
+            width: DEFAULT_FONT_WIDTH,
+            height: DEFAULT_FONT_HEIGHT,
+        };
+        let args = Punctuated::<MetaNameValue, Token![,]>::parse_terminated
+            .parse(input)
+            .expect("expected `key = value` pairs");
+        for arg in args {
+            let key = arg
+                .path
+                .get_ident()
+                .expect("expected an identifier as a key")
+                .to_string();
+            let Expr::Lit(value) = arg.value else {
+                panic!("{}: expected a literal", key);
+            };
+            match (key.as_str(), value.lit) {
+                ("path", Lit::Str(s)) => config.file_name = s.value(),
+                ("width", Lit::Int(i)) => config.width = i.base10_parse().unwrap(),
+                ("height", Lit::Int(i)) => config.height = i.base10_parse().unwrap(),
+                (key, _) => panic!("unexpected argument: {}", key),
+            }
+        }
+        assert!(
+            (1..=8).contains(&config.width),
+            "width should be in 1..=8 but got {}",
+            config.width
+        );
+        assert!(config.height > 0, "height should be greater than 0");
+        config
+    }
+}
+
+/// Generates `BITMAP_FONT: [[u8; FONT_HEIGHT]; 256]` and the `FONT_WIDTH` and `FONT_HEIGHT`
+/// constants. See FontConfig::parse for the optional arguments.
 #[proc_macro]
-pub fn gen_embedded_font(_input: TokenStream) -> TokenStream {
+pub fn gen_embedded_font(input: TokenStream) -> TokenStream {
+    let config = FontConfig::parse(input);
     let mut path = std::env::current_dir().expect("failed to get current dir");
     path.push(file!());
     path.pop();
     path.pop();
-    path.push("font.txt");
+    path.push(&config.file_name);
     let path = path
         .as_path()
         .to_str()
-        .expect("failed to generate path to font.txt");
-    gen_font_decl(path)
+        .expect("failed to generate path to the font file");
+    gen_font_decl(path, config.width, config.height)
 }
 
-fn gen_font_decl(file_name: &str) -> TokenStream {
-    let mut fonts: [[u8; 16]; 256] = [[0; 16]; 256];
+fn gen_font_decl(file_name: &str, width: usize, height: usize) -> TokenStream {
+    let mut fonts: Vec<Vec<u8>> = vec![vec![0; height]; 256];
     let mut file = File::open(file_name).expect("failed to open the font file");
     let mut input = String::new();
     file.read_to_string(&mut input)
         .expect("failed to read the file into string");
     let mut font_index = 0;
-    let mut row_index = 16;
+    let mut row_index = height;
     let mut line = 0;
     for row in input.split('\n') {
         line += 1;
         if row.starts_with("0x") {
             assert!(
-                row_index == 16,
-                "line {}: fonts[0x{:02X}] has {} rows but expected 16",
+                row_index == height,
+                "line {}: fonts[0x{:02X}] has {} rows but expected {}",
                 line,
                 font_index,
-                row_index
+                row_index,
+                height
             );
             let row_trimmed = row.trim_start_matches("0x");
             font_index = match usize::from_str_radix(row_trimmed, 16) {
@@ -50,13 +107,13 @@ fn gen_font_decl(file_name: &str) -> TokenStream {
             continue;
         }
         assert!(
-            row_index < 16,
+            row_index < height,
             "line {}: fonts[0x{:02X}] has extra rows",
             line,
             font_index
         );
         let mut row_bits = 0;
-        for i in 0..8 {
+        for i in 0..width {
             match row.chars().nth(i) {
                 Some('.') => (),
                 Some('*') => row_bits |= 1 << i,
@@ -70,12 +127,14 @@ fn gen_font_decl(file_name: &str) -> TokenStream {
         row_index += 1;
     }
 
-    let mut src = "pub static BITMAP_FONT: [[u8; 16]; 256] = [".to_string();
+    let mut src = format!("pub const FONT_WIDTH: i64 = {width};");
+    src += &format!("pub const FONT_HEIGHT: i64 = {height};");
+    src += &format!("pub static BITMAP_FONT: [[u8; {height}]; 256] = [");
     for f in fonts {
         src += "[";
         for (i, bits) in f.iter().enumerate() {
             src += &format!("{}", bits);
-            if i != 15 {
+            if i != height - 1 {
                 src += ", ";
             }
         }
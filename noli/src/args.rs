@@ -53,7 +53,11 @@ pub fn deserialize_args(data: &[u8]) -> Result<Vec<&str>> {
 
     tmp.copy_from_slice(&data[8..16]);
     let num_args = u64::from_le_bytes(tmp) as usize;
-    if (num_args + 1) * 16 > data.len() {
+    if num_args
+        .checked_add(1)
+        .and_then(|n| n.checked_mul(16))
+        .map_or(true, |header_size| header_size > data.len())
+    {
         return Err(Error::Failed("Invalid args data"));
     }
 
@@ -63,8 +67,11 @@ pub fn deserialize_args(data: &[u8]) -> Result<Vec<&str>> {
         let ofs = u64::from_le_bytes(tmp) as usize;
         tmp.copy_from_slice(&data[(16 + i * 16 + 8)..(16 + i * 16 + 16)]);
         let len = u64::from_le_bytes(tmp) as usize;
-        let s = core::str::from_utf8(&data[ofs..(ofs + len)]);
-        if let Ok(s) = s {
+        let s = ofs
+            .checked_add(len)
+            .and_then(|end| data.get(ofs..end))
+            .map(core::str::from_utf8);
+        if let Some(Ok(s)) = s {
             args.push(s);
         } else {
             return Err(Error::Failed("Invalid args data"));
@@ -124,4 +131,30 @@ mod tests {
         let actual = deserialize_args(&input).unwrap();
         assert_eq!(actual, expected_args());
     }
+
+    #[test]
+    fn test_args_round_trip() {
+        let args = ["hello0", "my file.txt", "", "日本語"];
+        let serialized = serialize_args(&args);
+        assert_eq!(deserialize_args(&serialized).unwrap(), args);
+        let serialized = serialize_args(&[]);
+        assert_eq!(deserialize_args(&serialized).unwrap(), Vec::<&str>::new());
+    }
+
+    #[test]
+    fn test_deserialize_args_rejects_malformed_data() {
+        let input = expected_bytes();
+        // Too short
+        assert!(deserialize_args(&input[..8]).is_err());
+        // Truncated data section
+        assert!(deserialize_args(&input[..70]).is_err());
+        // Huge num_args
+        let mut input = expected_bytes();
+        input[8..16].copy_from_slice(&u64::MAX.to_le_bytes());
+        assert!(deserialize_args(&input).is_err());
+        // Offset out of range
+        let mut input = expected_bytes();
+        input[16..24].copy_from_slice(&u64::MAX.to_le_bytes());
+        assert!(deserialize_args(&input).is_err());
+    }
 }
@@ -2,16 +2,27 @@ extern crate alloc;
 
 use crate::error::Error;
 use crate::error::Result;
+use crate::font::loaded_font;
 use crate::font::BITMAP_FONT;
+use crate::font::FONT_WIDTH;
 use crate::rect::Rect;
 use alloc::vec::Vec;
 use core::cmp::min;
 use core::cmp::Ordering;
 
+/// Swaps the R and B components of a 0xAARRGGBB color.
+pub fn swap_rb(color: u32) -> u32 {
+    (color & 0xFF00FF00) | ((color >> 16) & 0xFF) | ((color & 0xFF) << 16)
+}
+
 unsafe fn unchecked_draw_point<T: Bitmap>(buf: &mut T, color: u32, x: i64, y: i64) -> Result<()> {
-    // Assumes the buffer uses ARGB format
-    // which means that the components will be stored in [A, R, G, B] order.
-    // This is true for little-endian machine but not on big endian.
+    // Colors are given as 0xAARRGGBB, so the components will be stored in [B, G, R, A] order on
+    // little-endian machines. Swap R and B if the buffer expects [R, G, B, A] order.
+    let color = if buf.is_rgb_order() {
+        swap_rb(color)
+    } else {
+        color
+    };
     *buf.unchecked_pixel_at_mut(x, y) = color;
 
     Ok(())
@@ -69,7 +80,7 @@ pub fn bitmap_draw_line<T: Bitmap>(
 
 #[allow(clippy::many_single_char_names)]
 pub fn bitmap_draw_point<T: Bitmap>(buf: &mut T, color: u32, x: i64, y: i64) -> Result<()> {
-    if !buf.is_in_x_range(x) || !buf.is_in_x_range(x) {
+    if !buf.is_in_x_range(x) || !buf.is_in_y_range(y) {
         return Err(Error::GraphicsOutOfRange);
     }
     unsafe {
@@ -103,6 +114,89 @@ pub fn bitmap_draw_rect<T: Bitmap>(
     Ok(())
 }
 
+/// Draws the border of the rect with the given thickness, leaving the inside as is. The
+/// thickness is clamped to the half of the shorter side, rounded up, so a thick outline fills
+/// the whole rect. For an odd side, the borders on the opposite sides overlap by one pixel then.
+pub fn bitmap_draw_rect_outline<T: Bitmap>(
+    buf: &mut T,
+    color: u32,
+    px: i64,
+    py: i64,
+    w: i64,
+    h: i64,
+    thickness: i64,
+) -> Result<()> {
+    if !buf.is_in_x_range(px)
+        || !buf.is_in_y_range(py)
+        || !buf.is_in_x_range(px + w - 1)
+        || !buf.is_in_y_range(py + h - 1)
+    {
+        return Err(Error::GraphicsOutOfRange);
+    }
+    let t = thickness.min((min(w, h) + 1) / 2);
+    if t <= 0 {
+        return Ok(());
+    }
+
+    // Top and bottom
+    bitmap_draw_rect(buf, color, px, py, w, t)?;
+    bitmap_draw_rect(buf, color, px, py + h - t, w, t)?;
+    // Left and right, between the top and the bottom
+    if h > 2 * t {
+        bitmap_draw_rect(buf, color, px, py + t, t, h - 2 * t)?;
+        bitmap_draw_rect(buf, color, px + w - t, py + t, t, h - 2 * t)?;
+    }
+
+    Ok(())
+}
+
+/// Returns the color of the `row`-th row of a vertical gradient with `num_rows` rows, which
+/// starts with `top_color` and ends with `bottom_color`. Each 8-bit channel of 0xAARRGGBB is
+/// interpolated linearly.
+pub fn gradient_color_at(top_color: u32, bottom_color: u32, row: i64, num_rows: i64) -> u32 {
+    if num_rows <= 1 || row <= 0 {
+        return top_color;
+    }
+    if row >= num_rows - 1 {
+        return bottom_color;
+    }
+    (0..4).fold(0, |color, i| {
+        let shift = i * 8;
+        let top = ((top_color >> shift) & 0xFF) as i64;
+        let bottom = ((bottom_color >> shift) & 0xFF) as i64;
+        let c = top + (bottom - top) * row / (num_rows - 1);
+        color | ((c as u32) << shift)
+    })
+}
+
+/// Fills the rect with a vertical gradient from `top_color` to `bottom_color`, one color per row.
+pub fn bitmap_draw_gradient_rect<T: Bitmap>(
+    buf: &mut T,
+    top_color: u32,
+    bottom_color: u32,
+    px: i64,
+    py: i64,
+    w: i64,
+    h: i64,
+) -> Result<()> {
+    if !buf.is_in_x_range(px)
+        || !buf.is_in_y_range(py)
+        || !buf.is_in_x_range(px + w - 1)
+        || !buf.is_in_y_range(py + h - 1)
+    {
+        return Err(Error::GraphicsOutOfRange);
+    }
+
+    for dy in 0..h {
+        let color = gradient_color_at(top_color, bottom_color, dy, h);
+        for x in px..px + w {
+            bitmap_draw_point(buf, color, x, py + dy)?;
+        }
+    }
+
+    Ok(())
+}
+
 pub fn bitmap_draw_char_3x<T: Bitmap>(
     buf: &mut T,
     fg_color: u32,
@@ -177,18 +271,91 @@ pub fn bitmap_draw_char<T: Bitmap>(
     py: i64,
     c: char,
 ) -> Result<()> {
-    if !buf.is_in_x_range(px)
+    bitmap_draw_char_scaled(buf, fg_color, bg_color, px, py, c, 1)
+}
+
+/// Draws a char, rendering each pixel of the font as a `scale` x `scale` block.
+/// The font loaded by set_loaded_font() is used if any, otherwise the embedded 8x16 font is used.
+pub fn bitmap_draw_char_scaled<T: Bitmap>(
+    buf: &mut T,
+    fg_color: u32,
+    bg_color: Option<u32>,
+    px: i64,
+    py: i64,
+    c: char,
+    scale: i64,
+) -> Result<()> {
+    if let Some(font) = loaded_font() {
+        let (w, h) = (font.width(), font.height());
+        draw_glyph_scaled(buf, fg_color, bg_color, px, py, w, h, scale, |x, y| {
+            font.is_pixel_set(c, x, y)
+        })
+    } else {
+        draw_char_from_table(
+            buf,
+            &BITMAP_FONT,
+            FONT_WIDTH,
+            fg_color,
+            bg_color,
+            px,
+            py,
+            c,
+            scale,
+        )
+    }
+}
+
+/// Draws a char with a font table generated by font::gen_embedded_font!().
+#[allow(clippy::too_many_arguments)]
+fn draw_char_from_table<T: Bitmap, const H: usize>(
+    buf: &mut T,
+    table: &[[u8; H]; 256],
+    width: i64,
+    fg_color: u32,
+    bg_color: Option<u32>,
+    px: i64,
+    py: i64,
+    c: char,
+    scale: i64,
+) -> Result<()> {
+    let idx = c as usize;
+    draw_glyph_scaled(
+        buf,
+        fg_color,
+        bg_color,
+        px,
+        py,
+        width,
+        H as i64,
+        scale,
+        |x, y| idx >= 256 || ((table[idx][y as usize] >> x) & 1) == 1,
+    )
+}
+
+#[allow(clippy::too_many_arguments)]
+fn draw_glyph_scaled<T: Bitmap>(
+    buf: &mut T,
+    fg_color: u32,
+    bg_color: Option<u32>,
+    px: i64,
+    py: i64,
+    w: i64,
+    h: i64,
+    scale: i64,
+    is_set: impl Fn(i64, i64) -> bool,
+) -> Result<()> {
+    if scale <= 0
+        || !buf.is_in_x_range(px)
         || !buf.is_in_y_range(py)
-        || !buf.is_in_x_range(px + 8 - 1)
-        || !buf.is_in_y_range(py + 16 - 1)
+        || !buf.is_in_x_range(px + w * scale - 1)
+        || !buf.is_in_y_range(py + h * scale - 1)
     {
         return Err(Error::GraphicsOutOfRange);
     }
 
-    let idx = c as usize;
-    for y in 0..16_i64 {
-        for x in 0..8_i64 {
-            if idx >= 256 || ((BITMAP_FONT[idx][y as usize] >> x) & 1) == 1 {
+    for y in 0..h * scale {
+        for x in 0..w * scale {
+            if is_set(x / scale, y / scale) {
                 bitmap_draw_point(buf, fg_color, px + x, py + y)?;
             } else if let Some(bg_color) = bg_color {
                 bitmap_draw_point(buf, bg_color, px + x, py + y)?;
@@ -305,6 +472,27 @@ pub trait Bitmap {
     fn height(&self) -> i64;
     fn buf(&self) -> *const u8;
     fn buf_mut(&mut self) -> *mut u8;
+    /// Returns true if the pixels are stored in [R, G, B, X] byte order,
+    /// instead of [B, G, R, X] which matches with 0xXXRRGGBB colors on little-endian machines.
+    fn is_rgb_order(&self) -> bool {
+        false
+    }
+    /// Makes sure that the pixels drawn so far are visible (e.g. flushing caches for VRAM).
+    fn flush(&mut self) {}
+    /// Fills the visible pixels with black. The pixels beyond the width in each line are kept.
+    fn clear(&mut self) {
+        let bytes_per_line = (self.width() * self.bytes_per_pixel()) as usize;
+        for y in 0..self.height() {
+            // SAFETY: The line y is in the buffer and has at least `width` pixels
+            unsafe {
+                core::ptr::write_bytes(
+                    self.unchecked_pixel_at_mut(0, y) as *mut u8,
+                    0,
+                    bytes_per_line,
+                )
+            }
+        }
+    }
     fn pixel_at(&self, x: i64, y: i64) -> Option<&u32> {
         if self.is_in_x_range(x) && self.is_in_y_range(y) {
             // # Safety
@@ -432,7 +620,45 @@ pub fn transfer_rect<T: Bitmap>(
     Ok(())
 }
 
-#[derive(PartialEq, Eq, Debug)]
+/// Moves the pixels in `rect` up by `dy` pixels, and fills the rows exposed at the bottom with
+/// black. The rows are moved as bytes, so the colors are kept as is in any pixel order.
+pub fn scroll_up<T: Bitmap>(buf: &mut T, rect: &Rect, dy: i64) -> Result<()> {
+    if rect.is_empty() {
+        return Ok(());
+    }
+    if dy < 0
+        || !buf.is_in_x_range(rect.x())
+        || !buf.is_in_y_range(rect.y())
+        || !buf.is_in_x_range(rect.x() + rect.w() - 1)
+        || !buf.is_in_y_range(rect.y() + rect.h() - 1)
+    {
+        return Err(Error::GraphicsOutOfRange);
+    }
+    let dy = min(dy, rect.h());
+    let bytes_per_line = (rect.w() * buf.bytes_per_pixel()) as usize;
+    let bottom = rect.y() + rect.h();
+    for y in rect.y()..bottom - dy {
+        // SAFETY: Both lines are in the rect, which is checked to be in the buffer above
+        unsafe {
+            let src = buf.unchecked_pixel_at(rect.x(), y + dy) as *const u8;
+            let dst = buf.unchecked_pixel_at_mut(rect.x(), y) as *mut u8;
+            core::ptr::copy(src, dst, bytes_per_line);
+        }
+    }
+    for y in bottom - dy..bottom {
+        // SAFETY: Same as above
+        unsafe {
+            core::ptr::write_bytes(
+                buf.unchecked_pixel_at_mut(rect.x(), y) as *mut u8,
+                0,
+                bytes_per_line,
+            );
+        }
+    }
+    Ok(())
+}
+
+#[derive(Clone, PartialEq, Eq, Debug)]
 pub struct BitmapBuffer {
     buf: Vec<u8>,
     width: i64,
@@ -454,6 +680,26 @@ impl BitmapBuffer {
         buf.buf.resize((pixels_per_line * height * 4) as usize, 0);
         buf
     }
+    /// Wraps `buf` holding the pixels in [B, G, R, X] order, e.g. a decoded image, without
+    /// copying it. `buf` should have at least `pixels_per_line * height * 4` bytes.
+    pub fn from_vec(buf: Vec<u8>, width: i64, height: i64, pixels_per_line: i64) -> Result<Self> {
+        if width < 0 || height < 0 || pixels_per_line < width {
+            return Err(Error::Failed("BitmapBuffer: invalid size"));
+        }
+        let required_bytes = pixels_per_line
+            .checked_mul(height)
+            .and_then(|n| n.checked_mul(4))
+            .ok_or(Error::Failed("BitmapBuffer: too large"))?;
+        if buf.len() < required_bytes as usize {
+            return Err(Error::Failed("BitmapBuffer: buffer too small"));
+        }
+        Ok(Self {
+            buf,
+            width,
+            height,
+            pixels_per_line,
+        })
+    }
 }
 impl Bitmap for BitmapBuffer {
     fn bytes_per_pixel(&self) -> i64 {
@@ -476,12 +722,363 @@ impl Bitmap for BitmapBuffer {
     }
 }
 
+/// A view of a Bitmap which only allows drawing inside `clip`, given in the coordinates of the
+/// underlying bitmap. Pixels outside of the clip are reported as out of range, so the drawing
+/// functions leave them untouched. Nesting ClippedBitmaps intersects their clip rects.
+/// Only the range checks (is_in_x_range() / is_in_y_range()) are clipped: width(), height() and
+/// the raw buffer are those of the underlying bitmap, since the coordinates are not translated.
+/// Code writing to buf_mut() directly must check the range by itself.
+pub struct ClippedBitmap<'a, T: Bitmap> {
+    inner: &'a mut T,
+    clip: Rect,
+}
+impl<'a, T: Bitmap> ClippedBitmap<'a, T> {
+    pub fn new(inner: &'a mut T, clip: Rect) -> Self {
+        Self { inner, clip }
+    }
+    pub fn clip(&self) -> &Rect {
+        &self.clip
+    }
+}
+impl<T: Bitmap> Bitmap for ClippedBitmap<'_, T> {
+    fn bytes_per_pixel(&self) -> i64 {
+        self.inner.bytes_per_pixel()
+    }
+    fn pixels_per_line(&self) -> i64 {
+        self.inner.pixels_per_line()
+    }
+    /// Width of the underlying bitmap, not of the clip. See the doc of ClippedBitmap.
+    fn width(&self) -> i64 {
+        self.inner.width()
+    }
+    /// Height of the underlying bitmap, not of the clip. See the doc of ClippedBitmap.
+    fn height(&self) -> i64 {
+        self.inner.height()
+    }
+    fn buf(&self) -> *const u8 {
+        self.inner.buf()
+    }
+    /// The whole buffer of the underlying bitmap, which is not clipped.
+    fn buf_mut(&mut self) -> *mut u8 {
+        self.inner.buf_mut()
+    }
+    fn is_rgb_order(&self) -> bool {
+        self.inner.is_rgb_order()
+    }
+    fn flush(&mut self) {
+        self.inner.flush()
+    }
+    /// Fills only the pixels in the clip with black, unlike the default implementation which
+    /// clears the whole buffer.
+    fn clear(&mut self) {
+        let Some(rect) = Rect::new(0, 0, self.width(), self.height())
+            .and_then(|bounds| bounds.intersection(&self.clip))
+        else {
+            return;
+        };
+        for y in rect.y()..rect.y() + rect.h() {
+            for x in rect.x()..rect.x() + rect.w() {
+                // Pixels clipped by the inner bitmap (e.g. a nested ClippedBitmap) are skipped
+                let _ = bitmap_draw_point(self, 0, x, y);
+            }
+        }
+    }
+    fn is_in_x_range(&self, px: i64) -> bool {
+        self.inner.is_in_x_range(px) && self.clip.x() <= px && px < self.clip.x() + self.clip.w()
+    }
+    fn is_in_y_range(&self, py: i64) -> bool {
+        self.inner.is_in_y_range(py) && self.clip.y() <= py && py < self.clip.y() + self.clip.h()
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::Bitmap;
     use super::*;
     use alloc::vec;
 
+    struct RgbBitmapBuffer(BitmapBuffer);
+    impl Bitmap for RgbBitmapBuffer {
+        fn bytes_per_pixel(&self) -> i64 {
+            self.0.bytes_per_pixel()
+        }
+        fn pixels_per_line(&self) -> i64 {
+            self.0.pixels_per_line()
+        }
+        fn width(&self) -> i64 {
+            self.0.width()
+        }
+        fn height(&self) -> i64 {
+            self.0.height()
+        }
+        fn buf(&self) -> *const u8 {
+            self.0.buf()
+        }
+        fn buf_mut(&mut self) -> *mut u8 {
+            self.0.buf_mut()
+        }
+        fn is_rgb_order(&self) -> bool {
+            true
+        }
+    }
+
+    #[test]
+    fn from_vec_wraps_buffer() {
+        let pixels: Vec<u8> = [0x112233u32, 0x445566, 0x778899, 0xAABBCC]
+            .iter()
+            .flat_map(|p| p.to_le_bytes())
+            .collect();
+        let bmp = BitmapBuffer::from_vec(pixels.clone(), 1, 2, 2).unwrap();
+        assert_eq!(bmp.pixel_at(0, 0), Some(&0x112233));
+        assert_eq!(bmp.pixel_at(0, 1), Some(&0x778899));
+        assert_eq!(bmp.pixel_at(1, 0), None);
+        // A larger buffer is fine as well
+        let mut larger = pixels.clone();
+        larger.push(0);
+        assert!(BitmapBuffer::from_vec(larger, 2, 2, 2).is_ok());
+        // The decoded image can be blitted
+        let mut screen = BitmapBuffer::new(3, 3, 3);
+        draw_bmp_clipped(&mut screen, &bmp, 2, 1).unwrap();
+        assert_eq!(screen.pixel_at(2, 1), Some(&0x112233));
+        assert_eq!(screen.pixel_at(2, 2), Some(&0x778899));
+    }
+    #[test]
+    fn from_vec_rejects_invalid_buffer() {
+        assert_eq!(
+            BitmapBuffer::from_vec(vec![0; 15], 2, 2, 2),
+            Err(Error::Failed("BitmapBuffer: buffer too small"))
+        );
+        // The padding in the last line is required as well
+        assert!(BitmapBuffer::from_vec(vec![0; 24], 2, 2, 4).is_err());
+        assert!(BitmapBuffer::from_vec(vec![0; 16], 3, 1, 2).is_err());
+        assert!(BitmapBuffer::from_vec(vec![0; 16], -1, 1, 2).is_err());
+        assert!(BitmapBuffer::from_vec(Vec::new(), 1, i64::MAX, 1).is_err());
+        assert!(BitmapBuffer::from_vec(Vec::new(), 0, 0, 0).is_ok());
+    }
+    #[test]
+    fn scroll_up_moves_rows_in_rect() {
+        let mut buf = BitmapBuffer::new(3, 4, 3);
+        for y in 0..4 {
+            for x in 0..3 {
+                *buf.pixel_at_mut(x, y).unwrap() = (y * 10 + x + 1) as u32;
+            }
+        }
+        scroll_up(&mut buf, &Rect::new(1, 1, 2, 3).unwrap(), 1).unwrap();
+        let rows: Vec<Vec<u32>> = (0..4)
+            .map(|y| (0..3).map(|x| *buf.pixel_at(x, y).unwrap()).collect())
+            .collect();
+        assert_eq!(
+            rows,
+            vec![
+                vec![1, 2, 3],
+                vec![11, 22, 23],
+                vec![21, 32, 33],
+                vec![31, 0, 0],
+            ]
+        );
+        // Scrolling more than the height clears the rect
+        scroll_up(&mut buf, &Rect::new(0, 0, 3, 2).unwrap(), 5).unwrap();
+        assert!((0..2).all(|y| (0..3).all(|x| buf.pixel_at(x, y) == Some(&0))));
+        assert_eq!(buf.pixel_at(0, 2), Some(&21));
+        assert!(scroll_up(&mut buf, &Rect::new(0, 0, 3, 5).unwrap(), 1).is_err());
+        assert!(scroll_up(&mut buf, &Rect::new(0, 0, 3, 2).unwrap(), -1).is_err());
+    }
+    #[test]
+    fn clear_fills_visible_pixels_with_black() {
+        let mut buf = BitmapBuffer::new(2, 2, 3);
+        for y in 0..2 {
+            for x in 0..3 {
+                unsafe { *buf.unchecked_pixel_at_mut(x, y) = 0xFFFFFF };
+            }
+        }
+        buf.clear();
+        for y in 0..2 {
+            assert_eq!(buf.pixel_at(0, y), Some(&0));
+            assert_eq!(buf.pixel_at(1, y), Some(&0));
+            // Invisible pixels in the padding are not touched
+            assert_eq!(unsafe { *buf.unchecked_pixel_at(2, y) }, 0xFFFFFF);
+        }
+    }
+
+    #[test]
+    fn test_draw_char_scaled() {
+        const FG: u32 = 0xFFFFFF;
+        const BG: u32 = 0x000000;
+        let mut buf = BitmapBuffer::new(16, 32, 16);
+        bitmap_draw_char_scaled(&mut buf, FG, Some(BG), 0, 0, 'A', 2).unwrap();
+        for (y, row) in BITMAP_FONT['A' as usize].iter().enumerate() {
+            for x in 0..8 {
+                let expected = if (row >> x) & 1 == 1 { FG } else { BG };
+                for (dx, dy) in [(0, 0), (1, 0), (0, 1), (1, 1)] {
+                    assert_eq!(
+                        buf.pixel_at(x as i64 * 2 + dx, y as i64 * 2 + dy),
+                        Some(&expected)
+                    );
+                }
+            }
+        }
+    }
+    #[test]
+    fn test_draw_char_scaled_out_of_range() {
+        let mut buf = BitmapBuffer::new(16, 32, 16);
+        assert_eq!(
+            bitmap_draw_char_scaled(&mut buf, 0, None, 1, 0, 'A', 2),
+            Err(Error::GraphicsOutOfRange)
+        );
+        assert_eq!(
+            bitmap_draw_char_scaled(&mut buf, 0, None, 0, 0, 'A', 3),
+            Err(Error::GraphicsOutOfRange)
+        );
+        assert_eq!(
+            bitmap_draw_char_scaled(&mut buf, 0, None, 0, 0, 'A', 0),
+            Err(Error::GraphicsOutOfRange)
+        );
+        assert_eq!(bitmap_draw_char(&mut buf, 0, None, 8, 16, 'A'), Ok(()));
+    }
+    fn count_pixels(buf: &BitmapBuffer, color: u32) -> usize {
+        (0..buf.height())
+            .flat_map(|y| (0..buf.width()).map(move |x| (x, y)))
+            .filter(|(x, y)| buf.pixel_at(*x, *y) == Some(&color))
+            .count()
+    }
+    #[test]
+    fn clipped_bitmap_rejects_pixels_outside_of_clip() {
+        let mut buf = BitmapBuffer::new(8, 8, 8);
+        let clipped = ClippedBitmap::new(&mut buf, Rect::new(2, 3, 4, 2).unwrap());
+        // The dimensions are of the underlying bitmap since coordinates are not translated
+        assert_eq!((clipped.width(), clipped.height()), (8, 8));
+        assert!(clipped.pixel_at(2, 3).is_some());
+        assert!(clipped.pixel_at(5, 4).is_some());
+        assert!(clipped.pixel_at(1, 3).is_none());
+        assert!(clipped.pixel_at(6, 4).is_none());
+        assert!(clipped.pixel_at(2, 5).is_none());
+        // The clip is intersected with the bounds of the underlying bitmap
+        let clipped = ClippedBitmap::new(&mut buf, Rect::new(-4, -4, 100, 100).unwrap());
+        assert!(clipped.pixel_at(-1, 0).is_none());
+        assert!(clipped.pixel_at(8, 0).is_none());
+        assert!(clipped.pixel_at(7, 7).is_some());
+    }
+    #[test]
+    fn drawing_through_clipped_bitmap_leaves_outside_untouched() {
+        const COLOR: u32 = 0x00FF00;
+        let mut buf = BitmapBuffer::new(8, 8, 8);
+        let mut clipped = ClippedBitmap::new(&mut buf, Rect::new(2, 2, 4, 4).unwrap());
+        assert_eq!(bitmap_draw_rect(&mut clipped, COLOR, 2, 2, 4, 4), Ok(()));
+        assert_eq!(
+            bitmap_draw_rect(&mut clipped, COLOR, 0, 0, 8, 8),
+            Err(Error::GraphicsOutOfRange)
+        );
+        assert_eq!(
+            bitmap_draw_line(&mut clipped, COLOR, 0, 0, 7, 7),
+            Err(Error::GraphicsOutOfRange)
+        );
+        assert_eq!(bitmap_draw_line(&mut clipped, 0x0000FF, 2, 2, 5, 5), Ok(()));
+        assert_eq!(count_pixels(&buf, COLOR) + count_pixels(&buf, 0x0000FF), 16);
+        for y in 0..8 {
+            for x in 0..8 {
+                if !(2..6).contains(&x) || !(2..6).contains(&y) {
+                    assert_eq!(buf.pixel_at(x, y), Some(&0), "({x}, {y})");
+                }
+            }
+        }
+    }
+    #[test]
+    fn clearing_clipped_bitmap_leaves_outside_untouched() {
+        const COLOR: u32 = 0x00FF00;
+        let mut buf = BitmapBuffer::new(8, 8, 8);
+        assert_eq!(bitmap_draw_rect(&mut buf, COLOR, 0, 0, 8, 8), Ok(()));
+        ClippedBitmap::new(&mut buf, Rect::new(2, 3, 4, 2).unwrap()).clear();
+        assert_eq!(count_pixels(&buf, 0), 8);
+        assert_eq!(count_pixels(&buf, COLOR), 64 - 8);
+        assert_eq!(buf.pixel_at(2, 3), Some(&0));
+        assert_eq!(buf.pixel_at(5, 4), Some(&0));
+        assert_eq!(buf.pixel_at(1, 3), Some(&COLOR));
+        assert_eq!(buf.pixel_at(2, 5), Some(&COLOR));
+        // A clip sticking out of the bitmap is fine as well
+        ClippedBitmap::new(&mut buf, Rect::new(6, -2, 10, 4).unwrap()).clear();
+        assert_eq!(count_pixels(&buf, 0), 8 + 4);
+    }
+    #[test]
+    fn nested_clipped_bitmaps_intersect_clips() {
+        let mut buf = BitmapBuffer::new(8, 8, 8);
+        let mut outer = ClippedBitmap::new(&mut buf, Rect::new(0, 0, 4, 8).unwrap());
+        let mut inner = ClippedBitmap::new(&mut outer, Rect::new(2, 2, 6, 2).unwrap());
+        assert_eq!(bitmap_draw_rect(&mut inner, 1, 2, 2, 2, 2), Ok(()));
+        assert_eq!(
+            bitmap_draw_rect(&mut inner, 1, 2, 2, 3, 2),
+            Err(Error::GraphicsOutOfRange)
+        );
+        assert_eq!(count_pixels(&buf, 1), 4);
+    }
+    mod font_8x8 {
+        font::gen_embedded_font!(path = "font_8x8_test.txt", height = 8);
+    }
+    #[test]
+    fn test_draw_char_with_8x8_font() {
+        assert_eq!(font_8x8::FONT_WIDTH, 8);
+        assert_eq!(font_8x8::FONT_HEIGHT, 8);
+        const FG: u32 = 0xFFFFFF;
+        const BG: u32 = 0x123456;
+        let mut buf = BitmapBuffer::new(16, 16, 16);
+        draw_char_from_table(
+            &mut buf,
+            &font_8x8::BITMAP_FONT,
+            font_8x8::FONT_WIDTH,
+            FG,
+            Some(BG),
+            0,
+            0,
+            'A',
+            1,
+        )
+        .unwrap();
+        // Bounding box should be 8x8
+        assert_eq!(buf.pixel_at(0, 7), Some(&FG));
+        assert_eq!(buf.pixel_at(7, 7), Some(&BG));
+        assert_eq!(buf.pixel_at(0, 8), Some(&0));
+        assert_eq!(buf.pixel_at(8, 0), Some(&0));
+        // An 8x8 glyph fits at the bottom edge where an 8x16 glyph does not
+        assert_eq!(
+            draw_char_from_table(
+                &mut buf,
+                &font_8x8::BITMAP_FONT,
+                font_8x8::FONT_WIDTH,
+                FG,
+                None,
+                8,
+                8,
+                'A',
+                1
+            ),
+            Ok(())
+        );
+        assert_eq!(
+            bitmap_draw_char(&mut buf, FG, None, 8, 8, 'A'),
+            Err(Error::GraphicsOutOfRange)
+        );
+    }
+    #[test]
+    fn test_swap_rb() {
+        assert_eq!(swap_rb(0x00112233), 0x00332211);
+        assert_eq!(swap_rb(0xFFFF0000), 0xFF0000FF);
+        assert_eq!(swap_rb(0x0000FF00), 0x0000FF00);
+        assert_eq!(swap_rb(swap_rb(0x12345678)), 0x12345678);
+    }
+    #[test]
+    fn test_draw_point_byte_order() {
+        let mut bgr = BitmapBuffer::new(1, 1, 1);
+        bitmap_draw_point(&mut bgr, 0x00112233, 0, 0).unwrap();
+        assert_eq!(bgr.buf, vec![0x33, 0x22, 0x11, 0x00]);
+
+        let mut rgb = RgbBitmapBuffer(BitmapBuffer::new(1, 1, 1));
+        bitmap_draw_point(&mut rgb, 0x00112233, 0, 0).unwrap();
+        assert_eq!(rgb.0.buf, vec![0x11, 0x22, 0x33, 0x00]);
+
+        // Colors are converted when copied between bitmaps with different orders
+        let mut bgr = BitmapBuffer::new(1, 1, 1);
+        draw_bmp_clipped(&mut bgr, &rgb, 0, 0).unwrap();
+        assert_eq!(bgr.pixel_at(0, 0), Some(&0x00112233));
+    }
     #[test]
     fn test_buf_default() {
         let h = 13_i64;
@@ -588,6 +1185,85 @@ mod tests {
             }
         }
     }
+    #[test]
+    fn rect_outline_leaves_interior_untouched() {
+        const COLOR: u32 = 0xFF0000;
+        let mut buf = BitmapBuffer::new(8, 8, 8);
+        assert_eq!(
+            bitmap_draw_rect_outline(&mut buf, COLOR, 1, 2, 5, 4, 1),
+            Ok(())
+        );
+        for y in 0..8 {
+            for x in 0..8 {
+                let on_border = (1..6).contains(&x)
+                    && (2..6).contains(&y)
+                    && (x == 1 || x == 5 || y == 2 || y == 5);
+                let expected = if on_border { COLOR } else { 0 };
+                assert_eq!(buf.pixel_at(x, y), Some(&expected), "at ({x}, {y})");
+            }
+        }
+        assert_eq!(
+            bitmap_draw_rect_outline(&mut buf, COLOR, 4, 4, 5, 1, 1),
+            Err(Error::GraphicsOutOfRange)
+        );
+    }
+    #[test]
+    fn rect_outline_clamps_thickness() {
+        const COLOR: u32 = 0x00FF00;
+        let mut buf = BitmapBuffer::new(8, 8, 8);
+        assert_eq!(
+            bitmap_draw_rect_outline(&mut buf, COLOR, 0, 0, 8, 5, 100),
+            Ok(())
+        );
+        // Clamped to 3, which covers the whole rect since the height is 5
+        assert_eq!(count_pixels(&buf, COLOR), 8 * 5);
+        // An odd width works in the same way even though the left and right borders overlap
+        let mut buf = BitmapBuffer::new(8, 8, 8);
+        assert_eq!(
+            bitmap_draw_rect_outline(&mut buf, COLOR, 0, 0, 5, 7, 3),
+            Ok(())
+        );
+        assert_eq!(count_pixels(&buf, COLOR), 5 * 7);
+        let mut buf = BitmapBuffer::new(8, 8, 8);
+        assert_eq!(
+            bitmap_draw_rect_outline(&mut buf, COLOR, 0, 0, 8, 8, 2),
+            Ok(())
+        );
+        assert_eq!(count_pixels(&buf, COLOR), 8 * 8 - 4 * 4);
+        assert_eq!(buf.pixel_at(2, 2), Some(&0));
+        assert_eq!(buf.pixel_at(1, 1), Some(&COLOR));
+    }
+    #[test]
+    fn gradient_color_interpolates_each_channel() {
+        const TOP: u32 = 0x00_10_80_FF;
+        const BOTTOM: u32 = 0xFF_30_00_01;
+        assert_eq!(gradient_color_at(TOP, BOTTOM, 0, 5), TOP);
+        assert_eq!(gradient_color_at(TOP, BOTTOM, 4, 5), BOTTOM);
+        assert_eq!(gradient_color_at(TOP, BOTTOM, 2, 5), 0x7F_20_40_80);
+        assert_eq!(gradient_color_at(TOP, BOTTOM, 0, 1), TOP);
+    }
+    #[test]
+    fn gradient_rect_has_top_and_bottom_colors() {
+        const TOP: u32 = 0x000000;
+        const BOTTOM: u32 = 0x0000FF;
+        let mut buf = BitmapBuffer::new(4, 8, 4);
+        assert_eq!(
+            bitmap_draw_gradient_rect(&mut buf, TOP, BOTTOM, 1, 2, 2, 5),
+            Ok(())
+        );
+        for x in 1..3 {
+            assert_eq!(buf.pixel_at(x, 2), Some(&TOP));
+            assert_eq!(buf.pixel_at(x, 4), Some(&0x00007F));
+            assert_eq!(buf.pixel_at(x, 6), Some(&BOTTOM));
+        }
+        // Outside of the rect is untouched
+        assert_eq!(buf.pixel_at(0, 4), Some(&0));
+        assert_eq!(buf.pixel_at(1, 7), Some(&0));
+        assert_eq!(
+            bitmap_draw_gradient_rect(&mut buf, TOP, BOTTOM, 0, 0, 4, 9),
+            Err(Error::GraphicsOutOfRange)
+        );
+    }
 }
 
 /// Transfers the pixels in a rect sized (w, h) at (sx, sy) in the src bitmap
@@ -602,6 +1278,7 @@ pub fn draw_bmp_clipped<DstBitmap: Bitmap, SrcBitmap: Bitmap>(
     let src_rect = Rect::new(dx, dy, src.width(), src.height())?;
     let copy_rect = dst_rect.intersection(&src_rect)?;
     let (rx, ry) = copy_rect.frame_ranges();
+    let needs_swap = dst.is_rgb_order() != src.is_rgb_order();
 
     for y in ry.range.clone() {
         for x in rx.range.clone() {
@@ -609,7 +1286,7 @@ pub fn draw_bmp_clipped<DstBitmap: Bitmap, SrcBitmap: Bitmap>(
                 dst.pixel_at_mut(x, y),
                 src.pixel_at(x - src_rect.x(), y - src_rect.y()),
             ) {
-                *dstp = *srcp;
+                *dstp = if needs_swap { swap_rb(*srcp) } else { *srcp };
             }
         }
     }
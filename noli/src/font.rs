@@ -1,3 +1,249 @@
+extern crate alloc;
+
+use crate::error::Error;
+use crate::error::Result;
+use alloc::boxed::Box;
+use alloc::vec::Vec;
+use core::ptr::null_mut;
+use core::sync::atomic::AtomicPtr;
+use core::sync::atomic::Ordering;
+
 // This proc-macro call will generate an const variable that contains font bitmap.
 // Please check /wasabi/font for more info.
 font::gen_embedded_font!();
+
+const PSF1_MAGIC: [u8; 2] = [0x36, 0x04];
+const PSF1_MODE_512: u8 = 0x01;
+const PSF1_HEADER_SIZE: usize = 4;
+const PSF2_MAGIC: [u8; 4] = [0x72, 0xb5, 0x4a, 0x86];
+const PSF2_HEADER_SIZE: usize = 32;
+
+fn read_u32_le(data: &[u8], offset: usize) -> Result<u32> {
+    data.get(offset..offset + 4)
+        .map(|b| u32::from_le_bytes([b[0], b[1], b[2], b[3]]))
+        .ok_or(Error::Failed("PSF: header is truncated"))
+}
+
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub struct PsfHeader {
+    pub glyph_offset: usize,
+    pub num_glyphs: usize,
+    pub bytes_per_glyph: usize,
+    pub width: usize,
+    pub height: usize,
+}
+impl PsfHeader {
+    /// Parses a PSF1 or PSF2 header. The unicode table, if any, is ignored.
+    pub fn parse(data: &[u8]) -> Result<Self> {
+        if data.starts_with(&PSF2_MAGIC) {
+            let header_size = read_u32_le(data, 8)? as usize;
+            let header = Self {
+                glyph_offset: header_size,
+                num_glyphs: read_u32_le(data, 16)? as usize,
+                bytes_per_glyph: read_u32_le(data, 20)? as usize,
+                height: read_u32_le(data, 24)? as usize,
+                width: read_u32_le(data, 28)? as usize,
+            };
+            if header_size < PSF2_HEADER_SIZE {
+                return Err(Error::Failed("PSF2: header size is too small"));
+            }
+            if header.width == 0 || header.height == 0 || header.num_glyphs == 0 {
+                return Err(Error::Failed("PSF2: empty glyphs"));
+            }
+            if header.bytes_per_glyph < header.bytes_per_row() * header.height {
+                return Err(Error::Failed("PSF2: bytes_per_glyph is too small"));
+            }
+            Ok(header)
+        } else if data.starts_with(&PSF1_MAGIC) {
+            let mode = *data
+                .get(2)
+                .ok_or(Error::Failed("PSF1: header is truncated"))?;
+            let char_size = *data
+                .get(3)
+                .ok_or(Error::Failed("PSF1: header is truncated"))?;
+            if char_size == 0 {
+                return Err(Error::Failed("PSF1: empty glyphs"));
+            }
+            Ok(Self {
+                glyph_offset: PSF1_HEADER_SIZE,
+                num_glyphs: if mode & PSF1_MODE_512 != 0 { 512 } else { 256 },
+                bytes_per_glyph: char_size as usize,
+                width: 8,
+                height: char_size as usize,
+            })
+        } else {
+            Err(Error::Failed("Not a PSF font"))
+        }
+    }
+    pub fn bytes_per_row(&self) -> usize {
+        self.width.div_ceil(8)
+    }
+    pub fn glyph_range(&self, index: usize) -> Option<core::ops::Range<usize>> {
+        if index >= self.num_glyphs {
+            return None;
+        }
+        let start = self.glyph_offset + index * self.bytes_per_glyph;
+        Some(start..start + self.bytes_per_glyph)
+    }
+}
+
+pub struct Font {
+    header: PsfHeader,
+    glyphs: Vec<u8>,
+}
+impl Font {
+    pub fn parse(data: &[u8]) -> Result<Self> {
+        let header = PsfHeader::parse(data)?;
+        let end = header
+            .glyph_range(header.num_glyphs - 1)
+            .ok_or(Error::Failed("PSF: no glyphs"))?
+            .end;
+        let glyphs = data
+            .get(header.glyph_offset..end)
+            .ok_or(Error::Failed("PSF: glyph data is truncated"))?
+            .to_vec();
+        Ok(Self {
+            header: PsfHeader {
+                glyph_offset: 0,
+                ..header
+            },
+            glyphs,
+        })
+    }
+    pub fn width(&self) -> i64 {
+        self.header.width as i64
+    }
+    pub fn height(&self) -> i64 {
+        self.header.height as i64
+    }
+    /// Returns the bitmap of the glyph for `c`, or the first glyph if the font does not have it.
+    pub fn glyph(&self, c: char) -> &[u8] {
+        let range = self
+            .header
+            .glyph_range(c as usize)
+            .or(self.header.glyph_range(0))
+            .expect("Font should have at least one glyph");
+        &self.glyphs[range]
+    }
+    pub fn is_pixel_set(&self, c: char, x: i64, y: i64) -> bool {
+        if x < 0 || y < 0 || x >= self.width() || y >= self.height() {
+            return false;
+        }
+        let (x, y) = (x as usize, y as usize);
+        let row = y * self.header.bytes_per_row();
+        ((self.glyph(c)[row + x / 8] << (x % 8)) & 0x80) != 0
+    }
+}
+
+static LOADED_FONT: AtomicPtr<Font> = AtomicPtr::new(null_mut());
+
+/// Replaces the embedded font with `font` for the subsequent draw_char calls.
+pub fn set_loaded_font(font: Font) {
+    let font = Box::leak(Box::new(font));
+    LOADED_FONT.store(font, Ordering::SeqCst);
+}
+
+pub fn loaded_font() -> Option<&'static Font> {
+    let font = LOADED_FONT.load(Ordering::SeqCst);
+    // SAFETY: LOADED_FONT is either null or a pointer leaked by set_loaded_font.
+    unsafe { font.as_ref() }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn psf2_font(width: u32, height: u32, glyphs: &[&[u8]]) -> Vec<u8> {
+        let bytes_per_glyph = width.div_ceil(8) * height;
+        let mut data = Vec::new();
+        data.extend_from_slice(&PSF2_MAGIC);
+        for v in [
+            0,
+            PSF2_HEADER_SIZE as u32,
+            0,
+            glyphs.len() as u32,
+            bytes_per_glyph,
+            height,
+            width,
+        ] {
+            data.extend_from_slice(&v.to_le_bytes());
+        }
+        for g in glyphs {
+            assert_eq!(g.len(), bytes_per_glyph as usize);
+            data.extend_from_slice(g);
+        }
+        data
+    }
+
+    #[test]
+    fn parse_psf2_header() {
+        let data = psf2_font(10, 2, &[&[0; 4], &[0; 4], &[0; 4]]);
+        assert_eq!(
+            PsfHeader::parse(&data),
+            Ok(PsfHeader {
+                glyph_offset: 32,
+                num_glyphs: 3,
+                bytes_per_glyph: 4,
+                width: 10,
+                height: 2,
+            })
+        );
+    }
+    #[test]
+    fn parse_psf1_header() {
+        let header = PsfHeader::parse(&[0x36, 0x04, 0x01, 16]).unwrap();
+        assert_eq!(header.glyph_offset, 4);
+        assert_eq!(header.num_glyphs, 512);
+        assert_eq!(header.bytes_per_glyph, 16);
+        assert_eq!(header.width, 8);
+        assert_eq!(header.height, 16);
+    }
+    #[test]
+    fn parse_invalid_header() {
+        assert!(PsfHeader::parse(&[]).is_err());
+        assert!(PsfHeader::parse(&[0x00, 0x01, 0x02, 0x03]).is_err());
+        assert!(PsfHeader::parse(&PSF2_MAGIC).is_err());
+        let mut data = psf2_font(8, 2, &[&[0; 2]]);
+        data[20] = 1; // bytes_per_glyph
+        assert!(PsfHeader::parse(&data).is_err());
+    }
+    #[test]
+    fn glyph_offsets() {
+        let data = psf2_font(10, 2, &[&[0; 4], &[0; 4], &[0; 4]]);
+        let header = PsfHeader::parse(&data).unwrap();
+        assert_eq!(header.bytes_per_row(), 2);
+        assert_eq!(header.glyph_range(0), Some(32..36));
+        assert_eq!(header.glyph_range(2), Some(40..44));
+        assert_eq!(header.glyph_range(3), None);
+    }
+    #[test]
+    fn font_glyph_lookup() {
+        let data = psf2_font(
+            10,
+            2,
+            &[
+                &[0xff, 0xc0, 0xff, 0xc0],
+                &[0x80, 0x00, 0x00, 0x40],
+                &[0x01, 0x00, 0x00, 0x00],
+            ],
+        );
+        let font = Font::parse(&data).unwrap();
+        assert_eq!(font.width(), 10);
+        assert_eq!(font.height(), 2);
+        assert_eq!(font.glyph('\x01'), &[0x80, 0x00, 0x00, 0x40]);
+        // Glyphs out of range fall back to the first one
+        assert_eq!(font.glyph('A'), &[0xff, 0xc0, 0xff, 0xc0]);
+        assert!(font.is_pixel_set('\x01', 0, 0));
+        assert!(!font.is_pixel_set('\x01', 1, 0));
+        assert!(font.is_pixel_set('\x01', 9, 1));
+        assert!(font.is_pixel_set('\x02', 7, 0));
+        assert!(!font.is_pixel_set('\x02', 10, 0));
+    }
+    #[test]
+    fn font_truncated() {
+        let mut data = psf2_font(8, 2, &[&[0; 2], &[0; 2]]);
+        data.pop();
+        assert!(Font::parse(&data).is_err());
+        assert!(Font::parse(&[0x36, 0x04, 0x00, 16]).is_err());
+    }
+}
@@ -3,6 +3,7 @@ extern crate alloc;
 use crate::error::Error;
 use crate::error::Result;
 use crate::font::BITMAP_FONT;
+use crate::font::FONT_WIDTH;
 use crate::prelude::*;
 use core::cmp::max;
 use core::cmp::min;
@@ -247,7 +248,7 @@ pub fn draw_char_2x(color: u32, px: i64, py: i64, c: char) -> Result<()> {
 pub fn draw_char(color: u32, px: i64, py: i64, c: char) -> Result<()> {
     let font_data = BITMAP_FONT[c as usize];
     for (y, row_bitmap) in font_data.iter().enumerate() {
-        for x in 0..8 {
+        for x in 0..FONT_WIDTH {
             if (row_bitmap >> x) & 0b1 == 0b1 {
                 draw_point(color, px + x, py + y as i64)?;
             }
@@ -271,12 +272,16 @@ pub fn fill_circle(color: u32, center_x: i64, center_y: i64, radius: i64) -> Res
 }
 
 pub fn fill_rect(color: u32, px: i64, py: i64, width: i64, height: i64) -> Result<()> {
-    for dx in 0..width {
-        for dy in 0..height {
-            draw_point(color, px + dx, py + dy)?;
-        }
+    if width <= 0 || height <= 0 {
+        // Filling an empty rect is a no-op rather than an error
+        return Ok(());
+    }
+    let result = Api::draw_rect(px, py, width, height, color);
+    if result == 0 {
+        Ok(())
+    } else {
+        Err(Error::Failed("fill_rect: syscall failed"))
     }
-    Ok(())
 }
 
 pub fn draw_rect(color: u32, x: i64, y: i64, width: i64, height: i64) -> Result<()> {
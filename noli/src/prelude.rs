@@ -20,6 +20,10 @@ pub use crate::error::MainReturn;
 #[allow(unused)]
 pub use crate::error::Result;
 #[allow(unused)]
+pub use sys::api::KeyboardEvent;
+#[allow(unused)]
+pub use sys::api::Modifiers;
+#[allow(unused)]
 pub use sys::api::MouseEvent;
 #[allow(unused)]
 pub use sys::os::Api;
@@ -1,6 +1,8 @@
 use crate::graphics::ScalarRange;
+use core::cmp::max;
+use core::cmp::min;
 
-#[derive(PartialEq, Eq, Debug)]
+#[derive(Clone, PartialEq, Eq, Debug)]
 pub struct Rect {
     x: i64,
     y: i64,
@@ -8,8 +10,10 @@ pub struct Rect {
     h: i64,
 }
 impl Rect {
+    /// Returns None if the size is negative or the right or bottom edge overflows, so that
+    /// x + w and y + h can always be computed for a Rect.
     pub fn new(x: i64, y: i64, w: i64, h: i64) -> Option<Rect> {
-        if w < 0 || h < 0 {
+        if w < 0 || h < 0 || x.checked_add(w).is_none() || y.checked_add(h).is_none() {
             None
         } else {
             Some(Self { x, y, w, h })
@@ -33,7 +37,18 @@ impl Rect {
             ScalarRange::new(self.y, self.y + self.h).unwrap(),
         )
     }
+    pub fn is_empty(&self) -> bool {
+        self.w == 0 || self.h == 0
+    }
+    /// Returns true if (x, y) is inside of the rect. The right and bottom edges are exclusive.
+    pub fn contains_point(&self, x: i64, y: i64) -> bool {
+        self.x <= x && x < self.x + self.w && self.y <= y && y < self.y + self.h
+    }
+    /// Returns the overlapping area of the rects, or None if they do not overlap.
     pub fn intersection(&self, another: &Self) -> Option<Rect> {
+        if self.is_empty() || another.is_empty() {
+            return None;
+        }
         let (rx0, ry0) = self.frame_ranges();
         let (rx1, ry1) = another.frame_ranges();
         let rx = rx0.intersection(&rx1)?;
@@ -44,6 +59,20 @@ impl Rect {
         let h = ry.end() - ry.start();
         Some(Self { x, y, w, h })
     }
+    /// Returns the smallest rect that contains both rects. Empty rects are ignored.
+    pub fn union(&self, another: &Self) -> Rect {
+        if another.is_empty() {
+            return self.clone();
+        }
+        if self.is_empty() {
+            return another.clone();
+        }
+        let x = min(self.x, another.x);
+        let y = min(self.y, another.y);
+        let w = max(self.x + self.w, another.x + another.w) - x;
+        let h = max(self.y + self.h, another.y + another.h) - y;
+        Self { x, y, w, h }
+    }
 }
 
 #[cfg(test)]
@@ -77,9 +106,87 @@ mod rect_tests {
         assert!(Rect::new(0, 0, -1, -1).is_none());
     }
     #[test]
+    fn fails_to_create_overflowing_rect() {
+        assert!(Rect::new(i64::MAX - 1, 0, 10, 1).is_none());
+        assert!(Rect::new(0, i64::MAX - 1, 1, 10).is_none());
+        let r = Rect::new(i64::MAX - 10, i64::MAX - 10, 10, 10).unwrap();
+        assert_eq!(r.intersection(&Rect::new(0, 0, 8, 8).unwrap()), None);
+    }
+    #[test]
     fn calc_intersection() {
         let r1 = Rect::new(0, 0, 1, 1).unwrap();
         let self_intersect = r1.intersection(&r1).unwrap();
         assert_eq!(self_intersect, r1);
     }
+    #[test]
+    fn calc_union() {
+        let r1 = Rect::new(0, 0, 1, 1).unwrap();
+        assert_eq!(r1.union(&r1), r1);
+        let regions = [
+            Rect::new(10, 10, 5, 5).unwrap(),
+            Rect::new(2, 20, 1, 1).unwrap(),
+            Rect::new(12, 3, 30, 2).unwrap(),
+        ];
+        let bounding_box = regions[1..]
+            .iter()
+            .fold(regions[0].clone(), |acc, r| acc.union(r));
+        assert_eq!(bounding_box, Rect::new(2, 3, 40, 18).unwrap());
+    }
+    #[test]
+    fn intersection_of_rect_pairs() {
+        let r = Rect::new(0, 0, 4, 4).unwrap();
+        // Overlapping
+        assert_eq!(
+            r.intersection(&Rect::new(2, 1, 4, 4).unwrap()),
+            Rect::new(2, 1, 2, 3)
+        );
+        // Touching edges do not overlap
+        assert_eq!(r.intersection(&Rect::new(4, 0, 2, 2).unwrap()), None);
+        assert_eq!(r.intersection(&Rect::new(0, 4, 2, 2).unwrap()), None);
+        // Fully contained
+        let inner = Rect::new(1, 1, 2, 2).unwrap();
+        assert_eq!(r.intersection(&inner), Some(inner.clone()));
+        assert_eq!(inner.intersection(&r), Some(inner));
+        // Disjoint
+        assert_eq!(r.intersection(&Rect::new(10, 10, 1, 1).unwrap()), None);
+        // Empty
+        assert_eq!(r.intersection(&Rect::new(1, 1, 0, 2).unwrap()), None);
+    }
+    #[test]
+    fn union_of_rect_pairs() {
+        let r = Rect::new(0, 0, 4, 4).unwrap();
+        // Overlapping
+        assert_eq!(
+            r.union(&Rect::new(2, 1, 4, 4).unwrap()),
+            Rect::new(0, 0, 6, 5).unwrap()
+        );
+        // Touching edges
+        assert_eq!(
+            r.union(&Rect::new(4, 0, 2, 2).unwrap()),
+            Rect::new(0, 0, 6, 4).unwrap()
+        );
+        // Fully contained
+        assert_eq!(r.union(&Rect::new(1, 1, 2, 2).unwrap()), r);
+        // Disjoint
+        assert_eq!(
+            r.union(&Rect::new(10, 10, 1, 1).unwrap()),
+            Rect::new(0, 0, 11, 11).unwrap()
+        );
+        // Empty rects do not extend the union
+        assert_eq!(r.union(&Rect::new(100, 100, 0, 0).unwrap()), r);
+        assert_eq!(Rect::new(-5, -5, 0, 3).unwrap().union(&r), r);
+    }
+    #[test]
+    fn contains_point_and_is_empty() {
+        let r = Rect::new(1, 2, 3, 4).unwrap();
+        assert!(!r.is_empty());
+        assert!(r.contains_point(1, 2));
+        assert!(r.contains_point(3, 5));
+        assert!(!r.contains_point(4, 5));
+        assert!(!r.contains_point(3, 6));
+        assert!(!r.contains_point(0, 2));
+        let empty = Rect::new(1, 2, 0, 4).unwrap();
+        assert!(empty.is_empty());
+        assert!(!empty.contains_point(1, 2));
+    }
 }
@@ -4,8 +4,9 @@ use crate::bitmap::Bitmap;
 use crate::bitmap::BitmapBuffer;
 use crate::error::Result;
 use crate::graphics::draw_line;
-use crate::graphics::draw_point;
+use crate::graphics::fill_rect;
 use crate::rect::Rect;
+use alloc::vec::Vec;
 
 #[derive(PartialEq, Eq, Debug)]
 pub struct Sheet {
@@ -67,24 +68,21 @@ impl Sheet {
         .unwrap();
         let (x_range, y_range) = intersection_rect.frame_ranges();
         for y in y_range.range {
-            for x in x_range.range.clone() {
-                let p = self.bitmap.pixel_at(x, y).cloned().unwrap_or_default();
-                let x = x + self.x;
+            let row = x_range
+                .range
+                .clone()
+                .map(|x| self.bitmap.pixel_at(x, y).cloned().unwrap_or_default());
+            // Draw each run of the same color at once to reduce the number of syscalls
+            for (ofs, len, color) in color_runs(row) {
+                let x = x_range.start() + ofs + self.x;
                 let y = y + self.y;
-                let _ = draw_point(p, x, y);
+                let _ = fill_rect(color, x, y, len, 1);
             }
         }
     }
 
     pub fn flush(&self) {
-        for y in 0..self.bitmap.height() {
-            for x in 0..self.bitmap.width() {
-                let p = self.bitmap.pixel_at(x, y).cloned().unwrap_or_default();
-                let x = x + self.x;
-                let y = y + self.y;
-                let _ = draw_point(p, x, y);
-            }
-        }
+        self.flush_area(self.rect());
     }
 
     pub fn draw_border(&mut self, color: u32) -> Result<()> {
@@ -107,3 +105,32 @@ impl Sheet {
         Ok(())
     }
 }
+
+/// Splits a row of pixels into runs of the same color.
+/// Returns a list of (offset, length, color).
+fn color_runs<I: IntoIterator<Item = u32>>(pixels: I) -> Vec<(i64, i64, u32)> {
+    let mut runs: Vec<(i64, i64, u32)> = Vec::new();
+    for (i, c) in pixels.into_iter().enumerate() {
+        match runs.last_mut() {
+            Some((_, len, color)) if *color == c => *len += 1,
+            _ => runs.push((i as i64, 1, c)),
+        }
+    }
+    runs
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use alloc::vec;
+
+    #[test]
+    fn splits_row_into_color_runs() {
+        assert_eq!(color_runs(Vec::new()), vec![]);
+        assert_eq!(color_runs(vec![1, 1, 1]), vec![(0, 3, 1)]);
+        assert_eq!(
+            color_runs(vec![1, 2, 2, 1, 3]),
+            vec![(0, 1, 1), (1, 2, 2), (3, 1, 1), (4, 1, 3)]
+        );
+    }
+}
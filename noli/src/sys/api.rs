@@ -1,3 +1,6 @@
+pub use sabi::FramebufferInfo;
+pub use sabi::KeyboardEvent;
+pub use sabi::Modifiers;
 pub use sabi::MouseEvent;
 pub use sabi::RawIpV4Addr;
 
@@ -14,14 +17,62 @@ pub trait SystemApi {
     fn draw_point(_x: i64, _y: i64, _c: u32) -> u64 {
         unimplemented!();
     }
+    /// Fills a rectangle on the screen with the color. The part out of the screen is clipped.
+    /// Returns 0 on success.
+    fn draw_rect(_x: i64, _y: i64, _w: i64, _h: i64, _c: u32) -> u64 {
+        unimplemented!();
+    }
+    /// Draws the pixels given as (x, y, color) at once.
+    /// Returns the number of pixels drawn, or a negative value on failure.
+    /// -1: INVALID_BUFFER
+    fn draw_pixels(_pixels: &[(i64, i64, u32)]) -> i64 {
+        unimplemented!();
+    }
+    /// Draws a string in one line with the 8x16 font. The background is transparent if `bg`
+    /// is None. Returns 0 on success.
+    /// -1: INVALID_ARGS (e.g. the string does not fit in the screen)
+    fn draw_string(_x: i64, _y: i64, _fg: u32, _bg: Option<u32>, _s: &str) -> i64 {
+        unimplemented!();
+    }
+    /// Maps a back-buffer of the screen size into the app so that the app can write the pixels
+    /// directly. Returns None if it could not be mapped. Call present() to show the pixels.
+    fn map_framebuffer() -> Option<FramebufferInfo> {
+        unimplemented!();
+    }
+    /// Shows the rect of the back-buffer given by map_framebuffer() on the screen.
+    /// Returns 0 on success.
+    /// -1: INVALID_ARGS (e.g. the rect is out of the screen)
+    /// -2: NOT_MAPPED
+    fn present(_x: i64, _y: i64, _w: i64, _h: i64) -> i64 {
+        unimplemented!();
+    }
     fn noop() -> u64 {
         unimplemented!()
     }
+    /// Yields the execution to the OS so that the other tasks can run.
+    /// Apps which poll something in a loop should call this to avoid hogging the CPU.
+    fn yield_now() -> u64 {
+        unimplemented!()
+    }
+    /// Returns the milliseconds elapsed since the boot.
+    fn uptime_ms() -> u64 {
+        unimplemented!()
+    }
     /// Returns None if no key was in the queue.
     /// This may yield the execution to the OS.
     fn read_key() -> Option<char> {
         unimplemented!()
     }
+    /// Same as read_key() but lets the OS run until a key arrives, up to `timeout_ms`.
+    /// Returns None if it timed out.
+    fn read_key_timeout(_timeout_ms: u64) -> Option<char> {
+        unimplemented!()
+    }
+    /// Same as read_key() but also returns the modifier keys held at that time.
+    /// This may yield the execution to the OS.
+    fn read_key_event() -> Option<KeyboardEvent> {
+        unimplemented!()
+    }
     /// Returns Some if there is a new event, or None.
     /// This may yield the execution to the OS.
     fn get_mouse_cursor_info() -> Option<MouseEvent> {
@@ -67,4 +118,33 @@ pub trait SystemApi {
     fn read_from_tcp_socket(_handle: i64, _buf: &mut [u8]) -> i64 {
         unimplemented!()
     }
+    /// Opens a file in the in-memory filesystem of the OS.
+    /// If `create` is true, an empty file is created (or truncated if it exists).
+    /// Returns a non-negative file descriptor for the file.
+    /// -1: OPEN_FAILED
+    /// -2: INVALID_NAME
+    fn open_file(_name: &str, _create: bool) -> i64 {
+        unimplemented!()
+    }
+    /// Reads from the file descriptor. fd 0 is the stdin, which reads the key inputs.
+    /// Returns a non-negative byte size that is written to the given buffer.
+    /// 0 means the end of the file, or no key input for the stdin.
+    /// -1: NO_SUCH_FD
+    /// -2: READ_ERROR
+    fn read(_fd: i64, _buf: &mut [u8]) -> i64 {
+        unimplemented!()
+    }
+    /// Writes to the file descriptor. fd 1 and 2 are the stdout and stderr.
+    /// Writes to a file are always appended to the end of it.
+    /// Returns a non-negative byte size that is written.
+    /// -1: NO_SUCH_FD
+    /// -2: WRITE_ERROR
+    fn write(_fd: i64, _buf: &[u8]) -> i64 {
+        unimplemented!()
+    }
+    /// Returns 0 on success.
+    /// -1: NO_SUCH_FD
+    fn close(_fd: i64) -> i64 {
+        unimplemented!()
+    }
 }
@@ -3,6 +3,8 @@ extern crate std;
 use crate::sys::api::SystemApi;
 
 use std::print;
+use std::sync::OnceLock;
+use std::time::Instant;
 
 #[macro_export]
 macro_rules! entry_point {
@@ -14,6 +16,13 @@ macro_rules! entry_point {
             main();
         }
     };
+    ($path:path, args) => {
+        // Command line arguments are not passed on Linux targets
+        #[allow(unused_must_use)]
+        pub fn main() {
+            $path(&[]);
+        }
+    };
 }
 
 pub struct Api;
@@ -30,4 +39,15 @@ impl SystemApi for Api {
     fn draw_point(_x: i64, _y: i64, _c: u32) -> u64 {
         0
     }
+    fn draw_rect(_x: i64, _y: i64, _w: i64, _h: i64, _c: u32) -> u64 {
+        0
+    }
+    fn draw_pixels(pixels: &[(i64, i64, u32)]) -> i64 {
+        pixels.len() as i64
+    }
+    /// Returns the milliseconds elapsed since the first call on Linux targets
+    fn uptime_ms() -> u64 {
+        static START: OnceLock<Instant> = OnceLock::new();
+        START.get_or_init(Instant::now).elapsed().as_millis() as u64
+    }
 }
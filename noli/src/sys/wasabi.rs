@@ -1,11 +1,18 @@
+extern crate alloc;
+
 use crate::prelude::*;
 
+use alloc::vec::Vec;
 use core::alloc::GlobalAlloc;
 use core::alloc::Layout;
 use core::ptr::null_mut;
 use core::slice;
+use sabi::FramebufferInfo;
+use sabi::KeyboardEvent;
 use sabi::MouseEvent;
 use sabi::RawIpV4Addr;
+use sabi::RawPixel;
+use sabi::Syscall;
 
 #[panic_handler]
 fn panic(info: &core::panic::PanicInfo) -> ! {
@@ -31,6 +38,21 @@ macro_rules! entry_point {
             Api::exit(code)
         }
     };
+    // Use this form to receive the command line arguments as fn(args: &[&str]).
+    // Note: the function can not be named `main` since it is the entry point on Linux targets.
+    ($path:path, args) => {
+        #[no_mangle]
+        pub unsafe extern "C" fn entry() -> ! {
+            use noli::prelude::*;
+            let args = noli::args::from_env();
+            let ret = $path(&args);
+            let code = ret.as_return_code();
+            if code != 0 {
+                println!("{ret:?}")
+            }
+            Api::exit(code)
+        }
+    };
 }
 
 trait MutableAllocator {
@@ -92,22 +114,22 @@ unsafe impl GlobalAlloc for GlobalAllocatorWrapper {
 
 use core::arch::asm;
 
-fn syscall_0(func: u64) -> u64 {
+fn syscall_0(func: Syscall) -> u64 {
     syscall_5(func, 0, 0, 0, 0, 0)
 }
-fn syscall_1(func: u64, arg1: u64) -> u64 {
+fn syscall_1(func: Syscall, arg1: u64) -> u64 {
     syscall_5(func, arg1, 0, 0, 0, 0)
 }
-fn syscall_2(func: u64, arg1: u64, arg2: u64) -> u64 {
+fn syscall_2(func: Syscall, arg1: u64, arg2: u64) -> u64 {
     syscall_5(func, arg1, arg2, 0, 0, 0)
 }
-fn syscall_3(func: u64, arg1: u64, arg2: u64, arg3: u64) -> u64 {
+fn syscall_3(func: Syscall, arg1: u64, arg2: u64, arg3: u64) -> u64 {
     syscall_5(func, arg1, arg2, arg3, 0, 0)
 }
-fn syscall_4(func: u64, arg1: u64, arg2: u64, arg3: u64, arg4: u64) -> u64 {
+fn syscall_4(func: Syscall, arg1: u64, arg2: u64, arg3: u64, arg4: u64) -> u64 {
     syscall_5(func, arg1, arg2, arg3, arg4, 0)
 }
-fn syscall_5(func: u64, arg1: u64, arg2: u64, arg3: u64, arg4: u64, arg5: u64) -> u64 {
+fn syscall_5(func: Syscall, arg1: u64, arg2: u64, arg3: u64, arg4: u64, arg5: u64) -> u64 {
     let mut retv;
     unsafe {
         asm!(
@@ -117,7 +139,7 @@ fn syscall_5(func: u64, arg1: u64, arg2: u64, arg3: u64, arg4: u64, arg5: u64) -
         "pop rsp",
         out("rax") retv,
         out("rcx") _, // destroyed by the syscall instruction
-        in("rdx") func,
+        in("rdx") func as u64,
         in("rsi") arg1,
         in("rdi") arg2,
         in("r8") arg3,
@@ -133,39 +155,105 @@ pub struct Api;
 
 impl SystemApi for Api {
     fn exit(code: u64) -> ! {
-        syscall_1(0, code);
+        syscall_1(Syscall::Exit, code);
         unreachable!()
     }
     fn write_string(s: &str) -> u64 {
         let len = s.len() as u64;
         let s = s.as_ptr() as u64;
-        syscall_2(1, s, len)
+        syscall_2(Syscall::Print, s, len)
     }
     fn draw_point(x: i64, y: i64, c: u32) -> u64 {
-        syscall_3(2, x as u64, y as u64, c as u64)
+        syscall_3(Syscall::DrawPoint, x as u64, y as u64, c as u64)
+    }
+    fn draw_rect(x: i64, y: i64, w: i64, h: i64, c: u32) -> u64 {
+        syscall_5(
+            Syscall::DrawRect,
+            x as u64,
+            y as u64,
+            w as u64,
+            h as u64,
+            c as u64,
+        )
+    }
+    fn draw_pixels(pixels: &[(i64, i64, u32)]) -> i64 {
+        let pixels: Vec<RawPixel> = pixels
+            .iter()
+            .map(|&(x, y, color)| RawPixel { x, y, color })
+            .collect();
+        syscall_2(
+            Syscall::DrawPixels,
+            pixels.as_ptr() as u64,
+            pixels.len() as u64,
+        ) as i64
+    }
+    fn draw_string(x: i64, y: i64, fg: u32, bg: Option<u32>, s: &str) -> i64 {
+        syscall_5(
+            Syscall::DrawString,
+            x as u64,
+            y as u64,
+            sabi::pack_text_colors(fg, bg),
+            s.as_ptr() as u64,
+            s.len() as u64,
+        ) as i64
+    }
+    fn map_framebuffer() -> Option<FramebufferInfo> {
+        let mut info = FramebufferInfo::default();
+        let p = &mut info as *mut FramebufferInfo as u64;
+        if syscall_1(Syscall::MapFramebuffer, p) == 0 {
+            Some(info)
+        } else {
+            None
+        }
+    }
+    fn present(x: i64, y: i64, w: i64, h: i64) -> i64 {
+        syscall_4(Syscall::Present, x as u64, y as u64, w as u64, h as u64) as i64
     }
     fn noop() -> u64 {
-        syscall_0(3)
+        syscall_0(Syscall::Noop)
+    }
+    fn yield_now() -> u64 {
+        syscall_0(Syscall::Yield)
+    }
+    fn uptime_ms() -> u64 {
+        syscall_0(Syscall::UptimeMs)
     }
     fn read_key() -> Option<char> {
-        let c = syscall_0(4);
+        let c = syscall_0(Syscall::ReadKey);
         if c == 0 {
             None
         } else {
             char::from_u32(c as u32)
         }
     }
+    fn read_key_timeout(timeout_ms: u64) -> Option<char> {
+        let c = syscall_1(Syscall::ReadKeyTimeout, timeout_ms);
+        if c == 0 {
+            None
+        } else {
+            char::from_u32(c as u32)
+        }
+    }
+    fn read_key_event() -> Option<KeyboardEvent> {
+        let mut e: KeyboardEvent = KeyboardEvent::default();
+        let ep = &mut e as *mut KeyboardEvent as u64;
+        if syscall_1(Syscall::ReadKeyEvent, ep) == 0 {
+            Some(e)
+        } else {
+            None
+        }
+    }
     fn get_mouse_cursor_info() -> Option<MouseEvent> {
         let mut e: MouseEvent = MouseEvent::default();
         let ep = &mut e as *mut MouseEvent as u64;
-        if syscall_1(5, ep) == 0 {
+        if syscall_1(Syscall::GetMouseCursorInfo, ep) == 0 {
             Some(e)
         } else {
             None
         }
     }
     fn get_args_region() -> Option<&'static [u8]> {
-        let addr = syscall_0(6);
+        let addr = syscall_0(Syscall::GetArgsRegion);
         if addr == 0 {
             None
         } else {
@@ -178,7 +266,7 @@ impl SystemApi for Api {
     }
     fn nslookup(host: &str, result: &mut [RawIpV4Addr]) -> i64 {
         syscall_4(
-            7,
+            Syscall::Nslookup,
             host.as_ptr() as u64,
             host.len() as u64,
             result.as_ptr() as u64,
@@ -186,12 +274,54 @@ impl SystemApi for Api {
         ) as i64
     }
     fn open_tcp_socket(ip: RawIpV4Addr, port: u16) -> i64 {
-        syscall_2(8, u32::from_be_bytes(ip) as u64, port as u64) as i64
+        syscall_2(
+            Syscall::TcpConnect,
+            u32::from_be_bytes(ip) as u64,
+            port as u64,
+        ) as i64
     }
     fn write_to_tcp_socket(handle: i64, buf: &[u8]) -> i64 {
-        syscall_3(9, handle as u64, buf.as_ptr() as u64, buf.len() as u64) as i64
+        syscall_3(
+            Syscall::TcpWrite,
+            handle as u64,
+            buf.as_ptr() as u64,
+            buf.len() as u64,
+        ) as i64
     }
     fn read_from_tcp_socket(handle: i64, buf: &mut [u8]) -> i64 {
-        syscall_3(10, handle as u64, buf.as_mut_ptr() as u64, buf.len() as u64) as i64
+        syscall_3(
+            Syscall::TcpRead,
+            handle as u64,
+            buf.as_mut_ptr() as u64,
+            buf.len() as u64,
+        ) as i64
+    }
+    fn open_file(name: &str, create: bool) -> i64 {
+        let flags = if create { sabi::OPEN_FLAG_CREATE } else { 0 };
+        syscall_3(
+            Syscall::OpenFile,
+            name.as_ptr() as u64,
+            name.len() as u64,
+            flags,
+        ) as i64
+    }
+    fn read(fd: i64, buf: &mut [u8]) -> i64 {
+        syscall_3(
+            Syscall::Read,
+            fd as u64,
+            buf.as_mut_ptr() as u64,
+            buf.len() as u64,
+        ) as i64
+    }
+    fn write(fd: i64, buf: &[u8]) -> i64 {
+        syscall_3(
+            Syscall::Write,
+            fd as u64,
+            buf.as_ptr() as u64,
+            buf.len() as u64,
+        ) as i64
+    }
+    fn close(fd: i64) -> i64 {
+        syscall_1(Syscall::Close, fd as u64) as i64
     }
 }
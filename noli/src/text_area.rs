@@ -1,27 +1,160 @@
+extern crate alloc;
+
 use crate::bitmap::bitmap_draw_char;
 use crate::bitmap::bitmap_draw_line;
 use crate::bitmap::bitmap_draw_rect;
-use crate::bitmap::transfer_rect;
+use crate::bitmap::scroll_up;
 use crate::bitmap::Bitmap;
+use crate::error::Error;
 use crate::error::Result;
+use crate::font::FONT_HEIGHT;
+use crate::font::FONT_WIDTH;
+use crate::rect::Rect;
+use alloc::vec::Vec;
 use core::cmp::max;
 use core::fmt;
 
+/// Tab stops are placed at every TAB_WIDTH columns.
+pub const TAB_WIDTH: i64 = 8;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum TextAreaMode {
     Scroll,
     Ring,
 }
 
+/// What a text area should do for a char, returned by TextCursor::advance().
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct CursorStep {
+    /// The cell (column, row) to draw the char at
+    pub draw_at: Option<(i64, i64)>,
+    /// The row the cursor left for a new line, if any
+    pub line_feed_from: Option<i64>,
+    /// True if the area should be scrolled up by a line to make room for the new line
+    pub scroll: bool,
+}
+
+/// Position of the cursor in a text area of `cols` x `rows` cells.
+/// Lines are wrapped at the right edge. A line feed on the bottom row scrolls the area up in
+/// Scroll mode, or moves the cursor back to the top row in Ring mode.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TextCursor {
+    cx: i64,
+    cy: i64,
+    cols: i64,
+    rows: i64,
+    mode: TextAreaMode,
+}
+impl TextCursor {
+    pub fn new(cols: i64, rows: i64, mode: TextAreaMode) -> Self {
+        Self {
+            cx: 0,
+            cy: 0,
+            cols: max(1, cols),
+            rows: max(1, rows),
+            mode,
+        }
+    }
+    /// Returns the position of the cursor as (column, row).
+    pub fn position(&self) -> (i64, i64) {
+        (self.cx, self.cy)
+    }
+    pub fn reset(&mut self) {
+        self.cx = 0;
+        self.cy = 0;
+    }
+    pub fn set_mode(&mut self, mode: TextAreaMode) {
+        self.mode = mode;
+    }
+    /// Moves the cursor for `c`:
+    /// - '\n' moves to the start of the next line.
+    /// - '\r' moves to the start of the line.
+    /// - '\t' moves to the next tab stop, or to the next line if there is no more tab stop.
+    /// - '\x08' (backspace) and '\x7f' (delete) move left without erasing, stopping at the
+    ///   start of the line, so that "\x08 \x08" erases a char as on terminals.
+    /// - The other chars are drawn at the cursor, which moves right and wraps at the edge.
+    pub fn advance(&mut self, c: char) -> CursorStep {
+        let mut step = CursorStep::default();
+        match c {
+            '\n' => self.line_feed(&mut step),
+            '\r' => self.cx = 0,
+            '\t' => {
+                self.cx = (self.cx / TAB_WIDTH + 1) * TAB_WIDTH;
+                if self.cx >= self.cols {
+                    self.line_feed(&mut step);
+                }
+            }
+            '\x08' | '\x7f' => self.cx = max(0, self.cx - 1),
+            _ => {
+                step.draw_at = Some((self.cx, self.cy));
+                self.cx += 1;
+                if self.cx >= self.cols {
+                    self.line_feed(&mut step);
+                }
+            }
+        }
+        step
+    }
+    fn line_feed(&mut self, step: &mut CursorStep) {
+        step.line_feed_from = Some(self.cy);
+        self.cx = 0;
+        if self.cy + 1 < self.rows {
+            self.cy += 1;
+        } else {
+            match self.mode {
+                TextAreaMode::Scroll => step.scroll = true,
+                TextAreaMode::Ring => self.cy = 0,
+            }
+        }
+    }
+}
+
+/// Height of the underline cursor in pixels
+pub const CURSOR_HEIGHT: i64 = 2;
+
+/// Pixels in a rect saved before drawing something over it temporarily, e.g. a cursor.
+/// Pixels are copied as is, so they are restored exactly in any pixel order.
+#[derive(Debug, PartialEq, Eq)]
+pub struct SavedCell {
+    rect: Rect,
+    pixels: Vec<u32>,
+}
+impl SavedCell {
+    /// Returns None if the rect is not entirely in `buf`.
+    pub fn save<T: Bitmap>(buf: &T, rect: &Rect) -> Option<Self> {
+        let mut pixels = Vec::with_capacity((rect.w() * rect.h()) as usize);
+        for y in rect.y()..rect.y() + rect.h() {
+            for x in rect.x()..rect.x() + rect.w() {
+                pixels.push(*buf.pixel_at(x, y)?);
+            }
+        }
+        Some(Self {
+            rect: rect.clone(),
+            pixels,
+        })
+    }
+    pub fn restore<T: Bitmap>(&self, buf: &mut T) {
+        let rect = &self.rect;
+        let points = (rect.y()..rect.y() + rect.h())
+            .flat_map(|y| (rect.x()..rect.x() + rect.w()).map(move |x| (x, y)));
+        for ((x, y), pixel) in points.zip(self.pixels.iter()) {
+            if let Some(p) = buf.pixel_at_mut(x, y) {
+                *p = *pixel;
+            }
+        }
+    }
+}
+
 pub struct TextArea<T: Bitmap> {
     buf: T,
     x: i64,
     y: i64,
     w: i64,
     h: i64,
-    cx: i64,
-    cy: i64,
-    mode: TextAreaMode,
+    cursor: TextCursor,
     ring_count: usize,
+    // Pixels under the cursor while it is shown
+    under_cursor: Option<SavedCell>,
 }
 
 impl<T: Bitmap> TextArea<T> {
@@ -32,10 +165,9 @@ impl<T: Bitmap> TextArea<T> {
             y,
             w,
             h,
-            cx: 0,
-            cy: 0,
-            mode: TextAreaMode::Scroll,
+            cursor: TextCursor::new(w / FONT_WIDTH, h / FONT_HEIGHT, TextAreaMode::Scroll),
             ring_count: 0,
+            under_cursor: None,
         };
         text_area.clear_screen().unwrap();
         text_area
@@ -43,86 +175,104 @@ impl<T: Bitmap> TextArea<T> {
     fn clear_screen(&mut self) -> Result<()> {
         bitmap_draw_rect(&mut self.buf, 0x000000, self.x, self.y, self.w, self.h)
     }
+    /// Clears the area and moves the cursor to the top-left.
+    pub fn clear(&mut self) -> Result<()> {
+        self.under_cursor = None;
+        self.cursor.reset();
+        self.ring_count = 0;
+        self.clear_screen()
+    }
+    /// Returns the position of the cursor as (column, row).
+    pub fn cursor(&self) -> (i64, i64) {
+        self.cursor.position()
+    }
     pub fn set_mode(&mut self, mode: TextAreaMode) {
-        self.mode = mode;
+        self.cursor.set_mode(mode);
     }
-    fn new_line(&mut self) -> Result<()> {
-        self.cx = 0;
-        match self.mode {
-            TextAreaMode::Scroll => {
-                self.cy += 1;
-                if (self.cy + 1) * 16 <= self.h {
-                    return Ok(());
-                }
-                self.cy -= 1;
-                transfer_rect(
-                    &mut self.buf,
-                    self.x,
-                    self.y,
-                    self.x,
-                    self.y + 16,
-                    self.w,
-                    self.cy * 16,
-                )?;
-                bitmap_draw_rect(
-                    &mut self.buf,
-                    0x000000,
-                    self.x,
-                    self.y + self.cy * 16,
-                    self.w,
-                    16,
-                )?;
-            }
-            TextAreaMode::Ring => {
-                bitmap_draw_line(
-                    &mut self.buf,
-                    0xff << ((self.ring_count % 3) * 8),
-                    self.x,
-                    self.y + self.cy * 16,
-                    self.x,
-                    self.y + (self.cy + 1) * 16 - 1,
-                )?;
-                self.cy += 1;
-                if (self.cy + 1) * 16 > self.h {
-                    self.cy = 0;
-                    self.ring_count += 1;
-                }
-                bitmap_draw_rect(
-                    &mut self.buf,
-                    0x000000,
-                    self.x,
-                    self.y + self.cy * 16,
-                    self.w,
-                    16,
-                )?;
-            }
+    /// Draws an underline cursor at the cursor position. The pixels under it are saved and
+    /// restored by hide_cursor(), so the char in the cell is kept.
+    pub fn show_cursor(&mut self) -> Result<()> {
+        if self.under_cursor.is_some() {
+            return Ok(());
         }
+        let (cx, cy) = self.cursor.position();
+        let rect = Rect::new(
+            self.x + cx * FONT_WIDTH,
+            self.y + (cy + 1) * FONT_HEIGHT - CURSOR_HEIGHT,
+            FONT_WIDTH,
+            CURSOR_HEIGHT,
+        )
+        .ok_or(Error::GraphicsOutOfRange)?;
+        let saved = SavedCell::save(&self.buf, &rect).ok_or(Error::GraphicsOutOfRange)?;
+        bitmap_draw_rect(
+            &mut self.buf,
+            0xFFFFFF,
+            rect.x(),
+            rect.y(),
+            rect.w(),
+            rect.h(),
+        )?;
+        self.under_cursor = Some(saved);
         Ok(())
     }
-    fn move_cursor_next(&mut self) -> Result<()> {
-        self.cx += 1;
-        if self.cx * 8 + 8 <= self.w {
-            return Ok(());
+    pub fn hide_cursor(&mut self) {
+        if let Some(saved) = self.under_cursor.take() {
+            saved.restore(&mut self.buf);
         }
-        self.new_line()
     }
-    fn move_cursor_prev(&mut self) -> Result<()> {
-        self.cx = max(0, self.cx - 1);
-        Ok(())
+    pub fn is_cursor_shown(&self) -> bool {
+        self.under_cursor.is_some()
+    }
+    fn clear_row(&mut self, row: i64) -> Result<()> {
+        bitmap_draw_rect(
+            &mut self.buf,
+            0x000000,
+            self.x,
+            self.y + row * FONT_HEIGHT,
+            self.w,
+            FONT_HEIGHT,
+        )
+    }
+    fn feed_line(&mut self, from_row: i64, scroll: bool) -> Result<()> {
+        if scroll {
+            let rect = Rect::new(self.x, self.y, self.w, self.cursor.rows * FONT_HEIGHT)
+                .ok_or(Error::GraphicsOutOfRange)?;
+            return scroll_up(&mut self.buf, &rect, FONT_HEIGHT);
+        }
+        let (_, row) = self.cursor.position();
+        if self.cursor.mode == TextAreaMode::Ring {
+            // Mark the line just written so that the latest line can be found
+            bitmap_draw_line(
+                &mut self.buf,
+                0xff << ((self.ring_count % 3) * 8),
+                self.x,
+                self.y + from_row * FONT_HEIGHT,
+                self.x,
+                self.y + (from_row + 1) * FONT_HEIGHT - 1,
+            )?;
+            if row == 0 {
+                self.ring_count += 1;
+            }
+        }
+        self.clear_row(row)
     }
     pub fn print_char_with_color(&mut self, c: char, fg: u32, bg: u32) -> Result<()> {
-        match c {
-            '\n' => self.new_line(),
-            '\x08' | '\x7f' => self.move_cursor_prev(),
-            _ => bitmap_draw_char(
+        // The saved pixels will be stale once the area is updated
+        self.hide_cursor();
+        let step = self.cursor.advance(c);
+        if let Some((cx, cy)) = step.draw_at {
+            bitmap_draw_char(
                 &mut self.buf,
                 fg,
                 Some(bg),
-                self.x + self.cx * 8,
-                self.y + self.cy * 16,
+                self.x + cx * FONT_WIDTH,
+                self.y + cy * FONT_HEIGHT,
                 c,
-            )
-            .and_then(|_| -> Result<()> { self.move_cursor_next() }),
+            )?;
+        }
+        match step.line_feed_from {
+            Some(from_row) => self.feed_line(from_row, step.scroll),
+            None => Ok(()),
         }
     }
     pub fn print_char(&mut self, c: char) -> Result<()> {
@@ -145,3 +295,168 @@ impl<T: Bitmap> fmt::Write for TextArea<T> {
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bitmap::BitmapBuffer;
+
+    fn advance_all(cursor: &mut TextCursor, s: &str) -> Vec<CursorStep> {
+        s.chars().map(|c| cursor.advance(c)).collect()
+    }
+
+    #[test]
+    fn cursor_wraps_at_right_edge() {
+        let mut cursor = TextCursor::new(3, 2, TextAreaMode::Scroll);
+        let steps = advance_all(&mut cursor, "abcd");
+        assert_eq!(steps[0].draw_at, Some((0, 0)));
+        assert_eq!(steps[1].draw_at, Some((1, 0)));
+        assert_eq!(
+            steps[2],
+            CursorStep {
+                draw_at: Some((2, 0)),
+                line_feed_from: Some(0),
+                scroll: false,
+            }
+        );
+        assert_eq!(steps[3].draw_at, Some((0, 1)));
+        assert_eq!(cursor.position(), (1, 1));
+    }
+    #[test]
+    fn cursor_scrolls_at_bottom() {
+        let mut cursor = TextCursor::new(3, 2, TextAreaMode::Scroll);
+        advance_all(&mut cursor, "a\nb");
+        assert_eq!(cursor.position(), (1, 1));
+        assert_eq!(
+            cursor.advance('\n'),
+            CursorStep {
+                draw_at: None,
+                line_feed_from: Some(1),
+                scroll: true,
+            }
+        );
+        assert_eq!(cursor.position(), (0, 1));
+        // Wrapping on the bottom row scrolls as well
+        let steps = advance_all(&mut cursor, "xyz");
+        assert_eq!(steps[2].draw_at, Some((2, 1)));
+        assert!(steps[2].scroll);
+        assert_eq!(cursor.position(), (0, 1));
+    }
+    #[test]
+    fn cursor_goes_back_to_top_in_ring_mode() {
+        let mut cursor = TextCursor::new(3, 2, TextAreaMode::Ring);
+        let steps = advance_all(&mut cursor, "\n\n");
+        assert_eq!(steps[1].line_feed_from, Some(1));
+        assert!(!steps[1].scroll);
+        assert_eq!(cursor.position(), (0, 0));
+    }
+    #[test]
+    fn cursor_handles_control_chars() {
+        let mut cursor = TextCursor::new(20, 2, TextAreaMode::Scroll);
+        advance_all(&mut cursor, "abc\r");
+        assert_eq!(cursor.position(), (0, 0));
+        assert_eq!(cursor.advance('\t'), CursorStep::default());
+        assert_eq!(cursor.position(), (8, 0));
+        advance_all(&mut cursor, "a\t");
+        assert_eq!(cursor.position(), (16, 0));
+        // No more tab stops in the line
+        assert_eq!(cursor.advance('\t').line_feed_from, Some(0));
+        assert_eq!(cursor.position(), (0, 1));
+        // Backspace moves left without erasing, and stops at the start of the line
+        advance_all(&mut cursor, "ab\x08");
+        assert_eq!(cursor.position(), (1, 1));
+        assert_eq!(cursor.advance('\x7f'), CursorStep::default());
+        advance_all(&mut cursor, "\x08\x08");
+        assert_eq!(cursor.position(), (0, 1));
+    }
+    #[test]
+    fn text_area_scrolls_lines_up() {
+        let mut text_area = TextArea::new(
+            BitmapBuffer::new(FONT_WIDTH * 2, FONT_HEIGHT * 2, FONT_WIDTH * 2),
+            0,
+            0,
+            FONT_WIDTH * 2,
+            FONT_HEIGHT * 2,
+        );
+        let is_row_blank = |text_area: &TextArea<BitmapBuffer>, row: i64| {
+            (0..FONT_HEIGHT).all(|y| {
+                (0..FONT_WIDTH * 2)
+                    .all(|x| text_area.buf.pixel_at(x, row * FONT_HEIGHT + y) == Some(&0))
+            })
+        };
+        text_area.print_string("#\n").unwrap();
+        assert!(!is_row_blank(&text_area, 0));
+        assert!(is_row_blank(&text_area, 1));
+        text_area.print_string("\n").unwrap();
+        // The "#" moved up and went out of the area
+        assert!(is_row_blank(&text_area, 0));
+        assert!(is_row_blank(&text_area, 1));
+        assert_eq!(text_area.cursor(), (0, 1));
+    }
+    #[test]
+    fn saved_cell_is_restored() {
+        let mut buf = BitmapBuffer::new(4, 3, 4);
+        for y in 0..3 {
+            for x in 0..4 {
+                *buf.pixel_at_mut(x, y).unwrap() = (y * 4 + x) as u32;
+            }
+        }
+        let before = buf.clone();
+        let rect = Rect::new(1, 1, 2, 2).unwrap();
+        let saved = SavedCell::save(&buf, &rect).unwrap();
+        assert_eq!(saved.pixels, [5, 6, 9, 10]);
+        bitmap_draw_rect(&mut buf, 0xFFFFFF, 0, 0, 4, 3).unwrap();
+        saved.restore(&mut buf);
+        assert_eq!(buf.pixel_at(1, 1), Some(&5));
+        assert_eq!(buf.pixel_at(2, 2), Some(&10));
+        // Pixels outside the rect are not restored
+        assert_eq!(buf.pixel_at(0, 0), Some(&0xFFFFFF));
+        assert_eq!(buf.pixel_at(3, 2), Some(&0xFFFFFF));
+        bitmap_draw_rect(&mut buf, 0, 0, 0, 4, 3).unwrap();
+        SavedCell::save(&before, &Rect::new(0, 0, 4, 3).unwrap())
+            .unwrap()
+            .restore(&mut buf);
+        assert_eq!(buf, before);
+        assert_eq!(SavedCell::save(&buf, &Rect::new(3, 2, 2, 1).unwrap()), None);
+    }
+    #[test]
+    fn cursor_keeps_char_under_it() {
+        let mut text_area = TextArea::new(
+            BitmapBuffer::new(FONT_WIDTH * 2, FONT_HEIGHT, FONT_WIDTH * 2),
+            0,
+            0,
+            FONT_WIDTH * 2,
+            FONT_HEIGHT,
+        );
+        text_area.print_string("_\r").unwrap();
+        let with_char = text_area.buf.clone();
+        text_area.show_cursor().unwrap();
+        assert!(text_area.is_cursor_shown());
+        assert_ne!(text_area.buf, with_char);
+        // Showing it twice does not save the cursor itself
+        text_area.show_cursor().unwrap();
+        text_area.hide_cursor();
+        assert!(!text_area.is_cursor_shown());
+        assert_eq!(text_area.buf, with_char);
+        // Printing hides the cursor before drawing
+        text_area.show_cursor().unwrap();
+        text_area.print_string("\r").unwrap();
+        assert!(!text_area.is_cursor_shown());
+        assert_eq!(text_area.buf, with_char);
+    }
+    #[test]
+    fn clear_resets_cursor() {
+        let mut text_area = TextArea::new(
+            BitmapBuffer::new(FONT_WIDTH * 8, FONT_HEIGHT * 4, FONT_WIDTH * 8),
+            0,
+            0,
+            FONT_WIDTH * 8,
+            FONT_HEIGHT * 4,
+        );
+        assert_eq!(text_area.cursor(), (0, 0));
+        text_area.print_string("hello\nab").unwrap();
+        assert_eq!(text_area.cursor(), (2, 1));
+        text_area.clear().unwrap();
+        assert_eq!(text_area.cursor(), (0, 0));
+    }
+}
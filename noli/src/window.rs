@@ -183,6 +183,37 @@ impl Window {
         self.sheet.flush();
     }
 
+    /// Clips the given rect in the client area coordinates into the client area,
+    /// and returns it in the sheet coordinates.
+    /// Returns None if nothing is left after clipping.
+    fn clip_to_client_area(&self, px: i64, py: i64, width: i64, height: i64) -> Option<Rect> {
+        if width <= 0 || height <= 0 {
+            return None;
+        }
+        let client_area = Rect::new(
+            0,
+            TITLE_BAR_HEIGHT,
+            self.sheet.width(),
+            self.sheet.height() - TITLE_BAR_HEIGHT,
+        )?;
+        if client_area.w() <= 0 || client_area.h() <= 0 {
+            return None;
+        }
+        Rect::new(px, py + TITLE_BAR_HEIGHT, width, height)?.intersection(&client_area)
+    }
+
+    /// Fills the whole client area with the color.
+    pub fn fill(&mut self, color: u32) -> Result<()> {
+        self.fill_rect(
+            color,
+            0,
+            0,
+            self.sheet.width(),
+            self.sheet.height() - TITLE_BAR_HEIGHT,
+        )
+    }
+
+    /// Fills the rect with the color. The part outside of the client area is ignored.
     pub fn fill_rect(
         &mut self,
         color: u32,
@@ -191,22 +222,9 @@ impl Window {
         width: i64,
         height: i64,
     ) -> Result<()> {
-        if px < 0
-            || px + width > self.sheet.width()
-            || py < 0
-            || py + height + TITLE_BAR_HEIGHT > self.sheet.height()
-        {
-            return Err(Error::Failed("fill_rect: out of range"));
+        if let Some(r) = self.clip_to_client_area(px, py, width, height) {
+            bitmap_draw_rect(self.sheet.bitmap(), color, r.x(), r.y(), r.w(), r.h())?;
         }
-
-        bitmap_draw_rect(
-            self.sheet.bitmap(),
-            color,
-            px,
-            py + TITLE_BAR_HEIGHT,
-            width,
-            height,
-        )?;
         Ok(())
     }
 
@@ -322,3 +340,45 @@ impl Window {
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bitmap::Bitmap;
+    use alloc::string::ToString;
+
+    #[test]
+    fn clips_to_client_area() {
+        let w = Window::new("test".to_string(), 0, 10, 10, 100, 50 + TITLE_BAR_HEIGHT).unwrap();
+        assert_eq!(
+            w.clip_to_client_area(5, 5, 10, 10),
+            Rect::new(5, 5 + TITLE_BAR_HEIGHT, 10, 10)
+        );
+        assert_eq!(
+            w.clip_to_client_area(-5, -5, 10, 10),
+            Rect::new(0, TITLE_BAR_HEIGHT, 5, 5)
+        );
+        assert_eq!(
+            w.clip_to_client_area(95, 45, 10, 10),
+            Rect::new(95, 45 + TITLE_BAR_HEIGHT, 5, 5)
+        );
+        assert_eq!(w.clip_to_client_area(100, 0, 10, 10), None);
+        assert_eq!(w.clip_to_client_area(0, -10, 10, 10), None);
+        assert_eq!(w.clip_to_client_area(0, 0, 0, 10), None);
+    }
+
+    #[test]
+    fn fill_does_not_touch_title_bar() {
+        let mut w = Window::new("test".to_string(), 0, 10, 10, 100, 50 + TITLE_BAR_HEIGHT).unwrap();
+        w.fill(0x123456).unwrap();
+        let bitmap = w.sheet.bitmap();
+        assert_eq!(bitmap.pixel_at(0, TITLE_BAR_HEIGHT), Some(&0x123456));
+        assert_eq!(bitmap.pixel_at(99, 49 + TITLE_BAR_HEIGHT), Some(&0x123456));
+        assert_eq!(bitmap.pixel_at(0, TITLE_BAR_HEIGHT - 1), Some(&DARKBLUE));
+        // Partially out of range should be clamped rather than failing
+        w.fill_rect(0xff0000, 90, 40, 20, 20).unwrap();
+        let bitmap = w.sheet.bitmap();
+        assert_eq!(bitmap.pixel_at(99, 49 + TITLE_BAR_HEIGHT), Some(&0xff0000));
+        assert_eq!(bitmap.pixel_at(89, 39 + TITLE_BAR_HEIGHT), Some(&0x123456));
+    }
+}
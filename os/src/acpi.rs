@@ -220,10 +220,14 @@ impl GenericAddress {
 
 #[repr(packed)]
 pub struct Hpet {
+    // IA-PC HPET Specification 1.0a
+    // 3.2.4 The ACPI 2.0 HPET Description Table (HPET)
     _header: SystemDescriptionTableHeader,
-    _reserved0: u32,
+    event_timer_block_id: u32,
     address: GenericAddress,
-    _reserved1: u32,
+    _hpet_number: u8,
+    minimum_tick: u16,
+    _page_protection: u8,
 }
 impl AcpiTable for Hpet {
     const SIGNATURE: &'static [u8; 4] = b"HPET";
@@ -240,6 +244,27 @@ impl Hpet {
                 .map(|addr| &mut *(addr as *mut hpet::Registers))
         }
     }
+    pub fn num_of_comparators(&self) -> usize {
+        // To avoid "error: reference to packed field is unaligned"
+        let id = self.event_timer_block_id;
+        ((id >> 8) & 0b11111) as usize + 1
+    }
+    /// Minimum clock ticks that can be set without losing interrupts in periodic mode
+    pub fn minimum_tick(&self) -> u16 {
+        self.minimum_tick
+    }
+}
+impl fmt::Debug for Hpet {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let address = self.address.address;
+        write!(
+            f,
+            "Hpet {{ base: {:#X}, comparators: {}, minimum_tick: {} }}",
+            address,
+            self.num_of_comparators(),
+            self.minimum_tick()
+        )
+    }
 }
 const _: () = assert!(size_of::<Hpet>() == 56);
 
@@ -294,7 +319,7 @@ impl Fadt {
 
 pub struct Acpi {
     mcfg: &'static Mcfg,
-    hpet: &'static Hpet,
+    hpet: Option<&'static Hpet>,
     dsdt: &'static Dsdt,
 }
 impl<'a> Acpi {
@@ -309,7 +334,7 @@ impl<'a> Acpi {
         xsdt.list_all_tables();
 
         let mcfg = Mcfg::new(xsdt.find_table(b"MCFG").expect("MCFG not found"));
-        let hpet = Hpet::new(xsdt.find_table(b"HPET").expect("HPET not found"));
+        let hpet = xsdt.find_table(b"HPET").map(Hpet::new);
         let fadt = Fadt::new(xsdt.find_table(b"FACP").expect("FACP not found"));
         let dsdt = fadt.dsdt();
         Ok(Acpi { mcfg, hpet, dsdt })
@@ -317,10 +342,35 @@ impl<'a> Acpi {
     pub fn dsdt(&'a self) -> &'a Dsdt {
         self.dsdt
     }
-    pub fn hpet(&'a self) -> &'a Hpet {
+    pub fn hpet(&'a self) -> Option<&'a Hpet> {
         self.hpet
     }
     pub fn mcfg(&'a self) -> &'a Mcfg {
         self.mcfg
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // HPET table taken from QEMU
+    const HPET_TABLE: [u8; 56] = [
+        0x48, 0x50, 0x45, 0x54, 0x38, 0x00, 0x00, 0x00, //
+        0x01, 0x34, 0x42, 0x4F, 0x43, 0x48, 0x53, 0x20, //
+        0x42, 0x58, 0x50, 0x43, 0x20, 0x20, 0x20, 0x20, //
+        0x01, 0x00, 0x00, 0x00, 0x42, 0x58, 0x50, 0x43, //
+        0x01, 0x00, 0x00, 0x00, 0x01, 0xA2, 0x86, 0x80, //
+        0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0xD0, 0xFE, //
+        0x00, 0x00, 0x00, 0x00, 0x00, 0x80, 0x00, 0x00, //
+    ];
+
+    #[test_case]
+    fn hpet_table_is_parsed() {
+        let header = unsafe { &*(HPET_TABLE.as_ptr() as *const SystemDescriptionTableHeader) };
+        let hpet = Hpet::new(header);
+        assert_eq!(hpet.address.address_in_memory_space(), Ok(0xFED0_0000));
+        assert_eq!(hpet.num_of_comparators(), 3);
+        assert_eq!(hpet.minimum_tick(), 128);
+    }
+}
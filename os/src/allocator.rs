@@ -9,6 +9,7 @@ use alloc::alloc::GlobalAlloc;
 use alloc::alloc::Layout;
 use alloc::boxed::Box;
 use core::borrow::BorrowMut;
+use core::cell::Cell;
 use core::cell::RefCell;
 use core::cmp::max;
 use core::fmt;
@@ -38,8 +39,13 @@ const _: () = assert!(HEADER_SIZE == 32);
 const _: () = assert!(HEADER_SIZE.count_ones() == 1);
 pub const LAYOUT_PAGE_4K: Layout = unsafe { Layout::from_size_align_unchecked(4096, 4096) };
 impl Header {
-    fn can_provide(&self, size: usize, align: usize) -> bool {
-        self.size >= size + HEADER_SIZE * 3 + align
+    /// Returns the address to place an object of `size` at the end of this region, aligned to
+    /// `align`. The alignment can move the object down by up to `align - 1` bytes, so this checks
+    /// that the object, its Header and the Header of this region still fit in this region.
+    fn allocated_addr_for(&self, size: usize, align: usize) -> Option<usize> {
+        let allocated_addr = self.end_addr().checked_sub(size)? & !(align - 1);
+        let min_addr = (self as *const Header as usize).checked_add(HEADER_SIZE * 2)?;
+        (allocated_addr >= min_addr).then_some(allocated_addr)
     }
     fn is_allocated(&self) -> bool {
         self.is_allocated
@@ -65,11 +71,12 @@ impl Header {
     // Note: std::alloc::Layout doc says:
     // > All layouts have an associated size and a power-of-two alignment.
     fn provide(&mut self, size: usize, align: usize) -> Option<*mut u8> {
-        let size = max(round_up_to_nearest_pow2(size).ok()?, HEADER_SIZE);
+        // Zero-sized objects get the smallest block as well, so that they can be freed as usual.
+        let size = max(round_up_to_nearest_pow2(max(size, 1)).ok()?, HEADER_SIZE);
         let align = max(align, HEADER_SIZE);
-        if self.is_allocated() || !self.can_provide(size, align) {
+        if self.is_allocated() {
             None
-        } else {
+        } else if let Some(allocated_addr) = self.allocated_addr_for(size, align) {
             // |-----|----------------- self ---------|----------
             // |-----|----------------------          |----------
             //                                        ^ self.end_addr()
@@ -78,11 +85,11 @@ impl Header {
             //                              ^ header_for_allocated
             //                                      ^ header_for_padding
             //                                      ^ header_for_allocated.end_addr()
-            // self has enough space to allocate the requested object.
+            // self has enough space to allocate the requested object, including the padding for
+            // the alignment.
 
             // Make a Header for the allocated object
             let mut size_used = 0;
-            let allocated_addr = (self.end_addr() - size) & !(align - 1);
             let mut header_for_allocated =
                 unsafe { Self::new_from_addr(allocated_addr - HEADER_SIZE) };
             header_for_allocated.is_allocated = true;
@@ -104,6 +111,8 @@ impl Header {
             self.size -= size_used;
             self.next_header = Some(header_for_allocated);
             Some(allocated_addr as *mut u8)
+        } else {
+            None
         }
     }
 }
@@ -126,11 +135,13 @@ impl fmt::Debug for Header {
 
 pub struct FirstFitAllocator {
     first_header: RefCell<Option<Box<Header>>>,
+    total_bytes: Cell<usize>,
 }
 
 #[global_allocator]
 pub static ALLOCATOR: FirstFitAllocator = FirstFitAllocator {
     first_header: RefCell::new(None),
+    total_bytes: Cell::new(0),
 };
 
 unsafe impl Sync for FirstFitAllocator {}
@@ -166,6 +177,23 @@ impl FirstFitAllocator {
             }
         }
     }
+    /// Returns the total size of the free regions in bytes, including their headers.
+    pub fn free_bytes(&self) -> usize {
+        let first_header = self.first_header.borrow();
+        let mut header = first_header.as_ref();
+        let mut free_bytes = 0;
+        while let Some(e) = header {
+            if !e.is_allocated() {
+                free_bytes += e.size;
+            }
+            header = e.next_header.as_ref();
+        }
+        free_bytes
+    }
+    /// Returns the total size of the memory given to the allocator in bytes.
+    pub fn total_bytes(&self) -> usize {
+        self.total_bytes.get()
+    }
     pub fn init_with_mmap(&self, memory_map: &MemoryMapHolder) {
         let mut total_pages = 0;
         for e in memory_map.iter() {
@@ -176,6 +204,7 @@ impl FirstFitAllocator {
             self.add_free_from_descriptor(e);
             total_pages += e.number_of_pages;
         }
+        self.total_bytes.set(total_pages as usize * 4096);
         info!(
             "Allocator initialized. Total memory: {} MiB",
             total_pages * 4096 / 1024 / 1024
@@ -212,6 +241,66 @@ fn malloc_iterate_free_and_alloc() {
     }
 }
 
+#[test_case]
+fn free_bytes_is_restored_after_dealloc() {
+    let layout = Layout::from_size_align(8192, 4096).expect("Failed to create Layout");
+    let before = ALLOCATOR.free_bytes();
+    let p = ALLOCATOR.alloc_with_options(layout);
+    assert!(!p.is_null());
+    assert!(ALLOCATOR.free_bytes() < before);
+    unsafe { ALLOCATOR.dealloc(p, layout) }
+    assert_eq!(ALLOCATOR.free_bytes(), before);
+}
+
+#[test_case]
+fn zero_size_alloc_returns_aligned_block() {
+    for align in [1, 8, 4096] {
+        let layout = Layout::from_size_align(0, align).expect("Failed to create Layout");
+        let before = ALLOCATOR.free_bytes();
+        let p = ALLOCATOR.alloc_with_options(layout);
+        assert!(!p.is_null());
+        assert_eq!(p as usize % align, 0);
+        let q = ALLOCATOR.alloc_with_options(layout);
+        assert!(!q.is_null());
+        assert_ne!(p, q);
+        unsafe {
+            ALLOCATOR.dealloc(q, layout);
+            ALLOCATOR.dealloc(p, layout);
+        }
+        assert_eq!(ALLOCATOR.free_bytes(), before);
+    }
+}
+
+#[test_case]
+fn large_align_does_not_underflow_the_region() {
+    const SIZE: usize = 4096;
+    const ALIGN: usize = 4096;
+    let layout = Layout::from_size_align(SIZE * 3, ALIGN).expect("Failed to create Layout");
+    let buf = ALLOCATOR.alloc_with_options(layout);
+    assert!(!buf.is_null());
+    // Place a free region slightly larger than SIZE at various offsets from the alignment
+    for offset in (0..ALIGN).step_by(HEADER_SIZE * 8) {
+        for region_size in [
+            SIZE + HEADER_SIZE * 2,
+            SIZE + HEADER_SIZE * 4,
+            SIZE + ALIGN / 2,
+        ] {
+            let start = buf as usize + offset;
+            let mut header = unsafe { Header::new_from_addr(start) };
+            header.size = region_size;
+            if let Some(p) = header.provide(SIZE, ALIGN) {
+                let p = p as usize;
+                assert_eq!(p % ALIGN, 0);
+                assert!(p >= start + HEADER_SIZE * 2);
+                assert!(p + SIZE <= start + region_size);
+            }
+            // The Headers are in buf, which is freed below
+            Box::leak(header);
+        }
+    }
+    unsafe { ALLOCATOR.dealloc(buf, layout) }
+}
+
 #[test_case]
 fn malloc_align() {
     let mut pointers = [null_mut::<u8>(); 100];
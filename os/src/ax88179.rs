@@ -285,7 +285,7 @@ async fn init(ddc: &mut UsbDeviceDriverContext) -> Result<()> {
 pub async fn attach_usb_device(mut ddc: UsbDeviceDriverContext) -> Result<()> {
     init(&mut ddc).await?;
     for ep_desc in ddc.ep_desc_list() {
-        let dci = ep_desc.dci();
+        let dci = ep_desc.dci()?;
         let tring = ddc.ep_ring(dci)?.as_ref().expect("tring not found");
         match EndpointType::from(ep_desc) {
             EndpointType::InterruptIn => {
@@ -1,11 +1,9 @@
+use crate::error::Error;
+use crate::error::Result;
+
 /// A simple set of bits
 /// const type parameter N represents how many bytes it uses.
 /// 0..(N*8) will be the range of values.
-#[derive(Eq, PartialEq, Debug, Copy, Clone)]
-pub enum Error {
-    OutOfRange,
-}
-type Result<T> = core::result::Result<T, Error>;
 #[derive(Debug, Copy, Clone)]
 pub struct BitSet<const N: usize> {
     bytes: [u8; N],
@@ -19,14 +17,14 @@ impl<const N: usize> BitSet<N> {
             self.bytes[index / 8] |= 1u8 << (index % 8);
             Ok(())
         } else {
-            Err(Error::OutOfRange)
+            Err(Error::BitSetIndexOutOfRange)
         }
     }
     pub fn get(&self, index: usize) -> Result<bool> {
         if index < N * 8 {
             Ok((self.bytes[index / 8] & (1u8 << (index % 8))) != 0)
         } else {
-            Err(Error::OutOfRange)
+            Err(Error::BitSetIndexOutOfRange)
         }
     }
     pub fn remove(&mut self, index: usize) -> Result<()> {
@@ -34,7 +32,7 @@ impl<const N: usize> BitSet<N> {
             self.bytes[index / 8] &= !(1u8 << (index % 8));
             Ok(())
         } else {
-            Err(Error::OutOfRange)
+            Err(Error::BitSetIndexOutOfRange)
         }
     }
     pub fn clear(&mut self) {
@@ -80,7 +78,7 @@ mod test {
         for i in 0..24 {
             assert_eq!(b.get(i), Ok(false));
         }
-        assert!(b.get(24).is_err());
+        assert_eq!(b.get(24), Err(Error::BitSetIndexOutOfRange));
     }
     #[test_case]
     fn insert_get() {
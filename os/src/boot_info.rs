@@ -3,8 +3,11 @@ use crate::efi::fs::EfiFileName;
 use crate::error::Error;
 use crate::info;
 use crate::memory_map_holder::MemoryMapHolder;
+use crate::vram::FramebufferInfo;
 use crate::vram::VRAMBufferInfo;
 use crate::x86_64::apic::LocalApic;
+use crate::x86_64::cpuid::Feature;
+use crate::x86_64::cpuid::Leaf01Features;
 use crate::x86_64::read_cpuid;
 use crate::x86_64::CpuidRequest;
 use crate::x86_64::CpuidResponse;
@@ -120,6 +123,8 @@ impl CpuFeatures {
         info!("cpuid(0, 0).vendor = {:?}", &vendor_string);
 
         let leaf01 = query(CpuidRequest { eax: 1, ecx: 0 });
+        let leaf01_features = Leaf01Features::new(leaf01.ecx(), leaf01.edx());
+        info!("CPU features: {}", leaf01_features.summary());
 
         let max_extended_cpuid = query(CpuidRequest {
             eax: 0x8000_0000,
@@ -174,7 +179,7 @@ impl CpuFeatures {
         CpuFeatures {
             max_basic_cpuid,
             max_extended_cpuid,
-            has_x2apic: ((leaf01.ecx() >> 21) & 1) != 0,
+            has_x2apic: leaf01_features.has(Feature::X2Apic),
             has_tsc_deadline_mode: ((leaf01.ecx() >> 24) & 1) != 0,
             has_invariant_tsc,
             vendor_string,
@@ -216,6 +221,9 @@ impl BootInfo {
     pub fn vram(&self) -> VRAMBufferInfo {
         self.vram
     }
+    pub fn framebuffer(&self) -> FramebufferInfo {
+        self.vram.framebuffer()
+    }
     pub fn memory_map(&'static self) -> &'static MemoryMapHolder {
         &self.memory_map
     }
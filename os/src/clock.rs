@@ -0,0 +1,83 @@
+use crate::hpet::Hpet;
+use crate::tsc::Tsc;
+use crate::x86_64::apic::ApicTimer;
+
+/// A free-running counter that can be used to measure time.
+pub trait ClockSource {
+    /// Returns the current value of the counter
+    fn counter(&self) -> u64;
+    /// Returns the frequency of the counter in Hz
+    fn freq(&self) -> u64;
+    fn elapsed_ms_since(&self, start_counter: u64) -> u64 {
+        elapsed_ms_between(start_counter, self.counter(), self.freq())
+    }
+    fn now_ns(&self) -> u64 {
+        counter_to_ns(self.counter(), self.freq())
+    }
+}
+
+/// Returns the most precise clock available, or None if there is no clock.
+/// Invariant TSC is preferred over HPET since it is much cheaper to read, and the Local APIC
+/// timer is the last resort.
+pub fn best_clock() -> Option<&'static dyn ClockSource> {
+    if let Some(tsc) = Tsc::try_take() {
+        Some(tsc)
+    } else if let Some(hpet) = Hpet::try_take() {
+        Some(hpet)
+    } else if let Some(apic_timer) = ApicTimer::try_take() {
+        Some(apic_timer)
+    } else {
+        None
+    }
+}
+
+/// Converts a counter value of a timer running at `freq` Hz into milliseconds.
+pub fn counter_to_ms(counter: u64, freq: u64) -> u64 {
+    // counter * 1000 can overflow, so convert the whole seconds and the remainder separately.
+    // The remainder is less than freq, so it will not overflow as long as the freq is below
+    // u64::MAX / 1000 (HPET runs at ~10 MHz in practice).
+    counter / freq * 1000 + counter % freq * 1000 / freq
+}
+
+/// Converts a counter value of a timer running at `freq` Hz into nanoseconds.
+pub fn counter_to_ns(counter: u64, freq: u64) -> u64 {
+    (counter as u128 * 1_000_000_000 / freq as u128) as u64
+}
+
+/// Returns the milliseconds between two counter values, taking a wraparound into account.
+pub fn elapsed_ms_between(start_counter: u64, now_counter: u64, freq: u64) -> u64 {
+    counter_to_ms(now_counter.wrapping_sub(start_counter), freq)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test_case]
+    fn counter_to_ms_converts_with_given_freq() {
+        let freq = 10_000_000; // 10 MHz
+        assert_eq!(counter_to_ms(0, freq), 0);
+        assert_eq!(counter_to_ms(9_999, freq), 0);
+        assert_eq!(counter_to_ms(10_000, freq), 1);
+        assert_eq!(counter_to_ms(12_345_678, freq), 1234);
+    }
+    #[test_case]
+    fn counter_to_ms_does_not_overflow() {
+        let freq = 14_318_180; // Typical HPET frequency
+        assert_eq!(counter_to_ms(u64::MAX, freq), 1_288_344_194_144_056);
+    }
+    #[test_case]
+    fn counter_to_ns_converts_with_given_freq() {
+        let freq = 3_000_000_000; // 3 GHz
+        assert_eq!(counter_to_ns(3, freq), 1);
+        assert_eq!(counter_to_ns(3_000_000_000, freq), 1_000_000_000);
+        assert_eq!(counter_to_ns(4_500_000_000_000, freq), 1_500_000_000_000);
+    }
+    #[test_case]
+    fn elapsed_ms_between_handles_wraparound() {
+        let freq = 10_000_000;
+        assert_eq!(elapsed_ms_between(10_000, 30_000, freq), 2);
+        assert_eq!(elapsed_ms_between(u64::MAX - 9_999, 10_000, freq), 2);
+        assert_eq!(elapsed_ms_between(u64::MAX, u64::MAX, freq), 0);
+    }
+}
@@ -1,12 +1,17 @@
 extern crate alloc;
 
 use crate::boot_info::BootInfo;
+use crate::console::clear_console;
+use crate::console::CursorHideGuard;
 #[cfg(test)]
 use crate::debug;
 use crate::efi::fs::EfiFileName;
 use crate::error;
+use crate::error::Context;
 use crate::error::Error;
 use crate::error::Result;
+use crate::executor::global_tasks;
+use crate::executor::spawn_global;
 use crate::executor::yield_execution;
 use crate::info;
 use crate::loader::Elf;
@@ -14,25 +19,73 @@ use crate::mutex::Mutex;
 use crate::net::dns::query_dns;
 use crate::net::dns::DnsResponseEntry;
 use crate::net::icmp::IcmpPacket;
+use crate::net::loopback::mock_client_eth_addr;
+use crate::net::loopback::run_mock_server;
+use crate::net::loopback::MockServerInterface;
 use crate::net::manager::Network;
+use crate::path::resolve_path;
+use crate::print::hexdump;
 use crate::println;
+use crate::ramfs::Ramfs;
+use crate::screenshot::write_screenshot;
+use crate::serial::SerialPort;
 use crate::x86_64::trigger_debug_interrupt;
+use crate::x86_64::trigger_software_interrupt;
+use crate::x86_64::SOFTWARE_INTERRUPT_VECTORS;
+use crate::xhci::device::usb_device_list;
 use alloc::format;
+use alloc::rc::Rc;
+use alloc::string::String;
 use alloc::vec::Vec;
 use core::str::FromStr;
 use noli::mem::Sliceable;
 use noli::net::IpV4Addr;
 
+// Current working directory of the shell. Empty means the root.
+static CWD: Mutex<String> = Mutex::new(String::new());
+
+fn cwd() -> String {
+    let cwd = CWD.lock();
+    if cwd.is_empty() {
+        "/".into()
+    } else {
+        cwd.clone()
+    }
+}
+
+/// Directories are not stored anywhere, so a path is treated as a directory if it is the root
+/// or some files in the Ramfs are under it.
+fn is_dir(path: &str) -> bool {
+    let prefix = format!("{path}/");
+    path == "/"
+        || Ramfs::take()
+            .list()
+            .iter()
+            .any(|name| resolve_path("/", name).starts_with(&prefix))
+}
+
 async fn run_app(name: &str, args: &[&str]) -> Result<i64> {
     let boot_info = BootInfo::take();
     let root_files = boot_info.root_files();
     let root_files: alloc::vec::Vec<&crate::boot_info::File> =
         root_files.iter().filter_map(|e| e.as_ref()).collect();
+    // Names without a slash are looked up in the root like $PATH, and the others are resolved
+    // against the cwd. Apps are only in the root for now.
+    let path = if name.contains('/') {
+        resolve_path(&cwd(), name)
+    } else {
+        format!("/{name}")
+    };
+    let name = path
+        .strip_prefix('/')
+        .filter(|name| !name.is_empty() && !name.contains('/'))
+        .ok_or(Error::Failed("command::run_app: No such file or app"))?;
     let name = EfiFileName::from_str(name)?;
     let elf = root_files.iter().find(|&e| e.name() == &name);
     if let Some(elf) = elf {
         let elf = Elf::parse(elf)?;
         let app = elf.load()?;
+        let _cursor_hidden = CursorHideGuard::new();
         let result = app.exec(args).await?;
         #[cfg(test)]
         if result == 0 {
@@ -47,16 +100,168 @@ async fn run_app(name: &str, args: &[&str]) -> Result<i64> {
     }
 }
 
+/// (name, description, usage) of the built-in commands.
+/// Commands not listed here are treated as app names.
+pub const BUILTIN_COMMANDS: &[(&str, &str, &str)] = &[
+    ("help", "Show this help", "help"),
+    ("panic", "Trigger a breakpoint and resume", "panic"),
+    ("bp", "Trigger a breakpoint and resume", "bp"),
+    (
+        "int",
+        "Trigger a software interrupt (3: breakpoint, 6: invalid opcode)",
+        "int <vector>",
+    ),
+    (
+        "deadlock",
+        "Lock a mutex twice to test deadlock detection",
+        "deadlock",
+    ),
+    (
+        "wait_until_network_is_up",
+        "Wait until the default router is configured",
+        "wait_until_network_is_up",
+    ),
+    ("ip", "Show the network configuration", "ip"),
+    (
+        "loopback",
+        "Add a network interface answered by a mock DHCP/ARP/ICMP server",
+        "loopback",
+    ),
+    (
+        "ping",
+        "Send an ICMP echo request",
+        "ping <target_ipv4_addr>",
+    ),
+    (
+        "wait_until_dns_ready",
+        "Wait until the DNS server is configured",
+        "wait_until_dns_ready",
+    ),
+    (
+        "cmdhttpget",
+        "Send an HTTP GET request and print the response",
+        "cmdhttpget [host] [port]",
+    ),
+    ("arp", "Show the ARP table", "arp"),
+    ("nslookup", "Resolve a hostname via DNS", "nslookup <query>"),
+    ("usb", "List enumerated USB devices", "usb"),
+    (
+        "pcap",
+        "Show captured frames, or turn capturing on/off",
+        "pcap [on|off|clear]",
+    ),
+    ("ps", "List the tasks in the executor", "ps"),
+    ("cd", "Change the current directory", "cd [dir]"),
+    ("pwd", "Show the current directory", "pwd"),
+    ("clear", "Clear the screen", "clear"),
+    (
+        "screenshot",
+        "Dump the screen over serial as a base64-encoded PPM",
+        "screenshot",
+    ),
+];
+
+fn usage(name: &str) -> &'static str {
+    BUILTIN_COMMANDS
+        .iter()
+        .find(|(n, _, _)| *n == name)
+        .map(|(_, _, usage)| *usage)
+        .unwrap_or_default()
+}
+
+fn usage_error(name: &str) -> Error {
+    Error::FailedString(format!("usage: {}", usage(name)))
+}
+
+fn print_help() {
+    for (name, description, usage) in BUILTIN_COMMANDS {
+        println!("{name:24} {description}");
+        println!("{:24}   usage: {usage}", "");
+    }
+    println!("Other commands are executed as apps.");
+}
+
+/// Splits a command line into arguments.
+/// Arguments are separated by spaces, and spaces within double quotes are kept.
+/// A backslash escapes the following character.
+pub fn tokenize(cmdline: &str) -> Result<Vec<String>> {
+    let mut args = Vec::new();
+    let mut arg: Option<String> = None;
+    let mut in_quotes = false;
+    let mut chars = cmdline.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' => {
+                let c = chars
+                    .next()
+                    .ok_or(Error::Failed("tokenize: trailing backslash"))?;
+                arg.get_or_insert_with(String::new).push(c);
+            }
+            '"' => {
+                in_quotes = !in_quotes;
+                // Make sure that "" produces an empty argument
+                arg.get_or_insert_with(String::new);
+            }
+            c if c.is_ascii_whitespace() && !in_quotes => {
+                if let Some(arg) = arg.take() {
+                    args.push(arg);
+                }
+            }
+            c => {
+                arg.get_or_insert_with(String::new).push(c);
+            }
+        }
+    }
+    if in_quotes {
+        return Err(Error::Failed("tokenize: unterminated double quote"));
+    }
+    if let Some(arg) = arg {
+        args.push(arg);
+    }
+    Ok(args)
+}
+
+fn parse_interrupt_vector(arg: Option<&str>) -> Result<u8> {
+    let arg = arg.ok_or_else(|| usage_error("int"))?;
+    let vector = u8::from_str(arg)
+        .map_err(|_| Error::FailedString(format!("int: invalid vector: {arg}")))?;
+    if SOFTWARE_INTERRUPT_VECTORS.contains(&vector) {
+        Ok(vector)
+    } else {
+        Err(Error::FailedString(format!(
+            "int: unsupported vector: {vector} (supported: {SOFTWARE_INTERRUPT_VECTORS:?})"
+        )))
+    }
+}
+
+fn exit_message(app_name: &str, code: i64) -> String {
+    format!("{app_name} exited with code {code}")
+}
+
 pub async fn run(cmdline: &str) -> Result<()> {
     let network = Network::take();
-    let args = cmdline.trim();
-    let args: Vec<&str> = args.split(' ').collect();
+    let args = tokenize(cmdline)?;
+    let args: Vec<&str> = args.iter().map(|s| s.as_str()).collect();
     info!("Executing cmd: {args:?}");
     if let Some(&cmd) = args.first() {
+        if !BUILTIN_COMMANDS.iter().any(|(name, _, _)| *name == cmd) {
+            let code = run_app(cmd, &args).await?;
+            if code == 0 {
+                println!("{}", exit_message(cmd, code));
+            } else {
+                error!("{}", exit_message(cmd, code));
+            }
+            return Ok(());
+        }
         match cmd {
-            "panic" => {
+            "help" => print_help(),
+            "panic" | "bp" => {
                 trigger_debug_interrupt();
             }
+            "int" => {
+                let vector = parse_interrupt_vector(args.get(1).copied())?;
+                trigger_software_interrupt(vector)?;
+            }
             "deadlock" => {
                 let mutex: Mutex<()> = Mutex::new(());
                 let a = mutex.lock();
@@ -73,17 +278,17 @@ pub async fn run(cmdline: &str) -> Result<()> {
                 println!("router: {:?}", network.router());
                 println!("dns: {:?}", network.dns());
             }
+            "loopback" => {
+                let iface = Rc::new(MockServerInterface::new(mock_client_eth_addr()));
+                network.register_interface(Rc::downgrade(&iface) as _);
+                spawn_global(run_mock_server(iface));
+            }
             "ping" => {
-                if let Some(ip) = args.get(1) {
-                    let ip = IpV4Addr::from_str(ip);
-                    if let Ok(ip) = ip {
-                        network.send_ip_packet(IcmpPacket::new_request(ip).copy_into_slice());
-                    } else {
-                        println!("{ip:?}")
-                    }
-                } else {
-                    println!("usage: ip <target_ipv4_addr>")
-                }
+                let ip = args.get(1).ok_or_else(|| usage_error(cmd))?;
+                let ip = IpV4Addr::from_str(ip)?;
+                network
+                    .send_ip_packet_and_wait(IcmpPacket::new_request(ip).copy_into_slice())
+                    .await?;
             }
             "wait_until_dns_ready" => loop {
                 if let Some(dns_ip) = network.dns() {
@@ -112,11 +317,11 @@ pub async fn run(cmdline: &str) -> Result<()> {
                 let ip = if let Ok(ip) = IpV4Addr::from_str(host) {
                     ip
                 } else if let Some(DnsResponseEntry::A { addr, name: _ }) =
-                    query_dns(host).await?.first()
+                    query_dns(host).await.context(host)?.first()
                 {
                     *addr
                 } else {
-                    return Ok(());
+                    return Err(Error::DnsNotFound).context(host);
                 };
                 let sock = network.open_tcp_socket(ip, port)?;
                 sock.wait_until_connection_is_established().await;
@@ -137,22 +342,175 @@ pub async fn run(cmdline: &str) -> Result<()> {
                 println!("{:?}", network.arp_table_cloned())
             }
             "nslookup" => {
-                if let Some(query) = args.get(1) {
-                    let res = query_dns(query).await?;
-                    println!("{res:?}");
-                } else {
-                    println!("usage: nslookup <query>")
+                let query = args.get(1).ok_or_else(|| usage_error(cmd))?;
+                let res = query_dns(query).await?;
+                println!("{res:?}");
+            }
+            "usb" => {
+                for info in usb_device_list() {
+                    println!("{}", info.summary());
                 }
             }
-            app_name => {
-                let result = run_app(app_name, &args).await;
-                if result.is_ok() {
-                    info!("{result:?}");
-                } else {
-                    error!("{result:?}");
+            "pcap" => match args.get(1).copied() {
+                None => {
+                    if !network.is_capture_enabled() {
+                        println!("pcap: capturing is off. Run `pcap on` to start.");
+                    }
+                    for (i, frame) in network.captured_frames().iter().enumerate() {
+                        println!(
+                            "#{i} {:?} {}ms {} bytes",
+                            frame.direction,
+                            frame.timestamp_ms,
+                            frame.data.len()
+                        );
+                        hexdump(&frame.data);
+                    }
+                }
+                Some("on") => network.set_capture_enabled(true),
+                Some("off") => network.set_capture_enabled(false),
+                Some("clear") => network.clear_captured_frames(),
+                Some(_) => return Err(usage_error(cmd)),
+            },
+            "ps" => {
+                println!("{:32} {:8} {:>10}", "NAME", "STATE", "POLLS");
+                for task in global_tasks() {
+                    println!("{:32} {:8} {:>10}", task.name, task.state, task.poll_count);
+                }
+            }
+            "cd" => {
+                let path = resolve_path(&cwd(), args.get(1).copied().unwrap_or("/"));
+                if !is_dir(&path) {
+                    return Err(Error::FailedString(format!(
+                        "cd: no such directory: {path}"
+                    )));
                 }
+                *CWD.lock() = path;
+            }
+            "pwd" => println!("{}", cwd()),
+            "clear" => clear_console(),
+            "screenshot" => {
+                let vram = BootInfo::take().vram();
+                write_screenshot(&vram, &mut SerialPort::default())
+                    .or(Err(Error::Failed("screenshot: failed to write")))?;
+            }
+            _ => {
+                return Err(Error::FailedString(format!(
+                    "command::run: built-in command {cmd} is not implemented"
+                )))
             }
         }
     }
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::executor::block_on;
+    #[test_case]
+    fn builtin_commands_are_well_formed() {
+        assert!(!BUILTIN_COMMANDS.is_empty());
+        for (i, (name, description, usage)) in BUILTIN_COMMANDS.iter().enumerate() {
+            assert!(!name.is_empty() && !name.contains(' '));
+            assert!(!description.is_empty());
+            assert!(usage.starts_with(name));
+            assert!(BUILTIN_COMMANDS[i + 1..].iter().all(|(n, _, _)| n != name));
+        }
+        assert_eq!(usage("ping"), "ping <target_ipv4_addr>");
+        assert_eq!(usage("no_such_command"), "");
+    }
+    #[test_case]
+    fn parse_interrupt_vector_accepts_supported_vectors() {
+        assert_eq!(parse_interrupt_vector(Some("3")), Ok(3));
+        assert_eq!(parse_interrupt_vector(Some("6")), Ok(6));
+    }
+    #[test_case]
+    fn parse_interrupt_vector_rejects_invalid_args() {
+        assert_eq!(
+            parse_interrupt_vector(None),
+            Err(Error::FailedString("usage: int <vector>".into()))
+        );
+        assert!(parse_interrupt_vector(Some("three")).is_err());
+        assert!(parse_interrupt_vector(Some("-1")).is_err());
+        assert!(parse_interrupt_vector(Some("256")).is_err());
+        assert!(parse_interrupt_vector(Some("7")).is_err());
+        // Handlers that expect an error code or an EOI are not reachable with `int n`
+        assert!(parse_interrupt_vector(Some("13")).is_err());
+        assert!(parse_interrupt_vector(Some("14")).is_err());
+        assert!(parse_interrupt_vector(Some("32")).is_err());
+    }
+    #[test_case]
+    fn exit_message_shows_code() {
+        assert_eq!(exit_message("hello0", 0), "hello0 exited with code 0");
+        assert_eq!(exit_message("hello0", 42), "hello0 exited with code 42");
+        assert_eq!(exit_message("hello0", -1), "hello0 exited with code -1");
+    }
+    #[test_case]
+    fn run_returns_usage_error() {
+        assert_eq!(
+            block_on(run("ping")),
+            Err(Error::FailedString("usage: ping <target_ipv4_addr>".into()))
+        );
+        assert_eq!(
+            block_on(run("nslookup")),
+            Err(Error::FailedString("usage: nslookup <query>".into()))
+        );
+        assert_eq!(
+            block_on(run("pcap start")),
+            Err(Error::FailedString("usage: pcap [on|off|clear]".into()))
+        );
+        assert!(block_on(run("ping 10.0.2")).is_err());
+        assert_eq!(block_on(run("")), Ok(()));
+    }
+    #[test_case]
+    fn cd_changes_cwd_to_existing_dir() {
+        Ramfs::take().create("cmd_test/dir/file").unwrap();
+        assert_eq!(cwd(), "/");
+        assert_eq!(block_on(run("cd cmd_test/./dir")), Ok(()));
+        assert_eq!(cwd(), "/cmd_test/dir");
+        assert_eq!(block_on(run("cd ..//")), Ok(()));
+        assert_eq!(cwd(), "/cmd_test");
+        assert_eq!(
+            block_on(run("cd dir/file")),
+            Err(Error::FailedString(
+                "cd: no such directory: /cmd_test/dir/file".into()
+            ))
+        );
+        assert_eq!(cwd(), "/cmd_test");
+        assert_eq!(block_on(run("cd")), Ok(()));
+        assert_eq!(cwd(), "/");
+    }
+    #[test_case]
+    fn tokenize_quoted_args() {
+        assert_eq!(
+            tokenize("cat \"my file.txt\""),
+            Ok(alloc::vec!["cat".into(), "my file.txt".into()])
+        );
+        assert_eq!(
+            tokenize("echo a\"b c\"d \"\""),
+            Ok(alloc::vec!["echo".into(), "ab cd".into(), "".into()])
+        );
+    }
+    #[test_case]
+    fn tokenize_escaped_chars() {
+        assert_eq!(
+            tokenize(r#"echo "say \"hi\"" a\ b"#),
+            Ok(alloc::vec![
+                "echo".into(),
+                "say \"hi\"".into(),
+                "a b".into()
+            ])
+        );
+        assert!(tokenize("echo \\").is_err());
+        assert!(tokenize("echo \"abc").is_err());
+    }
+    #[test_case]
+    fn tokenize_collapses_spaces() {
+        assert_eq!(
+            tokenize("  ping   10.0.2.2  "),
+            Ok(alloc::vec!["ping".into(), "10.0.2.2".into()])
+        );
+        assert_eq!(tokenize("   "), Ok(Vec::new()));
+        assert_eq!(tokenize(""), Ok(Vec::new()));
+    }
+}
@@ -0,0 +1,433 @@
+extern crate alloc;
+
+use crate::boot_info::BootInfo;
+use crate::clock::best_clock;
+use crate::cmd::BUILTIN_COMMANDS;
+use crate::error::Result;
+use crate::executor::TimeoutFuture;
+use crate::info;
+use crate::print;
+use crate::print::GLOBAL_PRINTER;
+use crate::println;
+use crate::ramfs::Ramfs;
+use crate::serial::SerialPort;
+use alloc::format;
+use alloc::string::String;
+use alloc::string::ToString;
+use alloc::vec::Vec;
+use core::cmp::min;
+use core::fmt::Write;
+use core::sync::atomic::AtomicUsize;
+use core::sync::atomic::Ordering;
+use noli::bitmap::Bitmap;
+use noli::rect::Rect;
+use sabi::KeyboardEvent;
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum CompletionResult {
+    NoMatch,
+    /// Only this candidate starts with the prefix.
+    Unique(String),
+    /// The longest prefix shared by all the candidates that matched, and the candidates.
+    CommonPrefix {
+        prefix: String,
+        candidates: Vec<String>,
+    },
+}
+
+/// Completes `prefix` with the `candidates` that start with it.
+pub fn complete(prefix: &str, candidates: &[&str]) -> CompletionResult {
+    let mut matched: Vec<&str> = candidates
+        .iter()
+        .copied()
+        .filter(|c| c.starts_with(prefix))
+        .collect();
+    matched.sort();
+    matched.dedup();
+    match matched.as_slice() {
+        [] => CompletionResult::NoMatch,
+        [c] => CompletionResult::Unique(c.to_string()),
+        [first, rest @ ..] => {
+            let common = rest.iter().fold(first.len(), |len, c| {
+                first[..len]
+                    .char_indices()
+                    .zip(c.chars())
+                    .find(|((_, a), b)| a != b)
+                    .map(|((i, _), _)| i)
+                    .unwrap_or(min(len, c.len()))
+            });
+            CompletionResult::CommonPrefix {
+                prefix: first[..common].to_string(),
+                candidates: matched.iter().map(|c| c.to_string()).collect(),
+            }
+        }
+    }
+}
+
+pub const CURSOR_BLINK_INTERVAL_MS: u64 = 500;
+
+// Number of apps running. The cursor is not drawn while apps may be drawing on the screen.
+static NUM_RUNNING_APPS: AtomicUsize = AtomicUsize::new(0);
+
+/// Returns true if the cursor should be shown at `elapsed_ms` since the blinking started.
+/// The cursor is shown first, and toggled every `interval_ms`.
+pub fn is_cursor_blink_on(elapsed_ms: u64, interval_ms: u64) -> bool {
+    (elapsed_ms / interval_ms) & 1 == 0
+}
+
+/// Returns the milliseconds from `elapsed_ms` until the cursor is toggled next time.
+pub fn ms_until_next_blink(elapsed_ms: u64, interval_ms: u64) -> u64 {
+    interval_ms - elapsed_ms % interval_ms
+}
+
+/// Hides the console cursor until this is dropped. Hold this while running an app.
+pub struct CursorHideGuard {
+    _private: (),
+}
+impl CursorHideGuard {
+    pub fn new() -> Self {
+        NUM_RUNNING_APPS.fetch_add(1, Ordering::SeqCst);
+        GLOBAL_PRINTER.set_cursor_visible(false);
+        Self { _private: () }
+    }
+}
+impl Default for CursorHideGuard {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+impl Drop for CursorHideGuard {
+    fn drop(&mut self) {
+        NUM_RUNNING_APPS.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+/// Blinks the cursor of the console. A print that moved the shown cursor restarts the blinking
+/// from the "on" phase. Returns immediately if there is no clock to time the blinks.
+pub async fn blink_cursor() -> Result<()> {
+    let Some(clock) = best_clock() else {
+        info!("No clock available. The console cursor will not blink.");
+        return Ok(());
+    };
+    let mut start_counter = clock.counter();
+    loop {
+        if GLOBAL_PRINTER.take_cursor_redrawn() {
+            start_counter = clock.counter();
+        }
+        let elapsed_ms = clock.elapsed_ms_since(start_counter);
+        let visible = is_cursor_blink_on(elapsed_ms, CURSOR_BLINK_INTERVAL_MS)
+            && NUM_RUNNING_APPS.load(Ordering::SeqCst) == 0;
+        GLOBAL_PRINTER.set_cursor_visible(visible);
+        TimeoutFuture::new_ms(ms_until_next_blink(elapsed_ms, CURSOR_BLINK_INTERVAL_MS)).await;
+    }
+}
+
+/// Clears the whole screen and the console, and tells the terminal on the serial port to clear
+/// the screen as well.
+pub fn clear_console() {
+    let mut vram = BootInfo::take().vram();
+    vram.clear();
+    if let Some(screen) = Rect::new(0, 0, vram.width(), vram.height()) {
+        vram.mark_dirty(screen);
+    }
+    vram.flush();
+    GLOBAL_PRINTER.clear();
+    // Not print!() since the text area would show the escape sequence as is
+    let _ = SerialPort::default().write_str("\x1b[2J\x1b[H");
+}
+
+/// Returns true if `seq`, the chars after ESC, is a complete escape sequence. CSI sequences
+/// ("[" followed by parameters) end with a char in '@'..='~', and the others are 1 char long.
+fn is_escape_sequence_complete(seq: &str) -> bool {
+    match seq.strip_prefix('[') {
+        Some(csi) => csi.ends_with(|c| ('@'..='~').contains(&c)),
+        None => !seq.is_empty(),
+    }
+}
+
+/// Returns the names to complete the first token (command) or the others (file) with.
+fn completion_candidates(is_first_token: bool) -> Vec<String> {
+    let mut candidates: Vec<String> = BootInfo::take()
+        .root_files()
+        .iter()
+        .flatten()
+        .map(|f| format!("{}", f.name()))
+        .collect();
+    if is_first_token {
+        candidates.extend(BUILTIN_COMMANDS.iter().map(|(name, _, _)| name.to_string()));
+    } else {
+        candidates.extend(Ramfs::take().list());
+    }
+    candidates
+}
+
+/// Line editor for the shell prompt.
+#[derive(Default)]
+pub struct LineEditor {
+    line: String,
+    // True if the previous key was Tab, to list the candidates on a double Tab
+    last_key_was_tab: bool,
+    // Chars after ESC while an escape sequence is being received
+    escape: Option<String>,
+}
+impl LineEditor {
+    pub fn new() -> Self {
+        Self::default()
+    }
+    pub fn line(&self) -> &str {
+        &self.line
+    }
+    /// Handles a key input and echoes it back to the console.
+    /// Returns Some(line) once the line is completed with Enter.
+    pub fn handle_key(&mut self, e: KeyboardEvent) -> Option<String> {
+        if let Some(seq) = &mut self.escape {
+            if e.key.is_control() || e.modifiers.ctrl() || (seq.is_empty() && e.key != '[') {
+                // Not a part of an escape sequence (e.g. Enter, Ctrl-C, or a char after a lone
+                // ESC), so drop the incomplete sequence and handle the key as usual.
+                self.escape = None;
+            } else {
+                seq.push(e.key);
+                if is_escape_sequence_complete(seq) {
+                    let seq = self.escape.take().unwrap_or_default();
+                    self.handle_escape_sequence(&seq);
+                }
+                return None;
+            }
+        }
+        if e.key == '\x1b' {
+            self.escape = Some(String::new());
+            return None;
+        }
+        if e.key == '\t' {
+            self.handle_tab(completion_candidates);
+            return None;
+        }
+        self.last_key_was_tab = false;
+        match e.key {
+            '\r' | '\n' => {
+                println!();
+                return Some(core::mem::take(&mut self.line));
+            }
+            '\x03' => self.cancel(),
+            'c' | 'C' if e.modifiers.ctrl() => self.cancel(),
+            '\x7f' | '\x08' => {
+                if self.line.pop().is_some() {
+                    print!("{0} {0}", 0x08 as char);
+                }
+            }
+            c => {
+                print!("{c}");
+                self.line.push(c);
+            }
+        }
+        None
+    }
+    /// Clears the console on "ESC [ 2 J" (erase display) or "ESC [ H" (cursor home), which a
+    /// terminal on the host sends on clearing. The others (e.g. arrow keys) are ignored.
+    fn handle_escape_sequence(&mut self, seq: &str) {
+        if seq == "[2J" || seq == "[H" {
+            clear_console();
+            print!("> {}", self.line);
+        }
+    }
+    /// Completes the last token of the line with the candidates given by
+    /// `candidates(is_first_token)`, or lists them if it is pressed twice and nothing was
+    /// completed.
+    fn handle_tab(&mut self, candidates: impl FnOnce(bool) -> Vec<String>) {
+        let token_start = self.line.rfind(' ').map(|i| i + 1).unwrap_or(0);
+        let token = &self.line[token_start..];
+        let candidates = candidates(token_start == 0);
+        let candidates: Vec<&str> = candidates.iter().map(|c| c.as_str()).collect();
+        let completion = match complete(token, &candidates) {
+            CompletionResult::NoMatch => String::new(),
+            CompletionResult::Unique(c) => format!("{} ", &c[token.len()..]),
+            CompletionResult::CommonPrefix { prefix, candidates } => {
+                if prefix.len() == token.len() && self.last_key_was_tab {
+                    println!();
+                    println!("{}", candidates.join("  "));
+                    print!("> {}", self.line);
+                }
+                prefix[token.len()..].to_string()
+            }
+        };
+        print!("{completion}");
+        self.line.push_str(&completion);
+        self.last_key_was_tab = true;
+    }
+    /// Discards the current line and shows a fresh prompt.
+    fn cancel(&mut self) {
+        self.line.clear();
+        println!("^C");
+        print!("> ");
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::print::capture_output;
+    use sabi::Modifiers;
+    use sabi::MODIFIER_LEFT_CTRL;
+    fn key(key: char) -> KeyboardEvent {
+        KeyboardEvent {
+            key,
+            modifiers: Modifiers::default(),
+        }
+    }
+    #[test_case]
+    fn line_editor_runs_line_on_enter() {
+        let mut editor = LineEditor::new();
+        for c in "lx".chars() {
+            assert_eq!(editor.handle_key(key(c)), None);
+        }
+        assert_eq!(editor.handle_key(key('\x08')), None);
+        assert_eq!(editor.handle_key(key('s')), None);
+        assert_eq!(editor.handle_key(key('\n')), Some("ls".into()));
+        assert_eq!(editor.line(), "");
+    }
+    #[test_case]
+    fn unique_candidate_is_completed() {
+        assert_eq!(
+            complete("hell", &["hello0", "ping", "help"]),
+            CompletionResult::Unique("hello0".into())
+        );
+        assert_eq!(
+            complete("pi", &["hello0", "ping", "help"]),
+            CompletionResult::Unique("ping".into())
+        );
+        // Duplicated candidates are counted once
+        assert_eq!(
+            complete("", &["ps", "ps"]),
+            CompletionResult::Unique("ps".into())
+        );
+    }
+    #[test_case]
+    fn common_prefix_is_completed() {
+        assert_eq!(
+            complete("h", &["hello1", "help", "hello0", "ping"]),
+            CompletionResult::CommonPrefix {
+                prefix: "hel".into(),
+                candidates: alloc::vec!["hello0".into(), "hello1".into(), "help".into()],
+            }
+        );
+        assert_eq!(
+            complete("", &["wait_until_dns_ready", "wait_until_network_is_up"]),
+            CompletionResult::CommonPrefix {
+                prefix: "wait_until_".into(),
+                candidates: alloc::vec![
+                    "wait_until_dns_ready".into(),
+                    "wait_until_network_is_up".into()
+                ],
+            }
+        );
+        // One candidate can be the prefix of the other
+        assert_eq!(
+            complete("p", &["ps", "pse"]),
+            CompletionResult::CommonPrefix {
+                prefix: "ps".into(),
+                candidates: alloc::vec!["ps".into(), "pse".into()],
+            }
+        );
+    }
+    #[test_case]
+    fn no_match_is_reported() {
+        assert_eq!(
+            complete("x", &["hello0", "ping"]),
+            CompletionResult::NoMatch
+        );
+        assert_eq!(complete("", &[]), CompletionResult::NoMatch);
+    }
+    #[test_case]
+    fn line_editor_completes_last_token_on_tab() {
+        let mut editor = LineEditor::new();
+        for c in "cat my".chars() {
+            editor.handle_key(key(c));
+        }
+        let files = |is_first_token: bool| {
+            assert!(!is_first_token);
+            alloc::vec!["my_file0".into(), "my_file1".into()]
+        };
+        editor.handle_tab(files);
+        assert_eq!(editor.line(), "cat my_file");
+        // Nothing more to complete, so a double Tab only lists the candidates
+        editor.handle_tab(files);
+        assert_eq!(editor.line(), "cat my_file");
+        editor.handle_key(key('1'));
+        editor.handle_tab(files);
+        assert_eq!(editor.line(), "cat my_file1 ");
+    }
+    #[test_case]
+    fn cursor_blink_toggles_every_interval() {
+        assert!(is_cursor_blink_on(0, 500));
+        assert!(is_cursor_blink_on(499, 500));
+        assert!(!is_cursor_blink_on(500, 500));
+        assert!(!is_cursor_blink_on(999, 500));
+        assert!(is_cursor_blink_on(1000, 500));
+        assert!(!is_cursor_blink_on(1500, 500));
+        assert_eq!(ms_until_next_blink(0, 500), 500);
+        assert_eq!(ms_until_next_blink(499, 500), 1);
+        assert_eq!(ms_until_next_blink(500, 500), 500);
+        assert_eq!(ms_until_next_blink(1234, 500), 266);
+    }
+    #[test_case]
+    fn escape_sequence_is_not_echoed() {
+        assert!(!is_escape_sequence_complete(""));
+        assert!(!is_escape_sequence_complete("["));
+        assert!(!is_escape_sequence_complete("[2"));
+        assert!(is_escape_sequence_complete("[2J"));
+        assert!(is_escape_sequence_complete("[H"));
+        assert!(is_escape_sequence_complete("[1;5A"));
+        assert!(is_escape_sequence_complete("c"));
+        let mut editor = LineEditor::new();
+        let output = capture_output(|| {
+            for c in "ls\x1b[A\x1b[1;5D-l".chars() {
+                assert_eq!(editor.handle_key(key(c)), None);
+            }
+        });
+        assert_eq!(output, "ls-l");
+        assert_eq!(editor.line(), "ls-l");
+    }
+    #[test_case]
+    fn incomplete_escape_sequence_is_aborted() {
+        let mut editor = LineEditor::new();
+        let output = capture_output(|| {
+            // A lone ESC does not swallow the next char
+            for c in "\x1bls".chars() {
+                assert_eq!(editor.handle_key(key(c)), None);
+            }
+            // Enter completes the line even in the middle of a CSI sequence
+            for c in " \x1b[1".chars() {
+                assert_eq!(editor.handle_key(key(c)), None);
+            }
+            assert_eq!(editor.handle_key(key('\r')), Some("ls ".into()));
+        });
+        assert_eq!(output, "ls \n");
+        assert_eq!(editor.handle_key(key('a')), None);
+        assert_eq!(editor.line(), "a");
+        // Ctrl-C cancels the line as usual
+        editor.handle_key(key('\x1b'));
+        editor.handle_key(key('['));
+        assert_eq!(editor.handle_key(key('\x03')), None);
+        assert_eq!(editor.line(), "");
+        editor.handle_key(key('b'));
+        assert_eq!(editor.line(), "b");
+    }
+    #[test_case]
+    fn line_editor_ctrl_c_clears_line() {
+        let mut editor = LineEditor::new();
+        for c in "panic".chars() {
+            editor.handle_key(key(c));
+        }
+        let ctrl_c = KeyboardEvent {
+            key: 'c',
+            modifiers: Modifiers(MODIFIER_LEFT_CTRL),
+        };
+        assert_eq!(editor.handle_key(ctrl_c), None);
+        assert_eq!(editor.line(), "");
+        assert_eq!(editor.handle_key(key('\n')), Some("".into()));
+        // ETX from the serial console works as well
+        editor.handle_key(key('a'));
+        assert_eq!(editor.handle_key(key('\x03')), None);
+        assert_eq!(editor.handle_key(key('\r')), Some("".into()));
+    }
+}
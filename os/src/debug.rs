@@ -15,8 +15,9 @@ pub fn print_kernel_debug_metadata() {
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 #[repr(u32)]
 pub enum QemuExitCode {
-    Success = 0x1, // QEMU will exit with status 3
-    Fail = 0x2,    // QEMU will exit with status 5
+    Success = 0x1,         // QEMU will exit with status 3
+    Fail = 0x2,            // QEMU will exit with status 5
+    AssertionFailed = 0x3, // QEMU will exit with status 7
 }
 
 pub fn exit_qemu(exit_code: QemuExitCode) -> ! {
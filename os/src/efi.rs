@@ -56,7 +56,8 @@ pub struct EfiGraphicsOutputProtocolPixelInfo {
     version: u32,
     pub horizontal_resolution: u32,
     pub vertical_resolution: u32,
-    _padding0: [u32; 5],
+    pub pixel_format: u32,
+    _pixel_information: [u32; 4],
     pub pixels_per_scan_line: u32,
     _pinned: PhantomPinned,
 }
@@ -1,9 +1,12 @@
 extern crate alloc;
 
 use crate::efi::types::EfiStatus;
+use alloc::boxed::Box;
 use alloc::string::String;
+use core::fmt;
 use core::num::TryFromIntError;
 use noli::error::Error as NoliError;
+use noli::net::IpV4Addr;
 
 #[derive(Debug, PartialEq, Eq, Clone)]
 pub enum Error {
@@ -13,8 +16,13 @@ pub enum Error {
     FileNameTooLong,
     GraphicsError,
     PciBusDeviceFunctionOutOfRange,
-    ReadFileSizeMismatch { expected: usize, actual: usize },
+    ReadFileSizeMismatch {
+        expected: usize,
+        actual: usize,
+    },
     ApicRegIndexOutOfRange,
+    BitSetIndexOutOfRange,
+    CommandRingFull,
     CalcOutOfRange,
     PageNotFound,
     PciBarInvalid,
@@ -22,6 +30,40 @@ pub enum Error {
     TryFromIntError,
     LockFailed,
     NoliError(NoliError),
+    NoRoute(IpV4Addr),
+    ArpPending(IpV4Addr),
+    ArpTimeout(IpV4Addr),
+    DnsNotFound,
+    ChecksumMismatch,
+    /// An error annotated by Context::context(), e.g. with the name of the caller
+    WithContext {
+        context: String,
+        source: Box<Error>,
+    },
+}
+impl Error {
+    /// Returns the innermost error, skipping the contexts attached to it.
+    pub fn root_cause(&self) -> &Error {
+        match self {
+            Error::WithContext { source, .. } => source.root_cause(),
+            e => e,
+        }
+    }
+}
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Error::Failed(s) => write!(f, "{s}"),
+            Error::FailedString(s) => write!(f, "{s}"),
+            Error::NoRoute(ip) => write!(f, "No route to host {ip}"),
+            Error::ArpPending(ip) => write!(f, "ARP resolution for {ip} is in progress"),
+            Error::ArpTimeout(ip) => write!(f, "ARP resolution for {ip} timed out"),
+            Error::DnsNotFound => write!(f, "No such host is found by DNS"),
+            Error::ChecksumMismatch => write!(f, "Checksum mismatch"),
+            Error::WithContext { context, source } => write!(f, "{context}: {source}"),
+            e => write!(f, "{e:?}"),
+        }
+    }
 }
 impl From<EfiStatus> for Error {
     fn from(e: EfiStatus) -> Self {
@@ -52,3 +94,117 @@ impl From<NoliError> for Error {
     }
 }
 pub type Result<T> = core::result::Result<T, Error>;
+
+pub trait Context<T> {
+    /// Prepends `context` to the error, if any. The context is copied only on the error path.
+    fn context(self, context: &str) -> Result<T>;
+}
+impl<T, E: Into<Error>> Context<T> for core::result::Result<T, E> {
+    fn context(self, context: &str) -> Result<T> {
+        self.map_err(|e| Error::WithContext {
+            context: context.into(),
+            source: Box::new(e.into()),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bitset::BitSet;
+    use crate::net::ip::IpV4Packet;
+    use crate::pci::BusDeviceFunction;
+    use alloc::format;
+    use alloc::string::ToString;
+
+    #[test_case]
+    fn net_errors_are_displayed_with_details() {
+        let ip = IpV4Addr::new([10, 0, 2, 2]);
+        assert_eq!(
+            format!("{}", Error::NoRoute(ip)),
+            "No route to host 10.0.2.2"
+        );
+        assert_eq!(
+            format!("{}", Error::ArpPending(ip)),
+            "ARP resolution for 10.0.2.2 is in progress"
+        );
+        assert_eq!(
+            format!("{}", Error::ArpTimeout(ip)),
+            "ARP resolution for 10.0.2.2 timed out"
+        );
+        assert_eq!(
+            format!("{}", Error::DnsNotFound),
+            "No such host is found by DNS"
+        );
+        assert_eq!(format!("{}", Error::ChecksumMismatch), "Checksum mismatch");
+        assert_eq!(format!("{}", Error::PageNotFound), "PageNotFound");
+    }
+    #[test_case]
+    fn errors_from_each_module_propagate_with_question_mark() {
+        fn parse_ip_payload_offset(packet: &[u8]) -> Result<usize> {
+            let offset = IpV4Packet::payload_offset(packet)?;
+            Ok(offset)
+        }
+        fn insert_to_bitset(index: usize) -> Result<()> {
+            BitSet::<1>::new().insert(index)?;
+            Ok(())
+        }
+        fn pci_bdf(device: usize) -> Result<BusDeviceFunction> {
+            let bdf = BusDeviceFunction::new(0, device, 0)?;
+            Ok(bdf)
+        }
+        fn check_noli(e: NoliError) -> Result<()> {
+            Err::<(), NoliError>(e)?;
+            Ok(())
+        }
+        assert!(parse_ip_payload_offset(&[0u8; 4]).is_err());
+        assert_eq!(insert_to_bitset(8), Err(Error::BitSetIndexOutOfRange));
+        assert!(pci_bdf(1).is_ok());
+        assert_eq!(
+            pci_bdf(32).err(),
+            Some(Error::PciBusDeviceFunctionOutOfRange)
+        );
+        assert_eq!(
+            check_noli(NoliError::GraphicsOutOfRange),
+            Err(Error::GraphicsError)
+        );
+    }
+    #[test_case]
+    fn context_on_ok_is_noop() {
+        let r: Result<u32> = Ok(42);
+        assert_eq!(r.context("handle_rx_dhcp"), Ok(42));
+    }
+    #[test_case]
+    fn context_on_err_prepends_message() {
+        let r: Result<()> = Err(Error::Failed("Invalid op data len"));
+        let e = r.context("handle_rx_dhcp").unwrap_err();
+        assert_eq!(format!("{e}"), "handle_rx_dhcp: Invalid op data len");
+        assert_eq!(e.root_cause(), &Error::Failed("Invalid op data len"));
+
+        let e = Err::<(), _>(e).context("Loopback").unwrap_err();
+        assert_eq!(
+            format!("{e}"),
+            "Loopback: handle_rx_dhcp: Invalid op data len"
+        );
+        assert_eq!(e.root_cause(), &Error::Failed("Invalid op data len"));
+    }
+    #[test_case]
+    fn context_converts_foreign_errors() {
+        let e = u8::try_from(256u32).context("parse").unwrap_err();
+        assert_eq!(e.root_cause(), &Error::TryFromIntError);
+        assert_eq!(format!("{e}"), "parse: TryFromIntError");
+    }
+    #[test_case]
+    fn conversions_into_error() {
+        assert_eq!(Error::from("oops"), Error::Failed("oops"));
+        assert_eq!(
+            Error::from("oops".to_string()),
+            Error::FailedString("oops".to_string())
+        );
+        assert_eq!(format!("{}", Error::from("oops")), "oops");
+        assert_eq!(
+            Error::from(u8::try_from(256u32).unwrap_err()),
+            Error::TryFromIntError
+        );
+    }
+}
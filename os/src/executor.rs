@@ -1,14 +1,20 @@
 extern crate alloc;
 
+use crate::clock::best_clock;
+use crate::clock::ClockSource;
 use crate::error::Error;
 use crate::error::Result;
-use crate::hpet::Hpet;
 use crate::info;
 use crate::mutex::Mutex;
 use crate::process::Scheduler;
+use crate::warn;
 use crate::x86_64::busy_loop_hint;
 use alloc::boxed::Box;
 use alloc::collections::VecDeque;
+use alloc::format;
+use alloc::string::String;
+use alloc::vec::Vec;
+use core::fmt;
 use core::fmt::Debug;
 use core::future::Future;
 use core::panic::Location;
@@ -40,10 +46,39 @@ pub async fn yield_execution() {
     Yield::default().await
 }
 
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TaskState {
+    /// Spawned but not polled yet
+    Ready,
+    /// Returned Poll::Pending on the last poll and waiting to be polled again
+    Pending,
+    /// Being polled right now
+    Running,
+}
+impl fmt::Display for TaskState {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.pad(match self {
+            TaskState::Ready => "ready",
+            TaskState::Pending => "pending",
+            TaskState::Running => "running",
+        })
+    }
+}
+
+/// A snapshot of a task in an Executor, for diagnostics.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TaskInfo {
+    pub name: String,
+    pub state: TaskState,
+    pub poll_count: usize,
+}
+
 pub struct Task<T> {
     future: Pin<Box<dyn Future<Output = Result<T>>>>,
+    name: Option<&'static str>,
     created_at_file: &'static str,
     created_at_line: u32,
+    poll_count: usize,
 }
 impl<T> Task<T> {
     #[track_caller]
@@ -51,17 +86,58 @@ impl<T> Task<T> {
         Task {
             // Pin the task here to avoid invalidating the self references used in  the future
             future: Box::pin(future),
+            name: None,
             created_at_file: Location::caller().file(),
             created_at_line: Location::caller().line(),
+            poll_count: 0,
+        }
+    }
+    #[track_caller]
+    pub fn new_named(
+        name: &'static str,
+        future: impl Future<Output = Result<T>> + 'static,
+    ) -> Task<T> {
+        let mut task = Self::new(future);
+        task.name = Some(name);
+        task
+    }
+    /// Returns the name given on creation, or the location where the task was created.
+    pub fn name(&self) -> String {
+        self.running().name()
+    }
+    pub fn poll_count(&self) -> usize {
+        self.poll_count
+    }
+    fn info(&self, state: TaskState) -> TaskInfo {
+        TaskInfo {
+            name: self.name(),
+            state,
+            poll_count: self.poll_count,
+        }
+    }
+    fn running(&self) -> RunningTask {
+        RunningTask {
+            name: self.name,
+            created_at_file: self.created_at_file,
+            created_at_line: self.created_at_line,
+            poll_count: self.poll_count,
         }
     }
     fn poll(&mut self, context: &mut Context) -> Poll<Result<T>> {
+        self.poll_count += 1;
         self.future.as_mut().poll(context)
     }
 }
 impl<T> Debug for Task<T> {
     fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
-        write!(f, "Task({}:{})", self.created_at_file, self.created_at_line)
+        match self.name {
+            Some(name) => write!(
+                f,
+                "Task({name} @ {}:{})",
+                self.created_at_file, self.created_at_line
+            ),
+            None => write!(f, "Task({}:{})", self.created_at_file, self.created_at_line),
+        }
     }
 }
 // Do nothing, just no_ops.
@@ -86,6 +162,17 @@ pub fn spawn_global(future: impl Future<Output = Result<()>> + 'static) {
     ROOT_EXECUTOR.lock().spawn(task);
 }
 
+#[track_caller]
+pub fn spawn_global_named(name: &'static str, future: impl Future<Output = Result<()>> + 'static) {
+    let task = Task::new_named(name, future);
+    ROOT_EXECUTOR.lock().spawn(task);
+}
+
+/// Returns the snapshot of the tasks in the global executor.
+pub fn global_tasks() -> Vec<TaskInfo> {
+    ROOT_EXECUTOR.lock().tasks()
+}
+
 pub fn run_global_poll_loop() -> ! {
     info!("Starting global poll loop");
     loop {
@@ -121,12 +208,50 @@ pub fn block_on_and_schedule<T>(future: impl Future<Output = Result<T>> + 'stati
     }
 }
 
+/// A single poll longer than this is reported since it starves the other tasks.
+const SLOW_POLL_THRESHOLD_MS: u64 = 100;
+
+/// Returns true if a poll which took `elapsed_ms` exceeds `threshold_ms`.
+pub fn is_slow_poll(elapsed_ms: u64, threshold_ms: u64) -> bool {
+    elapsed_ms > threshold_ms
+}
+
+/// What is recorded about the task being polled. This is cheap to build on every poll, and
+/// turned into a TaskInfo only when the tasks are listed.
+#[derive(Clone, Copy)]
+struct RunningTask {
+    name: Option<&'static str>,
+    created_at_file: &'static str,
+    created_at_line: u32,
+    poll_count: usize,
+}
+impl RunningTask {
+    fn name(&self) -> String {
+        match self.name {
+            Some(name) => name.into(),
+            None => format!("{}:{}", self.created_at_file, self.created_at_line),
+        }
+    }
+    fn info(&self) -> TaskInfo {
+        TaskInfo {
+            name: self.name(),
+            state: TaskState::Running,
+            poll_count: self.poll_count,
+        }
+    }
+}
+
 pub struct Executor {
     task_queue: Option<VecDeque<Task<()>>>,
+    // The task taken out of the queue to be polled
+    running: Option<RunningTask>,
 }
 impl Executor {
     const fn default() -> Self {
-        Self { task_queue: None }
+        Self {
+            task_queue: None,
+            running: None,
+        }
     }
     fn task_queue(&mut self) -> &mut VecDeque<Task<()>> {
         if self.task_queue.is_none() {
@@ -137,37 +262,82 @@ impl Executor {
     pub fn spawn(&mut self, task: Task<()>) {
         self.task_queue().push_back(task)
     }
+    /// Returns the snapshot of the task being polled (if any), followed by the queued tasks.
+    pub fn tasks(&mut self) -> Vec<TaskInfo> {
+        let running = self.running.map(|task| task.info());
+        running
+            .into_iter()
+            .chain(self.task_queue().iter().map(|task| {
+                task.info(if task.poll_count() == 0 {
+                    TaskState::Ready
+                } else {
+                    TaskState::Pending
+                })
+            }))
+            .collect()
+    }
     pub fn poll(executor: &Mutex<Self>) {
-        let task = executor.lock().task_queue().pop_front();
+        let task = {
+            let mut executor = executor.lock();
+            let task = executor.task_queue().pop_front();
+            executor.running = task.as_ref().map(|task| RunningTask {
+                poll_count: task.poll_count + 1,
+                ..task.running()
+            });
+            task
+        };
         if let Some(mut task) = task {
             let waker = dummy_waker();
             let mut context = Context::from_waker(&waker);
-            match task.poll(&mut context) {
+            let clock = best_clock();
+            let start_counter = clock.map(|c| c.counter()).unwrap_or(0);
+            let result = task.poll(&mut context);
+            if let Some(clock) = clock {
+                let elapsed_ms = clock.elapsed_ms_since(start_counter);
+                if is_slow_poll(elapsed_ms, SLOW_POLL_THRESHOLD_MS) {
+                    warn!("{task:?} did not yield for {elapsed_ms} ms");
+                }
+            }
+            match result {
                 Poll::Ready(result) => {
+                    executor.lock().running = None;
                     info!("Task completed: {:?}: {:?}", task, result);
                 }
                 Poll::Pending => {
-                    executor.lock().task_queue().push_back(task);
+                    let mut executor = executor.lock();
+                    executor.running = None;
+                    executor.task_queue().push_back(task);
                 }
             }
         }
     }
 }
 
+/// Completes immediately if no clock is available, which happens only if even the Local APIC
+/// timer could not be calibrated. See init_timer().
 pub struct TimeoutFuture {
-    time_out: u64,
+    clock: Option<&'static dyn ClockSource>,
+    start_counter: u64,
+    timeout_ms: u64,
 }
 impl TimeoutFuture {
     pub fn new_ms(timeout_ms: u64) -> Self {
-        let time_out = Hpet::take().main_counter() + Hpet::take().freq() / 1000 * timeout_ms;
-        Self { time_out }
+        let clock = best_clock();
+        let start_counter = clock.map(|c| c.counter()).unwrap_or(0);
+        Self {
+            clock,
+            start_counter,
+            timeout_ms,
+        }
     }
 }
 impl Future for TimeoutFuture {
     type Output = ();
     fn poll(self: Pin<&mut Self>, _: &mut Context) -> Poll<()> {
-        let time_out = self.time_out;
-        if time_out < Hpet::take().main_counter() {
+        let Some(clock) = self.clock else {
+            return Poll::Ready(());
+        };
+        if clock.elapsed_ms_since(self.start_counter) >= self.timeout_ms {
             Poll::Ready(())
         } else {
             Poll::Pending
@@ -204,3 +374,70 @@ pub async fn with_timeout_ms<F: Future>(f: F, timeout: u64) -> Result<F::Output>
     let (_, res) = SelectFuture::new(t, f).await;
     res.ok_or(Error::Failed("Timed out"))
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    #[test_case]
+    fn task_names_default_to_the_location() {
+        let task = Task::<()>::new(async { Ok(()) });
+        assert!(task.name().starts_with(file!()));
+        let task = Task::<()>::new_named("serial", async { Ok(()) });
+        assert_eq!(task.name(), "serial");
+        assert_eq!(task.poll_count(), 0);
+    }
+    #[test_case]
+    fn slow_poll_is_detected_above_threshold() {
+        assert!(!is_slow_poll(0, SLOW_POLL_THRESHOLD_MS));
+        assert!(!is_slow_poll(
+            SLOW_POLL_THRESHOLD_MS,
+            SLOW_POLL_THRESHOLD_MS
+        ));
+        assert!(is_slow_poll(
+            SLOW_POLL_THRESHOLD_MS + 1,
+            SLOW_POLL_THRESHOLD_MS
+        ));
+        assert!(is_slow_poll(u64::MAX, 0));
+    }
+    #[test_case]
+    fn polling_updates_reported_tasks() {
+        static EXECUTOR: Mutex<Executor> = Mutex::new(Executor::default());
+        static SEEN_BY_TASK: Mutex<Vec<TaskInfo>> = Mutex::new(Vec::new());
+        let info = |name: &str, state, poll_count| TaskInfo {
+            name: name.into(),
+            state,
+            poll_count,
+        };
+        EXECUTOR.lock().spawn(Task::new_named(
+            "yielder",
+            core::future::pending::<Result<()>>(),
+        ));
+        EXECUTOR.lock().spawn(Task::new_named("observer", async {
+            *SEEN_BY_TASK.lock() = EXECUTOR.lock().tasks();
+            Ok(())
+        }));
+        assert_eq!(
+            EXECUTOR.lock().tasks(),
+            [
+                info("yielder", TaskState::Ready, 0),
+                info("observer", TaskState::Ready, 0)
+            ]
+        );
+        Executor::poll(&EXECUTOR);
+        Executor::poll(&EXECUTOR);
+        // The observer sees itself running while the yielder waits in the queue
+        assert_eq!(
+            *SEEN_BY_TASK.lock(),
+            [
+                info("observer", TaskState::Running, 1),
+                info("yielder", TaskState::Pending, 1)
+            ]
+        );
+        Executor::poll(&EXECUTOR);
+        // The observer has completed and removed from the executor
+        assert_eq!(
+            EXECUTOR.lock().tasks(),
+            [info("yielder", TaskState::Pending, 2)]
+        );
+    }
+}
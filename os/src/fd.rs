@@ -0,0 +1,174 @@
+extern crate alloc;
+
+use crate::error::Error;
+use crate::error::Result;
+use crate::input::InputManager;
+use crate::print;
+use crate::ramfs::OpenFile;
+use crate::ramfs::Ramfs;
+use alloc::collections::VecDeque;
+use alloc::string::String;
+use alloc::vec::Vec;
+
+pub const FD_STDIN: i64 = 0;
+pub const FD_STDOUT: i64 = 1;
+
+/// Reads the key inputs as UTF-8 bytes. Bytes of a char which did not fit in the buffer are
+/// kept and returned by the next read.
+#[derive(Default)]
+pub struct StdinReader {
+    pending: VecDeque<u8>,
+}
+impl StdinReader {
+    /// Returns the number of bytes copied into `buf`, which can be 0 if there was no input.
+    pub fn read_from(&mut self, input: &InputManager, buf: &mut [u8]) -> usize {
+        let mut len = 0;
+        while len < buf.len() {
+            if self.pending.is_empty() {
+                let Some(c) = input.pop_input() else {
+                    break;
+                };
+                let mut encoded = [0u8; 4];
+                self.pending
+                    .extend(c.encode_utf8(&mut encoded).as_bytes().iter());
+            }
+            while let (Some(dst), Some(src)) = (buf.get_mut(len), self.pending.front()) {
+                *dst = *src;
+                self.pending.pop_front();
+                len += 1;
+            }
+        }
+        len
+    }
+}
+
+pub enum FileDescriptor {
+    Stdin(StdinReader),
+    Stdout,
+    Stderr,
+    Ramfs(OpenFile),
+}
+impl FileDescriptor {
+    pub fn read(&mut self, buf: &mut [u8]) -> Result<usize> {
+        match self {
+            FileDescriptor::Stdin(reader) => Ok(reader.read_from(&InputManager::take(), buf)),
+            FileDescriptor::Stdout | FileDescriptor::Stderr => {
+                Err(Error::Failed("fd: not readable"))
+            }
+            FileDescriptor::Ramfs(file) => file.read(Ramfs::take(), buf),
+        }
+    }
+    pub fn write(&mut self, data: &[u8]) -> Result<usize> {
+        match self {
+            FileDescriptor::Stdin(_) => Err(Error::Failed("fd: not writable")),
+            FileDescriptor::Stdout | FileDescriptor::Stderr => {
+                print!("{}", String::from_utf8_lossy(data));
+                Ok(data.len())
+            }
+            FileDescriptor::Ramfs(file) => file.write(Ramfs::take(), data),
+        }
+    }
+}
+
+/// The file descriptors of an app. fd 0, 1 and 2 are the stdin, stdout and stderr.
+pub struct FileDescriptorTable {
+    entries: Vec<Option<FileDescriptor>>,
+}
+impl FileDescriptorTable {
+    /// Opens `fd` with the lowest number available, and returns the number.
+    pub fn insert(&mut self, fd: FileDescriptor) -> i64 {
+        let index = match self.entries.iter().position(|e| e.is_none()) {
+            Some(index) => {
+                self.entries[index] = Some(fd);
+                index
+            }
+            None => {
+                self.entries.push(Some(fd));
+                self.entries.len() - 1
+            }
+        };
+        index as i64
+    }
+    pub fn get_mut(&mut self, fd: i64) -> Option<&mut FileDescriptor> {
+        let index = usize::try_from(fd).ok()?;
+        self.entries.get_mut(index)?.as_mut()
+    }
+    pub fn remove(&mut self, fd: i64) -> Option<FileDescriptor> {
+        let index = usize::try_from(fd).ok()?;
+        let removed = self.entries.get_mut(index)?.take();
+        while let Some(None) = self.entries.last() {
+            self.entries.pop();
+        }
+        removed
+    }
+}
+impl Default for FileDescriptorTable {
+    fn default() -> Self {
+        Self {
+            entries: Vec::from([
+                Some(FileDescriptor::Stdin(StdinReader::default())),
+                Some(FileDescriptor::Stdout),
+                Some(FileDescriptor::Stderr),
+            ]),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::print::capture_output;
+    #[test_case]
+    fn lowest_free_fd_is_allocated() {
+        let mut fds = FileDescriptorTable::default();
+        assert!(matches!(
+            fds.get_mut(FD_STDIN),
+            Some(FileDescriptor::Stdin(_))
+        ));
+        assert!(matches!(
+            fds.get_mut(FD_STDOUT),
+            Some(FileDescriptor::Stdout)
+        ));
+        assert!(matches!(fds.get_mut(2), Some(FileDescriptor::Stderr)));
+        assert!(fds.get_mut(3).is_none());
+        assert!(fds.get_mut(-1).is_none());
+        assert_eq!(fds.insert(FileDescriptor::Stdout), 3);
+        assert_eq!(fds.insert(FileDescriptor::Stdout), 4);
+        assert!(fds.remove(3).is_some());
+        assert!(fds.remove(3).is_none());
+        assert_eq!(fds.insert(FileDescriptor::Stdout), 3);
+        assert!(fds.remove(FD_STDIN).is_some());
+        assert_eq!(fds.insert(FileDescriptor::Stderr), 0);
+        assert_eq!(fds.insert(FileDescriptor::Stderr), 5);
+    }
+    #[test_case]
+    fn stdin_reads_from_input_queue() {
+        let mut fds = FileDescriptorTable::default();
+        let stdin = fds.get_mut(FD_STDIN).unwrap();
+        let mut buf = [0u8; 2];
+        assert_eq!(stdin.read(&mut buf), Ok(0));
+        let input = InputManager::take();
+        input.push_input('a');
+        input.push_input('b');
+        input.push_input('あ');
+        assert_eq!(stdin.read(&mut buf), Ok(2));
+        assert_eq!(&buf, b"ab");
+        // A char across the reads is split into its UTF-8 bytes
+        assert_eq!(stdin.read(&mut buf), Ok(2));
+        assert_eq!(&buf, &"あ".as_bytes()[..2]);
+        assert_eq!(stdin.read(&mut buf), Ok(1));
+        assert_eq!(buf[0], "あ".as_bytes()[2]);
+        assert_eq!(stdin.read(&mut buf), Ok(0));
+        assert!(stdin.write(b"x").is_err());
+    }
+    #[test_case]
+    fn stdout_writes_to_console() {
+        let mut fds = FileDescriptorTable::default();
+        let output = capture_output(|| {
+            let stdout = fds.get_mut(FD_STDOUT).unwrap();
+            assert_eq!(stdout.write(b"hello"), Ok(5));
+            assert!(stdout.read(&mut [0u8; 1]).is_err());
+        });
+        assert_eq!(output, "hello");
+    }
+}
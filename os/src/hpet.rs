@@ -1,3 +1,5 @@
+use crate::clock::counter_to_ms;
+use crate::clock::ClockSource;
 use core::fmt;
 use core::mem::size_of;
 use core::ptr::read_volatile;
@@ -63,6 +65,10 @@ impl Hpet {
     pub fn take() -> &'static mut Self {
         unsafe { HPET.as_mut().expect("HPET is not initialized") }
     }
+    /// Returns None if HPET is not initialized (e.g. the platform does not have it).
+    pub fn try_take() -> Option<&'static Self> {
+        unsafe { HPET.as_ref() }
+    }
     /// # Safety
     /// This is safe if it is called only once.
     pub unsafe fn set(hpet: Hpet) {
@@ -127,10 +133,22 @@ impl Hpet {
         // This is safe as far as self is properly constructed.
         self.freq
     }
+    /// Returns the milliseconds elapsed since the main counter was reset during the boot.
+    pub fn uptime_ms(&self) -> u64 {
+        counter_to_ms(self.main_counter(), self.freq())
+    }
     pub fn notify_end_of_interrupt(&mut self) {
         self.registers.interrupt_status.store(0, Ordering::Relaxed);
     }
 }
+impl ClockSource for Hpet {
+    fn counter(&self) -> u64 {
+        self.main_counter()
+    }
+    fn freq(&self) -> u64 {
+        self.freq
+    }
+}
 impl fmt::Debug for Hpet {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         writeln!(
@@ -142,3 +160,14 @@ impl fmt::Debug for Hpet {
         )
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test_case]
+    fn try_take_returns_none_before_init() {
+        // HPET is not initialized in the unit test environment
+        assert!(Hpet::try_take().is_none());
+    }
+}
@@ -3,32 +3,47 @@ extern crate alloc;
 use crate::acpi::Acpi;
 use crate::boot_info::BootInfo;
 use crate::boot_info::File;
+use crate::clock::best_clock;
+use crate::clock::ClockSource;
 use crate::efi;
+use crate::efi::fs::EfiFileName;
 use crate::error;
 use crate::hpet;
+use crate::info;
 use crate::memory_map_holder;
+use crate::net::manager::Network;
 use crate::pci::Pci;
+use crate::rtl8139::Rtl8139Driver;
 use crate::serial::SerialPort;
 use crate::serial::SerialPortIndex;
+use crate::tsc::Tsc;
 use crate::util;
 use crate::util::size_in_pages_from_bytes;
 use crate::vram;
 use crate::vram::VRAMBufferInfo;
+use crate::warn;
 use crate::x86_64;
+use crate::x86_64::apic::ApicTimer;
 use crate::x86_64::apic::IoApic;
 use crate::x86_64::block_interrupts;
 use crate::x86_64::gdt::Gdt;
+use crate::x86_64::gdt::GdtBuilder;
 use crate::x86_64::idt::Idt;
 use crate::x86_64::idt::TaskStateSegment64;
 use crate::x86_64::paging::write_cr3;
 use crate::x86_64::paging::PageAttr;
 use crate::x86_64::paging::PML4;
 use crate::x86_64::CpuidRequest;
+use crate::xhci::driver::XhciDriverForPci;
 use alloc::boxed::Box;
+use alloc::format;
+use alloc::string::String;
 use core::cmp::max;
+use core::fmt;
 use core::fmt::Write;
 use core::pin::Pin;
 use core::slice;
+use core::str::FromStr;
 use efi::types::EfiHandle;
 use efi::EfiMemoryType::CONVENTIONAL_MEMORY;
 use efi::EfiMemoryType::LOADER_CODE;
@@ -36,7 +51,10 @@ use efi::EfiMemoryType::LOADER_DATA;
 use error::Result;
 use hpet::Hpet;
 use noli::bitmap::Bitmap;
-use noli::text_area;
+use noli::font::set_loaded_font;
+use noli::font::Font;
+use noli::font::FONT_HEIGHT;
+use noli::font::FONT_WIDTH;
 use noli::text_area::TextArea;
 use util::PAGE_SIZE;
 
@@ -182,16 +200,42 @@ pub fn init_global_allocator() {
     crate::allocator::ALLOCATOR.init_with_mmap(BootInfo::take().memory_map());
 }
 
+fn init_console_font() {
+    let Ok(name) = EfiFileName::from_str("font.psf") else {
+        return;
+    };
+    let Some(file) = BootInfo::take()
+        .root_files()
+        .iter()
+        .filter_map(|e| e.as_ref())
+        .find(|e| e.name() == &name)
+    else {
+        return;
+    };
+    match Font::parse(file.data()) {
+        Ok(font) if font.width() == FONT_WIDTH && font.height() == FONT_HEIGHT => {
+            info!("Loaded console font from font.psf");
+            set_loaded_font(font);
+        }
+        Ok(font) => warn!(
+            "font.psf: {}x{} fonts are not supported. Using the embedded font.",
+            font.width(),
+            font.height()
+        ),
+        Err(e) => warn!("font.psf: {e:?}. Using the embedded font."),
+    }
+}
+
 pub fn init_graphical_terminal() {
+    init_console_font();
     let vram = BootInfo::take().vram();
-    let mut textarea = TextArea::new(
+    let textarea = TextArea::new(
         vram,
         0,
         vram.height() / 4 * 3,
         vram.width(),
         vram.height() / 4,
     );
-    textarea.set_mode(text_area::TextAreaMode::Ring);
     crate::print::GLOBAL_PRINTER.set_text_area(textarea);
 }
 
@@ -227,19 +271,25 @@ pub struct InterruptConfiguration {
 pub fn init_interrupts() -> Result<InterruptConfiguration> {
     block_interrupts();
     let tss64 = TaskStateSegment64::new()?;
-    let gdt = Gdt::new(&tss64)?;
+    let (gdt, selectors) = GdtBuilder::new()
+        .kernel_code()
+        .kernel_data()
+        .user_code()
+        .user_data()
+        .tss(&tss64)
+        .load()?;
     unsafe {
-        x86_64::write_cs(x86_64::KERNEL_CS);
-        x86_64::write_ss(x86_64::KERNEL_DS);
-        x86_64::write_es(x86_64::KERNEL_DS);
-        x86_64::write_ds(x86_64::KERNEL_DS);
-        x86_64::write_fs(x86_64::KERNEL_DS);
-        x86_64::write_gs(x86_64::KERNEL_DS);
+        x86_64::write_cs(selectors.kernel_cs);
+        x86_64::write_ss(selectors.kernel_ds);
+        x86_64::write_es(selectors.kernel_ds);
+        x86_64::write_ds(selectors.kernel_ds);
+        x86_64::write_fs(selectors.kernel_ds);
+        x86_64::write_gs(selectors.kernel_ds);
     }
     x86_64::disable_legacy_pic();
     let bsp_local_apic = BootInfo::take().bsp_local_apic();
     IoApic::init(bsp_local_apic).expect("Failed to init I/O APIC");
-    let idt = Idt::new(x86_64::KERNEL_CS)?;
+    let idt = Idt::new(selectors.kernel_cs)?;
     Ok(InterruptConfiguration { tss64, gdt, idt })
 }
 
@@ -274,23 +324,132 @@ pub fn detect_core_clock_freq() -> u32 {
 
 pub fn init_timer() {
     let acpi = BootInfo::take().acpi();
-    unsafe {
-        // This is safe since this is the only place to create HPET instance.
-        Hpet::set(Hpet::new(
-            acpi.hpet()
-                .base_address()
-                .expect("Failed to get HPET base address"),
-        ));
+    if let Some(hpet) = acpi.hpet() {
+        info!("{hpet:?}");
+        unsafe {
+            // This is safe since this is the only place to create HPET instance.
+            Hpet::set(Hpet::new(
+                hpet.base_address()
+                    .expect("Failed to get HPET base address"),
+            ));
+        }
+    } else {
+        warn!("HPET not found");
+    }
+    if BootInfo::take().cpu_features().has_invariant_tsc {
+        // Use the PIT as the reference if HPET is not available
+        let tsc = match Hpet::try_take() {
+            Some(hpet) => Tsc::calibrate(hpet, 10),
+            None => Tsc::calibrate_with_pit(10),
+        };
+        match tsc {
+            Ok(tsc) => {
+                info!("TSC frequency: {} Hz", tsc.freq());
+                // This is safe since this is the only place to create TSC instance.
+                unsafe { Tsc::set(tsc) };
+            }
+            Err(e) => warn!("{e:?}"),
+        }
+    }
+    if best_clock().is_none() {
+        match ApicTimer::calibrate_with_pit(BootInfo::take().bsp_local_apic(), 10) {
+            Ok(timer) => {
+                info!("Local APIC timer frequency: {} Hz", timer.freq());
+                // This is safe since this is the only place to create ApicTimer instance.
+                unsafe { ApicTimer::set(timer) };
+            }
+            Err(e) => warn!("No clock available. Timeouts will expire immediately: {e:?}"),
+        }
+    }
+}
+
+/// Hardware and memory details gathered during init, printed once as a boot banner.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BootSummary {
+    pub total_memory_bytes: usize,
+    pub cpu_vendor: String,
+    pub num_pci_devices: usize,
+    pub framebuffer_width: i64,
+    pub framebuffer_height: i64,
+    pub usb_initialized: bool,
+    pub network_initialized: bool,
+}
+impl BootSummary {
+    fn collect(pci: &Pci, usb_initialized: bool) -> Self {
+        let boot_info = BootInfo::take();
+        let vram = boot_info.vram();
+        Self {
+            total_memory_bytes: crate::allocator::ALLOCATOR.total_bytes(),
+            cpu_vendor: format!("{:?}", boot_info.cpu_features().vendor_string),
+            num_pci_devices: pci.list_devices().len(),
+            framebuffer_width: vram.width(),
+            framebuffer_height: vram.height(),
+            usb_initialized,
+            // Not take() since it starts the network manager as a side effect
+            network_initialized: Network::try_take()
+                .is_some_and(|network| network.num_interfaces() > 0),
+        }
+    }
+}
+impl fmt::Display for BootSummary {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let status = |initialized| if initialized { "ready" } else { "unavailable" };
+        writeln!(f, "WasabiOS boot summary:")?;
+        writeln!(
+            f,
+            "  Memory      : {} MiB",
+            self.total_memory_bytes / 1024 / 1024
+        )?;
+        writeln!(f, "  CPU vendor  : {}", self.cpu_vendor)?;
+        writeln!(f, "  PCI devices : {}", self.num_pci_devices)?;
+        writeln!(
+            f,
+            "  Framebuffer : {}x{}",
+            self.framebuffer_width, self.framebuffer_height
+        )?;
+        writeln!(f, "  USB         : {}", status(self.usb_initialized))?;
+        write!(f, "  Network     : {}", status(self.network_initialized))
     }
 }
 
 pub fn init_pci() {
     let acpi = BootInfo::take().acpi();
     let mcfg = acpi.mcfg();
-    let pci = Pci::new(mcfg);
+    let mut pci = Pci::new(mcfg);
+    pci.register_driver(Box::<Rtl8139Driver>::default());
+    pci.register_driver(Box::<XhciDriverForPci>::default());
+    pci.scan_devices();
     // This is safe since it is only called once
     unsafe { Pci::set(pci) };
-    Pci::take()
-        .probe_devices()
-        .expect("Failed to probe devices");
+    let pci = Pci::take();
+    let xhc = XhciDriverForPci::probe(pci.list_devices());
+    if let Err(e) = &xhc {
+        warn!("{e:?}: continuing without USB. Only serial input is available.");
+    }
+    pci.probe_devices().expect("Failed to probe devices");
+    let usb_initialized = xhc.is_ok_and(|bdf| pci.device(bdf).is_some());
+    info!("{}", BootSummary::collect(pci, usb_initialized));
+}
+
+#[test_case]
+fn boot_summary_layout_is_stable() {
+    let summary = BootSummary {
+        total_memory_bytes: 512 * 1024 * 1024,
+        cpu_vendor: String::from("GenuineIntel"),
+        num_pci_devices: 6,
+        framebuffer_width: 1280,
+        framebuffer_height: 800,
+        usb_initialized: true,
+        network_initialized: false,
+    };
+    let expected = [
+        "WasabiOS boot summary:",
+        "  Memory      : 512 MiB",
+        "  CPU vendor  : GenuineIntel",
+        "  PCI devices : 6",
+        "  Framebuffer : 1280x800",
+        "  USB         : ready",
+        "  Network     : unavailable",
+    ];
+    assert_eq!(format!("{summary}"), expected.join("\n"));
 }
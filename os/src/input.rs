@@ -3,6 +3,8 @@ extern crate alloc;
 use crate::mutex::Mutex;
 use alloc::collections::VecDeque;
 use alloc::rc::Rc;
+use sabi::KeyboardEvent;
+use sabi::Modifiers;
 use sabi::MouseEvent;
 
 static INPUT_MANAGER: Mutex<Option<Rc<InputManager>>> = Mutex::new(None);
@@ -25,14 +27,16 @@ impl KeyEvent {
 }
 
 pub struct InputManager {
-    input_queue: Mutex<VecDeque<char>>,
+    input_queue: Mutex<VecDeque<KeyboardEvent>>,
     cursor_queue: Mutex<VecDeque<MouseEvent>>,
+    modifiers: Mutex<Modifiers>,
 }
 impl InputManager {
     fn new() -> Self {
         Self {
             input_queue: Mutex::new(VecDeque::new()),
             cursor_queue: Mutex::new(VecDeque::new()),
+            modifiers: Mutex::new(Modifiers::default()),
         }
     }
     pub fn take() -> Rc<Self> {
@@ -40,14 +44,29 @@ impl InputManager {
         let instance = instance.get_or_insert_with(|| Rc::new(Self::new()));
         instance.clone()
     }
+    /// Enqueues a key input along with the modifier keys currently held down.
     pub fn push_input(&self, value: char) {
-        self.input_queue.lock().push_back(value)
+        let e = KeyboardEvent {
+            key: value,
+            modifiers: self.modifiers(),
+        };
+        self.input_queue.lock().push_back(e)
     }
     pub fn pop_input(&self) -> Option<char> {
+        self.pop_key_event().map(|e| e.key)
+    }
+    pub fn pop_key_event(&self) -> Option<KeyboardEvent> {
         self.input_queue.lock().pop_front()
     }
+    pub fn set_modifiers(&self, modifiers: Modifiers) {
+        *self.modifiers.lock() = modifiers
+    }
+    /// Returns the modifier keys currently held down.
+    pub fn modifiers(&self) -> Modifiers {
+        *self.modifiers.lock()
+    }
 
-    // x, y: 0f32..1f32, top left origin
+    // position: in the screen coordinates, top left origin
     pub fn push_cursor_input_absolute(&self, e: MouseEvent) {
         self.cursor_queue.lock().push_back(e)
     }
@@ -55,3 +74,31 @@ impl InputManager {
         self.cursor_queue.lock().pop_front()
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use sabi::MouseButtonState;
+    use sabi::PointerPosition;
+    #[test_case]
+    fn cursor_input_round_trip() {
+        let im = InputManager::new();
+        assert!(im.pop_cursor_input_absolute().is_none());
+        im.push_cursor_input_absolute(MouseEvent {
+            button: MouseButtonState::from_lcr(true, false, false),
+            position: PointerPosition::from_xy(12, 34),
+            wheel: -1,
+        });
+        im.push_cursor_input_absolute(MouseEvent::new(
+            MouseButtonState::default(),
+            PointerPosition::from_xy(56, 78),
+        ));
+        let e = im.pop_cursor_input_absolute().unwrap();
+        assert!(e.button.l());
+        assert_eq!((e.position.x, e.position.y, e.wheel), (12, 34, -1));
+        let e = im.pop_cursor_input_absolute().unwrap();
+        assert!(!e.button.l());
+        assert_eq!((e.position.x, e.position.y, e.wheel), (56, 78, 0));
+        assert!(im.pop_cursor_input_absolute().is_none());
+    }
+}
@@ -31,12 +31,15 @@ pub mod allocator;
 mod ax88179;
 pub mod bitset;
 pub mod boot_info;
+pub mod clock;
 pub mod cmd;
+pub mod console;
 pub mod debug;
 pub mod efi;
 pub mod elf;
 pub mod error;
 pub mod executor;
+mod fd;
 pub mod hpet;
 pub mod init;
 pub mod input;
@@ -47,13 +50,18 @@ pub mod mutex;
 pub mod net;
 #[cfg(target_os = "uefi")]
 mod panic;
+mod path;
 pub mod pci;
+pub mod pit;
 pub mod print;
 pub mod process;
+mod ramfs;
 mod rtl8139;
+mod screenshot;
 pub mod serial;
 mod syscall;
 pub mod test_runner;
+pub mod tsc;
 mod usb;
 mod usb_hid_keyboard;
 mod usb_hid_tablet;
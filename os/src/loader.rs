@@ -57,7 +57,8 @@ impl<'a> LoadedElf<'a> {
             app_ctx.cpu.rflags = 2;
             app_ctx.cpu.rsp = stack_range.end() as u64; // stack grows toward 0, so empty stack pointer will be the end addr
         }
-        let app_proc = Box::new(ProcessContext::new(Some(stack), Some(args))?);
+        let mut app_proc = Box::new(ProcessContext::new(Some(stack), Some(args))?);
+        app_proc.add_user_range(self.region.range());
         let proc = ProcessContext::new_with_fn(
             exec_app_context_proc_func,
             Box::into_raw(app_proc) as u64,
@@ -18,6 +18,8 @@ use noli::bitmap::Bitmap;
 use os::boot_info::BootInfo;
 use os::boot_info::File;
 use os::cmd;
+use os::console::blink_cursor;
+use os::console::LineEditor;
 use os::debug;
 use os::efi::fs::EfiFileName;
 use os::efi::types::EfiHandle;
@@ -25,7 +27,7 @@ use os::error;
 use os::error::Error;
 use os::error::Result;
 use os::executor::run_global_poll_loop;
-use os::executor::spawn_global;
+use os::executor::spawn_global_named;
 use os::executor::yield_execution;
 use os::executor::TimeoutFuture;
 use os::info;
@@ -37,6 +39,7 @@ use os::print;
 use os::println;
 use os::serial::SerialPort;
 use os::x86_64;
+use os::x86_64::init_fpu;
 use os::x86_64::read_rsp;
 use os::x86_64::syscall::init_syscall;
 
@@ -152,33 +155,16 @@ fn run_tasks() -> Result<()> {
     let console_task = async {
         // Note: this message is used by e2e_test. Please do not remove.
         info!("console_task has started");
-        let mut s = String::new();
+        let mut editor = LineEditor::new();
         print!("> ");
         loop {
-            if let Some(c) = InputManager::take().pop_input() {
-                if c == '\r' || c == '\n' {
-                    println!();
-                    if let Err(e) = cmd::run(&s).await {
+            if let Some(e) = InputManager::take().pop_key_event() {
+                if let Some(line) = editor.handle_key(e) {
+                    if let Err(e) = cmd::run(&line).await {
                         error!("{e:?}");
                     };
-                    s.clear();
                     print!("> ");
                 }
-                match c {
-                    '\x7f' | '\x08' => {
-                        if !s.is_empty() {
-                            print!("{0} {0}", 0x08 as char);
-                            s.pop();
-                        }
-                    }
-                    '\n' => {
-                        // Do nothing
-                    }
-                    _ => {
-                        print!("{c}");
-                        s.push(c);
-                    }
-                }
             }
             TimeoutFuture::new_ms(20).await;
             yield_execution().await;
@@ -203,12 +189,13 @@ fn run_tasks() -> Result<()> {
         }
     };
     // Enqueue tasks
-    spawn_global(task0);
-    spawn_global(task1);
-    spawn_global(serial_task);
-    spawn_global(console_task);
-    spawn_global(init_task);
-    spawn_global(tcp_echo_task);
+    spawn_global_named("task0", task0);
+    spawn_global_named("task1", task1);
+    spawn_global_named("serial", serial_task);
+    spawn_global_named("console", console_task);
+    spawn_global_named("cursor_blink", blink_cursor());
+    spawn_global_named("init", init_task);
+    spawn_global_named("tcp_echo", tcp_echo_task);
     init::init_pci();
     // Start executing tasks
     run_global_poll_loop();
@@ -223,6 +210,7 @@ fn main() -> Result<()> {
     core::mem::forget(interrupt_config);
     init::init_paging()?;
     init::init_timer();
+    init_fpu()?;
     os::process::init();
     init_syscall();
 
@@ -8,6 +8,7 @@ use crate::util::PAGE_SIZE;
 use crate::x86_64::paging::with_current_page_table;
 use crate::x86_64::paging::PageAttr;
 use alloc::boxed::Box;
+use core::alloc::GlobalAlloc;
 use core::alloc::Layout;
 use core::fmt;
 use core::mem::ManuallyDrop;
@@ -15,6 +16,7 @@ use core::ops::Range;
 use core::pin::Pin;
 use core::slice;
 
+#[derive(Clone)]
 pub struct AddressRange {
     range: Range<usize>,
 }
@@ -73,16 +75,26 @@ impl fmt::Debug for AddressRange {
 /// Represents a physically-contiguous region of
 /// 4KiB memory pages.
 /// The allocated region will be uninitialized
+/// The region is returned to the allocator when this is dropped.
 pub struct ContiguousPhysicalMemoryPages {
     layout: Layout,
     phys_addr: *mut u8,
+    // true if the page attributes were changed from the kernel default
+    attr_modified: bool,
 }
 impl ContiguousPhysicalMemoryPages {
     pub fn alloc_pages(num_pages: usize) -> Result<Self> {
         let layout = Layout::from_size_align(PAGE_SIZE * num_pages, PAGE_SIZE)
             .or(Err(Error::Failed("Invalid layout")))?;
         let phys_addr = ALLOCATOR.alloc_with_options(layout);
-        Ok(Self { layout, phys_addr })
+        if phys_addr.is_null() {
+            return Err(Error::Failed("Failed to allocate pages"));
+        }
+        Ok(Self {
+            layout,
+            phys_addr,
+            attr_modified: false,
+        })
     }
     pub fn fill_with_bytes(&mut self, value: u8) {
         unsafe {
@@ -118,6 +130,7 @@ impl ContiguousPhysicalMemoryPages {
                     .expect("Failed to set mapping");
             });
         }
+        self.attr_modified = true;
         Ok(())
     }
     /// Allocates a physically-contiguous region of 4KiB memory pages that has enough space to
@@ -126,6 +139,17 @@ impl ContiguousPhysicalMemoryPages {
         Self::alloc_pages(size_in_pages_from_bytes(num_bytes))
     }
 }
+impl Drop for ContiguousPhysicalMemoryPages {
+    fn drop(&mut self) {
+        if self.attr_modified {
+            // Make sure that the pages are not accessible from apps anymore
+            // before handing them back to the allocator.
+            self.set_page_attr(PageAttr::ReadWriteKernel)
+                .expect("Failed to restore the page attributes");
+        }
+        unsafe { ALLOCATOR.dealloc(self.phys_addr, self.layout) }
+    }
+}
 
 // TODO(hikalium): replace this with ContiguousPhysicalMemoryPages
 pub fn alloc_pages(num_pages: usize) -> Result<Pin<Box<[u8]>>> {
@@ -11,10 +11,16 @@
 //! is unique so taking a mutable reference
 //! to it will be safe.
 
+extern crate alloc;
+
 use crate::error::Error;
 use crate::error::Result;
+use crate::executor::yield_execution;
+use crate::warn;
+use crate::x86_64::busy_loop_hint;
 use core::cell::SyncUnsafeCell;
 use core::fmt::Debug;
+use core::future::Future;
 use core::ops::Deref;
 use core::ops::DerefMut;
 use core::panic::Location;
@@ -36,6 +42,12 @@ impl<'a, T> MutexGuard<'a, T> {
             location: *Location::caller(),
         }
     }
+    /// Releases the lock and marks the mutex as poisoned. Call this on a path that gives up in
+    /// the middle of modifying the data (e.g. before panicking), since panics do not unwind here
+    /// and the guard would never be dropped otherwise.
+    pub fn poison(self) {
+        self.mutex.is_poisoned.store(true, Ordering::SeqCst);
+    }
 }
 unsafe impl<'a, T> Sync for MutexGuard<'a, T> {}
 impl<'a, T> Deref for MutexGuard<'a, T> {
@@ -64,6 +76,7 @@ impl<'a, T> Debug for MutexGuard<'a, T> {
 pub struct Mutex<T> {
     data: SyncUnsafeCell<T>,
     is_taken: AtomicBool,
+    is_poisoned: AtomicBool,
     taker_line_num: AtomicU32,
     created_at_file: &'static str,
     created_at_line: u32,
@@ -78,11 +91,13 @@ impl<T: Sized> Debug for Mutex<T> {
     }
 }
 impl<T: Sized> Mutex<T> {
+    const LOCK_ASYNC_SPIN_COUNT: usize = 100;
     #[track_caller]
     pub const fn new(data: T) -> Self {
         Self {
             data: SyncUnsafeCell::new(data),
             is_taken: AtomicBool::new(false),
+            is_poisoned: AtomicBool::new(false),
             taker_line_num: AtomicU32::new(0),
             created_at_file: Location::caller().file(),
             created_at_line: Location::caller().line(),
@@ -90,13 +105,15 @@ impl<T: Sized> Mutex<T> {
     }
     #[track_caller]
     fn try_lock(&self) -> Result<MutexGuard<T>> {
+        self.try_lock_at(Location::caller())
+    }
+    fn try_lock_at(&self, caller: &Location) -> Result<MutexGuard<'_, T>> {
         if self
             .is_taken
             .compare_exchange(false, true, Ordering::SeqCst, Ordering::SeqCst)
             .is_ok()
         {
-            self.taker_line_num
-                .store(Location::caller().line(), Ordering::SeqCst);
+            self.taker_line_num.store(caller.line(), Ordering::SeqCst);
             Ok(unsafe { MutexGuard::new(self, &self.data) })
         } else {
             Err(Error::LockFailed)
@@ -106,6 +123,7 @@ impl<T: Sized> Mutex<T> {
     pub fn lock(&self) -> MutexGuard<T> {
         for _ in 0..10000 {
             if let Ok(locked) = self.try_lock() {
+                self.warn_if_poisoned(Location::caller());
                 return locked;
             }
         }
@@ -117,6 +135,42 @@ impl<T: Sized> Mutex<T> {
             self.taker_line_num.load(Ordering::SeqCst),
         )
     }
+    /// Spins for a while like lock() but yields to the executor between retries, so that a task
+    /// holding this lock across an .await can run and release it.
+    /// This is not an async fn so that the caller's location is recorded, not this file.
+    #[track_caller]
+    pub fn lock_async(&self) -> impl Future<Output = MutexGuard<'_, T>> {
+        let caller = Location::caller();
+        async move {
+            loop {
+                for _ in 0..Self::LOCK_ASYNC_SPIN_COUNT {
+                    if let Ok(locked) = self.try_lock_at(caller) {
+                        self.warn_if_poisoned(caller);
+                        return locked;
+                    }
+                    busy_loop_hint();
+                }
+                yield_execution().await;
+            }
+        }
+    }
+    fn warn_if_poisoned(&self, caller: &Location) {
+        if self.is_poisoned() {
+            warn!(
+                "Locked a poisoned Mutex at {}:{}, caller: {:?}",
+                self.created_at_file, self.created_at_line, caller,
+            );
+        }
+    }
+    /// Returns true if a guard of this mutex has been released with MutexGuard::poison(), which
+    /// means the data may be left in an inconsistent state.
+    pub fn is_poisoned(&self) -> bool {
+        self.is_poisoned.load(Ordering::SeqCst)
+    }
+    /// Marks the data as consistent again, e.g. after it has been repaired or reset.
+    pub fn clear_poison(&self) {
+        self.is_poisoned.store(false, Ordering::SeqCst)
+    }
     pub fn under_locked<R: Sized>(&self, f: &dyn Fn(&mut T) -> Result<R>) -> Result<R> {
         let mut locked = self.lock();
         f(&mut *locked)
@@ -129,3 +183,97 @@ impl<T: Default> Default for Mutex<T> {
         Self::new(T::default())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::executor::dummy_waker;
+    use crate::print::capture_output;
+    use alloc::boxed::Box;
+    use alloc::rc::Rc;
+    use core::future::Future;
+    use core::pin::Pin;
+    use core::task::Context;
+    use core::task::Poll;
+
+    #[test_case]
+    fn poisoned_mutex_is_reported_on_lock() {
+        let m = Mutex::new(0);
+        {
+            let mut v = m.lock();
+            *v = 1;
+            // Simulates a task giving up in the middle of an update
+            v.poison();
+        }
+        assert!(m.is_poisoned());
+        let output = capture_output(|| {
+            assert_eq!(*m.lock(), 1);
+        });
+        assert!(output.contains("Locked a poisoned Mutex"));
+        m.clear_poison();
+        assert!(!m.is_poisoned());
+        let output = capture_output(|| {
+            let _v = m.lock();
+        });
+        assert_eq!(output, "");
+    }
+
+    #[test_case]
+    fn dropped_guard_does_not_poison() {
+        let m = Mutex::new(0);
+        *m.lock() = 1;
+        assert!(!m.is_poisoned());
+        assert_eq!(*m.lock(), 1);
+    }
+
+    #[test_case]
+    fn lock_async_records_the_caller_location() {
+        let m = Mutex::new(0);
+        let (line, mut f) = (line!(), Box::pin(m.lock_async()));
+        let waker = dummy_waker();
+        let mut context = Context::from_waker(&waker);
+        let Poll::Ready(v) = f.as_mut().poll(&mut context) else {
+            panic!("lock_async should succeed on an unlocked Mutex");
+        };
+        assert_eq!(m.taker_line_num.load(Ordering::SeqCst), line);
+        drop(v);
+    }
+
+    #[test_case]
+    fn lock_async_acquires_after_the_holder_yields() {
+        let m = Rc::new(Mutex::new(0));
+        let holder = {
+            let m = m.clone();
+            async move {
+                let mut v = m.lock();
+                for _ in 0..3 {
+                    yield_execution().await;
+                }
+                *v = 1;
+            }
+        };
+        let waiter = {
+            let m = m.clone();
+            async move {
+                let mut v = m.lock_async().await;
+                assert_eq!(*v, 1);
+                *v = 2;
+            }
+        };
+        let mut tasks: [Option<Pin<Box<dyn Future<Output = ()>>>>; 2] =
+            [Some(Box::pin(holder)), Some(Box::pin(waiter))];
+        let waker = dummy_waker();
+        let mut context = Context::from_waker(&waker);
+        for _ in 0..100 {
+            for task in tasks.iter_mut() {
+                if let Some(f) = task {
+                    if f.as_mut().poll(&mut context) == Poll::Ready(()) {
+                        *task = None;
+                    }
+                }
+            }
+        }
+        assert!(tasks.iter().all(|t| t.is_none()));
+        assert_eq!(*m.lock(), 2);
+    }
+}
@@ -1,10 +1,12 @@
 pub mod arp;
+pub mod capture;
 pub mod checksum;
 pub mod dhcp;
 pub mod dns;
 pub mod eth;
 pub mod icmp;
 pub mod ip;
+pub mod loopback;
 pub mod manager;
 pub mod tcp;
 pub mod udp;
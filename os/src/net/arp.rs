@@ -26,6 +26,9 @@ pub struct ArpPacket {
 }
 const _: () = assert!(size_of::<ArpPacket>() == 42);
 impl ArpPacket {
+    pub fn is_request(&self) -> bool {
+        self.op == [0x00, 0x01]
+    }
     pub fn is_response(&self) -> bool {
         self.op == [0x00, 0x02]
     }
@@ -35,6 +38,9 @@ impl ArpPacket {
     pub fn sender_ip_addr(&self) -> IpV4Addr {
         self.sender_ip
     }
+    pub fn target_ip_addr(&self) -> IpV4Addr {
+        self.target_ip
+    }
     pub fn request(src_eth: EthernetAddr, src_ip: IpV4Addr, dst_ip: IpV4Addr) -> Self {
         Self {
             eth_header: EthernetHeader::new(
@@ -53,6 +59,19 @@ impl ArpPacket {
             target_ip: dst_ip,
         }
     }
+    pub fn response(
+        src_eth: EthernetAddr,
+        src_ip: IpV4Addr,
+        dst_eth: EthernetAddr,
+        dst_ip: IpV4Addr,
+    ) -> Self {
+        Self {
+            eth_header: EthernetHeader::new(dst_eth, src_eth, EthernetType::arp()),
+            op: [0x00, 0x02],
+            target_mac: dst_eth,
+            ..Self::request(src_eth, src_ip, dst_ip)
+        }
+    }
 }
 impl Debug for ArpPacket {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
@@ -0,0 +1,93 @@
+extern crate alloc;
+
+use alloc::boxed::Box;
+use alloc::collections::VecDeque;
+
+/// Number of frames kept in the capture ring of the network manager.
+pub const CAPTURE_RING_CAPACITY: usize = 32;
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum CaptureDirection {
+    Rx,
+    Tx,
+}
+
+#[derive(Clone, Debug)]
+pub struct CapturedFrame {
+    pub direction: CaptureDirection,
+    pub timestamp_ms: u64,
+    pub data: Box<[u8]>,
+}
+
+/// Keeps the last `capacity` frames. The oldest frame is dropped when a new frame comes in.
+pub struct CaptureRing {
+    capacity: usize,
+    frames: VecDeque<CapturedFrame>,
+}
+impl CaptureRing {
+    pub const fn new(capacity: usize) -> Self {
+        Self {
+            capacity,
+            frames: VecDeque::new(),
+        }
+    }
+    pub fn push(&mut self, direction: CaptureDirection, timestamp_ms: u64, data: &[u8]) {
+        if self.capacity == 0 {
+            return;
+        }
+        while self.frames.len() >= self.capacity {
+            self.frames.pop_front();
+        }
+        self.frames.push_back(CapturedFrame {
+            direction,
+            timestamp_ms,
+            data: data.into(),
+        });
+    }
+    pub fn frames(&self) -> impl Iterator<Item = &CapturedFrame> {
+        self.frames.iter()
+    }
+    pub fn len(&self) -> usize {
+        self.frames.len()
+    }
+    pub fn is_empty(&self) -> bool {
+        self.frames.is_empty()
+    }
+    pub fn clear(&mut self) {
+        self.frames.clear();
+    }
+}
+
+#[test_case]
+fn capture_ring_evicts_oldest_frames() {
+    let mut ring = CaptureRing::new(3);
+    assert!(ring.is_empty());
+    for i in 0..5u8 {
+        let direction = if i % 2 == 0 {
+            CaptureDirection::Rx
+        } else {
+            CaptureDirection::Tx
+        };
+        ring.push(direction, 100 + i as u64, &[i; 4]);
+    }
+    assert_eq!(ring.len(), 3);
+    let frames: alloc::vec::Vec<&CapturedFrame> = ring.frames().collect();
+    assert_eq!(frames[0].direction, CaptureDirection::Rx);
+    assert_eq!(frames[0].timestamp_ms, 102);
+    assert_eq!(*frames[0].data, [2; 4]);
+    assert_eq!(frames[1].direction, CaptureDirection::Tx);
+    assert_eq!(frames[1].timestamp_ms, 103);
+    assert_eq!(*frames[1].data, [3; 4]);
+    assert_eq!(frames[2].direction, CaptureDirection::Rx);
+    assert_eq!(frames[2].timestamp_ms, 104);
+    assert_eq!(*frames[2].data, [4; 4]);
+    ring.clear();
+    assert!(ring.is_empty());
+}
+
+#[test_case]
+fn capture_ring_with_zero_capacity_keeps_nothing() {
+    let mut ring = CaptureRing::new(0);
+    ring.push(CaptureDirection::Tx, 0, &[1, 2, 3]);
+    assert!(ring.is_empty());
+}
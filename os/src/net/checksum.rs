@@ -7,6 +7,22 @@ impl InternetChecksum {
         // https://tools.ietf.org/html/rfc1071
         InternetChecksumGenerator::new().feed(data).checksum()
     }
+    /// Returns the checksum adjusted for a 16-bit word in the data being changed from `old_word`
+    /// to `new_word`, without recomputing the whole checksum.
+    pub fn update(old_csum: u16, old_word: u16, new_word: u16) -> u16 {
+        // https://tools.ietf.org/html/rfc1624 (Eqn. 3)
+        let mut sum = (!old_csum as u32) + (!old_word as u32) + (new_word as u32);
+        while (sum >> 16) != 0 {
+            sum = (sum & 0xffff) + (sum >> 16);
+        }
+        !(sum as u16)
+    }
+    pub fn from_u16(value: u16) -> Self {
+        Self(value.to_be_bytes())
+    }
+    pub fn as_u16(&self) -> u16 {
+        u16::from_be_bytes(self.0)
+    }
 }
 
 // https://tools.ietf.org/html/rfc1071
@@ -18,6 +34,8 @@ impl InternetChecksumGenerator {
     pub fn new() -> Self {
         Self::default()
     }
+    /// Adds `data` as a sequence of big-endian 16-bit words. If `data` has an odd length, the last
+    /// byte is padded with a zero byte on its right as described in RFC 1071.
     pub fn feed(&mut self, data: &[u8]) -> &mut Self {
         let iter = data.chunks(2);
         for w in iter {
@@ -58,3 +76,79 @@ fn internet_checksum() {
         InternetChecksum([0x61, 0xb8])
     );
 }
+
+#[test_case]
+fn internet_checksum_known_vectors() {
+    // Even length: the example in RFC 1071 section 3
+    assert_eq!(
+        InternetChecksum::calc(&[0x00, 0x01, 0xf2, 0x03, 0xf4, 0xf5, 0xf6, 0xf7]),
+        InternetChecksum([0x22, 0x0d])
+    );
+    // Odd length: the last byte is treated as 0xf600
+    assert_eq!(
+        InternetChecksum::calc(&[0x00, 0x01, 0xf2, 0x03, 0xf4, 0xf5, 0xf6]),
+        InternetChecksum([0x23, 0x04])
+    );
+    assert_eq!(
+        InternetChecksum::calc(&[0xab]),
+        InternetChecksum([0x54, 0xff])
+    );
+    // All zeros
+    assert_eq!(
+        InternetChecksum::calc(&[0; 8]),
+        InternetChecksum([0xff, 0xff])
+    );
+    assert_eq!(
+        InternetChecksum::calc(&[0; 7]),
+        InternetChecksum([0xff, 0xff])
+    );
+    // 0xffff + 0xffff + 0x0001 = 0x1ffff needs two end-around carries
+    assert_eq!(
+        InternetChecksum::calc(&[0xff, 0xff, 0xff, 0xff, 0x00, 0x01]),
+        InternetChecksum([0xff, 0xfe])
+    );
+    assert_eq!(
+        InternetChecksum::calc(&[0xff; 64]),
+        InternetChecksum([0x00, 0x00])
+    );
+}
+
+#[cfg(test)]
+fn assert_update_matches_calc(data: &[u8], word_index: usize, new_word: u16) {
+    let old_csum = InternetChecksum::calc(data).as_u16();
+    let mut new_data = data.to_vec();
+    let old_word = u16::from_be_bytes([new_data[word_index * 2], new_data[word_index * 2 + 1]]);
+    new_data[word_index * 2..word_index * 2 + 2].copy_from_slice(&new_word.to_be_bytes());
+    assert_eq!(
+        InternetChecksum::update(old_csum, old_word, new_word),
+        InternetChecksum::calc(&new_data).as_u16()
+    );
+}
+
+#[test_case]
+fn internet_checksum_incremental_update() {
+    let header = [
+        0x45, 0x00, 0x00, 0x73, 0x00, 0x00, 0x40, 0x00, 0x40, 0x11, 0x00, 0x00, 0xc0, 0xa8, 0x00,
+        0x01, 0xc0, 0xa8, 0x00, 0xc7,
+    ];
+    assert_eq!(InternetChecksum::calc(&header).as_u16(), 0xb861);
+    for (word_index, new_word) in [
+        (6, 0x0a00),
+        (7, 0x020f),
+        (6, 0x0000),
+        (7, 0xffff),
+        (9, 0x1234),
+        (0, 0x4500),
+    ] {
+        assert_update_matches_calc(&header, word_index, new_word);
+    }
+    // A change that makes the folded sum wrap around to 0xffff
+    assert_update_matches_calc(&[0x12, 0x34, 0x00, 0x00], 1, 0xffff - 0x1234);
+    assert_update_matches_calc(&[0xff, 0xff, 0xff, 0xff], 0, 0x0001);
+}
+
+#[test_case]
+fn internet_checksum_incremental_update_end_around_carry() {
+    // https://tools.ietf.org/html/rfc1624 section 4: Eqn. 3 gives 0x0000, not 0xffff
+    assert_eq!(InternetChecksum::update(0xdd2f, 0x5555, 0x3285), 0x0000);
+}
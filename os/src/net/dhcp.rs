@@ -114,6 +114,35 @@ impl DhcpPacket {
         ));
         Ok(this)
     }
+    /// Builds a BOOTREPLY which assigns `yiaddr` to the client. `options_len` bytes of options
+    /// are expected to follow the packet.
+    pub fn reply(
+        server_eth_addr: EthernetAddr,
+        server_ip: IpV4Addr,
+        client_eth_addr: EthernetAddr,
+        yiaddr: IpV4Addr,
+        options_len: usize,
+    ) -> Result<Self> {
+        let mut this = Self::default();
+        let eth = EthernetHeader::new(client_eth_addr, server_eth_addr, EthernetType::ip_v4());
+        let data_length = size_of::<Self>() - size_of::<IpV4Packet>() + options_len;
+        this.udp.ip = IpV4Packet::new(eth, yiaddr, server_ip, IpV4Protocol::udp(), data_length);
+        this.udp.set_src_port(UDP_PORT_DHCP_SERVER);
+        this.udp.set_dst_port(UDP_PORT_DHCP_CLIENT);
+        this.udp.set_data_size(data_length)?;
+        this.op = DHCP_OP_BOOTREPLY;
+        this.htype = 1;
+        this.hlen = 6;
+        this.xid = 0x1234;
+        this.yiaddr = yiaddr;
+        this.siaddr = server_ip;
+        this.chaddr = client_eth_addr;
+        this.cookie = [99, 130, 83, 99];
+        this.udp.ip.set_checksum(InternetChecksum::calc(
+            &this.udp.as_slice()[size_of::<EthernetHeader>()..size_of::<IpV4Packet>()],
+        ));
+        Ok(this)
+    }
 }
 impl Default for DhcpPacket {
     fn default() -> Self {
@@ -186,11 +186,15 @@ pub async fn query_dns(query: &str) -> Result<Vec<DnsResponseEntry>> {
                 yield_execution().await;
                 let res = PENDING_QUERIES.lock().get(&transaction_id).cloned();
                 if let Some(Some(res)) = res {
-                    return res;
+                    return if res.is_empty() {
+                        Err(Error::DnsNotFound)
+                    } else {
+                        Ok(res)
+                    };
                 }
             }
         },
         500,
     )
-    .await
+    .await?
 }
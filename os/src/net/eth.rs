@@ -1,9 +1,14 @@
 extern crate alloc;
 
+use crate::error::Error;
+use crate::error::Result;
+use alloc::boxed::Box;
 use alloc::fmt;
 use alloc::fmt::Debug;
 use alloc::fmt::Display;
+use alloc::vec::Vec;
 use core::mem::size_of;
+use core::str::FromStr;
 use noli::mem::Sliceable;
 
 #[repr(packed)]
@@ -53,6 +58,34 @@ impl EthernetAddr {
             mac: [0x00, 0x00, 0x00, 0x00, 0x00, 0x00],
         }
     }
+    pub fn is_broadcast(&self) -> bool {
+        *self == Self::broardcast()
+    }
+    /// Returns true if the I/G bit (the lowest bit of the first octet) is set.
+    /// Note that the broadcast address is also a multicast address.
+    pub fn is_multicast(&self) -> bool {
+        self.mac[0] & 1 == 1
+    }
+}
+impl FromStr for EthernetAddr {
+    type Err = Error;
+    /// Parses "aa:bb:cc:dd:ee:ff" or "aa-bb-cc-dd-ee-ff".
+    fn from_str(s: &str) -> Result<Self> {
+        const REASON: Error = Error::Failed("Invalid Ethernet address format");
+        let sep = if s.contains(':') { ':' } else { '-' };
+        let octets = s
+            .split(sep)
+            .map(|s| {
+                if s.len() == 2 && s.bytes().all(|c| c.is_ascii_hexdigit()) {
+                    u8::from_str_radix(s, 16).or(Err(REASON))
+                } else {
+                    Err(REASON)
+                }
+            })
+            .collect::<Result<Vec<u8>>>()?;
+        let mac: [u8; 6] = octets.try_into().or(Err(REASON))?;
+        Ok(Self { mac })
+    }
 }
 impl Debug for EthernetAddr {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
@@ -81,8 +114,82 @@ impl EthernetHeader {
     pub fn new(dst: EthernetAddr, src: EthernetAddr, eth_type: EthernetType) -> Self {
         Self { dst, src, eth_type }
     }
+    pub fn src(&self) -> EthernetAddr {
+        self.src
+    }
     pub fn eth_type(&self) -> EthernetType {
         self.eth_type
     }
 }
 unsafe impl Sliceable for EthernetHeader {}
+
+/// Minimum size of an Ethernet frame excluding FCS (46 bytes of payload + 14 bytes of header).
+pub const ETHERNET_MIN_FRAME_SIZE: usize = 60;
+
+/// Pads `frame` with zeros up to ETHERNET_MIN_FRAME_SIZE. Frames that are long enough are returned
+/// as is.
+pub fn pad_to_min_frame_size(frame: Box<[u8]>) -> Box<[u8]> {
+    if frame.len() >= ETHERNET_MIN_FRAME_SIZE {
+        return frame;
+    }
+    let mut frame = Vec::from(frame);
+    frame.resize(ETHERNET_MIN_FRAME_SIZE, 0);
+    frame.into_boxed_slice()
+}
+
+#[test_case]
+fn short_frames_are_padded() {
+    let frame: Box<[u8]> = [0xAB; size_of::<EthernetHeader>() + 20].into();
+    let frame = pad_to_min_frame_size(frame);
+    assert_eq!(frame.len(), ETHERNET_MIN_FRAME_SIZE);
+    assert!(frame[..34].iter().all(|b| *b == 0xAB));
+    assert!(frame[34..].iter().all(|b| *b == 0));
+}
+
+#[test_case]
+fn long_frames_are_untouched() {
+    let frame: Box<[u8]> = (0..100).collect();
+    let padded = pad_to_min_frame_size(frame.clone());
+    assert_eq!(padded, frame);
+    let frame: Box<[u8]> = [0xAB; ETHERNET_MIN_FRAME_SIZE].into();
+    assert_eq!(pad_to_min_frame_size(frame.clone()), frame);
+}
+
+#[test_case]
+fn parse_ethernet_addr() {
+    let expected = EthernetAddr::new([0xaa, 0xbb, 0xcc, 0x0d, 0xee, 0xff]);
+    assert_eq!(EthernetAddr::from_str("aa:bb:cc:0d:ee:ff"), Ok(expected));
+    assert_eq!(EthernetAddr::from_str("AA-BB-CC-0D-EE-FF"), Ok(expected));
+    assert_eq!(
+        alloc::format!("{}", EthernetAddr::from_str("52:54:00:12:34:56").unwrap()),
+        "52:54:00:12:34:56"
+    );
+    for s in [
+        "",
+        "aa:bb:cc:dd:ee",
+        "aa:bb:cc:dd:ee:ff:00",
+        "aa:bb:cc:dd:ee:fg",
+        "aa:bb:cc:d:ee:ff",
+        "aa:bb:cc:ddd:ee:ff",
+        "aa:bb-cc:dd:ee:ff",
+        "aa:bb:cc:dd:ee:+f",
+    ] {
+        assert!(
+            EthernetAddr::from_str(s).is_err(),
+            "{s:?} should be rejected"
+        );
+    }
+}
+
+#[test_case]
+fn ethernet_addr_predicates() {
+    assert!(EthernetAddr::broardcast().is_broadcast());
+    assert!(EthernetAddr::broardcast().is_multicast());
+    let multicast = EthernetAddr::new([0x01, 0x00, 0x5e, 0x00, 0x00, 0xfb]);
+    assert!(!multicast.is_broadcast());
+    assert!(multicast.is_multicast());
+    let unicast = EthernetAddr::new([0x52, 0x54, 0x00, 0x12, 0x34, 0x56]);
+    assert!(!unicast.is_broadcast());
+    assert!(!unicast.is_multicast());
+    assert!(!EthernetAddr::zero().is_multicast());
+}
@@ -52,6 +52,34 @@ impl IcmpPacket {
         this.csum = InternetChecksum::calc(&this.as_slice()[size_of::<IpV4Packet>()..]);
         this
     }
+    pub fn is_request(&self) -> bool {
+        self.icmp_type == IcmpType::request()
+    }
+    pub fn icmp_type(&self) -> IcmpType {
+        self.icmp_type
+    }
+    /// Builds an echo reply to this echo request, sent with the given Ethernet header.
+    pub fn reply(&self, eth: EthernetHeader) -> Self {
+        let mut ip = IpV4Packet::new(
+            eth,
+            self.ip.src(),
+            self.ip.dst(),
+            IpV4Protocol::icmp(),
+            size_of::<Self>() - size_of::<IpV4Packet>(),
+        );
+        ip.set_checksum(InternetChecksum::calc(
+            &ip.as_slice()[size_of::<EthernetHeader>()..],
+        ));
+        let mut this = Self {
+            ip,
+            icmp_type: IcmpType::reply(),
+            identifier: self.identifier,
+            sequence: self.sequence,
+            ..Default::default()
+        };
+        this.csum = InternetChecksum::calc(&this.as_slice()[size_of::<IpV4Packet>()..]);
+        this
+    }
 }
 impl Debug for IcmpPacket {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
@@ -1,8 +1,11 @@
 extern crate alloc;
 
+use crate::error::Error;
+use crate::error::Result;
 use crate::net::checksum::InternetChecksum;
 use crate::net::eth::EthernetHeader;
 use alloc::fmt::Debug;
+use alloc::vec::Vec;
 use core::mem::size_of;
 use noli::mem::Sliceable;
 use noli::net::IpV4Addr;
@@ -22,6 +25,9 @@ impl IpV4Protocol {
     }
 }
 
+/// TTL for the packets originated from this host.
+pub const DEFAULT_TTL: u8 = 64;
+
 #[repr(packed)]
 #[allow(unused)]
 #[derive(Copy, Clone, Default)]
@@ -56,7 +62,7 @@ impl IpV4Packet {
         let mut this = Self {
             eth,
             version_and_ihl: 0x45, // IPv4, header len = 5 * sizeof(uint32_t) = 20 bytes
-            ttl: 0xff,
+            ttl: DEFAULT_TTL,
             protocol,
             src,
             dst,
@@ -74,6 +80,31 @@ impl IpV4Packet {
     pub fn protocol(&self) -> IpV4Protocol {
         self.protocol
     }
+    pub fn ttl(&self) -> u8 {
+        self.ttl
+    }
+    /// Sets TTL. The header checksum is updated as well if it has been computed.
+    pub fn set_ttl(&mut self, ttl: u8) {
+        let old_word = u16::from_be_bytes([self.ttl, self.protocol.0]);
+        self.ttl = ttl;
+        if self.csum != InternetChecksum::default() {
+            let new_word = u16::from_be_bytes([self.ttl, self.protocol.0]);
+            self.csum = InternetChecksum::from_u16(InternetChecksum::update(
+                self.csum.as_u16(),
+                old_word,
+                new_word,
+            ));
+        }
+    }
+    /// Decrements TTL for forwarding the packet. Returns an error if the packet should be dropped
+    /// since TTL reaches zero, leaving the packet untouched.
+    pub fn decrement_ttl(&mut self) -> Result<()> {
+        if self.ttl <= 1 {
+            return Err(Error::Failed("IpV4Packet: TTL exceeded"));
+        }
+        self.set_ttl(self.ttl - 1);
+        Ok(())
+    }
     pub fn data_length(&self) -> usize {
         self.total_size() - (size_of::<Self>() - size_of::<EthernetHeader>())
     }
@@ -85,6 +116,53 @@ impl IpV4Packet {
     pub fn total_size(&self) -> usize {
         u16::from_be_bytes(self.length) as usize
     }
+    /// Size of the IPv4 header in bytes, computed from the IHL field (including options).
+    pub fn header_len(&self) -> usize {
+        (self.version_and_ihl & 0x0f) as usize * 4
+    }
+    /// Returns the offset of the IPv4 payload in `packet`, which starts with an Ethernet header.
+    pub fn payload_offset(packet: &[u8]) -> Result<usize> {
+        let header_len = Self::from_slice(packet)?.header_len();
+        if header_len < size_of::<Self>() - size_of::<EthernetHeader>() {
+            return Err(Error::Failed("IpV4Packet: IHL is too small"));
+        }
+        let offset = size_of::<EthernetHeader>() + header_len;
+        if offset > packet.len() {
+            return Err(Error::Failed("IpV4Packet: IHL exceeds the packet length"));
+        }
+        Ok(offset)
+    }
+    /// Returns a copy of `packet` without IPv4 options, so that the payload starts right after
+    /// the fixed 20-byte header.
+    pub fn strip_options(packet: &[u8]) -> Result<Vec<u8>> {
+        let payload_offset = Self::payload_offset(packet)?;
+        let options_len = payload_offset - size_of::<Self>();
+        if options_len == 0 {
+            return Ok(Vec::from(packet));
+        }
+        let mut header = *Self::from_slice(packet)?;
+        header.version_and_ihl = (header.version_and_ihl & 0xf0) | 5;
+        header.length = ((header.total_size().saturating_sub(options_len)) as u16).to_be_bytes();
+        header.clear_checksum();
+        let csum = InternetChecksum::calc(&header.as_slice()[size_of::<EthernetHeader>()..]);
+        header.set_checksum(csum);
+        let mut stripped = Vec::from(header.as_slice());
+        stripped.extend_from_slice(&packet[payload_offset..]);
+        Ok(stripped)
+    }
+    /// Verifies the header checksum of `packet`, which starts with an Ethernet header.
+    pub fn verify_checksum(packet: &[u8]) -> Result<()> {
+        let payload_offset = Self::payload_offset(packet)?;
+        if InternetChecksum::calc(&packet[size_of::<EthernetHeader>()..payload_offset])
+            != InternetChecksum::default()
+        {
+            return Err(Error::ChecksumMismatch);
+        }
+        Ok(())
+    }
+    pub fn checksum(&self) -> InternetChecksum {
+        self.csum
+    }
     pub fn clear_checksum(&mut self) {
         self.csum = InternetChecksum::default();
     }
@@ -93,3 +171,119 @@ impl IpV4Packet {
     }
 }
 unsafe impl Sliceable for IpV4Packet {}
+
+#[cfg(test)]
+fn ip_v4_packet_bytes(ihl: u8, payload: &[u8]) -> Vec<u8> {
+    let mut packet = Vec::from(EthernetHeader::default().as_slice());
+    let header_len = ihl as usize * 4;
+    let mut header = alloc::vec![0u8; header_len.max(20)];
+    header[0] = 0x40 | ihl;
+    header[2..4].copy_from_slice(&((header_len + payload.len()) as u16).to_be_bytes());
+    header[9] = IpV4Protocol::udp().0;
+    header[12..16].copy_from_slice(&[10, 0, 2, 15]);
+    header[16..20].copy_from_slice(&[10, 0, 2, 2]);
+    for (i, b) in header.iter_mut().enumerate().skip(20) {
+        // Options
+        *b = 0xA0 + i as u8;
+    }
+    packet.extend_from_slice(&header);
+    packet.extend_from_slice(payload);
+    packet
+}
+
+#[test_case]
+fn ip_v4_payload_offset() {
+    let packet = ip_v4_packet_bytes(5, &[1, 2, 3, 4]);
+    assert_eq!(IpV4Packet::from_slice(&packet).unwrap().header_len(), 20);
+    assert_eq!(IpV4Packet::payload_offset(&packet), Ok(34));
+    assert_eq!(IpV4Packet::strip_options(&packet), Ok(packet.clone()));
+
+    let packet = ip_v4_packet_bytes(6, &[1, 2, 3, 4]);
+    assert_eq!(IpV4Packet::from_slice(&packet).unwrap().header_len(), 24);
+    assert_eq!(IpV4Packet::payload_offset(&packet), Ok(38));
+    assert_eq!(&packet[38..], &[1, 2, 3, 4]);
+}
+
+#[test_case]
+fn ip_v4_strip_options() {
+    let packet = ip_v4_packet_bytes(6, &[1, 2, 3, 4]);
+    let stripped = IpV4Packet::strip_options(&packet).unwrap();
+    assert_eq!(stripped.len(), packet.len() - 4);
+    let ip = IpV4Packet::from_slice(&stripped).unwrap();
+    assert_eq!(ip.header_len(), 20);
+    assert_eq!(ip.total_size(), 24);
+    assert_eq!(ip.src(), IpV4Addr::new([10, 0, 2, 15]));
+    assert_eq!(ip.dst(), IpV4Addr::new([10, 0, 2, 2]));
+    assert_eq!(ip.protocol(), IpV4Protocol::udp());
+    assert_eq!(&stripped[34..], &[1, 2, 3, 4]);
+    // The header checksum should be valid
+    assert_eq!(
+        InternetChecksum::calc(&stripped[14..34]),
+        InternetChecksum::default()
+    );
+}
+
+#[test_case]
+fn ip_v4_invalid_ihl() {
+    let mut packet = ip_v4_packet_bytes(5, &[1, 2, 3, 4]);
+    packet[14] = 0x44;
+    assert!(IpV4Packet::payload_offset(&packet).is_err());
+    // IHL = 15 (60 bytes) exceeds the packet length
+    packet[14] = 0x4f;
+    assert!(IpV4Packet::payload_offset(&packet).is_err());
+    assert!(IpV4Packet::strip_options(&packet).is_err());
+}
+
+#[test_case]
+fn ip_v4_decrement_ttl() {
+    let mut packet = ip_v4_packet_bytes(5, &[1, 2, 3, 4]);
+    packet[22] = DEFAULT_TTL;
+    let csum = InternetChecksum::calc(&packet[14..34]);
+    let ip = IpV4Packet::from_slice_mut(&mut packet).unwrap();
+    ip.set_checksum(csum);
+    ip.decrement_ttl().unwrap();
+    assert_eq!(ip.ttl(), DEFAULT_TTL - 1);
+    // The header checksum should be still valid
+    assert_eq!(
+        InternetChecksum::calc(&packet[14..34]),
+        InternetChecksum::default()
+    );
+}
+
+#[test_case]
+fn ip_v4_decrement_ttl_drops_expired_packets() {
+    let mut packet = ip_v4_packet_bytes(5, &[1, 2, 3, 4]);
+    packet[22] = 1;
+    let csum = InternetChecksum::calc(&packet[14..34]);
+    let ip = IpV4Packet::from_slice_mut(&mut packet).unwrap();
+    ip.set_checksum(csum);
+    assert!(ip.decrement_ttl().is_err());
+    assert_eq!(ip.ttl(), 1);
+    assert_eq!(ip.checksum(), csum);
+    ip.set_ttl(0);
+    assert!(ip.decrement_ttl().is_err());
+    assert_eq!(ip.ttl(), 0);
+}
+
+#[test_case]
+fn ip_v4_verify_checksum() {
+    let mut packet = ip_v4_packet_bytes(6, &[1, 2, 3, 4]);
+    assert_eq!(
+        IpV4Packet::verify_checksum(&packet),
+        Err(Error::ChecksumMismatch)
+    );
+    let csum = InternetChecksum::calc(&packet[14..38]);
+    IpV4Packet::from_slice_mut(&mut packet)
+        .unwrap()
+        .set_checksum(csum);
+    assert_eq!(IpV4Packet::verify_checksum(&packet), Ok(()));
+    // Options are covered by the checksum, but the payload is not
+    packet[36] ^= 0xff;
+    assert_eq!(
+        IpV4Packet::verify_checksum(&packet),
+        Err(Error::ChecksumMismatch)
+    );
+    packet[36] ^= 0xff;
+    packet[38] ^= 0xff;
+    assert_eq!(IpV4Packet::verify_checksum(&packet), Ok(()));
+}
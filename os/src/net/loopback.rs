@@ -0,0 +1,298 @@
+extern crate alloc;
+
+use crate::error::Error;
+use crate::error::Result;
+use crate::executor::TimeoutFuture;
+use crate::mutex::Mutex;
+use crate::net::arp::ArpPacket;
+use crate::net::dhcp::DhcpPacket;
+use crate::net::dhcp::DHCP_OPT_DNS;
+use crate::net::dhcp::DHCP_OPT_MESSAGE_TYPE;
+use crate::net::dhcp::DHCP_OPT_MESSAGE_TYPE_ACK;
+use crate::net::dhcp::DHCP_OPT_MESSAGE_TYPE_END;
+use crate::net::dhcp::DHCP_OPT_NETMASK;
+use crate::net::dhcp::DHCP_OPT_ROUTER;
+use crate::net::eth::pad_to_min_frame_size;
+use crate::net::eth::EthernetAddr;
+use crate::net::eth::EthernetHeader;
+use crate::net::eth::EthernetType;
+use crate::net::icmp::IcmpPacket;
+use crate::net::ip::IpV4Packet;
+use crate::net::ip::IpV4Protocol;
+use crate::net::manager::NetworkInterface;
+use crate::net::udp::UdpPacket;
+use crate::net::udp::UDP_PORT_DHCP_SERVER;
+use crate::warn;
+use alloc::boxed::Box;
+use alloc::collections::VecDeque;
+use alloc::rc::Rc;
+use alloc::vec::Vec;
+use noli::mem::Sliceable;
+use noli::net::IpV4Addr;
+
+/// A NetworkInterface that receives the frames sent from it, for testing the network stack
+/// without hardware.
+pub struct LoopbackInterface {
+    eth_addr: EthernetAddr,
+    packets: Mutex<VecDeque<Box<[u8]>>>,
+}
+impl LoopbackInterface {
+    pub fn new(eth_addr: EthernetAddr) -> Self {
+        Self {
+            eth_addr,
+            packets: Mutex::new(VecDeque::new()),
+        }
+    }
+}
+impl NetworkInterface for LoopbackInterface {
+    fn name(&self) -> &str {
+        "Loopback"
+    }
+    fn ethernet_addr(&self) -> EthernetAddr {
+        self.eth_addr
+    }
+    fn push_packet(&self, packet: Box<[u8]>) -> Result<()> {
+        self.packets.lock().push_back(packet);
+        Ok(())
+    }
+    fn pop_packet(&self) -> Result<Box<[u8]>> {
+        self.packets
+            .lock()
+            .pop_front()
+            .ok_or(Error::Failed("No packets"))
+    }
+}
+
+// Addresses used by the mock server, which mimic the ones of the QEMU user network.
+pub fn mock_client_eth_addr() -> EthernetAddr {
+    EthernetAddr::new([0x52, 0x54, 0x00, 0x12, 0x34, 0x56])
+}
+pub fn mock_server_eth_addr() -> EthernetAddr {
+    EthernetAddr::new([0x52, 0x55, 0x0a, 0x00, 0x02, 0x02])
+}
+pub fn mock_client_ip() -> IpV4Addr {
+    IpV4Addr::new([10, 0, 2, 15])
+}
+pub fn mock_router_ip() -> IpV4Addr {
+    IpV4Addr::new([10, 0, 2, 2])
+}
+pub fn mock_dns_ip() -> IpV4Addr {
+    IpV4Addr::new([10, 0, 2, 3])
+}
+pub fn mock_netmask() -> IpV4Addr {
+    IpV4Addr::new([255, 255, 255, 0])
+}
+
+const MOCK_SERVER_POLL_MS: u64 = 10;
+
+/// Returns the frame that the mock server sends back for `frame`, if any. The mock server
+/// answers DHCP requests, ARP requests for any address other than the client's one, and ICMP
+/// echo requests to any address.
+pub fn mock_server_reply(frame: &[u8]) -> Result<Option<Box<[u8]>>> {
+    let eth = EthernetHeader::from_slice(frame)?;
+    let reply = match eth.eth_type() {
+        e if e == EthernetType::arp() => {
+            let arp = ArpPacket::from_slice(frame)?;
+            if !arp.is_request() || arp.target_ip_addr() == mock_client_ip() {
+                return Ok(None);
+            }
+            ArpPacket::response(
+                mock_server_eth_addr(),
+                arp.target_ip_addr(),
+                arp.sender_eth_addr(),
+                arp.sender_ip_addr(),
+            )
+            .copy_into_slice()
+        }
+        e if e == EthernetType::ip_v4() => match IpV4Packet::from_slice(frame)?.protocol() {
+            p if p == IpV4Protocol::udp()
+                && UdpPacket::from_slice(frame)?.dst_port() == UDP_PORT_DHCP_SERVER =>
+            {
+                mock_dhcp_reply(eth.src())?
+            }
+            p if p == IpV4Protocol::icmp() => {
+                let icmp = IcmpPacket::from_slice(frame)?;
+                if !icmp.is_request() {
+                    return Ok(None);
+                }
+                icmp.reply(EthernetHeader::new(
+                    eth.src(),
+                    mock_server_eth_addr(),
+                    EthernetType::ip_v4(),
+                ))
+                .copy_into_slice()
+            }
+            _ => return Ok(None),
+        },
+        _ => return Ok(None),
+    };
+    Ok(Some(pad_to_min_frame_size(reply)))
+}
+
+fn mock_dhcp_reply(client_eth_addr: EthernetAddr) -> Result<Box<[u8]>> {
+    let mut options = Vec::new();
+    options.extend([DHCP_OPT_MESSAGE_TYPE, 1, DHCP_OPT_MESSAGE_TYPE_ACK]);
+    options.extend([DHCP_OPT_NETMASK, 4]);
+    options.extend(mock_netmask().bytes());
+    options.extend([DHCP_OPT_ROUTER, 4]);
+    options.extend(mock_router_ip().bytes());
+    options.extend([DHCP_OPT_DNS, 4]);
+    options.extend(mock_dns_ip().bytes());
+    options.push(DHCP_OPT_MESSAGE_TYPE_END);
+    let dhcp = DhcpPacket::reply(
+        mock_server_eth_addr(),
+        mock_router_ip(),
+        client_eth_addr,
+        mock_client_ip(),
+        options.len(),
+    )?;
+    let mut reply = Vec::from(dhcp.as_slice());
+    reply.extend(options);
+    Ok(reply.into())
+}
+
+/// A NetworkInterface connected to a mock server instead of a wire, for bringing up the network
+/// stack without hardware. The frames sent from the interface are answered by
+/// run_mock_server().
+pub struct MockServerInterface {
+    eth_addr: EthernetAddr,
+    to_server: Mutex<VecDeque<Box<[u8]>>>,
+    to_client: Mutex<VecDeque<Box<[u8]>>>,
+}
+impl MockServerInterface {
+    pub fn new(eth_addr: EthernetAddr) -> Self {
+        Self {
+            eth_addr,
+            to_server: Mutex::new(VecDeque::new()),
+            to_client: Mutex::new(VecDeque::new()),
+        }
+    }
+    /// Answers the frames sent from the interface so far. Returns the number of frames handled.
+    pub fn process_frames(&self) -> usize {
+        let mut count = 0;
+        loop {
+            let Some(frame) = self.to_server.lock().pop_front() else {
+                return count;
+            };
+            count += 1;
+            match mock_server_reply(&frame) {
+                Ok(Some(reply)) => self.to_client.lock().push_back(reply),
+                Ok(None) => {}
+                Err(e) => warn!("mock server: dropping a malformed frame: {e:?}"),
+            }
+        }
+    }
+}
+impl NetworkInterface for MockServerInterface {
+    fn name(&self) -> &str {
+        "MockServer"
+    }
+    fn ethernet_addr(&self) -> EthernetAddr {
+        self.eth_addr
+    }
+    fn push_packet(&self, packet: Box<[u8]>) -> Result<()> {
+        self.to_server.lock().push_back(packet);
+        Ok(())
+    }
+    fn pop_packet(&self) -> Result<Box<[u8]>> {
+        self.to_client
+            .lock()
+            .pop_front()
+            .ok_or(Error::Failed("No packets"))
+    }
+}
+
+/// Runs the mock server for `iface` forever. The interface is kept alive while this runs.
+pub async fn run_mock_server(iface: Rc<MockServerInterface>) -> Result<()> {
+    loop {
+        iface.process_frames();
+        TimeoutFuture::new_ms(MOCK_SERVER_POLL_MS).await;
+    }
+}
+
+#[test_case]
+fn loopback_returns_pushed_frames() {
+    let iface = LoopbackInterface::new(EthernetAddr::new([0x02, 0, 0, 0, 0, 1]));
+    assert!(iface.pop_packet().is_err());
+    iface.push_packet([1, 2, 3].into()).unwrap();
+    iface.push_packet([4, 5].into()).unwrap();
+    assert_eq!(iface.pop_packet(), Ok([1, 2, 3].into()));
+    assert_eq!(iface.pop_packet(), Ok([4, 5].into()));
+    assert!(iface.pop_packet().is_err());
+}
+
+#[test_case]
+fn loopback_interfaces_are_isolated() {
+    let iface0 = LoopbackInterface::new(EthernetAddr::new([0x02, 0, 0, 0, 0, 1]));
+    let iface1 = LoopbackInterface::new(EthernetAddr::new([0x02, 0, 0, 0, 0, 2]));
+    iface0.push_packet([0xAA; 60].into()).unwrap();
+    assert!(iface1.pop_packet().is_err());
+    iface1.push_packet([0xBB; 60].into()).unwrap();
+    assert_eq!(iface0.pop_packet(), Ok([0xAA; 60].into()));
+    assert_eq!(iface1.pop_packet(), Ok([0xBB; 60].into()));
+}
+
+#[test_case]
+fn mock_server_answers_arp_requests() {
+    let req = ArpPacket::request(mock_client_eth_addr(), mock_client_ip(), mock_router_ip());
+    let reply = mock_server_reply(req.as_slice()).unwrap().unwrap();
+    let arp = ArpPacket::from_slice(&reply).unwrap();
+    assert!(arp.is_response());
+    assert_eq!(arp.sender_ip_addr(), mock_router_ip());
+    assert_eq!(arp.sender_eth_addr(), mock_server_eth_addr());
+    assert_eq!(arp.target_ip_addr(), mock_client_ip());
+    // The client's own address must not be claimed by the server
+    let req = ArpPacket::request(mock_client_eth_addr(), mock_client_ip(), mock_client_ip());
+    assert_eq!(mock_server_reply(req.as_slice()), Ok(None));
+    // Responses are not answered
+    assert_eq!(mock_server_reply(arp.as_slice()), Ok(None));
+}
+
+#[test_case]
+fn mock_server_answers_dhcp_requests() {
+    let req = DhcpPacket::request(mock_client_eth_addr()).unwrap();
+    let reply = mock_server_reply(req.as_slice()).unwrap().unwrap();
+    assert_eq!(IpV4Packet::verify_checksum(&reply), Ok(()));
+    let dhcp = DhcpPacket::from_slice(&reply).unwrap();
+    assert!(dhcp.is_boot_reply());
+    assert_eq!(dhcp.yiaddr(), mock_client_ip());
+    assert_eq!(dhcp.chaddr(), mock_client_eth_addr());
+    let options = &reply[core::mem::size_of::<DhcpPacket>()..];
+    assert_eq!(
+        options[..3],
+        [DHCP_OPT_MESSAGE_TYPE, 1, DHCP_OPT_MESSAGE_TYPE_ACK]
+    );
+    assert_eq!(options[3..9], [DHCP_OPT_NETMASK, 4, 255, 255, 255, 0]);
+    assert_eq!(options[9..15], [DHCP_OPT_ROUTER, 4, 10, 0, 2, 2]);
+}
+
+#[test_case]
+fn mock_server_answers_icmp_echo_requests() {
+    let mut req = IcmpPacket::new_request(IpV4Addr::new([8, 8, 8, 8])).copy_into_slice();
+    IpV4Packet::from_slice_mut(&mut req)
+        .unwrap()
+        .set_src(mock_client_ip());
+    let reply = mock_server_reply(&req).unwrap().unwrap();
+    assert_eq!(IpV4Packet::verify_checksum(&reply), Ok(()));
+    let ip = IpV4Packet::from_slice(&reply).unwrap();
+    assert_eq!(ip.src(), IpV4Addr::new([8, 8, 8, 8]));
+    assert_eq!(ip.dst(), mock_client_ip());
+    let icmp = IcmpPacket::from_slice(&reply).unwrap();
+    assert!(!icmp.is_request());
+    // Replies are not answered
+    assert_eq!(mock_server_reply(&reply), Ok(None));
+}
+
+#[test_case]
+fn mock_server_interface_delivers_replies() {
+    let iface = MockServerInterface::new(mock_client_eth_addr());
+    let req = DhcpPacket::request(mock_client_eth_addr()).unwrap();
+    iface.push_packet(req.copy_into_slice()).unwrap();
+    // Nothing is delivered until the server handles the request
+    assert!(iface.pop_packet().is_err());
+    assert_eq!(iface.process_frames(), 1);
+    let reply = iface.pop_packet().unwrap();
+    assert!(DhcpPacket::from_slice(&reply).unwrap().is_boot_reply());
+    assert!(iface.pop_packet().is_err());
+    assert_eq!(iface.process_frames(), 0);
+}
@@ -1,14 +1,22 @@
 extern crate alloc;
 
+use crate::error::Context;
 use crate::error::Error;
 use crate::error::Result;
+#[cfg(test)]
+use crate::executor::block_on;
 use crate::executor::spawn_global;
+use crate::executor::with_timeout_ms;
 use crate::executor::yield_execution;
 use crate::executor::TimeoutFuture;
+use crate::hpet::Hpet;
 use crate::info;
 use crate::mutex::Mutex;
-use crate::mutex::MutexGuard;
 use crate::net::arp::ArpPacket;
+use crate::net::capture::CaptureDirection;
+use crate::net::capture::CaptureRing;
+use crate::net::capture::CapturedFrame;
+use crate::net::capture::CAPTURE_RING_CAPACITY;
 use crate::net::checksum::InternetChecksum;
 use crate::net::dhcp::DhcpPacket;
 use crate::net::dhcp::DHCP_OPT_DNS;
@@ -22,12 +30,16 @@ use crate::net::dhcp::DHCP_OPT_NETMASK;
 use crate::net::dhcp::DHCP_OPT_ROUTER;
 use crate::net::dns::parse_dns_response;
 use crate::net::dns::PORT_DNS_SERVER;
+use crate::net::eth::pad_to_min_frame_size;
 use crate::net::eth::EthernetAddr;
 use crate::net::eth::EthernetHeader;
 use crate::net::eth::EthernetType;
 use crate::net::icmp::IcmpPacket;
 use crate::net::ip::IpV4Packet;
 use crate::net::ip::IpV4Protocol;
+use crate::net::ip::DEFAULT_TTL;
+#[cfg(test)]
+use crate::net::loopback::LoopbackInterface;
 use crate::net::tcp::TcpPacket;
 use crate::net::tcp::TcpSocket;
 use crate::net::udp::UdpPacket;
@@ -38,11 +50,13 @@ use crate::warn;
 use alloc::boxed::Box;
 use alloc::collections::btree_map;
 use alloc::collections::BTreeMap;
+use alloc::collections::BTreeSet;
 use alloc::collections::VecDeque;
 use alloc::rc::Rc;
 use alloc::rc::Weak;
 use alloc::vec::Vec;
 use core::mem::size_of;
+use core::ops::RangeInclusive;
 use core::sync::atomic::AtomicBool;
 use core::sync::atomic::Ordering;
 use noli::mem::Sliceable;
@@ -57,9 +71,134 @@ pub trait NetworkInterface {
     }
 }
 
+// https://datatracker.ietf.org/doc/html/rfc6335#section-6
+// the Dynamic Ports, also known as the Private or Ephemeral Ports, from 49152-65535
+pub const EPHEMERAL_PORT_RANGE: RangeInclusive<u16> = 49152..=65535;
+
+/// How long the originator of a packet waits for it to be sent or dropped
+pub const TX_STATUS_TIMEOUT_MS: u64 = 1000;
+
+/// How long a packet waits for the ARP reply for its next hop before giving up
+pub const ARP_TIMEOUT_MS: u64 = 1000;
+
+/// Upper bound of the packets handled per interface (or from the tx queue) in one tick of the
+/// network manager, so that a flood of packets can not starve the other tasks.
+pub const MAX_PACKETS_PER_TICK: usize = 64;
+
+/// Hands out ports in EPHEMERAL_PORT_RANGE in a round-robin manner, skipping ports in use.
+pub struct EphemeralPortAllocator {
+    next: u16,
+    in_use: BTreeSet<u16>,
+}
+impl EphemeralPortAllocator {
+    pub const fn new() -> Self {
+        Self {
+            next: *EPHEMERAL_PORT_RANGE.start(),
+            in_use: BTreeSet::new(),
+        }
+    }
+    pub fn allocate(&mut self) -> Result<u16> {
+        for _ in EPHEMERAL_PORT_RANGE {
+            let port = self.next;
+            self.next = if port == *EPHEMERAL_PORT_RANGE.end() {
+                *EPHEMERAL_PORT_RANGE.start()
+            } else {
+                port + 1
+            };
+            if self.in_use.insert(port) {
+                return Ok(port);
+            }
+        }
+        Err(Error::Failed("No more available ephemeral port"))
+    }
+    /// Marks `port` as in use. Returns false if it is already in use.
+    /// Ports out of EPHEMERAL_PORT_RANGE (e.g. well-known ports) are never handed out by this
+    /// allocator, so they are not tracked.
+    pub fn reserve(&mut self, port: u16) -> bool {
+        !EPHEMERAL_PORT_RANGE.contains(&port) || self.in_use.insert(port)
+    }
+    /// Makes `port` available again. Returns false if it was not in use.
+    pub fn release(&mut self, port: u16) -> bool {
+        self.in_use.remove(&port)
+    }
+    pub fn is_in_use(&self, port: u16) -> bool {
+        self.in_use.contains(&port)
+    }
+}
+impl Default for EphemeralPortAllocator {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum TxDropReason {
+    InvalidPacket,
+    /// The IP address or the netmask of this host is not configured yet.
+    NotConfigured,
+    /// The destination is not in the local network and no router is configured.
+    NoRoute(IpV4Addr),
+    /// The next hop (the destination or the router) is not in the ARP table yet. ARP requests
+    /// have been sent for it, so sending the packet again later may succeed.
+    ArpPending(IpV4Addr),
+    /// The interface for the next hop has been removed.
+    InterfaceGone,
+}
+impl TxDropReason {
+    pub fn as_error(&self) -> Error {
+        match self {
+            TxDropReason::InvalidPacket => Error::Failed("Invalid IPv4 packet"),
+            TxDropReason::NotConfigured => Error::Failed("Network is not configured"),
+            TxDropReason::NoRoute(dst) => Error::NoRoute(*dst),
+            TxDropReason::ArpPending(next_hop) => Error::ArpPending(*next_hop),
+            TxDropReason::InterfaceGone => Error::Failed("Network interface is gone"),
+        }
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum TxResult {
+    /// There was no packet to send.
+    Idle,
+    Sent,
+    Dropped(TxDropReason),
+}
+
+/// Delivers the result of sending a packet to its originator.
+#[derive(Default)]
+pub struct TxStatus {
+    result: Mutex<Option<Result<()>>>,
+}
+impl TxStatus {
+    fn complete(&self, result: Result<()>) {
+        *self.result.lock() = Some(result);
+    }
+    /// Returns None if the packet is still in the tx queue.
+    pub fn result(&self) -> Option<Result<()>> {
+        self.result.lock().clone()
+    }
+    /// Waits until the packet is sent or dropped, for up to `timeout_ms`.
+    pub async fn wait(&self, timeout_ms: u64) -> Result<()> {
+        let result = async {
+            loop {
+                if let Some(result) = self.result() {
+                    return result;
+                }
+                yield_execution().await;
+            }
+        };
+        with_timeout_ms(result, timeout_ms)
+            .await
+            .or(Err(Error::Failed(
+                "Timed out waiting for the packet to be sent",
+            )))?
+    }
+}
+
 pub type ArpTable = BTreeMap<IpV4Addr, (EthernetAddr, Weak<dyn NetworkInterface>)>;
 pub type TcpSocketTable = BTreeMap<u16, Rc<TcpSocket>>;
 pub type UdpSocketTable = BTreeMap<u16, Rc<UdpSocket>>;
+type IpTxQueue = VecDeque<(Box<[u8]>, Option<Rc<TxStatus>>)>;
 
 pub struct Network {
     interfaces: Mutex<Vec<Weak<dyn NetworkInterface>>>,
@@ -68,11 +207,13 @@ pub struct Network {
     router: Mutex<Option<IpV4Addr>>,
     dns: Mutex<Option<IpV4Addr>>,
     self_ip: Mutex<Option<IpV4Addr>>,
-    ip_tx_queue: Mutex<VecDeque<Box<[u8]>>>,
-    tcp_dynamic_port_hint: Mutex<u16>,
+    ip_tx_queue: Mutex<IpTxQueue>,
+    ephemeral_ports: Mutex<EphemeralPortAllocator>,
     tcp_socket_table: Mutex<TcpSocketTable>,
     udp_socket_table: Mutex<UdpSocketTable>,
     arp_table: Mutex<ArpTable>,
+    capture_enabled: AtomicBool,
+    capture_ring: Mutex<CaptureRing>,
 }
 impl Network {
     fn new() -> Self {
@@ -84,10 +225,12 @@ impl Network {
             dns: Mutex::new(None),
             self_ip: Mutex::new(None),
             ip_tx_queue: Mutex::new(VecDeque::new()),
-            tcp_dynamic_port_hint: Mutex::new(0),
+            ephemeral_ports: Mutex::new(EphemeralPortAllocator::new()),
             tcp_socket_table: Mutex::new(BTreeMap::new()),
             udp_socket_table: Mutex::new(BTreeMap::new()),
             arp_table: Mutex::new(BTreeMap::new()),
+            capture_enabled: AtomicBool::new(false),
+            capture_ring: Mutex::new(CaptureRing::new(CAPTURE_RING_CAPACITY)),
         }
     }
     pub fn take() -> Rc<Network> {
@@ -107,37 +250,41 @@ impl Network {
         });
         network.clone()
     }
+    /// Returns the Network if it has been created by take(), without starting it.
+    pub fn try_take() -> Option<Rc<Network>> {
+        NETWORK.lock().clone()
+    }
     pub fn register_interface(&self, iface: Weak<dyn NetworkInterface>) {
         let mut interfaces = self.interfaces.lock();
         interfaces.push(iface);
         self.interface_has_added.store(true, Ordering::SeqCst);
     }
-    fn pick_unused_dynamic_tcp_port(&self) -> Result<(u16, MutexGuard<TcpSocketTable>)> {
-        // https://datatracker.ietf.org/doc/html/rfc6335#section-6
-        // the Dynamic Ports, also known as the Private or Ephemeral Ports, from 49152-65535
-        const PORT_RANGE: core::ops::Range<u16> = 49152..65535;
-        let mut port = *self.tcp_dynamic_port_hint.lock();
-        if !PORT_RANGE.contains(&port) {
-            port = PORT_RANGE.start;
-        }
-        let locked_table = self.tcp_socket_table.lock();
-        // First scan
-        while PORT_RANGE.contains(&port) && locked_table.contains_key(&port) {
-            port += 1;
-        }
-        if PORT_RANGE.contains(&port) {
-            return Ok((port, locked_table));
-        }
-        // Second scan
-        port = PORT_RANGE.start;
-        while PORT_RANGE.contains(&port) && locked_table.contains_key(&port) {
-            port += 1;
-        }
-        if PORT_RANGE.contains(&port) {
-            Ok((port, locked_table))
-        } else {
-            Err(Error::Failed("No more available TCP port"))
-        }
+    /// Removes `iface` from the interfaces, e.g. when its device is detached.
+    pub fn unregister_interface(&self, iface: &Weak<dyn NetworkInterface>) {
+        self.interfaces
+            .lock()
+            .retain(|registered| !Weak::ptr_eq(registered, iface));
+    }
+    pub fn is_interface_registered(&self, iface: &Weak<dyn NetworkInterface>) -> bool {
+        self.interfaces
+            .lock()
+            .iter()
+            .any(|registered| Weak::ptr_eq(registered, iface))
+    }
+    /// Returns the number of the registered interfaces which are still alive.
+    pub fn num_interfaces(&self) -> usize {
+        self.interfaces
+            .lock()
+            .iter()
+            .filter(|iface| iface.strong_count() > 0)
+            .count()
+    }
+    /// Picks an unused port from the ephemeral port range and marks it as in use.
+    pub fn allocate_ephemeral_port(&self) -> Result<u16> {
+        self.ephemeral_ports.lock().allocate()
+    }
+    pub fn release_port(&self, port: u16) {
+        self.ephemeral_ports.lock().release(port);
     }
     pub fn register_tcp_socket(&self, sock: Rc<TcpSocket>) -> Result<()> {
         {
@@ -159,22 +306,41 @@ impl Network {
         if let Some(self_port) = sock.self_port() {
             let mut locked_table = self.tcp_socket_table.lock();
             if let btree_map::Entry::Vacant(e) = locked_table.entry(self_port) {
+                self.ephemeral_ports.lock().reserve(self_port);
                 e.insert(sock);
                 Ok(())
             } else {
                 Err(Error::Failed("TCP port is already in use"))
             }
         } else {
-            let (port, mut locked_table) = self.pick_unused_dynamic_tcp_port()?;
+            let port = self.allocate_ephemeral_port()?;
             info!("dynamic TCP port {port} is picked");
             sock.set_self_port(port);
-            locked_table.insert(port, sock);
+            self.tcp_socket_table.lock().insert(port, sock);
             Ok(())
         }
     }
     pub fn register_udp_socket(&self, port: u16, s: Rc<UdpSocket>) {
+        self.ephemeral_ports.lock().reserve(port);
         self.udp_socket_table.lock().insert(port, s);
     }
+    /// Removes `sock` from the socket table and makes its port available again.
+    pub fn close_tcp_socket(&self, sock: &Rc<TcpSocket>) {
+        let Some(port) = sock.self_port() else {
+            return;
+        };
+        let mut table = self.tcp_socket_table.lock();
+        if table.get(&port).is_some_and(|s| Rc::ptr_eq(s, sock)) {
+            table.remove(&port);
+            self.release_port(port);
+        }
+    }
+    /// Removes the UDP socket bound to `port` and makes the port available again.
+    pub fn close_udp_socket(&self, port: u16) {
+        if self.udp_socket_table.lock().remove(&port).is_some() {
+            self.release_port(port);
+        }
+    }
     pub fn netmask(&self) -> Option<IpV4Addr> {
         *self.netmask.lock()
     }
@@ -200,7 +366,22 @@ impl Network {
         *self.self_ip.lock() = value;
     }
     pub fn send_ip_packet(&self, packet: Box<[u8]>) {
-        self.ip_tx_queue.lock().push_back(packet)
+        self.enqueue_ip_packet(packet, None);
+    }
+    /// Same as send_ip_packet, but the returned TxStatus tells whether the packet has been sent
+    /// or dropped.
+    pub fn send_ip_packet_with_status(&self, packet: Box<[u8]>) -> Rc<TxStatus> {
+        let status = Rc::new(TxStatus::default());
+        self.enqueue_ip_packet(packet, Some(status.clone()));
+        status
+    }
+    fn enqueue_ip_packet(&self, mut packet: Box<[u8]>, status: Option<Rc<TxStatus>>) {
+        if let Ok(ip_packet) = IpV4Packet::from_slice_mut(&mut packet) {
+            if ip_packet.ttl() == 0 {
+                ip_packet.set_ttl(DEFAULT_TTL);
+            }
+        }
+        self.ip_tx_queue.lock().push_back((packet, status))
     }
     pub fn arp_table_cloned(&self) -> ArpTable {
         self.arp_table.lock().clone()
@@ -216,6 +397,172 @@ impl Network {
     pub fn arp_table_get(&self, ip_addr: IpV4Addr) -> Option<EthernetAddr> {
         self.arp_table.lock().get(&ip_addr).map(|e| e.0)
     }
+    /// Waits until `ip_addr` is registered in the ARP table, for up to `timeout_ms`.
+    pub async fn wait_for_arp(&self, ip_addr: IpV4Addr, timeout_ms: u64) -> Result<EthernetAddr> {
+        let resolved = async {
+            loop {
+                if let Some(eth_addr) = self.arp_table_get(ip_addr) {
+                    return eth_addr;
+                }
+                yield_execution().await;
+            }
+        };
+        with_timeout_ms(resolved, timeout_ms)
+            .await
+            .or(Err(Error::ArpTimeout(ip_addr)))
+    }
+    /// Sends `packet` and waits for the result. If the next hop is not in the ARP table yet,
+    /// waits for the ARP reply and sends the packet again.
+    pub async fn send_ip_packet_and_wait(&self, packet: Box<[u8]>) -> Result<()> {
+        let result = self
+            .send_ip_packet_with_status(packet.clone())
+            .wait(TX_STATUS_TIMEOUT_MS)
+            .await;
+        let Err(Error::ArpPending(next_hop)) = result else {
+            return result;
+        };
+        self.wait_for_arp(next_hop, ARP_TIMEOUT_MS).await?;
+        self.send_ip_packet_with_status(packet)
+            .wait(TX_STATUS_TIMEOUT_MS)
+            .await
+    }
+    pub fn is_capture_enabled(&self) -> bool {
+        self.capture_enabled.load(Ordering::SeqCst)
+    }
+    pub fn set_capture_enabled(&self, enabled: bool) {
+        self.capture_enabled.store(enabled, Ordering::SeqCst);
+    }
+    /// Records a frame to the capture ring if capturing is enabled.
+    pub fn capture_frame(&self, direction: CaptureDirection, frame: &[u8]) {
+        if !self.is_capture_enabled() {
+            return;
+        }
+        let timestamp_ms = Hpet::try_take().map(|h| h.uptime_ms()).unwrap_or(0);
+        self.capture_ring
+            .lock()
+            .push(direction, timestamp_ms, frame);
+    }
+    pub fn captured_frames(&self) -> Vec<CapturedFrame> {
+        self.capture_ring.lock().frames().cloned().collect()
+    }
+    pub fn clear_captured_frames(&self) {
+        self.capture_ring.lock().clear();
+    }
+    /// Handles the received packets queued in each interface, up to MAX_PACKETS_PER_TICK per
+    /// interface. Returns the number of packets handled.
+    fn process_rx(&self) -> Result<usize> {
+        let interfaces: Vec<Rc<dyn NetworkInterface>> = self
+            .interfaces
+            .lock()
+            .iter()
+            .filter_map(|iface| iface.upgrade())
+            .collect();
+        let mut count = 0;
+        for iface in &interfaces {
+            for _ in 0..MAX_PACKETS_PER_TICK {
+                let Ok(packet) = iface.pop_packet() else {
+                    break;
+                };
+                self.capture_frame(CaptureDirection::Rx, &packet);
+                count += 1;
+                handle_receive(&packet, iface).context(iface.name())?;
+            }
+        }
+        Ok(count)
+    }
+    /// Sends the IPv4 packets in the tx queue, up to MAX_PACKETS_PER_TICK. Returns the number of
+    /// packets taken from the queue.
+    fn process_tx_queue(&self) -> Result<usize> {
+        let mut count = 0;
+        while count < MAX_PACKETS_PER_TICK {
+            if self.process_tx()? == TxResult::Idle {
+                break;
+            }
+            count += 1;
+        }
+        Ok(count)
+    }
+    /// Sends an IPv4 packet in the tx queue, if any.
+    fn process_tx(&self) -> Result<TxResult> {
+        let Some((packet, status)) = self.ip_tx_queue.lock().pop_front() else {
+            return Ok(TxResult::Idle);
+        };
+        let result = self.transmit_ip_packet(packet);
+        if let Ok(TxResult::Dropped(reason)) = &result {
+            warn!("net: tx: dropped a packet: {reason:?}");
+        }
+        if let Some(status) = status {
+            status.complete(match &result {
+                Ok(TxResult::Dropped(reason)) => Err(reason.as_error()),
+                Ok(_) => Ok(()),
+                Err(e) => Err(e.clone()),
+            });
+        }
+        result
+    }
+    fn transmit_ip_packet(&self, mut org_packet: Box<[u8]>) -> Result<TxResult> {
+        let Ok(ip_packet) = IpV4Packet::from_slice_mut(&mut org_packet) else {
+            return Ok(TxResult::Dropped(TxDropReason::InvalidPacket));
+        };
+        let dst_ip = ip_packet.dst();
+        let (Some(src_ip), Some(mask)) = (self.self_ip(), self.netmask()) else {
+            return Ok(TxResult::Dropped(TxDropReason::NotConfigured));
+        };
+        let next_hop_ip = if src_ip.network_prefix(mask) == dst_ip.network_prefix(mask) {
+            dst_ip
+        } else if let Some(router_ip) = self.router() {
+            router_ip
+        } else {
+            return Ok(TxResult::Dropped(TxDropReason::NoRoute(dst_ip)));
+        };
+        let next_hop_info = self.arp_table.lock().get(&next_hop_ip).cloned();
+        let Some((next_hop, iface)) = next_hop_info else {
+            warn!("{next_hop_ip} is not in the ARP table. Sending ARP from all the interfaces.");
+            let interfaces = self.interfaces.lock();
+            for iface in &*interfaces {
+                if let Some(iface) = iface.upgrade() {
+                    let arp_req = ArpPacket::request(iface.ethernet_addr(), src_ip, next_hop_ip);
+                    self.push_packet_to(&iface, arp_req.copy_into_slice())?;
+                }
+            }
+            return Ok(TxResult::Dropped(TxDropReason::ArpPending(next_hop_ip)));
+        };
+        let Some(iface) = iface.upgrade() else {
+            return Ok(TxResult::Dropped(TxDropReason::InterfaceGone));
+        };
+        let old_src = ip_packet.src();
+        ip_packet.set_src(src_ip);
+        ip_packet.eth = EthernetHeader::new(next_hop, iface.ethernet_addr(), EthernetType::ip_v4());
+        let csum = ip_packet.checksum();
+        let csum = if csum == InternetChecksum::default() {
+            // The checksum has not been computed yet
+            InternetChecksum::calc(
+                &org_packet[size_of::<EthernetHeader>()..size_of::<IpV4Packet>()],
+            )
+        } else {
+            // Only the source address has changed
+            let (old, new) = (old_src.bytes(), src_ip.bytes());
+            let csum = InternetChecksum::update(
+                csum.as_u16(),
+                u16::from_be_bytes([old[0], old[1]]),
+                u16::from_be_bytes([new[0], new[1]]),
+            );
+            let csum = InternetChecksum::update(
+                csum,
+                u16::from_be_bytes([old[2], old[3]]),
+                u16::from_be_bytes([new[2], new[3]]),
+            );
+            InternetChecksum::from_u16(csum)
+        };
+        IpV4Packet::from_slice_mut(&mut org_packet)?.set_checksum(csum);
+        self.push_packet_to(&iface, org_packet)?;
+        Ok(TxResult::Sent)
+    }
+    fn push_packet_to(&self, iface: &Rc<dyn NetworkInterface>, packet: Box<[u8]>) -> Result<()> {
+        let packet = pad_to_min_frame_size(packet);
+        self.capture_frame(CaptureDirection::Tx, &packet);
+        iface.push_packet(packet)
+    }
     pub fn open_tcp_socket(&self, ip: IpV4Addr, port: u16) -> Result<Rc<TcpSocket>> {
         let sock = TcpSocket::new_client(ip, port);
         info!("socket created: {sock:?}");
@@ -283,7 +630,7 @@ fn handle_rx_dhcp_client(packet: &[u8], iface: &Rc<dyn NetworkInterface>) -> Res
                         network.set_router(Some(*router));
                         let arp_req =
                             ArpPacket::request(iface.ethernet_addr(), new_self_ip, *router);
-                        iface.push_packet(arp_req.copy_into_slice())?;
+                        network.push_packet_to(iface, arp_req.copy_into_slice())?;
                     }
                 }
                 DHCP_OPT_DNS => {
@@ -292,7 +639,7 @@ fn handle_rx_dhcp_client(packet: &[u8], iface: &Rc<dyn NetworkInterface>) -> Res
                         //network.set_dns(Some(*dns));
                         network.set_dns(Some(IpV4Addr::new([8, 8, 8, 8])));
                         let arp_req = ArpPacket::request(iface.ethernet_addr(), new_self_ip, *dns);
-                        iface.push_packet(arp_req.copy_into_slice())?;
+                        network.push_packet_to(iface, arp_req.copy_into_slice())?;
                     }
                 }
                 _ => {}
@@ -307,7 +654,9 @@ fn handle_rx_dhcp_client(packet: &[u8], iface: &Rc<dyn NetworkInterface>) -> Res
 fn handle_rx_udp(packet: &[u8], iface: &Rc<dyn NetworkInterface>) -> Result<()> {
     let udp = UdpPacket::from_slice(packet)?;
     match (udp.src_port(), udp.dst_port()) {
-        (UDP_PORT_DHCP_SERVER, UDP_PORT_DHCP_CLIENT) => handle_rx_dhcp_client(packet, iface),
+        (UDP_PORT_DHCP_SERVER, UDP_PORT_DHCP_CLIENT) => {
+            handle_rx_dhcp_client(packet, iface).context("handle_rx_dhcp")
+        }
         (_, dst) => {
             if let Some(sock) = Network::take().udp_socket_table.lock().get(&dst) {
                 sock.handle_rx(packet)
@@ -354,17 +703,32 @@ fn handle_rx_arp(packet: &[u8], iface: &Rc<dyn NetworkInterface>) -> Result<()>
     }
 }
 
+fn handle_rx_ip_v4(packet: &[u8], iface: &Rc<dyn NetworkInterface>) -> Result<()> {
+    match IpV4Packet::from_slice(packet)?.protocol() {
+        e if e == IpV4Protocol::udp() => handle_rx_udp(packet, iface),
+        e if e == IpV4Protocol::tcp() => handle_rx_tcp(packet).context("handle_rx_tcp"),
+        e if e == IpV4Protocol::icmp() => handle_rx_icmp(packet),
+        e => {
+            warn!("handle_receive: Unknown ip_v4.protocol: {e:?}");
+            Ok(())
+        }
+    }
+}
+
 fn handle_receive(packet: &[u8], iface: &Rc<dyn NetworkInterface>) -> Result<()> {
     match EthernetHeader::from_slice(packet)?.eth_type() {
-        e if e == EthernetType::ip_v4() => match IpV4Packet::from_slice(packet)?.protocol() {
-            e if e == IpV4Protocol::udp() => handle_rx_udp(packet, iface),
-            e if e == IpV4Protocol::tcp() => handle_rx_tcp(packet),
-            e if e == IpV4Protocol::icmp() => handle_rx_icmp(packet),
-            e => {
-                warn!("handle_receive: Unknown ip_v4.protocol: {e:?}");
-                Ok(())
+        e if e == EthernetType::ip_v4() => {
+            if let Err(e) = IpV4Packet::verify_checksum(packet) {
+                warn!("handle_receive: dropping an IPv4 packet: {e}");
+                return Ok(());
+            }
+            if IpV4Packet::payload_offset(packet)? == size_of::<IpV4Packet>() {
+                handle_rx_ip_v4(packet, iface)
+            } else {
+                // Packets of the upper layers assume that the IPv4 header has no options
+                handle_rx_ip_v4(&IpV4Packet::strip_options(packet)?, iface)
             }
-        },
+        }
         e if e == EthernetType::arp() => handle_rx_arp(packet, iface),
         e => {
             warn!("handle_receive: Unknown eth_type {e:?}");
@@ -373,9 +737,11 @@ fn handle_receive(packet: &[u8], iface: &Rc<dyn NetworkInterface>) -> Result<()>
     }
 }
 
-fn probe_interfaces() -> Result<()> {
+async fn probe_interfaces() -> Result<()> {
     let network = Network::take();
-    let interfaces = network.interfaces.lock();
+    // The lock is held while queueing the DHCP requests, so yield if a driver is registering an
+    // interface at the same time.
+    let interfaces = network.interfaces.lock_async().await;
     if network
         .interface_has_added
         .compare_exchange_weak(true, false, Ordering::SeqCst, Ordering::Relaxed)
@@ -386,79 +752,323 @@ fn probe_interfaces() -> Result<()> {
             if let Some(iface) = iface.upgrade() {
                 info!("  {:?} {}", iface.ethernet_addr(), iface.name());
                 let dhcp_req = DhcpPacket::request(iface.ethernet_addr())?;
-                iface.push_packet(dhcp_req.copy_into_slice())?;
+                network.push_packet_to(&iface, dhcp_req.copy_into_slice())?;
             }
         }
     }
     Ok(())
 }
 
-fn process_tx() -> Result<()> {
-    let network = Network::take();
-    if let Some(mut org_packet) = network.ip_tx_queue.lock().pop_front() {
-        if let Ok(ip_packet) = IpV4Packet::from_slice_mut(&mut org_packet) {
-            let dst_ip = ip_packet.dst();
-            if let (Some(src_ip), Some(mask)) = (*network.self_ip.lock(), *network.netmask.lock()) {
-                let network_prefix = src_ip.network_prefix(mask);
-                let next_hop_info = if network_prefix == dst_ip.network_prefix(mask) {
-                    network.arp_table.lock().get(&dst_ip).cloned()
-                } else {
-                    network
-                        .router
-                        .lock()
-                        .and_then(|router_ip| network.arp_table.lock().get(&router_ip).cloned())
-                };
-                if let Some((next_hop, iface)) = next_hop_info {
-                    ip_packet.set_src(src_ip);
-                    if let Some(iface) = iface.upgrade() {
-                        ip_packet.eth = EthernetHeader::new(
-                            next_hop,
-                            iface.ethernet_addr(),
-                            EthernetType::ip_v4(),
-                        );
-                        ip_packet.clear_checksum();
-                        let csum = InternetChecksum::calc(
-                            &org_packet[size_of::<EthernetHeader>()..size_of::<IpV4Packet>()],
-                        );
-                        if let Ok(ip_packet) = IpV4Packet::from_slice_mut(&mut org_packet) {
-                            ip_packet.set_checksum(csum);
-                            iface.push_packet(org_packet.clone())?;
-                        }
-                    }
-                } else {
-                    warn!("No route to {dst_ip}. Sending ARP from all the interfaces.");
-                    let interfaces = network.interfaces.lock();
-                    for iface in &*interfaces {
-                        if let Some(iface) = iface.upgrade() {
-                            let arp_req = ArpPacket::request(iface.ethernet_addr(), src_ip, dst_ip);
-                            iface.push_packet(arp_req.copy_into_slice())?;
-                        }
-                    }
-                }
-            }
-        }
-    }
-    Ok(())
+fn process_tx() -> Result<usize> {
+    Network::take().process_tx_queue()
 }
-fn process_rx() -> Result<()> {
-    let network = Network::take();
-    let interfaces = network.interfaces.lock();
-    for iface in &*interfaces {
-        if let Some(iface) = iface.upgrade() {
-            if let Ok(packet) = iface.pop_packet() {
-                handle_receive(&packet, &iface)?;
-            }
-        }
+fn process_rx() -> Result<usize> {
+    Network::take().process_rx()
+}
+
+pub const MIN_POLL_DELAY_MS: u64 = 1;
+pub const MAX_POLL_DELAY_MS: u64 = 100;
+
+/// Returns the delay before the next tick of the network manager. Polls again soon after doing
+/// some work, since more packets (e.g. replies) are likely to follow, and backs off exponentially
+/// up to MAX_POLL_DELAY_MS while idle.
+pub fn next_delay_ms(did_work: bool, current_delay_ms: u64) -> u64 {
+    if did_work {
+        MIN_POLL_DELAY_MS
+    } else {
+        current_delay_ms
+            .saturating_mul(2)
+            .clamp(MIN_POLL_DELAY_MS, MAX_POLL_DELAY_MS)
     }
-    Ok(())
 }
 
 pub async fn network_manager_thread() -> Result<()> {
     info!("Network manager started running");
+    let mut delay_ms = MAX_POLL_DELAY_MS;
     loop {
-        probe_interfaces()?;
-        process_tx()?;
-        process_rx()?;
-        TimeoutFuture::new_ms(100).await;
+        probe_interfaces().await?;
+        let num_tx = process_tx()?;
+        let num_rx = process_rx()?;
+        delay_ms = next_delay_ms(num_tx + num_rx > 0, delay_ms);
+        TimeoutFuture::new_ms(delay_ms).await;
+    }
+}
+
+#[test_case]
+fn try_take_returns_the_network_created_by_take() {
+    let network = Network::take();
+    assert!(Network::try_take().is_some_and(|n| Rc::ptr_eq(&n, &network)));
+}
+
+#[test_case]
+fn ephemeral_port_allocation_skips_used_ports() {
+    let mut ports = EphemeralPortAllocator::new();
+    assert!(ports.reserve(49153));
+    assert!(!ports.reserve(49153));
+    assert_eq!(ports.allocate(), Ok(49152));
+    assert_eq!(ports.allocate(), Ok(49154));
+    assert!(ports.is_in_use(49152));
+    assert!(ports.is_in_use(49153));
+    assert!(ports.is_in_use(49154));
+}
+
+#[test_case]
+fn ephemeral_port_allocation_wraps_around() {
+    let mut ports = EphemeralPortAllocator::new();
+    for port in *EPHEMERAL_PORT_RANGE.start()..=65533 {
+        assert!(ports.reserve(port));
+    }
+    assert_eq!(ports.allocate(), Ok(65534));
+    assert_eq!(ports.allocate(), Ok(65535));
+    assert!(ports.allocate().is_err());
+    assert!(ports.release(49160));
+    assert!(!ports.release(49160));
+    assert!(!ports.is_in_use(49160));
+    assert_eq!(ports.allocate(), Ok(49160));
+    assert!(ports.is_in_use(49160));
+}
+
+#[test_case]
+fn well_known_ports_are_not_tracked_as_ephemeral() {
+    let mut ports = EphemeralPortAllocator::new();
+    assert!(ports.reserve(53));
+    assert!(ports.reserve(53));
+    assert!(!ports.is_in_use(53));
+    assert!(!ports.release(53));
+}
+
+#[test_case]
+fn closing_sockets_releases_their_ports() {
+    let network = Network::new();
+    network.register_udp_socket(53, Rc::new(UdpSocket::default()));
+    assert!(!network.ephemeral_ports.lock().is_in_use(53));
+    network.register_udp_socket(50000, Rc::new(UdpSocket::default()));
+    assert!(network.ephemeral_ports.lock().is_in_use(50000));
+    network.close_udp_socket(50000);
+    assert!(!network.ephemeral_ports.lock().is_in_use(50000));
+    assert!(!network.udp_socket_table.lock().contains_key(&50000));
+    assert!(network.udp_socket_table.lock().contains_key(&53));
+
+    let sock = Rc::new(TcpSocket::new_client(IpV4Addr::new([10, 0, 2, 2]), 80));
+    network.register_tcp_socket(sock.clone()).unwrap();
+    let port = sock.self_port().unwrap();
+    assert!(network.ephemeral_ports.lock().is_in_use(port));
+    // Another socket on the same port is not closed by mistake
+    let other = Rc::new(TcpSocket::new_client(IpV4Addr::new([10, 0, 2, 2]), 80));
+    other.set_self_port(port);
+    network.close_tcp_socket(&other);
+    assert!(network.ephemeral_ports.lock().is_in_use(port));
+    network.close_tcp_socket(&sock);
+    assert!(!network.ephemeral_ports.lock().is_in_use(port));
+    assert!(!network.tcp_socket_table.lock().contains_key(&port));
+}
+
+#[test_case]
+fn send_without_route_reports_no_route() {
+    let network = Network::new();
+    network.set_self_ip(Some(IpV4Addr::new([10, 0, 2, 15])));
+    network.set_netmask(Some(IpV4Addr::new([255, 255, 255, 0])));
+    let dst = IpV4Addr::new([8, 8, 8, 8]);
+    let status = network.send_ip_packet_with_status(IcmpPacket::new_request(dst).copy_into_slice());
+    assert_eq!(status.result(), None);
+    assert_eq!(
+        network.process_tx(),
+        Ok(TxResult::Dropped(TxDropReason::NoRoute(dst)))
+    );
+    assert_eq!(
+        block_on(async move { status.wait(TX_STATUS_TIMEOUT_MS).await }),
+        Err(Error::NoRoute(dst))
+    );
+    assert_eq!(network.process_tx(), Ok(TxResult::Idle));
+}
+
+#[test_case]
+fn unregistered_interface_is_forgotten() {
+    let network = Network::new();
+    let iface: Rc<dyn NetworkInterface> = Rc::new(LoopbackInterface::new(EthernetAddr::new([
+        0x02, 0, 0, 0, 0, 1,
+    ])));
+    let iface = Rc::downgrade(&iface);
+    network.register_interface(iface.clone());
+    assert!(network.is_interface_registered(&iface));
+    assert_eq!(network.num_interfaces(), 1);
+    network.unregister_interface(&iface);
+    assert!(!network.is_interface_registered(&iface));
+    assert_eq!(network.num_interfaces(), 0);
+}
+
+#[test_case]
+fn send_to_unresolved_next_hop_reports_arp_pending() {
+    let network = Network::new();
+    let iface: Rc<dyn NetworkInterface> = Rc::new(LoopbackInterface::new(EthernetAddr::new([
+        0x02, 0, 0, 0, 0, 1,
+    ])));
+    network.register_interface(Rc::downgrade(&iface));
+    network.set_self_ip(Some(IpV4Addr::new([10, 0, 2, 15])));
+    network.set_netmask(Some(IpV4Addr::new([255, 255, 255, 0])));
+    let router = IpV4Addr::new([10, 0, 2, 2]);
+    network.set_router(Some(router));
+    // The ARP request is sent for the router, not for the destination out of the network
+    let status = network.send_ip_packet_with_status(
+        IcmpPacket::new_request(IpV4Addr::new([8, 8, 8, 8])).copy_into_slice(),
+    );
+    assert_eq!(
+        network.process_tx(),
+        Ok(TxResult::Dropped(TxDropReason::ArpPending(router)))
+    );
+    assert_eq!(status.result(), Some(Err(Error::ArpPending(router))));
+    let frame = iface.pop_packet().unwrap();
+    let arp = ArpPacket::from_slice(&frame).unwrap();
+    assert_eq!(arp.target_ip_addr(), router);
+    assert!(iface.pop_packet().is_err());
+}
+
+#[test_case]
+fn wait_for_arp_times_out_without_reply() {
+    let network = Rc::new(Network::new());
+    let iface: Rc<dyn NetworkInterface> = Rc::new(LoopbackInterface::new(EthernetAddr::new([
+        0x02, 0, 0, 0, 0, 1,
+    ])));
+    let ip = IpV4Addr::new([10, 0, 2, 2]);
+    let peer_eth = EthernetAddr::new([0x02, 0, 0, 0, 0, 2]);
+    let result = block_on({
+        let network = network.clone();
+        async move { network.wait_for_arp(ip, 10).await }
+    });
+    assert_eq!(result, Err(Error::ArpTimeout(ip)));
+    network.arp_table_register(ip, peer_eth, Rc::downgrade(&iface));
+    assert_eq!(
+        block_on(async move { network.wait_for_arp(ip, 10).await }),
+        Ok(peer_eth)
+    );
+}
+
+#[test_case]
+fn tx_status_wait_times_out() {
+    let status = TxStatus::default();
+    assert!(block_on(async move { status.wait(10).await }).is_err());
+}
+
+#[test_case]
+fn send_before_configuration_is_dropped() {
+    let network = Network::new();
+    let dst = IpV4Addr::new([10, 0, 2, 2]);
+    let status = network.send_ip_packet_with_status(IcmpPacket::new_request(dst).copy_into_slice());
+    assert_eq!(
+        network.process_tx(),
+        Ok(TxResult::Dropped(TxDropReason::NotConfigured))
+    );
+    assert_eq!(
+        status.result(),
+        Some(Err(Error::Failed("Network is not configured")))
+    );
+}
+
+#[test_case]
+fn process_tx_sends_frames_to_the_next_hop_interface() {
+    let network = Network::new();
+    let self_eth = EthernetAddr::new([0x02, 0, 0, 0, 0, 1]);
+    let peer_eth = EthernetAddr::new([0x02, 0, 0, 0, 0, 2]);
+    let iface: Rc<dyn NetworkInterface> = Rc::new(LoopbackInterface::new(self_eth));
+    network.register_interface(Rc::downgrade(&iface));
+    network.set_self_ip(Some(IpV4Addr::new([10, 0, 2, 15])));
+    network.set_netmask(Some(IpV4Addr::new([255, 255, 255, 0])));
+    let dst = IpV4Addr::new([10, 0, 2, 2]);
+    network.arp_table_register(dst, peer_eth, Rc::downgrade(&iface));
+    network.send_ip_packet(IcmpPacket::new_request(dst).copy_into_slice());
+    assert_eq!(network.process_tx(), Ok(TxResult::Sent));
+    let frame = iface.pop_packet().unwrap();
+    let ip = IpV4Packet::from_slice(&frame).unwrap();
+    assert_eq!(ip.src(), IpV4Addr::new([10, 0, 2, 15]));
+    assert_eq!(ip.dst(), dst);
+    assert_eq!(
+        InternetChecksum::calc(&frame[size_of::<EthernetHeader>()..size_of::<IpV4Packet>()]),
+        InternetChecksum::default()
+    );
+    assert_eq!(EthernetAddr::new(frame[0..6].try_into().unwrap()), peer_eth);
+    assert_eq!(
+        EthernetAddr::new(frame[6..12].try_into().unwrap()),
+        self_eth
+    );
+    assert!(iface.pop_packet().is_err());
+}
+
+#[test_case]
+fn process_rx_handles_all_queued_frames() {
+    let network = Network::new();
+    let iface: Rc<dyn NetworkInterface> = Rc::new(LoopbackInterface::new(EthernetAddr::new([
+        0x02, 0, 0, 0, 0, 1,
+    ])));
+    network.register_interface(Rc::downgrade(&iface));
+    for _ in 0..5 {
+        iface
+            .push_packet(IcmpPacket::new_request(IpV4Addr::new([10, 0, 2, 15])).copy_into_slice())
+            .unwrap();
+    }
+    assert_eq!(network.process_rx(), Ok(5));
+    assert!(iface.pop_packet().is_err());
+    assert_eq!(network.process_rx(), Ok(0));
+}
+
+#[test_case]
+fn process_rx_is_bounded_per_tick() {
+    let network = Network::new();
+    let iface: Rc<dyn NetworkInterface> = Rc::new(LoopbackInterface::new(EthernetAddr::new([
+        0x02, 0, 0, 0, 0, 1,
+    ])));
+    network.register_interface(Rc::downgrade(&iface));
+    for _ in 0..MAX_PACKETS_PER_TICK + 3 {
+        iface
+            .push_packet(IcmpPacket::new_request(IpV4Addr::new([10, 0, 2, 15])).copy_into_slice())
+            .unwrap();
+    }
+    assert_eq!(network.process_rx(), Ok(MAX_PACKETS_PER_TICK));
+    assert_eq!(network.process_rx(), Ok(3));
+    assert!(iface.pop_packet().is_err());
+}
+
+#[test_case]
+fn process_tx_queue_drains_the_queue() {
+    let network = Network::new();
+    let iface: Rc<dyn NetworkInterface> = Rc::new(LoopbackInterface::new(EthernetAddr::new([
+        0x02, 0, 0, 0, 0, 1,
+    ])));
+    network.register_interface(Rc::downgrade(&iface));
+    network.set_self_ip(Some(IpV4Addr::new([10, 0, 2, 15])));
+    network.set_netmask(Some(IpV4Addr::new([255, 255, 255, 0])));
+    let dst = IpV4Addr::new([10, 0, 2, 2]);
+    network.arp_table_register(
+        dst,
+        EthernetAddr::new([0x02, 0, 0, 0, 0, 2]),
+        Rc::downgrade(&iface),
+    );
+    for _ in 0..3 {
+        network.send_ip_packet(IcmpPacket::new_request(dst).copy_into_slice());
+    }
+    assert_eq!(network.process_tx_queue(), Ok(3));
+    for _ in 0..3 {
+        assert!(iface.pop_packet().is_ok());
+    }
+    assert!(iface.pop_packet().is_err());
+    assert_eq!(network.process_tx_queue(), Ok(0));
+}
+
+#[test_case]
+fn next_delay_ms_ramps_up_on_work() {
+    assert_eq!(next_delay_ms(true, MAX_POLL_DELAY_MS), MIN_POLL_DELAY_MS);
+    assert_eq!(next_delay_ms(true, 8), MIN_POLL_DELAY_MS);
+    assert_eq!(next_delay_ms(true, MIN_POLL_DELAY_MS), MIN_POLL_DELAY_MS);
+}
+
+#[test_case]
+fn next_delay_ms_backs_off_when_idle() {
+    let mut delay_ms = MIN_POLL_DELAY_MS;
+    let mut delays = Vec::new();
+    for _ in 0..9 {
+        delay_ms = next_delay_ms(false, delay_ms);
+        delays.push(delay_ms);
     }
+    assert_eq!(delays, [2, 4, 8, 16, 32, 64, 100, 100, 100]);
+    assert_eq!(next_delay_ms(false, 0), MIN_POLL_DELAY_MS);
+    assert_eq!(next_delay_ms(false, u64::MAX), MAX_POLL_DELAY_MS);
 }
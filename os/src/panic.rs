@@ -7,6 +7,31 @@ use core::panic::PanicInfo;
 #[cfg(test)]
 use crate::debug;
 
+/// Set WASABI_PANIC="reboot" at build time to reset the machine after a panic, e.g. for
+/// unattended runs. The machine halts otherwise so that the state can be inspected.
+/// `make run PANIC=reboot` sets it, as does the build of generated/bin/os.efi. Other builds
+/// (e.g. a plain `cargo run`) use the default unless WASABI_PANIC is exported.
+const PANIC_MODE: Option<&str> = option_env!("WASABI_PANIC");
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum PanicAction {
+    Halt,
+    Reboot,
+    ExitQemu,
+}
+
+/// Decides what to do after reporting a panic.
+/// Tests always make QEMU exit so that the failure is reported to the test runner.
+fn panic_action(mode: Option<&str>, is_test: bool) -> PanicAction {
+    if is_test {
+        PanicAction::ExitQemu
+    } else if mode == Some("reboot") {
+        PanicAction::Reboot
+    } else {
+        PanicAction::Halt
+    }
+}
+
 #[panic_handler]
 fn panic(info: &PanicInfo) -> ! {
     let mut serial_writer = SerialPort::default();
@@ -14,8 +39,35 @@ fn panic(info: &PanicInfo) -> ! {
     dump_stack();
     writeln!(serial_writer, "[PANIC] {info:?}").unwrap();
     println!("[PANIC] {info:?}");
-    #[cfg(not(test))]
-    crate::x86_64::rest_in_peace();
-    #[cfg(test)]
-    debug::exit_qemu(debug::QemuExitCode::Fail);
+    match panic_action(PANIC_MODE, cfg!(test)) {
+        PanicAction::Halt => crate::x86_64::rest_in_peace(),
+        PanicAction::Reboot => {
+            writeln!(serial_writer, "[PANIC] rebooting...").unwrap();
+            crate::x86_64::reboot()
+        }
+        #[cfg(test)]
+        PanicAction::ExitQemu => debug::exit_qemu(debug::QemuExitCode::Fail),
+        // panic_action() never returns ExitQemu for non-test builds, but halt just in case
+        // instead of panicking again inside the panic handler.
+        #[cfg(not(test))]
+        PanicAction::ExitQemu => crate::x86_64::rest_in_peace(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    #[test_case]
+    fn panic_action_follows_the_mode() {
+        assert_eq!(panic_action(None, false), PanicAction::Halt);
+        assert_eq!(panic_action(Some(""), false), PanicAction::Halt);
+        assert_eq!(panic_action(Some("halt"), false), PanicAction::Halt);
+        assert_eq!(panic_action(Some("reboot"), false), PanicAction::Reboot);
+        assert_eq!(panic_action(Some("Reboot"), false), PanicAction::Halt);
+    }
+    #[test_case]
+    fn tests_always_exit_qemu_on_panic() {
+        assert_eq!(panic_action(None, true), PanicAction::ExitQemu);
+        assert_eq!(panic_action(Some("reboot"), true), PanicAction::ExitQemu);
+    }
 }
@@ -0,0 +1,59 @@
+extern crate alloc;
+
+use alloc::string::String;
+use alloc::vec::Vec;
+
+/// Joins `path` to `cwd` unless `path` is absolute, and normalizes the result.
+/// Empty and "." components are dropped, and ".." removes the previous one (but never goes
+/// above the root), so the result is an absolute path without a trailing slash, e.g. "/a/b".
+pub fn resolve_path(cwd: &str, path: &str) -> String {
+    let base = if path.starts_with('/') { "" } else { cwd };
+    let mut components: Vec<&str> = Vec::new();
+    for c in base.split('/').chain(path.split('/')) {
+        match c {
+            "" | "." => {}
+            ".." => {
+                components.pop();
+            }
+            c => components.push(c),
+        }
+    }
+    if components.is_empty() {
+        return "/".into();
+    }
+    let mut resolved = String::new();
+    for c in components {
+        resolved.push('/');
+        resolved.push_str(c);
+    }
+    resolved
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    #[test_case]
+    fn relative_path_is_joined_to_cwd() {
+        assert_eq!(resolve_path("/", "hello0"), "/hello0");
+        assert_eq!(resolve_path("/a", "b/c"), "/a/b/c");
+        assert_eq!(resolve_path("/a/", "b"), "/a/b");
+        assert_eq!(resolve_path("/a", ""), "/a");
+    }
+    #[test_case]
+    fn absolute_path_ignores_cwd() {
+        assert_eq!(resolve_path("/a", "/b"), "/b");
+        assert_eq!(resolve_path("/a", "/"), "/");
+    }
+    #[test_case]
+    fn dots_and_repeated_slashes_are_normalized() {
+        assert_eq!(resolve_path("/a", "."), "/a");
+        assert_eq!(resolve_path("/a", "./b/."), "/a/b");
+        assert_eq!(resolve_path("/a/b", ".."), "/a");
+        assert_eq!(resolve_path("/a/b", "../c"), "/a/c");
+        assert_eq!(resolve_path("/a", "b//c///"), "/a/b/c");
+        assert_eq!(resolve_path("//a", "b"), "/a/b");
+        // ".." at the root stays at the root
+        assert_eq!(resolve_path("/", ".."), "/");
+        assert_eq!(resolve_path("/a", "../../../b"), "/b");
+    }
+}
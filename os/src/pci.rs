@@ -5,14 +5,11 @@ use crate::error;
 use crate::error::Error;
 use crate::error::Result;
 use crate::info;
-use crate::rtl8139::Rtl8139Driver;
 use crate::x86_64::paging::with_current_page_table;
 use crate::x86_64::paging::PageAttr;
-use crate::xhci::driver::XhciDriverForPci;
 use alloc::boxed::Box;
 use alloc::collections::btree_map::BTreeMap;
 use alloc::rc::Rc;
-use alloc::vec;
 use alloc::vec::Vec;
 use core::cell::RefCell;
 use core::fmt;
@@ -158,6 +155,10 @@ impl fmt::Debug for dyn PciDeviceDriver {
 
 pub trait PciDeviceDriverInstance {
     fn name(&self) -> &str;
+    /// The device that this instance is attached to.
+    fn bdf(&self) -> BusDeviceFunction;
+    /// Called when the device is removed from Pci. Drivers should stop using the device here.
+    fn detach(&self) {}
 }
 impl fmt::Debug for dyn PciDeviceDriverInstance {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
@@ -165,6 +166,12 @@ impl fmt::Debug for dyn PciDeviceDriverInstance {
     }
 }
 
+const REG_COMMAND: usize = 0x04;
+const CMD_IO_SPACE: u16 = 1 << 0;
+const CMD_MEMORY_SPACE: u16 = 1 << 1;
+const CMD_BUS_MASTER: u16 = 1 << 2;
+const CMD_INTERRUPT_DISABLE: u16 = 1 << 10;
+
 struct ConfigRegisters<T> {
     access_type: PhantomData<T>,
 }
@@ -212,6 +219,8 @@ impl BarMem64 {
 pub struct Pci {
     ecm_range: Range<usize>,
     drivers: Vec<Rc<Box<dyn PciDeviceDriver>>>,
+    // Devices found by scan_devices()
+    device_list: Vec<(BusDeviceFunction, VendorDeviceId)>,
     devices: RefCell<BTreeMap<BusDeviceFunction, Rc<Box<dyn PciDeviceDriverInstance>>>>,
 }
 impl Pci {
@@ -220,17 +229,18 @@ impl Pci {
         assert!(mcfg.num_of_entries() == 1);
         let pci_config_space_base = mcfg.entry(0).expect("Out of range").base_address() as usize;
         let pci_config_space_end = pci_config_space_base + (1 << 24);
-        let drivers = vec![
-            Rc::new(Box::<Rtl8139Driver>::default() as Box<dyn PciDeviceDriver>),
-            Rc::new(Box::<XhciDriverForPci>::default() as Box<dyn PciDeviceDriver>),
-        ];
 
         Pci {
             ecm_range: pci_config_space_base..pci_config_space_end,
-            drivers,
+            drivers: Vec::new(),
+            device_list: Vec::new(),
             devices: RefCell::new(BTreeMap::new()),
         }
     }
+    /// Adds a driver to be tried by probe_devices(). Drivers are tried in the registration order.
+    pub fn register_driver(&mut self, driver: Box<dyn PciDeviceDriver>) {
+        self.drivers.push(Rc::new(driver));
+    }
     pub fn try_bar0_io(&self, bdf: BusDeviceFunction) -> Result<u16> {
         let bar0 = self.read_register_u32(bdf, 0x10)?;
         if bar0 & 0b11 == 0b01
@@ -294,19 +304,31 @@ impl Pci {
         self.write_register_u32(bdf, byte_offset + 4, hi)?;
         Ok(())
     }
-    pub fn set_command_and_status_flags(&self, bdf: BusDeviceFunction, flags: u32) -> Result<()> {
-        let cmd_and_status = self.read_register_u32(bdf, 0x04 /* Command and status */)?;
-        self.write_register_u32(
-            bdf,
-            0x04, /* Command and status */
-            flags | cmd_and_status,
-        )
+    pub fn write_register_u16(
+        &self,
+        bdf: BusDeviceFunction,
+        byte_offset: usize,
+        data: u16,
+    ) -> Result<()> {
+        ConfigRegisters::write(self.ecm_base(bdf), byte_offset, data)
+    }
+    /// Sets `flags` in the command register. The status register is not written since writing 1s
+    /// to it clears the status bits.
+    pub fn set_command_flags(&self, bdf: BusDeviceFunction, flags: u16) -> Result<()> {
+        let cmd = self.read_register_u16(bdf, REG_COMMAND)?;
+        self.write_register_u16(bdf, REG_COMMAND, flags | cmd)
+    }
+    pub fn enable_io_space(&self, bdf: BusDeviceFunction) -> Result<()> {
+        self.set_command_flags(bdf, CMD_IO_SPACE)
+    }
+    pub fn enable_memory_space(&self, bdf: BusDeviceFunction) -> Result<()> {
+        self.set_command_flags(bdf, CMD_MEMORY_SPACE)
     }
     pub fn enable_bus_master(&self, bdf: BusDeviceFunction) -> Result<()> {
-        self.set_command_and_status_flags(bdf, 1 << 2 /* Bus Master Enable */)
+        self.set_command_flags(bdf, CMD_BUS_MASTER)
     }
     pub fn disable_interrupt(&self, bdf: BusDeviceFunction) -> Result<()> {
-        self.set_command_and_status_flags(bdf, 1 << 10 /* Interrupt Disable */)
+        self.set_command_flags(bdf, CMD_INTERRUPT_DISABLE)
     }
     pub fn capabilities(&self, id: BusDeviceFunction) -> Option<CapabilityIterator> {
         let status = self.read_register_u16(id, 0x06).ok()?;
@@ -331,29 +353,43 @@ impl Pci {
             Some(VendorDeviceId { vendor, device })
         }
     }
+    /// Reads the vendor and device ids of every BDF once, since scanning all of them is slow.
+    /// The result is returned by list_devices().
+    pub fn scan_devices(&mut self) {
+        self.device_list = BusDeviceFunction::iter()
+            .filter_map(|bdf| Some((bdf, self.read_vendor_id_and_device_id(bdf)?)))
+            .collect();
+    }
+    /// Returns the devices found by the last scan_devices().
+    pub fn list_devices(&self) -> &[(BusDeviceFunction, VendorDeviceId)] {
+        &self.device_list
+    }
     pub fn probe_devices(&self) -> Result<()> {
-        for bdf in BusDeviceFunction::iter() {
-            if let Some(vd) = self.read_vendor_id_and_device_id(bdf) {
-                if self.devices.borrow_mut().contains_key(&bdf) {
-                    continue;
-                }
-                for d in &self.drivers {
-                    if d.supports(vd) {
-                        match d.attach(bdf) {
-                            Ok(di) => {
-                                info!("Driver loaded: {:?}: {}", bdf, di.name());
-                                self.devices.borrow_mut().insert(bdf, Rc::new(di));
-                            }
-                            Err(e) => {
-                                error!("Failed to attach {:?} for {:?}: {:?}", d, bdf, e);
-                            }
-                        }
-                    }
-                }
+        for &(bdf, vd) in self.list_devices() {
+            if self.devices.borrow_mut().contains_key(&bdf) {
+                continue;
+            }
+            if let Some(di) = attach_first_driver(&self.drivers, bdf, vd) {
+                info!("Driver loaded: {:?}: {}", bdf, di.name());
+                self.devices.borrow_mut().insert(bdf, Rc::new(di));
             }
         }
         Ok(())
     }
+    pub fn device(&self, bdf: BusDeviceFunction) -> Option<Rc<Box<dyn PciDeviceDriverInstance>>> {
+        self.devices.borrow().get(&bdf).cloned()
+    }
+    /// Detaches the driver instance from the device at `bdf`.
+    pub fn detach_device(&self, bdf: BusDeviceFunction) -> Result<()> {
+        let di = self
+            .devices
+            .borrow_mut()
+            .remove(&bdf)
+            .ok_or(Error::Failed("Pci: no driver is attached to the device"))?;
+        info!("Driver detached: {:?}: {}", bdf, di.name());
+        di.detach();
+        Ok(())
+    }
     pub fn take() -> &'static Self {
         // SAFETY: Taking static immutable reference here is safe because BOOT_INFO is only set once and no
         // one will take a mutable reference to it.
@@ -374,6 +410,26 @@ unsafe impl Sync for Pci {
 }
 static mut PCI: Option<Pci> = None;
 
+/// Tries `drivers` in order and returns the instance of the first driver that supports the device
+/// and attaches to it successfully.
+fn attach_first_driver(
+    drivers: &[Rc<Box<dyn PciDeviceDriver>>],
+    bdf: BusDeviceFunction,
+    vd: VendorDeviceId,
+) -> Option<Box<dyn PciDeviceDriverInstance>> {
+    for d in drivers {
+        if d.supports(vd) {
+            match d.attach(bdf) {
+                Ok(di) => return Some(di),
+                Err(e) => {
+                    error!("Failed to attach {:?} for {:?}: {:?}", d, bdf, e);
+                }
+            }
+        }
+    }
+    None
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -400,4 +456,127 @@ mod tests {
         }
         assert_eq!(count, 0x10000);
     }
+
+    struct MockDriver {
+        name: &'static str,
+        supports: bool,
+        attaches: bool,
+    }
+    impl PciDeviceDriver for MockDriver {
+        fn supports(&self, _vp: VendorDeviceId) -> bool {
+            self.supports
+        }
+        fn attach(&self, bdf: BusDeviceFunction) -> Result<Box<dyn PciDeviceDriverInstance>> {
+            if self.attaches {
+                Ok(Box::new(MockDriverInstance {
+                    name: self.name,
+                    bdf,
+                }))
+            } else {
+                Err(Error::Failed("MockDriver: attach failed"))
+            }
+        }
+        fn name(&self) -> &str {
+            self.name
+        }
+    }
+    struct MockDriverInstance {
+        name: &'static str,
+        bdf: BusDeviceFunction,
+    }
+    impl PciDeviceDriverInstance for MockDriverInstance {
+        fn name(&self) -> &str {
+            self.name
+        }
+        fn bdf(&self) -> BusDeviceFunction {
+            self.bdf
+        }
+    }
+    fn mock_driver(
+        name: &'static str,
+        supports: bool,
+        attaches: bool,
+    ) -> Rc<Box<dyn PciDeviceDriver>> {
+        Rc::new(Box::new(MockDriver {
+            name,
+            supports,
+            attaches,
+        }))
+    }
+    #[test_case]
+    fn drivers_are_tried_in_order() {
+        let bdf = BusDeviceFunction::new(0, 3, 0).unwrap();
+        let vd = VendorDeviceId {
+            vendor: 0x1234,
+            device: 0x5678,
+        };
+        // The first driver does not support the device
+        let drivers = [
+            mock_driver("first", false, true),
+            mock_driver("second", true, true),
+        ];
+        let di = attach_first_driver(&drivers, bdf, vd).expect("should be attached");
+        assert_eq!(di.name(), "second");
+        // The first driver supports the device but fails to attach
+        let drivers = [
+            mock_driver("first", true, false),
+            mock_driver("second", true, true),
+        ];
+        let di = attach_first_driver(&drivers, bdf, vd).expect("should be attached");
+        assert_eq!(di.name(), "second");
+        // Probing stops at the first driver that attaches
+        let drivers = [
+            mock_driver("first", true, true),
+            mock_driver("second", true, true),
+        ];
+        let di = attach_first_driver(&drivers, bdf, vd).expect("should be attached");
+        assert_eq!(di.name(), "first");
+        let drivers = [
+            mock_driver("first", false, true),
+            mock_driver("second", true, false),
+        ];
+        assert!(attach_first_driver(&drivers, bdf, vd).is_none());
+    }
+    #[test_case]
+    fn command_register_flags() {
+        // Config space of bus 0, device 0, function 0
+        let mut config_space = Box::new([0u32; 1024]);
+        // Status: Capabilities List, Detected Parity Error (write 1 to clear)
+        config_space[1] = 0x8010_0000;
+        let base = config_space.as_mut_ptr() as usize;
+        let pci = Pci {
+            ecm_range: base..base + size_of::<[u32; 1024]>(),
+            drivers: Vec::new(),
+            device_list: Vec::new(),
+            devices: RefCell::new(BTreeMap::new()),
+        };
+        let bdf = BusDeviceFunction::new(0, 0, 0).unwrap();
+        pci.enable_bus_master(bdf).unwrap();
+        assert_eq!(pci.read_register_u16(bdf, REG_COMMAND), Ok(0b100));
+        pci.enable_memory_space(bdf).unwrap();
+        assert_eq!(pci.read_register_u16(bdf, REG_COMMAND), Ok(0b110));
+        pci.enable_io_space(bdf).unwrap();
+        assert_eq!(pci.read_register_u16(bdf, REG_COMMAND), Ok(0b111));
+        pci.disable_interrupt(bdf).unwrap();
+        assert_eq!(pci.read_register_u16(bdf, REG_COMMAND), Ok(0x0407));
+        // The status register should be untouched
+        assert_eq!(pci.read_register_u16(bdf, 0x06), Ok(0x8010));
+        drop(pci);
+        assert_eq!(config_space[1], 0x8010_0407);
+    }
+    #[test_case]
+    fn driver_instance_knows_its_bdf() {
+        let vd = VendorDeviceId {
+            vendor: 0x1234,
+            device: 0x5678,
+        };
+        let drivers = [mock_driver("mock", true, true)];
+        for bdf in [
+            BusDeviceFunction::new(0, 3, 0).unwrap(),
+            BusDeviceFunction::new(1, 31, 7).unwrap(),
+        ] {
+            let di = attach_first_driver(&drivers, bdf, vd).expect("should be attached");
+            assert!(di.bdf() == bdf);
+        }
+    }
 }
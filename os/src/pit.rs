@@ -0,0 +1,73 @@
+use crate::error::Error;
+use crate::error::Result;
+use crate::tsc::freq_from_calibration;
+use crate::x86_64::busy_loop_hint;
+use crate::x86_64::read_io_port_u8;
+use crate::x86_64::write_io_port_u8;
+
+/// Frequency of the input clock of the PIT (Intel 8254) in Hz
+pub const PIT_FREQ: u64 = 1_193_182;
+
+const PORT_PIT_CH2_DATA: u16 = 0x42;
+const PORT_PIT_MODE_COMMAND: u16 = 0x43;
+// Channel 2, lobyte/hibyte access, mode 0 (interrupt on terminal count), binary
+const PIT_CMD_CH2_ONESHOT: u8 = 0b1011_0000;
+// Port 0x61 (NMI Status and Control) gates the channel 2 and exposes its output.
+const PORT_NMI_SC: u16 = 0x61;
+const NMI_SC_TIMER2_GATE: u8 = 1 << 0;
+const NMI_SC_SPEAKER_DATA: u8 = 1 << 1;
+const NMI_SC_TIMER2_OUT: u8 = 1 << 5;
+/// Gives up waiting for the PIT after this many polls, in case the platform does not have it
+const MAX_POLLS: u64 = 1 << 28;
+
+/// Returns the initial count of the PIT to wait for `duration_ms`, or None if it does not fit in
+/// the 16-bit counter (i.e. `duration_ms` is 0 or longer than ~54 ms).
+pub fn count_for_ms(duration_ms: u64) -> Option<u16> {
+    u16::try_from(PIT_FREQ * duration_ms / 1000)
+        .ok()
+        .filter(|count| *count > 0)
+}
+
+/// Measures the frequency of `counter` by busy-waiting for `duration_ms` with the channel 2 of
+/// the PIT, which is available even if the platform does not have HPET.
+/// c.f. pit_calibrate_tsc() in arch/x86/kernel/tsc.c of Linux
+pub fn measure_freq(counter: impl Fn() -> u64, duration_ms: u64) -> Result<u64> {
+    let count =
+        count_for_ms(duration_ms).ok_or(Error::Failed("PIT: calibration duration out of range"))?;
+    // Stop the channel 2 and keep the speaker off while programming it
+    let nmi_sc = read_io_port_u8(PORT_NMI_SC) & !(NMI_SC_TIMER2_GATE | NMI_SC_SPEAKER_DATA);
+    write_io_port_u8(PORT_NMI_SC, nmi_sc);
+    write_io_port_u8(PORT_PIT_MODE_COMMAND, PIT_CMD_CH2_ONESHOT);
+    write_io_port_u8(PORT_PIT_CH2_DATA, count as u8);
+    write_io_port_u8(PORT_PIT_CH2_DATA, (count >> 8) as u8);
+    let start = counter();
+    write_io_port_u8(PORT_NMI_SC, nmi_sc | NMI_SC_TIMER2_GATE);
+    let mut num_polls = 0;
+    while read_io_port_u8(PORT_NMI_SC) & NMI_SC_TIMER2_OUT == 0 {
+        num_polls += 1;
+        if num_polls > MAX_POLLS {
+            write_io_port_u8(PORT_NMI_SC, nmi_sc);
+            return Err(Error::Failed("PIT: timed out"));
+        }
+        busy_loop_hint();
+    }
+    let ticks = counter().wrapping_sub(start);
+    write_io_port_u8(PORT_NMI_SC, nmi_sc);
+    match freq_from_calibration(ticks, count as u64, PIT_FREQ) {
+        0 => Err(Error::Failed("PIT: the counter did not advance")),
+        freq => Ok(freq),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test_case]
+    fn count_for_ms_fits_in_16_bits() {
+        assert_eq!(count_for_ms(10), Some(11931));
+        assert_eq!(count_for_ms(54), Some(64431));
+        assert_eq!(count_for_ms(55), None);
+        assert_eq!(count_for_ms(0), None);
+    }
+}
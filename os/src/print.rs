@@ -1,3 +1,5 @@
+extern crate alloc;
+
 use crate::println;
 use crate::serial::SerialPort;
 use crate::vram::VRAMBufferInfo;
@@ -5,16 +7,51 @@ use core::cell::RefCell;
 use core::fmt;
 use core::mem::size_of;
 use core::slice;
+use core::sync::atomic::AtomicBool;
+use core::sync::atomic::Ordering;
 use noli::text_area::TextArea;
 
+#[cfg(test)]
+use crate::mutex::Mutex;
+#[cfg(test)]
+use alloc::format;
+#[cfg(test)]
+use alloc::string::String;
+#[cfg(test)]
+use alloc::vec::Vec;
+
 pub struct GlobalPrinter {
     text_area: RefCell<Option<TextArea<VRAMBufferInfo>>>,
+    // Set when a print drew the cursor again after hiding it. See take_cursor_redrawn().
+    cursor_redrawn: AtomicBool,
 }
 
 impl GlobalPrinter {
     pub fn set_text_area(&self, text_area: TextArea<VRAMBufferInfo>) {
         *self.text_area.borrow_mut() = Some(text_area);
     }
+    /// Clears the text area and moves its cursor to the top-left.
+    pub fn clear(&self) {
+        if let Some(text_area) = &mut *self.text_area.borrow_mut() {
+            text_area.clear().unwrap();
+        }
+    }
+    /// Shows or hides the cursor of the text area.
+    pub fn set_cursor_visible(&self, visible: bool) {
+        if let Some(text_area) = &mut *self.text_area.borrow_mut() {
+            if visible {
+                // The cursor can be out of the screen only if the text area is, so ignore it
+                let _ = text_area.show_cursor();
+            } else {
+                text_area.hide_cursor();
+            }
+        }
+    }
+    /// Returns true (only once) if a print redrew the cursor since the last call, so that the
+    /// blinking can restart from the "on" phase.
+    pub fn take_cursor_redrawn(&self) -> bool {
+        self.cursor_redrawn.swap(false, Ordering::SeqCst)
+    }
 }
 
 /// # Safety
@@ -24,6 +61,7 @@ unsafe impl Sync for GlobalPrinter {}
 
 pub static GLOBAL_PRINTER: GlobalPrinter = GlobalPrinter {
     text_area: RefCell::new(None),
+    cursor_redrawn: AtomicBool::new(false),
 };
 
 #[macro_export]
@@ -54,12 +92,53 @@ macro_rules! error {
             ($($arg:tt)*) => ($crate::print!("[ERROR] {}:{}:  {}\n", file!(), line!(), format_args!($($arg)*)));
 }
 
+/// Output of print! and println! goes here instead of the serial port and the screen while this
+/// is Some. See capture_output().
+#[cfg(test)]
+static CAPTURED_OUTPUT: Mutex<Option<Vec<u8>>> = Mutex::new(None);
+
+/// Runs `f` and returns everything printed with the print macros during it, so that tests can
+/// check the output of commands without a console.
+#[cfg(test)]
+pub fn capture_output<F: FnOnce()>(f: F) -> String {
+    let prev = CAPTURED_OUTPUT.lock().replace(Vec::new());
+    f();
+    let captured = core::mem::replace(&mut *CAPTURED_OUTPUT.lock(), prev).unwrap_or_default();
+    String::from_utf8_lossy(&captured).into_owned()
+}
+
+#[cfg(test)]
+fn try_capture(args: fmt::Arguments) -> bool {
+    match CAPTURED_OUTPUT.lock().as_mut() {
+        Some(buf) => {
+            buf.extend_from_slice(format!("{args}").as_bytes());
+            true
+        }
+        None => false,
+    }
+}
+#[cfg(not(test))]
+fn try_capture(_args: fmt::Arguments) -> bool {
+    false
+}
+
 #[doc(hidden)]
 pub fn _print(args: fmt::Arguments) {
+    if try_capture(args) {
+        return;
+    }
     let mut writer = SerialPort::default();
     fmt::write(&mut writer, args).unwrap();
     match &mut *GLOBAL_PRINTER.text_area.borrow_mut() {
-        Some(w) => fmt::write(w, args).unwrap(),
+        Some(w) => {
+            let cursor_was_shown = w.is_cursor_shown();
+            fmt::write(w, args).unwrap();
+            if cursor_was_shown {
+                // Printing hides the cursor, so draw it at the new position right away
+                let _ = w.show_cursor();
+                GLOBAL_PRINTER.cursor_redrawn.store(true, Ordering::SeqCst);
+            }
+        }
         None => {}
     }
 }
@@ -121,3 +200,23 @@ pub fn hexdump(bytes: &[u8]) {
 pub fn hexdump_struct<T>(data: &T) {
     hexdump(unsafe { slice::from_raw_parts(data as *const T as *const u8, size_of::<T>()) })
 }
+
+#[test_case]
+fn println_output_can_be_captured() {
+    let output = capture_output(|| {
+        println!("Hello, {}!", "wasabi");
+        print!("{:02X}", 0xAu8);
+    });
+    assert_eq!(output, "Hello, wasabi!\n0A");
+}
+
+#[test_case]
+fn nested_capture_output_is_separated() {
+    let outer = capture_output(|| {
+        print!("outer ");
+        let inner = capture_output(|| print!("inner"));
+        assert_eq!(inner, "inner");
+        print!("done");
+    });
+    assert_eq!(outer, "outer done");
+}
@@ -2,10 +2,15 @@ extern crate alloc;
 
 use crate::error::Error;
 use crate::error::Result;
+use crate::fd::FileDescriptor;
+use crate::fd::FileDescriptorTable;
+use crate::memory::AddressRange;
 use crate::memory::ContiguousPhysicalMemoryPages;
 use crate::mutex::Mutex;
 use crate::net::manager::Network;
 use crate::net::tcp::TcpSocket;
+use crate::ramfs::OpenFile;
+use crate::ramfs::Ramfs;
 use crate::x86_64::context::unchecked_load_context;
 use crate::x86_64::context::unchecked_switch_context;
 use crate::x86_64::context::ExecutionContext;
@@ -15,6 +20,7 @@ use alloc::collections::btree_map;
 use alloc::collections::BTreeMap;
 use alloc::collections::VecDeque;
 use alloc::rc::Rc;
+use alloc::vec::Vec;
 use core::future::Future;
 use core::pin::Pin;
 use core::sync::atomic::AtomicBool;
@@ -43,6 +49,23 @@ pub struct ProcessContext {
     exit_code: Rc<AtomicI64>,
     tcp_sockets: BTreeMap<i64, Rc<TcpSocket>>,
     next_tcp_socket_handle: i64,
+    fds: FileDescriptorTable,
+    // Back-buffer written by the app directly. Dropping it revokes the access from the app.
+    framebuffer: Option<ContiguousPhysicalMemoryPages>,
+    // Memory ranges that the process is allowed to pass to syscalls
+    user_ranges: Vec<AddressRange>,
+}
+impl Drop for ProcessContext {
+    fn drop(&mut self) {
+        if self.tcp_sockets.is_empty() {
+            return;
+        }
+        // The sockets opened by the app are not used by anyone else
+        let network = Network::take();
+        for sock in self.tcp_sockets.values() {
+            network.close_tcp_socket(sock);
+        }
+    }
 }
 impl ProcessContext {
     pub fn new(
@@ -60,11 +83,17 @@ impl ProcessContext {
             }
             None => None,
         };
-        Ok(Self {
-            args_region,
-            stack_region,
-            ..Default::default()
-        })
+        let user_ranges = stack_region
+            .iter()
+            .chain(args_region.iter())
+            .map(|r| r.range())
+            .collect();
+        // Fields are assigned one by one since ProcessContext implements Drop
+        let mut this = Self::default();
+        this.args_region = args_region;
+        this.stack_region = stack_region;
+        this.user_ranges = user_ranges;
+        Ok(this)
     }
     pub fn new_with_fn(f: extern "sysv64" fn(u64), arg1: u64) -> Result<ProcessContext> {
         let mut stack = ContiguousPhysicalMemoryPages::alloc_bytes(1024 * 1024)?;
@@ -86,6 +115,29 @@ impl ProcessContext {
     pub fn context(&mut self) -> &Mutex<ExecutionContext> {
         &mut self.context
     }
+    pub fn add_user_range(&mut self, range: AddressRange) {
+        self.user_ranges.push(range)
+    }
+    pub fn user_ranges(&self) -> &[AddressRange] {
+        &self.user_ranges
+    }
+    /// Allocates a zero-filled back-buffer of `num_bytes` and makes it accessible from the app.
+    /// Returns the same buffer if it is already mapped.
+    pub fn map_framebuffer(&mut self, num_bytes: usize) -> Result<AddressRange> {
+        if let Some(framebuffer) = &self.framebuffer {
+            return Ok(framebuffer.range());
+        }
+        let mut framebuffer = ContiguousPhysicalMemoryPages::alloc_bytes(num_bytes)?;
+        framebuffer.fill_with_bytes(0);
+        framebuffer.set_page_attr(PageAttr::ReadWriteUser)?;
+        let range = framebuffer.range();
+        self.user_ranges.push(range.clone());
+        self.framebuffer = Some(framebuffer);
+        Ok(range)
+    }
+    pub fn framebuffer(&self) -> Option<&ContiguousPhysicalMemoryPages> {
+        self.framebuffer.as_ref()
+    }
     pub fn args_region_start_addr(&self) -> Option<usize> {
         self.args_region.as_ref().map(|ar| ar.range().start())
     }
@@ -106,11 +158,25 @@ impl ProcessContext {
     pub fn tcp_socket(&self, handle: i64) -> Option<Rc<TcpSocket>> {
         self.tcp_sockets.get(&handle).cloned()
     }
+    // Open a file in the Ramfs and issue a file descriptor for it
+    pub fn open_file(&mut self, name: &str, create: bool) -> Result<i64> {
+        let file = OpenFile::open(Ramfs::take(), name, create)?;
+        Ok(self.fds.insert(FileDescriptor::Ramfs(file)))
+    }
+    pub fn fd_mut(&mut self, fd: i64) -> Option<&mut FileDescriptor> {
+        self.fds.get_mut(fd)
+    }
+    pub fn close_fd(&mut self, fd: i64) -> Option<FileDescriptor> {
+        self.fds.remove(fd)
+    }
 }
 
 pub struct Scheduler {
     // The first element is the "current" process
     queue: Mutex<VecDeque<ProcessContext>>,
+    // The process that exited most recently. It can't be dropped on exit since its stack is
+    // still in use at that point, so it is kept here until the next process exits.
+    exited_process: Mutex<Option<ProcessContext>>,
 }
 impl Scheduler {
     pub fn root() -> &'static Self {
@@ -119,6 +185,7 @@ impl Scheduler {
     pub const fn new() -> Self {
         Self {
             queue: Mutex::new(VecDeque::new()),
+            exited_process: Mutex::new(None),
         }
     }
     pub fn schedule(&self, proc: ProcessContext) {
@@ -128,7 +195,7 @@ impl Scheduler {
         self.queue.lock().clear();
     }
     pub fn exit_current_process(&self, exit_code: i64) -> ! {
-        let to = {
+        let (to, prev_exited) = {
             let mut queue = self.queue.lock();
             if queue.len() <= 1 {
                 // No process to switch
@@ -147,8 +214,11 @@ impl Scheduler {
                     .lock()
                     .as_mut_ptr()
             };
-            to
+            let prev_exited = self.exited_process.lock().replace(from);
+            (to, prev_exited)
         };
+        // The previously exited process is not running anymore, so its resources can be freed
+        drop(prev_exited);
         unsafe { unchecked_load_context(to) };
         unreachable!("Nothing should come back here");
     }
@@ -162,16 +232,23 @@ impl Scheduler {
                 return;
             }
             queue.rotate_left(1);
+            if queue
+                .front()
+                .is_some_and(|to| to.exited.load(Ordering::SeqCst))
+            {
+                // The queue has been rotated already, so it can't be trusted after this
+                queue.poison();
+                panic!("trying to switch to exited process...!!!")
+            }
             // SAFETY: to and from is valid until the context switch happens. Also, the execution
             // should not be interrupted until the context switch completes.
             unsafe {
                 let to = queue
                     .front_mut()
-                    .expect("queue should have a process to swith to");
-                if to.exited.load(Ordering::SeqCst) {
-                    panic!("trying to switch to exited process...!!!")
-                }
-                let to = to.context().lock().as_mut_ptr();
+                    .expect("queue should have a process to swith to")
+                    .context()
+                    .lock()
+                    .as_mut_ptr();
                 let from = queue
                     .back_mut()
                     .expect("queue should have a process to swith to")
@@ -215,7 +292,9 @@ impl<'a> Future for ProcessCompletionFuture<'a> {
 #[cfg(test)]
 mod test {
     use super::*;
+    use crate::allocator::ALLOCATOR;
     use crate::executor::block_on;
+    use crate::util::PAGE_SIZE;
     pub static ANOTHER_FUNC_COUNT: Mutex<usize> = Mutex::new(0);
     pub static TEST_SCHEDULER: Scheduler = Scheduler::new();
     extern "sysv64" fn another_proc_func(_: u64) {
@@ -290,4 +369,20 @@ mod test {
         TEST_SCHEDULER.schedule(proc);
         assert_eq!(block_on(wait), Ok(0));
     }
+    fn create_and_drop_app_process() -> Result<()> {
+        let mut stack = ContiguousPhysicalMemoryPages::alloc_bytes(1024 * 1024)?;
+        stack.set_page_attr(PageAttr::ReadWriteUser)?;
+        let proc = ProcessContext::new(Some(stack), Some(&["app", "arg1"]))?;
+        drop(proc);
+        Ok(())
+    }
+    #[test_case]
+    fn process_resources_are_freed_on_drop() {
+        create_and_drop_app_process().expect("Proc creation should succeed");
+        let free_after_first = ALLOCATOR.free_bytes();
+        create_and_drop_app_process().expect("Proc creation should succeed");
+        let free_after_second = ALLOCATOR.free_bytes();
+        // Allow some fixed overhead (e.g. page tables created on demand)
+        assert!(free_after_second + 8 * PAGE_SIZE >= free_after_first);
+    }
 }
@@ -0,0 +1,162 @@
+extern crate alloc;
+
+use crate::error::Error;
+use crate::error::Result;
+use crate::mutex::Mutex;
+use alloc::collections::BTreeMap;
+use alloc::string::String;
+use alloc::vec::Vec;
+
+static RAMFS: Ramfs = Ramfs::new();
+
+/// A flat in-memory filesystem to give apps a scratch storage.
+/// The contents are lost on reboot.
+pub struct Ramfs {
+    files: Mutex<BTreeMap<String, Vec<u8>>>,
+}
+impl Ramfs {
+    pub const fn new() -> Self {
+        Self {
+            files: Mutex::new(BTreeMap::new()),
+        }
+    }
+    pub fn take() -> &'static Ramfs {
+        &RAMFS
+    }
+    /// Creates an empty file. An existing file with the same name is truncated.
+    pub fn create(&self, name: &str) -> Result<()> {
+        if name.is_empty() {
+            return Err(Error::Failed("ramfs: empty file name"));
+        }
+        self.files.lock().insert(name.into(), Vec::new());
+        Ok(())
+    }
+    /// Appends `bytes` to the file.
+    pub fn write(&self, name: &str, bytes: &[u8]) -> Result<()> {
+        self.files
+            .lock()
+            .get_mut(name)
+            .ok_or(Error::Failed("ramfs: no such file"))?
+            .extend_from_slice(bytes);
+        Ok(())
+    }
+    pub fn read(&self, name: &str) -> Option<Vec<u8>> {
+        self.files.lock().get(name).cloned()
+    }
+    /// Copies the contents of the file from `offset` into `buf`. Returns the number of bytes
+    /// copied, or None if the file does not exist.
+    pub fn read_at(&self, name: &str, offset: usize, buf: &mut [u8]) -> Option<usize> {
+        let files = self.files.lock();
+        let rest = files.get(name)?.get(offset..).unwrap_or_default();
+        let len = core::cmp::min(rest.len(), buf.len());
+        buf[..len].copy_from_slice(&rest[..len]);
+        Some(len)
+    }
+    pub fn exists(&self, name: &str) -> bool {
+        self.files.lock().contains_key(name)
+    }
+    /// Returns the file names in the lexicographical order.
+    pub fn list(&self) -> Vec<String> {
+        self.files.lock().keys().cloned().collect()
+    }
+}
+impl Default for Ramfs {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// A file in the Ramfs opened by an app.
+/// Reads advance the offset, while writes are always appended to the end of the file.
+pub struct OpenFile {
+    name: String,
+    read_offset: usize,
+}
+impl OpenFile {
+    pub fn open(fs: &Ramfs, name: &str, create: bool) -> Result<Self> {
+        if create {
+            fs.create(name)?;
+        } else if !fs.exists(name) {
+            return Err(Error::Failed("ramfs: no such file"));
+        }
+        Ok(Self {
+            name: name.into(),
+            read_offset: 0,
+        })
+    }
+    /// Returns the number of bytes copied into `buf`, or 0 at the end of the file.
+    pub fn read(&mut self, fs: &Ramfs, buf: &mut [u8]) -> Result<usize> {
+        let len = fs
+            .read_at(&self.name, self.read_offset, buf)
+            .ok_or(Error::Failed("ramfs: no such file"))?;
+        self.read_offset += len;
+        Ok(len)
+    }
+    pub fn write(&self, fs: &Ramfs, data: &[u8]) -> Result<usize> {
+        fs.write(&self.name, data)?;
+        Ok(data.len())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    #[test_case]
+    fn create_write_read_round_trip() {
+        let fs = Ramfs::new();
+        assert_eq!(fs.read("a.txt"), None);
+        assert!(fs.write("a.txt", b"hello").is_err());
+        fs.create("a.txt").unwrap();
+        assert_eq!(fs.read("a.txt"), Some(Vec::new()));
+        fs.write("a.txt", b"hello, ").unwrap();
+        fs.write("a.txt", b"world").unwrap();
+        assert_eq!(
+            fs.read("a.txt").as_deref(),
+            Some(b"hello, world".as_slice())
+        );
+        // Creating it again truncates the file
+        fs.create("a.txt").unwrap();
+        assert_eq!(fs.read("a.txt"), Some(Vec::new()));
+        assert!(fs.create("").is_err());
+    }
+    #[test_case]
+    fn list_returns_sorted_names() {
+        let fs = Ramfs::new();
+        assert!(fs.list().is_empty());
+        fs.create("b").unwrap();
+        fs.create("a").unwrap();
+        fs.create("c").unwrap();
+        fs.create("a").unwrap();
+        assert_eq!(fs.list(), ["a", "b", "c"]);
+    }
+    #[test_case]
+    fn read_at_copies_from_offset() {
+        let fs = Ramfs::new();
+        let mut buf = [0u8; 4];
+        assert_eq!(fs.read_at("a", 0, &mut buf), None);
+        fs.create("a").unwrap();
+        fs.write("a", b"hello").unwrap();
+        assert_eq!(fs.read_at("a", 3, &mut buf), Some(2));
+        assert_eq!(&buf[..2], b"lo");
+        assert_eq!(fs.read_at("a", 5, &mut buf), Some(0));
+        assert_eq!(fs.read_at("a", 100, &mut buf), Some(0));
+    }
+    #[test_case]
+    fn open_file_reads_sequentially() {
+        let fs = Ramfs::new();
+        assert!(OpenFile::open(&fs, "log", false).is_err());
+        let writer = OpenFile::open(&fs, "log", true).unwrap();
+        assert_eq!(writer.write(&fs, b"abcde"), Ok(5));
+        let mut reader = OpenFile::open(&fs, "log", false).unwrap();
+        let mut buf = [0u8; 3];
+        assert_eq!(reader.read(&fs, &mut buf), Ok(3));
+        assert_eq!(&buf, b"abc");
+        assert_eq!(reader.read(&fs, &mut buf), Ok(2));
+        assert_eq!(&buf[..2], b"de");
+        assert_eq!(reader.read(&fs, &mut buf), Ok(0));
+        // Appended data becomes visible to the reader
+        writer.write(&fs, b"f").unwrap();
+        assert_eq!(reader.read(&fs, &mut buf), Ok(1));
+        assert_eq!(buf[0], b'f');
+    }
+}
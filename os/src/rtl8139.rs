@@ -14,7 +14,7 @@ use crate::pci::Pci;
 use crate::pci::PciDeviceDriver;
 use crate::pci::PciDeviceDriverInstance;
 use crate::pci::VendorDeviceId;
-use crate::x86_64::busy_loop_hint;
+use crate::util::poll_until;
 use crate::x86_64::read_io_port_u32;
 use crate::x86_64::read_io_port_u8;
 use crate::x86_64::write_io_port_u16;
@@ -23,6 +23,7 @@ use crate::x86_64::write_io_port_u8;
 use alloc::boxed::Box;
 use alloc::collections::VecDeque;
 use alloc::rc::Rc;
+use alloc::rc::Weak;
 use core::mem::MaybeUninit;
 use core::pin::Pin;
 use core::slice;
@@ -85,6 +86,57 @@ impl TxContext {
     }
 }
 
+// IDR0-5: ID registers that hold the MAC address
+const REG_IDR0: u16 = 0x00;
+const REG_RBSTART: u16 = 0x30;
+const REG_CMD: u16 = 0x37;
+const REG_IMR: u16 = 0x3C;
+const REG_TCR: u16 = 0x40;
+const REG_RCR: u16 = 0x44;
+const REG_CONFIG1: u16 = 0x52;
+
+const CMD_TE: u8 = 1 << 2;
+const CMD_RE: u8 = 1 << 3;
+const CMD_RST: u8 = 1 << 4;
+
+// Accept All Packets (promiscuous)
+const RCR_AAP: u32 = 1 << 0;
+// Accept Physical Match Packets
+const RCR_APM: u32 = 1 << 1;
+// Accept Multicast Packets
+const RCR_AM: u32 = 1 << 2;
+// Accept Broadcast Packets
+const RCR_AB: u32 = 1 << 3;
+// Let the packets overflow the end of the rx buffer instead of wrapping around.
+// RTL8139_RXBUF_SIZE has a room for it.
+const RCR_WRAP: u32 = 1 << 7;
+// RBLEN (bits 11-12) = 0b00 selects 8K + 16 bytes of the rx buffer.
+const RCR_RBLEN_8K: u32 = 0b00 << 11;
+
+// Interframe Gap Time = 0b11 (the standard 9.6us), Max DMA Burst Size = 0b111 (2048 bytes)
+const TCR_VALUE: u32 = (0b11 << 24) | (0b111 << 8);
+
+const RESET_POLL_LIMIT: usize = 1_000_000;
+
+/// Returns the value for the Receive Configuration Register.
+fn rcr_value(promiscuous: bool) -> u32 {
+    let rcr = RCR_APM | RCR_AM | RCR_AB | RCR_WRAP | RCR_RBLEN_8K;
+    if promiscuous {
+        rcr | RCR_AAP
+    } else {
+        rcr
+    }
+}
+
+/// Assembles the MAC address from the ID registers read by `read_u8(offset)`.
+fn eth_addr_from_idr(read_u8: impl Fn(u16) -> u8) -> EthernetAddr {
+    let mut eth_addr = [0u8; 6];
+    for (i, e) in eth_addr.iter_mut().enumerate() {
+        *e = read_u8(REG_IDR0 + i as u16);
+    }
+    EthernetAddr::new(eth_addr)
+}
+
 struct Rtl8139 {
     _bdf: BusDeviceFunction,
     io_base: u16,
@@ -100,19 +152,18 @@ impl Rtl8139 {
         pci.enable_bus_master(bdf)?;
         // Assume that BAR0 has IO Port address
         let io_base = pci.try_bar0_io(bdf)?;
-        let mut eth_addr = [0u8; 6];
-        for (i, e) in eth_addr.iter_mut().enumerate() {
-            *e = read_io_port_u8(io_base + i as u16);
-        }
-        let eth_addr = EthernetAddr::new(eth_addr);
+        pci.enable_io_space(bdf)?;
+        let eth_addr = eth_addr_from_idr(|offset| read_io_port_u8(io_base + offset));
         info!("eth_addr: {:?}", eth_addr);
         // Turn on
-        write_io_port_u8(io_base + 0x52, 0);
+        write_io_port_u8(io_base + REG_CONFIG1, 0);
         // Software Reset
-        write_io_port_u8(io_base + 0x37, 0x10);
-        while (read_io_port_u8(io_base + 0x37) & 0x10) != 0 {
-            busy_loop_hint();
-        }
+        write_io_port_u8(io_base + REG_CMD, CMD_RST);
+        poll_until(
+            || (read_io_port_u8(io_base + REG_CMD) & CMD_RST) == 0,
+            RESET_POLL_LIMIT,
+        )
+        .or(Err(Error::Failed("RTL8139: software reset timed out")))?;
 
         let d = Self {
             _bdf: bdf,
@@ -126,10 +177,14 @@ impl Rtl8139 {
             rx.buf.as_ref().as_ptr()
         };
         assert!((rx_buf_ptr as usize) < ((u32::MAX) as usize - RTL8139_RXBUF_SIZE));
-        write_io_port_u32(io_base + 0x30, rx_buf_ptr as usize as u32);
-        write_io_port_u32(io_base + 0x3C, 0x0005); // Interrupts: Transmit OK, Receive OK
-        write_io_port_u32(io_base + 0x44, 0x8f); // WRAP+AB+AM+APM+AAP (receive any type of packets)
-        write_io_port_u8(io_base + 0x37, 0x0C); // RE+TE (Enable Rx and Tx)
+        write_io_port_u32(io_base + REG_RBSTART, rx_buf_ptr as usize as u32);
+        write_io_port_u32(io_base + REG_IMR, 0x0005); // Interrupts: Transmit OK, Receive OK
+
+        // Enable Rx and Tx before configuring them, since RCR and TCR may not be writable while
+        // they are disabled (see rtl8139_hw_start() in Linux's 8139too.c)
+        write_io_port_u8(io_base + REG_CMD, CMD_RE | CMD_TE);
+        write_io_port_u32(io_base + REG_RCR, rcr_value(true)); // Receive any type of packets
+        write_io_port_u32(io_base + REG_TCR, TCR_VALUE);
 
         Ok(d)
     }
@@ -266,24 +321,64 @@ impl NetworkInterface for Rtl8139 {
     }
 }
 
-pub struct Rtl8139DriverInstance {}
+pub struct Rtl8139DriverInstance {
+    bdf: BusDeviceFunction,
+    // Keeps the per-device state (e.g. ring buffers) owned by the instance
+    device: Rc<Rtl8139>,
+}
 impl Rtl8139DriverInstance {
     fn new(bdf: BusDeviceFunction) -> Result<Self> {
         let d = Rc::new(Rtl8139::new(bdf)?);
+        let iface = Rc::downgrade(&d) as Weak<dyn NetworkInterface>;
+        Network::take().register_interface(iface.clone());
         {
             let d = Rc::downgrade(&d);
-            Network::take().register_interface(d);
+            spawn_global(async move {
+                // Stop polling once the interface is unregistered by detach()
+                while Network::take().is_interface_registered(&iface) {
+                    let Some(d) = d.upgrade() else {
+                        break;
+                    };
+                    d.poll().await?
+                }
+                info!("rtl8139: stopped polling");
+                Ok(())
+            });
         }
-        spawn_global(async move {
-            loop {
-                d.poll().await?
-            }
-        });
-        Ok(Self {})
+        Ok(Self { bdf, device: d })
     }
 }
 impl PciDeviceDriverInstance for Rtl8139DriverInstance {
     fn name(&self) -> &str {
         "Rtl8139DriverInstance"
     }
+    fn bdf(&self) -> BusDeviceFunction {
+        self.bdf
+    }
+    fn detach(&self) {
+        // The device is released after this, so stop the interface first
+        let iface = Rc::downgrade(&self.device) as Weak<dyn NetworkInterface>;
+        Network::take().unregister_interface(&iface);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    #[test_case]
+    fn rcr_configuration_word() {
+        // WRAP+AB+AM+APM+AAP
+        assert_eq!(rcr_value(true), 0x8f);
+        assert_eq!(rcr_value(false), 0x8e);
+        // The rx buffer should be large enough for RBLEN = 8K + 16 with WRAP
+        assert_eq!(RTL8139_RXBUF_SIZE, 8192 + 16 + 1500);
+    }
+    #[test_case]
+    fn eth_addr_is_read_from_idr() {
+        let regs = [0x52, 0x54, 0x00, 0x12, 0x34, 0x56, 0xff, 0xff];
+        assert_eq!(
+            eth_addr_from_idr(|offset| regs[offset as usize]),
+            EthernetAddr::new([0x52, 0x54, 0x00, 0x12, 0x34, 0x56])
+        );
+    }
 }
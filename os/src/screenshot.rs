@@ -0,0 +1,136 @@
+extern crate alloc;
+
+use alloc::format;
+use alloc::string::String;
+use alloc::vec::Vec;
+use core::fmt;
+use noli::bitmap::swap_rb;
+use noli::bitmap::Bitmap;
+
+const BASE64_CHARS: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+
+/// Returns the header of a binary PPM (P6) image with 8-bit channels.
+pub fn ppm_header(width: i64, height: i64) -> String {
+    format!("P6\n{width} {height}\n255\n")
+}
+
+/// Extracts [R, G, B] from a 0xAARRGGBB color.
+pub fn argb_to_rgb(argb: u32) -> [u8; 3] {
+    [(argb >> 16) as u8, (argb >> 8) as u8, argb as u8]
+}
+
+/// Encodes the bytes written into base64, one line per write() call. Bytes which do not fill
+/// a 3-byte group are carried over to the next line, so the lines can be decoded as a whole
+/// after joining them.
+struct Base64Writer<'a, W: fmt::Write> {
+    w: &'a mut W,
+    pending: Vec<u8>,
+}
+impl<'a, W: fmt::Write> Base64Writer<'a, W> {
+    fn new(w: &'a mut W) -> Self {
+        Self {
+            w,
+            pending: Vec::new(),
+        }
+    }
+    fn encode_group(&mut self, group: &[u8]) -> fmt::Result {
+        let v = (group[0] as u32) << 16
+            | (*group.get(1).unwrap_or(&0) as u32) << 8
+            | *group.get(2).unwrap_or(&0) as u32;
+        for i in 0..4 {
+            let c = if i <= group.len() {
+                BASE64_CHARS[((v >> (18 - 6 * i)) & 0x3F) as usize] as char
+            } else {
+                '='
+            };
+            self.w.write_char(c)?;
+        }
+        Ok(())
+    }
+    fn write(&mut self, data: &[u8]) -> fmt::Result {
+        self.pending.extend_from_slice(data);
+        let num_full_bytes = self.pending.len() / 3 * 3;
+        let pending = core::mem::take(&mut self.pending);
+        for group in pending[..num_full_bytes].chunks(3) {
+            self.encode_group(group)?;
+        }
+        self.pending = pending[num_full_bytes..].to_vec();
+        self.w.write_char('\n')
+    }
+    fn finish(mut self) -> fmt::Result {
+        let pending = core::mem::take(&mut self.pending);
+        if !pending.is_empty() {
+            self.encode_group(&pending)?;
+            self.w.write_char('\n')?;
+        }
+        Ok(())
+    }
+}
+
+/// Writes the bitmap as a base64-encoded PPM image, between "SCREENSHOT BEGIN" and
+/// "SCREENSHOT END" lines. The image is encoded row by row so the whole image is never held in
+/// memory. To restore the image on the host, decode the lines in between with `base64 -d`.
+pub fn write_screenshot<T: Bitmap, W: fmt::Write>(bitmap: &T, w: &mut W) -> fmt::Result {
+    let width = bitmap.width();
+    let height = bitmap.height();
+    writeln!(w, "SCREENSHOT BEGIN {width}x{height}")?;
+    let mut encoder = Base64Writer::new(w);
+    encoder.write(ppm_header(width, height).as_bytes())?;
+    let mut row = Vec::with_capacity(width as usize * 3);
+    for y in 0..height {
+        row.clear();
+        for x in 0..width {
+            let pixel = *bitmap.pixel_at(x, y).ok_or(fmt::Error)?;
+            let argb = if bitmap.is_rgb_order() {
+                swap_rb(pixel)
+            } else {
+                pixel
+            };
+            row.extend_from_slice(&argb_to_rgb(argb));
+        }
+        encoder.write(&row)?;
+    }
+    encoder.finish()?;
+    writeln!(w, "SCREENSHOT END")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use noli::bitmap::bitmap_draw_point;
+    use noli::bitmap::BitmapBuffer;
+    #[test_case]
+    fn ppm_header_has_size() {
+        assert_eq!(ppm_header(1024, 768), "P6\n1024 768\n255\n");
+        assert_eq!(ppm_header(1, 1), "P6\n1 1\n255\n");
+    }
+    #[test_case]
+    fn rgb_is_extracted_from_argb() {
+        assert_eq!(argb_to_rgb(0x00123456), [0x12, 0x34, 0x56]);
+        assert_eq!(argb_to_rgb(0xFFE33B26), [0xE3, 0x3B, 0x26]);
+        assert_eq!(argb_to_rgb(0xFF000000), [0, 0, 0]);
+    }
+    #[test_case]
+    fn base64_lines_carry_over_partial_groups() {
+        let mut out = String::new();
+        let mut encoder = Base64Writer::new(&mut out);
+        encoder.write(b"Ma").unwrap();
+        encoder.write(b"nMa").unwrap();
+        encoder.write(b"").unwrap();
+        encoder.finish().unwrap();
+        assert_eq!(out, "\nTWFu\n\nTWE=\n");
+    }
+    #[test_case]
+    fn screenshot_is_framed_ppm() {
+        let mut bitmap = BitmapBuffer::new(2, 1, 2);
+        bitmap_draw_point(&mut bitmap, 0xFF0000, 0, 0).unwrap();
+        bitmap_draw_point(&mut bitmap, 0x0000FF, 1, 0).unwrap();
+        let mut out = String::new();
+        write_screenshot(&bitmap, &mut out).unwrap();
+        // base64 of "P6\n2 1\n255\n" and then [FF 00 00 00 00 FF]
+        assert_eq!(
+            out,
+            "SCREENSHOT BEGIN 2x1\nUDYKMiAxCjI1\nNQr/AAAA\nAP8=\nSCREENSHOT END\n"
+        );
+    }
+}
@@ -1,23 +1,44 @@
+extern crate alloc;
+
 use crate::boot_info::BootInfo;
+use crate::clock::best_clock;
 use crate::error;
+use crate::error::Error;
+use crate::error::Result;
 use crate::executor::block_on_and_schedule;
+use crate::fd::FD_STDIN;
+use crate::fd::FD_STDOUT;
+use crate::hpet::Hpet;
 use crate::info;
 use crate::input::InputManager;
+use crate::memory::AddressRange;
 use crate::net::dns::query_dns;
 use crate::net::dns::DnsResponseEntry;
 use crate::print;
 use crate::println;
 use crate::process::Scheduler;
 use crate::process::CURRENT_PROCESS;
+use crate::vram::VRAMBufferInfo;
 use crate::x86_64::syscall::return_to_os;
 use crate::x86_64::syscall::write_exit_reason;
 use crate::x86_64::syscall::write_return_value;
+use alloc::vec::Vec;
+use core::mem::align_of;
+use core::mem::size_of;
 use core::ptr::write_volatile;
 use noli::bitmap::bitmap_draw_point;
+use noli::bitmap::bitmap_draw_rect;
+use noli::bitmap::bitmap_draw_string;
+use noli::bitmap::Bitmap;
 use noli::net::IpV4Addr;
+use noli::rect::Rect;
+use sabi::KeyboardEvent;
 use sabi::MouseEvent;
+use sabi::RawPixel;
+use sabi::Syscall;
 
-fn exit_to_os(retv: u64) -> ! {
+/// Terminates the current app and resumes the OS with `retv` as the exit code.
+pub fn exit_to_os(retv: u64) -> ! {
     write_exit_reason(0);
     write_return_value(retv);
     return_to_os();
@@ -28,11 +49,20 @@ fn sys_exit(args: &[u64; 5]) -> ! {
     exit_to_os(args[0]);
 }
 
+/// Writes to the stdout of the app.
+/// Returns -1 (INVALID_BUFFER) if the buffer is not accessible from the app or the stdout is
+/// not writable.
 fn sys_print(args: &[u64; 5]) -> u64 {
-    // TODO(hikalium): validate the buffer
-    let s = args[0] as *const u8;
-    let len = args[1] as usize;
-    let s = unsafe { core::str::from_utf8_unchecked(core::slice::from_raw_parts(s, len)) };
+    let Ok(s) = validate_user_slice(args[0], args[1]) else {
+        return -1i64 as u64;
+    };
+    if let Some(proc) = CURRENT_PROCESS.lock().as_mut() {
+        return match proc.fd_mut(FD_STDOUT).map(|fd| fd.write(s)) {
+            Some(Ok(_)) => 0,
+            _ => -1i64 as u64,
+        };
+    }
+    let s = unsafe { core::str::from_utf8_unchecked(s) };
 
     print!("{}", s);
     0
@@ -51,10 +81,256 @@ fn sys_draw_point(args: &[u64; 5]) -> u64 {
     if result.is_err() {
         1
     } else {
+        flush_vram_rect(&mut vram, Rect::new(x, y, 1, 1));
         0
     }
 }
 
+fn sys_draw_rect(args: &[u64; 5]) -> u64 {
+    let mut vram = BootInfo::take().vram();
+    let x = args[0] as i64;
+    let y = args[1] as i64;
+    let w = args[2] as i64;
+    let h = args[3] as i64;
+    let c = args[4] as u32;
+    match draw_rect_clipped(&mut vram, c, x, y, w, h) {
+        Ok(rect) => {
+            flush_vram_rect(&mut vram, rect);
+            0
+        }
+        Err(_) => 1,
+    }
+}
+
+/// Fills the part of the rect which is within `buf`, so that a sheet straddling the edge of
+/// the screen can still be drawn. Returns the rect actually drawn, or None if the rect is
+/// entirely out of `buf`.
+fn draw_rect_clipped<T: Bitmap>(
+    buf: &mut T,
+    color: u32,
+    x: i64,
+    y: i64,
+    w: i64,
+    h: i64,
+) -> Result<Option<Rect>> {
+    let rect = Rect::new(x, y, w, h).ok_or(Error::Failed("draw_rect: invalid rect"))?;
+    let Some(rect) = rect.intersection(&Rect::new(0, 0, buf.width(), buf.height()).unwrap()) else {
+        return Ok(None);
+    };
+    bitmap_draw_rect(buf, color, rect.x(), rect.y(), rect.w(), rect.h())?;
+    Ok(Some(rect))
+}
+
+fn flush_vram_rect(vram: &mut VRAMBufferInfo, rect: Option<Rect>) {
+    if let Some(rect) = rect {
+        vram.mark_dirty(rect);
+    }
+    vram.flush();
+}
+
+/// Upper bound of the length of a string in a single draw_string call
+const MAX_DRAW_STRING_BYTES: u64 = 4096;
+
+/// Draws the string passed to the draw_string syscall on `buf`, after checking that the string
+/// is valid UTF-8 in one of `user_ranges` and it fits within `buf`.
+/// Returns the rect which covers the string.
+fn draw_user_string_in<T: Bitmap>(
+    buf: &mut T,
+    user_ranges: &[AddressRange],
+    args: &[u64; 5],
+) -> Result<Option<Rect>> {
+    let x = args[0] as i64;
+    let y = args[1] as i64;
+    let (fg, bg) = sabi::unpack_text_colors(args[2]);
+    if !(0..buf.width()).contains(&x) || !(0..buf.height()).contains(&y) {
+        return Err(Error::Failed("draw_string: out of the screen"));
+    }
+    if args[4] > MAX_DRAW_STRING_BYTES {
+        return Err(Error::Failed("draw_string: too long"));
+    }
+    let s = user_slice_in::<u8>(user_ranges, args[3], args[4])?;
+    let s = core::str::from_utf8(s).or(Err(Error::Failed("draw_string: invalid UTF-8")))?;
+    // Reject the string before drawing anything so that it is not left half-drawn
+    let w = 8 * s.chars().count() as i64;
+    if x + w > buf.width() || y + 16 > buf.height() {
+        return Err(Error::Failed("draw_string: out of the screen"));
+    }
+    bitmap_draw_string(buf, fg, bg, x, y, s)?;
+    Ok(Rect::new(x, y, w, 16))
+}
+
+/// Returns -1 if the string or the position is invalid.
+fn sys_draw_string(args: &[u64; 5]) -> i64 {
+    let mut vram = BootInfo::take().vram();
+    match draw_user_string_in(&mut vram, &current_user_ranges(), args) {
+        Ok(rect) => {
+            flush_vram_rect(&mut vram, rect);
+            0
+        }
+        Err(_) => -1,
+    }
+}
+
+/// Gives the app a back-buffer of the screen size and writes its geometry as a
+/// sabi::FramebufferInfo to args[0]. The buffer is unmapped when the app exits.
+/// Returns 0 on success, -1 if the buffer for the info is invalid, or -2 if the allocation failed.
+fn sys_map_framebuffer(args: &[u64; 5]) -> i64 {
+    let ptr = args[0];
+    if user_slice::<sabi::FramebufferInfo>(ptr, 1).is_err() {
+        return -1;
+    }
+    let vram = BootInfo::take().vram();
+    let stride = vram.pixels_per_line();
+    let height = vram.height();
+    let range = {
+        let mut proc = CURRENT_PROCESS.lock();
+        let Some(proc) = proc.as_mut() else {
+            return -2;
+        };
+        let num_bytes = (stride * height * vram.bytes_per_pixel()) as usize;
+        let Ok(range) = proc.map_framebuffer(num_bytes) else {
+            return -2;
+        };
+        range
+    };
+    let info = sabi::FramebufferInfo {
+        base: range.start() as u64,
+        stride: stride as u64,
+        width: vram.width() as u64,
+        height: height as u64,
+    };
+    unsafe { write_volatile(ptr as *mut sabi::FramebufferInfo, info) }
+    0
+}
+
+/// Copies the pixels in `rect` from the back-buffer `src`, which has `stride` pixels in a line,
+/// to the same position of `dst`. Returns the rect copied, which is clipped by `dst`, or None
+/// without drawing anything if `src` does not cover the rect.
+fn present_back_buffer<T: Bitmap>(
+    dst: &mut T,
+    src: &[u8],
+    stride: i64,
+    rect: Rect,
+) -> Option<Rect> {
+    let rect = Rect::new(0, 0, dst.width(), dst.height())?.intersection(&rect)?;
+    let end_offset = ((rect.y() + rect.h() - 1) * stride + rect.x() + rect.w()) * 4;
+    if end_offset as usize > src.len() {
+        return None;
+    }
+    for y in rect.y()..rect.y() + rect.h() {
+        for x in rect.x()..rect.x() + rect.w() {
+            let offset = ((y * stride + x) * 4) as usize;
+            let pixel = src.get(offset..offset + 4)?;
+            let color = u32::from_le_bytes(pixel.try_into().ok()?);
+            bitmap_draw_point(dst, color, x, y).ok()?;
+        }
+    }
+    Some(rect)
+}
+
+/// Shows the rect (args[0], args[1], args[2], args[3]) of the back-buffer on the screen.
+/// Returns 0 on success, -1 if the rect is not on the screen, or -2 if no back-buffer is mapped.
+fn sys_present(args: &[u64; 5]) -> i64 {
+    let Some(rect) = Rect::new(
+        args[0] as i64,
+        args[1] as i64,
+        args[2] as i64,
+        args[3] as i64,
+    ) else {
+        return -1;
+    };
+    let mut vram = BootInfo::take().vram();
+    let stride = vram.pixels_per_line();
+    let rect = {
+        let proc = CURRENT_PROCESS.lock();
+        let Some(framebuffer) = proc.as_ref().and_then(|proc| proc.framebuffer()) else {
+            return -2;
+        };
+        present_back_buffer(&mut vram, framebuffer.as_slice(), stride, rect)
+    };
+    if rect.is_none() {
+        return -1;
+    }
+    flush_vram_rect(&mut vram, rect);
+    0
+}
+
+/// Upper bound of the number of pixels in a single draw_pixels call
+const MAX_PIXELS_PER_CALL: u64 = 1 << 20;
+
+/// Returns a slice of `len` elements of T at `ptr`, which should be in one of `user_ranges`.
+/// Rejects null, misaligned, overflowing, and out-of-range buffers.
+fn user_slice_in<'a, T>(user_ranges: &[AddressRange], ptr: u64, len: u64) -> Result<&'a [T]> {
+    if ptr == 0 {
+        return Err(Error::Failed("user_slice: null pointer"));
+    }
+    if ptr % align_of::<T>() as u64 != 0 {
+        return Err(Error::Failed("user_slice: misaligned pointer"));
+    }
+    let size = len
+        .checked_mul(size_of::<T>() as u64)
+        .ok_or(Error::Failed("user_slice: size overflow"))?;
+    let end = ptr
+        .checked_add(size)
+        .ok_or(Error::Failed("user_slice: end address overflow"))?;
+    if size != 0
+        && !user_ranges
+            .iter()
+            .any(|r| r.start() as u64 <= ptr && end <= r.end() as u64)
+    {
+        return Err(Error::Failed("user_slice: out of the app's memory"));
+    }
+    Ok(unsafe { core::slice::from_raw_parts(ptr as *const T, len as usize) })
+}
+
+fn current_user_ranges() -> Vec<AddressRange> {
+    CURRENT_PROCESS
+        .lock()
+        .as_ref()
+        .map(|proc| proc.user_ranges().to_vec())
+        .unwrap_or_default()
+}
+
+/// Returns a slice of `len` elements of T at `ptr` passed from the current app.
+fn user_slice<'a, T>(ptr: u64, len: u64) -> Result<&'a [T]> {
+    user_slice_in(&current_user_ranges(), ptr, len)
+}
+
+fn validate_user_slice<'a>(ptr: u64, len: u64) -> Result<&'a [u8]> {
+    user_slice(ptr, len)
+}
+
+fn validate_user_slice_mut<'a>(ptr: u64, len: u64) -> Result<&'a mut [u8]> {
+    user_slice::<u8>(ptr, len)?;
+    Ok(unsafe { core::slice::from_raw_parts_mut(ptr as *mut u8, len as usize) })
+}
+
+fn sys_draw_pixels(args: &[u64; 5]) -> i64 {
+    let len = args[1];
+    if len > MAX_PIXELS_PER_CALL {
+        return -1;
+    }
+    let Ok(pixels) = user_slice::<RawPixel>(args[0], len) else {
+        return -1;
+    };
+    let mut vram = BootInfo::take().vram();
+    let mut drawn = 0;
+    let mut bounding_box: Option<Rect> = None;
+    for p in pixels {
+        if bitmap_draw_point(&mut vram, p.color, p.x, p.y).is_ok() {
+            drawn += 1;
+            if let Some(r) = Rect::new(p.x, p.y, 1, 1) {
+                bounding_box = Some(match bounding_box {
+                    Some(b) => b.union(&r),
+                    None => r,
+                });
+            }
+        }
+    }
+    flush_vram_rect(&mut vram, bounding_box);
+    drawn
+}
+
 fn sys_read_key(_args: &[u64; 5]) -> u64 {
     if let Some(c) = InputManager::take().pop_input() {
         c as u64
@@ -64,14 +340,85 @@ fn sys_read_key(_args: &[u64; 5]) -> u64 {
     }
 }
 
-fn sys_get_mouse_cursor_position(args: &[u64; 5]) -> u64 {
-    if let Some(e) = InputManager::take().pop_cursor_input_absolute() {
-        unsafe { write_volatile(args[0] as *mut MouseEvent, e) }
-        0
-    } else {
+/// Pops a key with `pop`, calling `wait` to let the others run until a key arrives or
+/// `elapsed_ms` reaches `timeout_ms`. Returns None if it timed out.
+fn wait_for_key(
+    timeout_ms: u64,
+    mut elapsed_ms: impl FnMut() -> u64,
+    mut pop: impl FnMut() -> Option<char>,
+    mut wait: impl FnMut(),
+) -> Option<char> {
+    loop {
+        if let Some(c) = pop() {
+            return Some(c);
+        }
+        if elapsed_ms() >= timeout_ms {
+            return None;
+        }
+        wait();
+    }
+}
+
+/// Same as sys_read_key(), but waits for a key up to args[0] ms while the others run.
+/// Returns 0 if it timed out, or immediately if no clock is available.
+fn sys_read_key_timeout(args: &[u64; 5]) -> u64 {
+    let clock = best_clock();
+    let start_counter = clock.map(|c| c.counter()).unwrap_or(0);
+    let input = InputManager::take();
+    wait_for_key(
+        args[0],
+        || {
+            clock
+                .map(|c| c.elapsed_ms_since(start_counter))
+                .unwrap_or(u64::MAX)
+        },
+        || input.pop_input(),
+        || Scheduler::root().switch_process(),
+    )
+    .map(|c| c as u64)
+    .unwrap_or(0)
+}
+
+/// Pops an event with `pop` and writes it to the buffer at `ptr`, which should be in one of
+/// `user_ranges`. The buffer is checked before popping so that an event is never lost.
+/// Returns 0 if an event is written, 1 if there was no event, or -1 (INVALID_BUFFER).
+fn write_event_in<T>(
+    user_ranges: &[AddressRange],
+    ptr: u64,
+    pop: impl FnOnce() -> Option<T>,
+) -> i64 {
+    if user_slice_in::<T>(user_ranges, ptr, 1).is_err() {
+        return -1;
+    }
+    match pop() {
+        Some(e) => {
+            unsafe { write_volatile(ptr as *mut T, e) }
+            0
+        }
+        None => 1,
+    }
+}
+
+/// Same as write_event_in(), but also lets the OS run if there was no event.
+fn write_event_or_yield<T>(ptr: u64, pop: impl FnOnce() -> Option<T>) -> u64 {
+    let result = write_event_in(&current_user_ranges(), ptr, pop);
+    if result == 1 {
         Scheduler::root().switch_process();
-        1
     }
+    result as u64
+}
+
+fn sys_read_key_event(args: &[u64; 5]) -> u64 {
+    write_event_or_yield::<KeyboardEvent>(args[0], || InputManager::take().pop_key_event())
+}
+
+fn sys_get_mouse_cursor_position(args: &[u64; 5]) -> u64 {
+    write_event_or_yield::<MouseEvent>(args[0], || InputManager::take().pop_cursor_input_absolute())
+}
+
+/// Returns 0 if there is no HPET.
+fn sys_uptime_ms(_args: &[u64; 5]) -> u64 {
+    Hpet::try_take().map(|h| h.uptime_ms()).unwrap_or(0)
 }
 
 fn sys_get_args_region(_args: &[u64; 5]) -> u64 {
@@ -114,20 +461,24 @@ fn sys_nslookup(args: &[u64; 5]) -> i64 {
         // > Name resolution APIs and libraries SHOULD recognize "invalid" names as special and SHOULD always return immediate negative responses.
         return -2;
     }
-    let r = block_on_and_schedule(query_dns(host));
-    if let Ok(r) = &r {
-        if let Some(r) = r.first() {
-            let DnsResponseEntry::A { name: _, addr } = &r;
-            result[0] = addr.bytes();
-            return 1;
-        } else {
+    match block_on_and_schedule(query_dns(host)) {
+        Ok(r) => {
+            if let Some(DnsResponseEntry::A { name: _, addr }) = r.first() {
+                result[0] = addr.bytes();
+                return 1;
+            }
             error!("empty response so return NXDOMAIN");
-            return -2;
+            -2
+        }
+        Err(Error::DnsNotFound) => {
+            error!("{host}: {}", Error::DnsNotFound);
+            -2
+        }
+        Err(e) => {
+            error!("{e:?}");
+            -1
         }
-    } else {
-        error!("{r:?}")
     }
-    -1
 }
 
 fn sys_tcp_connect(args: &[u64; 5]) -> i64 {
@@ -147,11 +498,8 @@ fn sys_tcp_connect(args: &[u64; 5]) -> i64 {
 
 fn sys_tcp_write(args: &[u64; 5]) -> i64 {
     let handle = args[0] as i64;
-    let buf = {
-        let buf = args[1] as *const u8;
-        let len = args[2] as usize;
-        // TODO(hikalium): validate the buffer
-        unsafe { core::slice::from_raw_parts(buf, len) }
+    let Ok(buf) = validate_user_slice(args[1], args[2]) else {
+        return -2;
     };
     let sock = if let Some(proc) = CURRENT_PROCESS.lock().as_mut() {
         if let Some(sock) = proc.tcp_socket(handle) {
@@ -222,20 +570,104 @@ fn sys_tcp_read(args: &[u64; 5]) -> i64 {
     }
 }
 
+fn sys_open_file(args: &[u64; 5]) -> i64 {
+    let Ok(name) = validate_user_slice(args[0], args[1]) else {
+        return -2;
+    };
+    let Ok(name) = core::str::from_utf8(name) else {
+        return -2;
+    };
+    let create = args[2] & sabi::OPEN_FLAG_CREATE != 0;
+    if let Some(proc) = CURRENT_PROCESS.lock().as_mut() {
+        proc.open_file(name, create).unwrap_or(-1)
+    } else {
+        -1
+    }
+}
+
+fn sys_read(args: &[u64; 5]) -> i64 {
+    let fd = args[0] as i64;
+    let Ok(buf) = validate_user_slice_mut(args[1], args[2]) else {
+        return -2;
+    };
+    let result = {
+        let mut proc = CURRENT_PROCESS.lock();
+        let Some(file) = proc.as_mut().and_then(|proc| proc.fd_mut(fd)) else {
+            return -1;
+        };
+        file.read(buf)
+    };
+    match result {
+        Ok(0) if fd == FD_STDIN => {
+            // No key input yet. Let the others run.
+            Scheduler::root().switch_process();
+            0
+        }
+        Ok(len) => len as i64,
+        Err(_) => -2,
+    }
+}
+
+fn sys_write(args: &[u64; 5]) -> i64 {
+    let fd = args[0] as i64;
+    let Ok(buf) = validate_user_slice(args[1], args[2]) else {
+        return -2;
+    };
+    let mut proc = CURRENT_PROCESS.lock();
+    let Some(file) = proc.as_mut().and_then(|proc| proc.fd_mut(fd)) else {
+        return -1;
+    };
+    match file.write(buf) {
+        Ok(len) => len as i64,
+        Err(_) => -2,
+    }
+}
+
+fn sys_close(args: &[u64; 5]) -> i64 {
+    let fd = args[0] as i64;
+    match CURRENT_PROCESS
+        .lock()
+        .as_mut()
+        .and_then(|proc| proc.close_fd(fd))
+    {
+        Some(_) => 0,
+        None => -1,
+    }
+}
+
+/// Lets the OS run the other tasks, and then resumes the app.
+fn sys_yield(_args: &[u64; 5]) -> u64 {
+    Scheduler::root().switch_process();
+    0
+}
+
 pub fn syscall_handler(op: u64, args: &[u64; 5]) -> u64 {
-    match op {
-        0 => sys_exit(args),
-        1 => sys_print(args),
-        2 => sys_draw_point(args),
-        3 => sys_noop(args),
-        4 => sys_read_key(args),
-        5 => sys_get_mouse_cursor_position(args),
-        6 => sys_get_args_region(args),
-        7 => sys_nslookup(args) as u64,
-        8 => sys_tcp_connect(args) as u64,
-        9 => sys_tcp_write(args) as u64,
-        10 => sys_tcp_read(args) as u64,
-        op => {
+    match Syscall::from_u64(op) {
+        Some(Syscall::Exit) => sys_exit(args),
+        Some(Syscall::Print) => sys_print(args),
+        Some(Syscall::DrawPoint) => sys_draw_point(args),
+        Some(Syscall::Noop) => sys_noop(args),
+        Some(Syscall::ReadKey) => sys_read_key(args),
+        Some(Syscall::GetMouseCursorInfo) => sys_get_mouse_cursor_position(args),
+        Some(Syscall::GetArgsRegion) => sys_get_args_region(args),
+        Some(Syscall::Nslookup) => sys_nslookup(args) as u64,
+        Some(Syscall::TcpConnect) => sys_tcp_connect(args) as u64,
+        Some(Syscall::TcpWrite) => sys_tcp_write(args) as u64,
+        Some(Syscall::TcpRead) => sys_tcp_read(args) as u64,
+        Some(Syscall::ReadKeyEvent) => sys_read_key_event(args),
+        Some(Syscall::DrawRect) => sys_draw_rect(args),
+        Some(Syscall::DrawPixels) => sys_draw_pixels(args) as u64,
+        Some(Syscall::UptimeMs) => sys_uptime_ms(args),
+        Some(Syscall::OpenFile) => sys_open_file(args) as u64,
+        Some(Syscall::Read) => sys_read(args) as u64,
+        Some(Syscall::Write) => sys_write(args) as u64,
+        Some(Syscall::Close) => sys_close(args) as u64,
+        Some(Syscall::Yield) => sys_yield(args),
+        Some(Syscall::DrawString) => sys_draw_string(args) as u64,
+        Some(Syscall::MapFramebuffer) => sys_map_framebuffer(args) as u64,
+        Some(Syscall::Present) => sys_present(args) as u64,
+        Some(Syscall::ReadKeyTimeout) => sys_read_key_timeout(args),
+        None => {
             println!("syscall: unimplemented syscall: {}", op);
             // Return u64::MAX here as it may be the "most unexpected value" that can crash the
             // program without keep going. For example, most of the syscalls uses negative values
@@ -247,3 +679,222 @@ pub fn syscall_handler(op: u64, args: &[u64; 5]) -> u64 {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use alloc::collections::VecDeque;
+    use core::cell::Cell;
+    use core::cell::RefCell;
+    use noli::bitmap::BitmapBuffer;
+    use sabi::MouseButtonState;
+    use sabi::PointerPosition;
+    #[test_case]
+    fn draw_rect_clips_to_screen_edge() {
+        let mut buf = BitmapBuffer::new(8, 8, 8);
+        // A sheet straddling the right and the bottom edges of the screen
+        assert_eq!(
+            draw_rect_clipped(&mut buf, 0xff0000, 6, 5, 4, 4),
+            Ok(Rect::new(6, 5, 2, 3))
+        );
+        assert_eq!(buf.pixel_at(7, 7), Some(&0xff0000));
+        assert_eq!(buf.pixel_at(5, 7), Some(&0));
+        // ...and the left and the top edges
+        assert_eq!(
+            draw_rect_clipped(&mut buf, 0x00ff00, -2, -3, 4, 4),
+            Ok(Rect::new(0, 0, 2, 1))
+        );
+        assert_eq!(buf.pixel_at(1, 0), Some(&0x00ff00));
+        assert_eq!(buf.pixel_at(1, 1), Some(&0));
+        // Entirely out of the screen, or empty
+        assert_eq!(draw_rect_clipped(&mut buf, 0, 8, 0, 4, 4), Ok(None));
+        assert_eq!(draw_rect_clipped(&mut buf, 0, 0, 0, 0, 4), Ok(None));
+        assert!(draw_rect_clipped(&mut buf, 0, 0, 0, -1, 4).is_err());
+        // Rects whose edges overflow are rejected instead of panicking
+        assert!(draw_rect_clipped(&mut buf, 0, i64::MAX - 1, 0, 10, 4).is_err());
+        assert!(draw_rect_clipped(&mut buf, 0, 0, i64::MAX - 1, 4, 10).is_err());
+    }
+    #[test_case]
+    fn user_slice_rejects_invalid_buffers() {
+        let ranges = [AddressRange::new(0x1000, 0x2000)];
+        assert!(user_slice_in::<RawPixel>(&ranges, 0, 1).is_err());
+        assert!(user_slice_in::<RawPixel>(&ranges, 0x1001, 1).is_err());
+        assert!(user_slice_in::<RawPixel>(&ranges, 0x1000, u64::MAX).is_err());
+        assert!(user_slice_in::<RawPixel>(&ranges, 0x1000, u64::MAX / 24).is_err());
+        assert!(user_slice_in::<RawPixel>(&ranges, u64::MAX - 7, 1).is_err());
+    }
+    #[test_case]
+    fn user_slice_checks_app_memory_range() {
+        let ranges = [
+            AddressRange::new(0x1000, 0x2000),
+            AddressRange::new(0x8000, 0x9000),
+        ];
+        assert!(user_slice_in::<u8>(&ranges, 0x1000, 0x1000).is_ok());
+        assert!(user_slice_in::<u8>(&ranges, 0x8ff0, 0x10).is_ok());
+        // Crossing the end of a range
+        assert!(user_slice_in::<u8>(&ranges, 0x1ff0, 0x11).is_err());
+        // Spanning two ranges with a gap
+        assert!(user_slice_in::<u8>(&ranges, 0x1000, 0x8000).is_err());
+        // Out of any range
+        assert!(user_slice_in::<u8>(&ranges, 0x4000, 1).is_err());
+        assert!(user_slice_in::<u8>(&[], 0x1000, 1).is_err());
+        // Empty slices are fine as they are never dereferenced
+        assert!(user_slice_in::<u8>(&ranges, 0x4000, 0).is_ok());
+    }
+    #[test_case]
+    fn mouse_event_is_written_only_if_available() {
+        let mut dst = MouseEvent::default();
+        let ptr = &mut dst as *mut MouseEvent as u64;
+        let ranges = [AddressRange::from_start_and_size(
+            ptr as usize,
+            size_of::<MouseEvent>(),
+        )];
+        let e = MouseEvent {
+            button: MouseButtonState::from_lcr(true, false, true),
+            position: PointerPosition::from_xy(-5, 7),
+            wheel: 2,
+        };
+        assert_eq!(write_event_in(&ranges, ptr, || Some(e)), 0);
+        assert!(dst.button.l() && !dst.button.c() && dst.button.r());
+        assert_eq!((dst.position.x, dst.position.y, dst.wheel), (-5, 7, 2));
+        assert_eq!(write_event_in::<MouseEvent>(&ranges, ptr, || None), 1);
+        // The event is kept in the queue if the buffer is invalid
+        let mut popped = false;
+        let pop = || {
+            popped = true;
+            Some(e)
+        };
+        assert_eq!(write_event_in(&[], ptr, pop), -1);
+        assert!(!popped);
+        assert_eq!(write_event_in(&ranges, ptr + 1, || Some(e)), -1);
+    }
+    #[test_case]
+    fn draw_string_validates_user_string() {
+        let mut buf = BitmapBuffer::new(64, 32, 64);
+        let text = *b"Hi\xFF";
+        let ranges = [AddressRange::from_start_and_size(
+            text.as_ptr() as usize,
+            text.len(),
+        )];
+        let colors = sabi::pack_text_colors(0xFFFFFF, None);
+        let ptr = text.as_ptr() as u64;
+        // Invalid UTF-8
+        assert!(draw_user_string_in(&mut buf, &ranges, &[0, 0, colors, ptr, 3]).is_err());
+        // Out of the app's memory
+        assert!(draw_user_string_in(&mut buf, &ranges, &[0, 0, colors, ptr + 1, 3]).is_err());
+        assert!(draw_user_string_in(&mut buf, &[], &[0, 0, colors, ptr, 2]).is_err());
+        // Out of the screen
+        assert!(draw_user_string_in(&mut buf, &ranges, &[64, 0, colors, ptr, 2]).is_err());
+        assert!(
+            draw_user_string_in(&mut buf, &ranges, &[0, -1i64 as u64, colors, ptr, 2]).is_err()
+        );
+        // Starts within the screen but runs over the bottom or the right edge
+        assert!(draw_user_string_in(&mut buf, &ranges, &[0, 31, colors, ptr, 2]).is_err());
+        assert!(draw_user_string_in(&mut buf, &ranges, &[56, 0, colors, ptr, 2]).is_err());
+        assert!((0..64)
+            .flat_map(|x| (0..32).map(move |y| (x, y)))
+            .all(|(x, y)| *buf.pixel_at(x, y).unwrap() == 0));
+        // "Hi" is drawn
+        let rect = draw_user_string_in(&mut buf, &ranges, &[8, 4, colors, ptr, 2]).unwrap();
+        assert_eq!(rect, Rect::new(8, 4, 16, 16));
+        assert!((8..24)
+            .flat_map(|x| (4..20).map(move |y| (x, y)))
+            .any(|(x, y)| *buf.pixel_at(x, y).unwrap() == 0xFFFFFF));
+        assert!((0..8).all(|x| *buf.pixel_at(x, 10).unwrap() == 0));
+        // Touching the bottom-right corner is fine
+        let rect = draw_user_string_in(&mut buf, &ranges, &[48, 16, colors, ptr, 2]).unwrap();
+        assert_eq!(rect, Rect::new(48, 16, 16, 16));
+    }
+    #[test_case]
+    fn user_slice_decodes_pixels() {
+        let pixels = [
+            RawPixel {
+                x: 1,
+                y: 2,
+                color: 0xff0000,
+            },
+            RawPixel {
+                x: -3,
+                y: 4,
+                color: 0x00ff00,
+            },
+        ];
+        let ranges = [AddressRange::from_start_and_size(
+            pixels.as_ptr() as usize,
+            size_of::<[RawPixel; 2]>(),
+        )];
+        let decoded = user_slice_in::<RawPixel>(&ranges, pixels.as_ptr() as u64, 2).unwrap();
+        assert_eq!(decoded, pixels);
+        assert!(user_slice_in::<RawPixel>(&ranges, pixels.as_ptr() as u64, 3).is_err());
+    }
+    #[test_case]
+    fn back_buffer_is_presented_within_screen() {
+        let mut screen = BitmapBuffer::new(3, 2, 4);
+        let mut back_buffer = [0u8; 4 * 4 * 2];
+        back_buffer[4..8].copy_from_slice(&0x123456u32.to_le_bytes());
+        back_buffer[(4 + 2) * 4..(4 + 3) * 4].copy_from_slice(&0xABCDEFu32.to_le_bytes());
+        assert_eq!(
+            present_back_buffer(&mut screen, &back_buffer, 4, Rect::new(1, 0, 8, 8).unwrap()),
+            Rect::new(1, 0, 2, 2)
+        );
+        assert_eq!(screen.pixel_at(0, 0), Some(&0));
+        assert_eq!(screen.pixel_at(1, 0), Some(&0x123456));
+        assert_eq!(screen.pixel_at(2, 1), Some(&0xABCDEF));
+        assert_eq!(
+            present_back_buffer(&mut screen, &back_buffer, 4, Rect::new(3, 0, 1, 1).unwrap()),
+            None
+        );
+        // The back-buffer is too small for the screen, so nothing is drawn
+        let mut screen = BitmapBuffer::new(3, 2, 4);
+        assert_eq!(
+            present_back_buffer(
+                &mut screen,
+                &back_buffer[..8],
+                4,
+                Rect::new(0, 0, 3, 2).unwrap()
+            ),
+            None
+        );
+        assert_eq!(screen.pixel_at(1, 0), Some(&0));
+    }
+    #[test_case]
+    fn read_key_times_out_without_input() {
+        let now_ms = Cell::new(0);
+        let mut num_waits = 0;
+        let key = wait_for_key(
+            30,
+            || now_ms.get(),
+            || None,
+            || {
+                now_ms.set(now_ms.get() + 10);
+                num_waits += 1;
+            },
+        );
+        assert_eq!(key, None);
+        assert_eq!(num_waits, 3);
+        // A zero timeout does not wait at all
+        let key = wait_for_key(0, || 0, || None, || panic!("should not wait"));
+        assert_eq!(key, None);
+    }
+    #[test_case]
+    fn read_key_wakes_on_input() {
+        let queue = RefCell::new(VecDeque::new());
+        let mut num_waits = 0;
+        let key = wait_for_key(
+            1000,
+            || 0,
+            || queue.borrow_mut().pop_front(),
+            || {
+                num_waits += 1;
+                if num_waits == 2 {
+                    queue.borrow_mut().push_back('a');
+                    queue.borrow_mut().push_back('b');
+                }
+            },
+        );
+        assert_eq!(key, Some('a'));
+        assert_eq!(num_waits, 2);
+        // The key which came at the same time is kept for the next read
+        assert_eq!(queue.borrow_mut().pop_front(), Some('b'));
+    }
+}
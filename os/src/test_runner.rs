@@ -1,12 +1,100 @@
+extern crate alloc;
+
 use crate::debug;
 use crate::info;
 use crate::serial;
 use core::any::type_name;
+use core::fmt;
 use core::fmt::Write;
 use serial::SerialPort;
 use serial::SerialPortIndex;
 
+#[cfg(test)]
+use alloc::string::String;
+#[cfg(test)]
+use core::cell::RefCell;
+
+/// Only the tests whose names match this filter are executed. It is given at compile time
+/// (e.g. `make run_os_test TEST_FILTER=net::`) and all the tests run if it is not set.
+const TEST_FILTER: Option<&str> = option_env!("WASABI_TEST_FILTER");
+
+/// Returns true if the test `name` should run with `filter`. The filter matches either a prefix
+/// of the full path or a prefix of the path without the crate name, so "net::" selects
+/// "os::net::ip::checksum" as well. An empty filter matches all the tests.
+pub fn matches_filter(name: &str, filter: &str) -> bool {
+    if filter.is_empty() || name.starts_with(filter) {
+        return true;
+    }
+    name.split_once("::")
+        .is_some_and(|(_, path)| path.starts_with(filter))
+}
+
+/// Asserts that the expression is true. Unlike assert!, the failing expression is written to the
+/// serial console and QEMU exits with QemuExitCode::AssertionFailed.
+#[macro_export]
+macro_rules! test_assert {
+    (@with $report:expr; $cond:expr $(,)?) => {
+        if !$cond {
+            ($report)(
+                file!(),
+                line!(),
+                format_args!("test_assert!({})", stringify!($cond)),
+            );
+        }
+    };
+    ($cond:expr $(,)?) => {
+        $crate::test_assert!(@with $crate::test_runner::report_assertion_failure; $cond)
+    };
+}
+
+/// Asserts that the two expressions are equal. On failure, both values are written to the serial
+/// console along with the expressions and QEMU exits with QemuExitCode::AssertionFailed.
+#[macro_export]
+macro_rules! test_assert_eq {
+    (@with $report:expr; $left:expr, $right:expr $(,)?) => {
+        match (&$left, &$right) {
+            (left, right) => {
+                if !(*left == *right) {
+                    ($report)(
+                        file!(),
+                        line!(),
+                        format_args!(
+                            "test_assert_eq!({}, {})\n  left:  {:?}\n  right: {:?}",
+                            stringify!($left),
+                            stringify!($right),
+                            left,
+                            right
+                        ),
+                    );
+                }
+            }
+        }
+    };
+    ($left:expr, $right:expr $(,)?) => {
+        $crate::test_assert_eq!(
+            @with $crate::test_runner::report_assertion_failure; $left, $right
+        )
+    };
+}
+
+pub fn write_assertion_failure(
+    w: &mut impl Write,
+    file: &str,
+    line: u32,
+    args: fmt::Arguments,
+) -> fmt::Result {
+    writeln!(w, "[ASSERT ] {file}:{line}: {args}")
+}
+
+#[doc(hidden)]
+pub fn report_assertion_failure(file: &str, line: u32, args: fmt::Arguments) -> ! {
+    let mut writer = SerialPort::default();
+    let _ = write_assertion_failure(&mut writer, file, line, args);
+    debug::exit_qemu(debug::QemuExitCode::AssertionFailed)
+}
+
 pub trait Testable {
+    fn name(&self) -> &'static str;
     fn run(&self);
 }
 
@@ -14,6 +102,9 @@ impl<T> Testable for T
 where
     T: Fn(),
 {
+    fn name(&self) -> &'static str {
+        type_name::<T>()
+    }
     fn run(&self) {
         let mut writer = SerialPort::new(SerialPortIndex::Com2);
         writer.init();
@@ -25,10 +116,77 @@ where
 }
 
 pub fn test_runner(tests: &[&dyn Testable]) -> ! {
+    let filter = TEST_FILTER.unwrap_or("");
     info!("Running {} tests...", tests.len());
+    if !filter.is_empty() {
+        info!("Test filter: {filter:?}");
+    }
+    let mut writer = SerialPort::default();
     for test in tests {
-        test.run();
+        if matches_filter(test.name(), filter) {
+            test.run();
+        } else {
+            writeln!(writer, "[SKIP   ] --- {}", test.name()).unwrap();
+        }
     }
     info!("Done!");
     debug::exit_qemu(debug::QemuExitCode::Success)
 }
+
+#[test_case]
+fn empty_filter_matches_all_tests() {
+    test_assert!(matches_filter("os::net::ip::checksum", ""));
+    test_assert!(matches_filter("", ""));
+}
+
+#[test_case]
+fn filter_matches_exact_name() {
+    test_assert!(matches_filter(
+        "os::util::poll_until_completes",
+        "os::util::poll_until_completes"
+    ));
+    test_assert!(matches_filter(
+        "os::util::poll_until_completes",
+        "util::poll_until_completes"
+    ));
+    test_assert!(!matches_filter(
+        "os::util::poll_until_completes",
+        "util::poll_until_completes2"
+    ));
+}
+
+#[test_case]
+fn filter_matches_prefix() {
+    test_assert!(matches_filter("os::net::ip::checksum", "os::net::"));
+    test_assert!(matches_filter("os::net::ip::checksum", "net::"));
+    test_assert!(matches_filter("os::net::ip::checksum", "net::ip"));
+    test_assert!(!matches_filter("os::net::ip::checksum", "ip::"));
+    test_assert!(!matches_filter("os::xhci::ring::tests::fill", "net::"));
+}
+
+#[test_case]
+fn test_assert_eq_reports_both_operands() {
+    let log = RefCell::new(String::new());
+    let report = |file: &str, line: u32, args: fmt::Arguments| {
+        write_assertion_failure(&mut *log.borrow_mut(), file, line, args).unwrap();
+    };
+    test_assert_eq!(@with report; 1 + 1, 2);
+    test_assert_eq!(*log.borrow(), "");
+    test_assert_eq!(@with report; 1 + 1, 3);
+    test_assert!(log.borrow().starts_with("[ASSERT ] "));
+    test_assert!(log.borrow().contains("test_assert_eq!(1 + 1, 3)"));
+    test_assert!(log.borrow().contains("left:  2"));
+    test_assert!(log.borrow().contains("right: 3"));
+}
+
+#[test_case]
+fn test_assert_reports_the_expression() {
+    let log = RefCell::new(String::new());
+    let report = |file: &str, line: u32, args: fmt::Arguments| {
+        write_assertion_failure(&mut *log.borrow_mut(), file, line, args).unwrap();
+    };
+    test_assert!(@with report; 1 < 2);
+    test_assert_eq!(*log.borrow(), "");
+    test_assert!(@with report; 2 < 1);
+    test_assert!(log.borrow().contains("test_assert!(2 < 1)"));
+}
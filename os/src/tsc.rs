@@ -0,0 +1,82 @@
+use crate::clock::ClockSource;
+use crate::error::Error;
+use crate::error::Result;
+use crate::pit;
+use crate::x86_64::busy_loop_hint;
+use crate::x86_64::rdtsc;
+
+/// Time Stamp Counter, which can be used as a clock source if it is invariant.
+/// c.f. SDM Vol.3 18.17.1 Invariant TSC
+pub struct Tsc {
+    freq: u64,
+}
+static mut TSC: Option<Tsc> = None;
+impl Tsc {
+    /// Returns None if TSC is not calibrated (e.g. it is not invariant).
+    pub fn try_take() -> Option<&'static Self> {
+        unsafe { TSC.as_ref() }
+    }
+    /// # Safety
+    /// This is safe if it is called only once.
+    pub unsafe fn set(tsc: Tsc) {
+        assert!(TSC.is_none());
+        TSC = Some(tsc);
+    }
+    /// Measures the frequency of TSC by comparing it with the reference clock for `duration_ms`.
+    pub fn calibrate(reference: &dyn ClockSource, duration_ms: u64) -> Result<Self> {
+        let ref_start = reference.counter();
+        let tsc_start = rdtsc();
+        while reference.elapsed_ms_since(ref_start) < duration_ms {
+            busy_loop_hint();
+        }
+        let ref_ticks = reference.counter().wrapping_sub(ref_start);
+        let tsc_ticks = rdtsc().wrapping_sub(tsc_start);
+        let freq = freq_from_calibration(tsc_ticks, ref_ticks, reference.freq());
+        if freq == 0 {
+            return Err(Error::Failed("Failed to calibrate TSC"));
+        }
+        Ok(Self { freq })
+    }
+    /// Measures the frequency of TSC with the PIT for `duration_ms`, for platforms without HPET.
+    pub fn calibrate_with_pit(duration_ms: u64) -> Result<Self> {
+        let freq = pit::measure_freq(rdtsc, duration_ms)?;
+        Ok(Self { freq })
+    }
+}
+impl ClockSource for Tsc {
+    fn counter(&self) -> u64 {
+        rdtsc()
+    }
+    fn freq(&self) -> u64 {
+        self.freq
+    }
+}
+
+/// Returns the frequency of a counter that ticked `ticks` times while the reference clock running
+/// at `ref_freq` Hz ticked `ref_ticks` times.
+pub fn freq_from_calibration(ticks: u64, ref_ticks: u64, ref_freq: u64) -> u64 {
+    if ref_ticks == 0 {
+        return 0;
+    }
+    (ticks as u128 * ref_freq as u128 / ref_ticks as u128) as u64
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test_case]
+    fn freq_is_calculated_from_calibration() {
+        // 10ms on 10MHz reference clock
+        assert_eq!(
+            freq_from_calibration(30_000_000, 100_000, 10_000_000),
+            3_000_000_000
+        );
+        // 3 GHz TSC measured with a 14.31818 MHz HPET for 1 second
+        assert_eq!(
+            freq_from_calibration(3_000_000_000, 14_318_180, 14_318_180),
+            3_000_000_000
+        );
+        assert_eq!(freq_from_calibration(1234, 0, 10_000_000), 0);
+    }
+}
@@ -1,4 +1,9 @@
+extern crate alloc;
+
+use crate::error::Error;
+use crate::error::Result;
 use crate::util::IntoPinnedMutableSlice;
+use alloc::string::String;
 use core::marker::PhantomPinned;
 use core::mem::size_of;
 use noli::mem::Sliceable;
@@ -166,12 +171,54 @@ pub struct EndpointDescriptor {
     pub interval: u8,
 }
 impl EndpointDescriptor {
-    pub fn dci(&self) -> usize {
-        ((self.endpoint_address & 0xF) * 2 + (self.endpoint_address >> 7)) as usize
+    /// Device Context Index of this endpoint, which is used to index the endpoint rings.
+    pub fn dci(&self) -> Result<usize> {
+        dci_from_endpoint_address(self.endpoint_address)
+    }
+}
+
+/// Device Context Index of the Default Control Endpoint (EP0), which is bidirectional.
+pub const DCI_EP0: usize = 1;
+
+/// Returns the Device Context Index for the endpoint `ep_num` in the direction `is_in`.
+/// See 4.5.1 Device Context Index of the xHCI spec.
+pub fn dci_from_endpoint(ep_num: u8, is_in: bool) -> Result<usize> {
+    match ep_num {
+        0 => Ok(DCI_EP0),
+        1..=15 => Ok(ep_num as usize * 2 + is_in as usize),
+        _ => Err(Error::Failed("Endpoint number out of range")),
     }
 }
+
+/// Returns the Device Context Index for the bEndpointAddress of an Endpoint Descriptor.
+pub fn dci_from_endpoint_address(endpoint_address: u8) -> Result<usize> {
+    if endpoint_address & 0x70 != 0 {
+        return Err(Error::Failed("Reserved bits of bEndpointAddress are set"));
+    }
+    dci_from_endpoint(endpoint_address & 0xF, endpoint_address & 0x80 != 0)
+}
 const _: () = assert!(size_of::<EndpointDescriptor>() == 7);
 
+/// Decodes a String Descriptor (bLength, bDescriptorType, then UTF-16LE code units).
+/// Bytes beyond bLength are ignored.
+pub fn decode_string_descriptor(buf: &[u8]) -> Result<String> {
+    let [desc_len, desc_type, ..] = *buf else {
+        return Err(Error::Failed("String Descriptor is too short"));
+    };
+    if desc_type != DescriptorType::String as u8 {
+        return Err(Error::Failed("Not a String Descriptor"));
+    }
+    let payload = buf
+        .get(2..desc_len as usize)
+        .ok_or(Error::Failed("String Descriptor is truncated"))?;
+    let units = payload
+        .chunks_exact(2)
+        .map(|c| u16::from_le_bytes([c[0], c[1]]));
+    Ok(char::decode_utf16(units)
+        .map(|c| c.unwrap_or(char::REPLACEMENT_CHARACTER))
+        .collect())
+}
+
 unsafe impl IntoPinnedMutableSlice for DeviceDescriptor {}
 unsafe impl IntoPinnedMutableSlice for ConfigDescriptor {}
 unsafe impl IntoPinnedMutableSlice for InterfaceDescriptor {}
@@ -181,3 +228,53 @@ unsafe impl Sliceable for DeviceDescriptor {}
 unsafe impl Sliceable for ConfigDescriptor {}
 unsafe impl Sliceable for InterfaceDescriptor {}
 unsafe impl Sliceable for EndpointDescriptor {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    #[test_case]
+    fn decode_string_descriptor_utf16le() {
+        // "QEMU" as reported by the QEMU USB tablet, followed by garbage
+        let buf = [
+            0x0a, 0x03, 0x51, 0x00, 0x45, 0x00, 0x4d, 0x00, 0x55, 0x00, 0xff, 0xff,
+        ];
+        assert_eq!(decode_string_descriptor(&buf), Ok("QEMU".into()));
+        // Non-ASCII characters
+        let buf = [0x06, 0x03, 0x42, 0x30, 0x44, 0x30];
+        assert_eq!(decode_string_descriptor(&buf), Ok("あい".into()));
+    }
+    #[test_case]
+    fn decode_string_descriptor_rejects_malformed() {
+        assert!(decode_string_descriptor(&[0x02]).is_err());
+        assert!(decode_string_descriptor(&[0x04, 0x01, 0x51, 0x00]).is_err());
+        assert!(decode_string_descriptor(&[0x0a, 0x03, 0x51, 0x00]).is_err());
+    }
+    #[test_case]
+    fn dci_of_endpoints() {
+        assert_eq!(dci_from_endpoint(0, false), Ok(DCI_EP0));
+        assert_eq!(dci_from_endpoint(0, true), Ok(DCI_EP0));
+        assert_eq!(dci_from_endpoint(1, false), Ok(2));
+        assert_eq!(dci_from_endpoint(1, true), Ok(3));
+        assert_eq!(dci_from_endpoint(2, true), Ok(5));
+        assert_eq!(dci_from_endpoint(15, true), Ok(31));
+        assert!(dci_from_endpoint(16, false).is_err());
+        assert!(dci_from_endpoint(16, true).is_err());
+    }
+    #[test_case]
+    fn dci_of_endpoint_addresses() {
+        let ep_desc = |endpoint_address| EndpointDescriptor {
+            endpoint_address,
+            ..Default::default()
+        };
+        assert_eq!(ep_desc(0x00).dci(), Ok(DCI_EP0));
+        assert_eq!(ep_desc(0x81).dci(), Ok(3));
+        assert_eq!(ep_desc(0x01).dci(), Ok(2));
+        assert_eq!(ep_desc(0x82).dci(), Ok(5));
+        assert_eq!(ep_desc(0x02).dci(), Ok(4));
+        assert_eq!(ep_desc(0x8f).dci(), Ok(31));
+        assert_eq!(ep_desc(0x0f).dci(), Ok(30));
+        // Endpoint number 16 does not fit in the 4 bits and sets a reserved bit
+        assert!(ep_desc(0x90).dci().is_err());
+        assert!(ep_desc(0x10).dci().is_err());
+    }
+}
@@ -4,6 +4,7 @@ use crate::bitset::BitSet;
 use crate::error;
 use crate::error::Error;
 use crate::error::Result;
+use crate::executor::SelectFuture;
 use crate::info;
 use crate::input::InputManager;
 use crate::input::KeyEvent;
@@ -12,11 +13,14 @@ use crate::usb::descriptor::ConfigDescriptor;
 use crate::usb::descriptor::EndpointDescriptor;
 use crate::usb::descriptor::InterfaceDescriptor;
 use crate::usb::descriptor::UsbDescriptor;
+use crate::usb::descriptor::DCI_EP0;
+use crate::warn;
 use crate::xhci::device::UsbDeviceDriverContext;
 use crate::xhci::device::UsbHidProtocol;
 use crate::xhci::future::EventFuture;
 use alloc::format;
 use alloc::vec::Vec;
+use sabi::Modifiers;
 
 pub fn pick_config(
     descriptors: &Vec<UsbDescriptor>,
@@ -54,7 +58,9 @@ pub fn pick_config(
     Ok((config_desc, interface_desc, ep_desc_list))
 }
 
-pub async fn init_usb_hid_keyboard(ddc: &mut UsbDeviceDriverContext) -> Result<()> {
+pub async fn init_usb_hid_keyboard(
+    ddc: &mut UsbDeviceDriverContext,
+) -> Result<InterfaceDescriptor> {
     let descriptors = ddc.descriptors();
     let (config_desc, interface_desc, ep_desc_list) = pick_config(descriptors)?;
     ddc.set_config(config_desc.config_value()).await?;
@@ -68,13 +74,42 @@ pub async fn init_usb_hid_keyboard(ddc: &mut UsbDeviceDriverContext) -> Result<(
     // or Stream Transfer Ring of the Device Slot.
     for ep_desc in &ep_desc_list {
         let ep_ring = ddc
-            .ep_ring(ep_desc.dci())?
+            .ep_ring(ep_desc.dci()?)?
             .as_ref()
             .ok_or(Error::Failed("Endpoint not created"))?;
         ep_ring.fill_ring()?;
         ddc.notify_ep(ep_desc)?;
     }
-    Ok(())
+    Ok(interface_desc)
+}
+
+const USAGE_ID_CAPS_LOCK: u8 = 0x39;
+const USAGE_ID_SCROLL_LOCK: u8 = 0x47;
+const USAGE_ID_NUM_LOCK: u8 = 0x53;
+
+/// States of the lock keys, which are reflected to the LEDs on the keyboard.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct LockKeys {
+    pub num_lock: bool,
+    pub caps_lock: bool,
+    pub scroll_lock: bool,
+}
+impl LockKeys {
+    /// Toggles the state if `usage_id` is one of the lock keys. Returns false otherwise.
+    pub fn toggle(&mut self, usage_id: u8) -> bool {
+        let state = match usage_id {
+            USAGE_ID_NUM_LOCK => &mut self.num_lock,
+            USAGE_ID_CAPS_LOCK => &mut self.caps_lock,
+            USAGE_ID_SCROLL_LOCK => &mut self.scroll_lock,
+            _ => return false,
+        };
+        *state = !*state;
+        true
+    }
+    /// Returns the LED byte of the Output report of boot keyboards ([HID] Appendix B.1).
+    pub fn led_report(&self) -> u8 {
+        (self.num_lock as u8) | (self.caps_lock as u8) << 1 | (self.scroll_lock as u8) << 2
+    }
 }
 
 fn usage_id_to_char(usage_id: u8) -> Result<KeyEvent> {
@@ -97,16 +132,68 @@ fn usage_id_to_char(usage_id: u8) -> Result<KeyEvent> {
     }
 }
 
-pub async fn usb_hid_keyboard_mainloop(ddc: UsbDeviceDriverContext) -> Result<()> {
+const USAGE_ID_ERROR_ROLL_OVER: u8 = 0x01;
+
+/// Returns true if the keyboard reports the phantom state, which means that too many keys are
+/// pressed to tell which ones are. All the key slots are filled with ErrorRollOver in that case.
+fn is_phantom_state(report: &[u8; 8]) -> bool {
+    report[2..].contains(&USAGE_ID_ERROR_ROLL_OVER)
+}
+
+/// Returns the Usage IDs of the keys pressed in the boot keyboard report.
+fn pressed_keys(report: &[u8; 8]) -> BitSet<32> {
+    let mut keys = BitSet::<32>::new();
+    // First two bytes are modifiers and reserved, so skip them. 0 means that the slot is empty.
+    for id in report[2..].iter().filter(|id| **id != 0) {
+        keys.insert(*id as usize).unwrap();
+    }
+    keys
+}
+
+/// Returns the Usage IDs of the keys which are pressed in `next` but were not in `prev`.
+fn newly_pressed_keys(prev: &BitSet<32>, next: &BitSet<32>) -> Vec<u8> {
+    next.iter()
+        .filter(|id| !prev.get(*id).unwrap_or(false))
+        .map(|id| id as u8)
+        .collect()
+}
+
+pub async fn usb_hid_keyboard_mainloop(
+    mut ddc: UsbDeviceDriverContext,
+    interface_desc: InterfaceDescriptor,
+) -> Result<()> {
     let port = ddc.port();
     let slot = ddc.slot();
     let xhci = ddc.xhci();
     let portsc = xhci.portsc(port)?.upgrade().ok_or("PORTSC was invalid")?;
     let mut prev_pressed_keys = BitSet::<32>::new();
+    let mut lock_keys = LockKeys::default();
+    // The LEDs are optional, and many keyboards STALL SET_REPORT, so keep going on failure
+    if let Err(e) = ddc
+        .set_report(&interface_desc, &[lock_keys.led_report()])
+        .await
+    {
+        warn!("usb_hid_keyboard: failed to set the LEDs: {e:?}");
+    }
     let event_trb = EventFuture::new_transfer_event_on_slot(xhci.primary_event_ring(), slot);
+    let port_status_change =
+        EventFuture::new_port_status_change(xhci.primary_event_ring(), Some(port));
     loop {
-        let event_trb = event_trb.clone().await;
+        let (event_trb, port_status_change_event) =
+            SelectFuture::new(event_trb.clone(), port_status_change.clone()).await;
+        if port_status_change_event.is_some() && !portsc.ccs() {
+            // Free the slot so that the xHC can assign it to the next device
+            ddc.disable_slot().await?;
+            return Err(Error::FailedString(format!("port {} disconnected", port)));
+        }
+        let Some(event_trb) = event_trb else {
+            continue;
+        };
         match event_trb {
+            Ok(trb) if trb.dci() == DCI_EP0 => {
+                // Completion of a request on the control endpoint, e.g. set_report()
+                continue;
+            }
             Ok(trb) => {
                 let transfer_trb_ptr = trb.data() as usize;
                 let mut report = [0u8; 8];
@@ -122,46 +209,127 @@ pub async fn usb_hid_keyboard_mainloop(ddc: UsbDeviceDriverContext) -> Result<()
                     tring.dequeue_trb(transfer_trb_ptr)?;
                     xhci.notify_ep(slot, trb.dci())?;
                 }
-                let mut next_pressed_keys = BitSet::<32>::new();
-                // First two bytes are modifiers, so skip them
-                let keycodes = report.iter().skip(2);
-                for value in keycodes {
-                    next_pressed_keys.insert(*value as usize).unwrap();
+                InputManager::take().set_modifiers(Modifiers(report[0]));
+                if is_phantom_state(&report) {
+                    // Keep the previous key states until the keyboard reports them correctly
+                    continue;
                 }
-                let change = prev_pressed_keys.symmetric_difference(&next_pressed_keys);
-                for id in change.iter() {
-                    let c = usage_id_to_char(id as u8);
-                    if let Ok(c) = c {
-                        if !prev_pressed_keys.get(id).unwrap_or(false) {
-                            // the key state was changed from released to pressed
-                            if c == KeyEvent::None {
-                                continue;
-                            }
+                let next_pressed_keys = pressed_keys(&report);
+                let mut lock_keys_changed = false;
+                for id in newly_pressed_keys(&prev_pressed_keys, &next_pressed_keys) {
+                    if lock_keys.toggle(id) {
+                        lock_keys_changed = true;
+                        continue;
+                    }
+                    match usage_id_to_char(id) {
+                        Ok(c) => {
                             if let Some(c) = c.to_char() {
+                                let c = if lock_keys.caps_lock {
+                                    c.to_ascii_uppercase()
+                                } else {
+                                    c
+                                };
                                 InputManager::take().push_input(c);
                             }
                         }
-                    } else {
-                        error!("{c:?}");
+                        Err(e) => error!("{e:?}"),
                     }
                 }
                 prev_pressed_keys = next_pressed_keys;
+                if lock_keys_changed {
+                    if let Err(e) = ddc
+                        .set_report(&interface_desc, &[lock_keys.led_report()])
+                        .await
+                    {
+                        warn!("usb_hid_keyboard: failed to update the LEDs: {e:?}");
+                    }
+                }
             }
             Err(e) => {
                 error!("e: {:?}", e);
             }
         }
-        if !portsc.ccs() {
-            return Err(Error::FailedString(format!("port {} disconnected", port)));
-        }
     }
 }
 
 pub async fn attach_usb_device(mut ddc: UsbDeviceDriverContext) -> Result<()> {
-    init_usb_hid_keyboard(&mut ddc).await?;
+    let interface_desc = init_usb_hid_keyboard(&mut ddc).await?;
     // Note: this message is used by e2etest - please keep this as is!
     info!("usb_hid_keyboard is ready");
-    let e = usb_hid_keyboard_mainloop(ddc).await;
+    let e = usb_hid_keyboard_mainloop(ddc, interface_desc).await;
     info!("usb_hid_keyboard exited: {e:?}");
     e
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    #[test_case]
+    fn modifier_byte_decoding() {
+        let m = Modifiers(0);
+        assert!(!m.ctrl() && !m.shift() && !m.alt() && !m.gui());
+        // Left Ctrl
+        let m = Modifiers(0x01);
+        assert!(m.ctrl() && !m.shift() && !m.alt() && !m.gui());
+        // Right Shift + Left Alt
+        let m = Modifiers(0x24);
+        assert!(!m.ctrl() && m.shift() && m.alt() && !m.gui());
+        // Right GUI + Right Ctrl
+        let m = Modifiers(0x90);
+        assert!(m.ctrl() && !m.shift() && !m.alt() && m.gui());
+    }
+    #[test_case]
+    fn lock_keys_led_report() {
+        let mut keys = LockKeys::default();
+        assert_eq!(keys.led_report(), 0);
+        assert!(keys.toggle(USAGE_ID_NUM_LOCK));
+        assert_eq!(keys.led_report(), 0b001);
+        assert!(keys.toggle(USAGE_ID_CAPS_LOCK));
+        assert_eq!(keys.led_report(), 0b011);
+        assert!(keys.toggle(USAGE_ID_SCROLL_LOCK));
+        assert_eq!(keys.led_report(), 0b111);
+        assert!(keys.toggle(USAGE_ID_NUM_LOCK));
+        assert_eq!(keys.led_report(), 0b110);
+        // 'a' is not a lock key
+        assert!(!keys.toggle(4));
+        assert_eq!(keys.led_report(), 0b110);
+        let keys = LockKeys {
+            num_lock: false,
+            caps_lock: true,
+            scroll_lock: false,
+        };
+        assert_eq!(keys.led_report(), 0b010);
+    }
+    #[test_case]
+    fn phantom_state_is_detected() {
+        assert!(is_phantom_state(&[0, 0, 1, 1, 1, 1, 1, 1]));
+        assert!(is_phantom_state(&[0x02, 0, 1, 1, 1, 1, 1, 1]));
+        assert!(!is_phantom_state(&[0, 0, 4, 5, 0, 0, 0, 0]));
+        // The modifier byte is not a key slot
+        assert!(!is_phantom_state(&[0x01, 0, 0, 0, 0, 0, 0, 0]));
+    }
+    #[test_case]
+    fn modifier_only_report_has_no_key_events() {
+        let prev = pressed_keys(&[0; 8]);
+        // Left Ctrl + Left Shift
+        let next = pressed_keys(&[0x03, 0, 0, 0, 0, 0, 0, 0]);
+        assert_eq!(next.iter().count(), 0);
+        assert!(newly_pressed_keys(&prev, &next).is_empty());
+    }
+    #[test_case]
+    fn only_newly_pressed_keys_are_reported() {
+        let prev = pressed_keys(&[0, 0, 4, 0, 0, 0, 0, 0]);
+        let next = pressed_keys(&[0, 0, 4, 5, 0, 0, 0, 0]);
+        assert_eq!(newly_pressed_keys(&prev, &next), [5]);
+        // Releasing a key is not reported
+        assert!(newly_pressed_keys(&next, &prev).is_empty());
+    }
+    #[test_case]
+    fn input_manager_tracks_modifiers() {
+        let im = InputManager::take();
+        im.set_modifiers(Modifiers(0x11));
+        assert!(im.modifiers().ctrl());
+        im.set_modifiers(Modifiers::default());
+        assert!(!im.modifiers().ctrl());
+    }
+}
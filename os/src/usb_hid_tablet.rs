@@ -4,12 +4,14 @@ use crate::boot_info::BootInfo;
 use crate::error;
 use crate::error::Error;
 use crate::error::Result;
+use crate::executor::SelectFuture;
 use crate::input::InputManager;
 use crate::memory::Mmio;
 use crate::usb::descriptor::ConfigDescriptor;
 use crate::usb::descriptor::EndpointDescriptor;
 use crate::usb::descriptor::InterfaceDescriptor;
 use crate::usb::descriptor::UsbDescriptor;
+use crate::warn;
 use crate::xhci::device::UsbDeviceDriverContext;
 use crate::xhci::device::UsbHidProtocol;
 use crate::xhci::future::EventFuture;
@@ -56,6 +58,45 @@ pub fn pick_config(
     Ok((config_desc, interface_desc, ep_desc_list))
 }
 
+/// Decodes an input report of the QEMU USB tablet:
+/// buttons(u8), x(u16), y(u16) and wheel(i8, optional)
+/// into a MouseEvent in the screen coordinates of the given size.
+fn decode_report(report: &[u8], w: f64, h: f64) -> Result<MouseEvent> {
+    if report.len() < 5 {
+        return Err(Error::Failed("USB HID tablet report is too short"));
+    }
+    let max_x = w - 1.0;
+    let max_y = h - 1.0;
+
+    let b = report[0];
+    let l = b & 1 != 0;
+    let r = b & 2 != 0;
+    let c = b & 4 != 0;
+    let button = MouseButtonState::from_lcr(l, c, r);
+
+    // 0~32767, top left origin (on QEMU)
+    let px = [report[1], report[2]];
+    let py = [report[3], report[4]];
+    let px = u16::from_le_bytes(px);
+    let py = u16::from_le_bytes(py);
+    let px = px as f64 / 32768f64;
+    let py = py as f64 / 32768f64;
+    // convert to the screen corrdinates
+    let px = px * w;
+    let py = py * h;
+    let px = unsafe { px.clamp(0.0, max_x).to_int_unchecked() };
+    let py = unsafe { py.clamp(0.0, max_y).to_int_unchecked() };
+    let position = PointerPosition::from_xy(px, py);
+
+    let wheel = report.get(5).map(|v| *v as i8).unwrap_or(0);
+
+    Ok(MouseEvent {
+        button,
+        position,
+        wheel,
+    })
+}
+
 pub async fn init_usb_hid_tablet(ddc: &mut UsbDeviceDriverContext) -> Result<()> {
     let descriptors = ddc.descriptors();
     let (config_desc, interface_desc, ep_desc_list) = pick_config(descriptors)?;
@@ -70,7 +111,7 @@ pub async fn init_usb_hid_tablet(ddc: &mut UsbDeviceDriverContext) -> Result<()>
     // or Stream Transfer Ring of the Device Slot.
     for ep_desc in &ep_desc_list {
         let ep_ring = ddc
-            .ep_ring(ep_desc.dci())?
+            .ep_ring(ep_desc.dci()?)?
             .as_ref()
             .ok_or(Error::Failed("Endpoint not created"))?;
         ep_ring.fill_ring()?;
@@ -90,12 +131,21 @@ pub async fn attach_usb_device(mut ddc: UsbDeviceDriverContext) -> Result<()> {
     let vram = BootInfo::take().vram();
     let w = vram.width() as f64;
     let h = vram.height() as f64;
-    let max_x = w - 1.0;
-    let max_y = h - 1.0;
 
     let event_trb = EventFuture::new_transfer_event_on_slot(xhci.primary_event_ring(), slot);
+    let port_status_change =
+        EventFuture::new_port_status_change(xhci.primary_event_ring(), Some(port));
     loop {
-        let event_trb = event_trb.clone().await;
+        let (event_trb, port_status_change_event) =
+            SelectFuture::new(event_trb.clone(), port_status_change.clone()).await;
+        if port_status_change_event.is_some() && !portsc.ccs() {
+            // Free the slot so that the xHC can assign it to the next device
+            ddc.disable_slot().await?;
+            return Err(Error::FailedString(format!("port {} disconnected", port)));
+        }
+        let Some(event_trb) = event_trb else {
+            continue;
+        };
         match event_trb {
             Ok(trb) => {
                 let transfer_trb_ptr = trb.data() as usize;
@@ -113,34 +163,40 @@ pub async fn attach_usb_device(mut ddc: UsbDeviceDriverContext) -> Result<()> {
                     xhci.notify_ep(slot, trb.dci())?;
                 }
 
-                let b = report[0];
-                let l = b & 1 != 0;
-                let r = b & 2 != 0;
-                let c = b & 4 != 0;
-                let button = MouseButtonState::from_lcr(l, c, r);
-
-                // 0~32767, top left origin (on QEMU)
-                let px = [report[1], report[2]];
-                let py = [report[3], report[4]];
-                let px = u16::from_le_bytes(px);
-                let py = u16::from_le_bytes(py);
-                let px = px as f64 / 32768f64;
-                let py = py as f64 / 32768f64;
-                // convert to the screen corrdinates
-                let px = px * w;
-                let py = py * h;
-                let px = unsafe { px.clamp(0.0, max_x).to_int_unchecked() };
-                let py = unsafe { py.clamp(0.0, max_y).to_int_unchecked() };
-                let position = PointerPosition::from_xy(px, py);
-
-                InputManager::take().push_cursor_input_absolute(MouseEvent { button, position });
+                match decode_report(&report, w, h) {
+                    Ok(e) => InputManager::take().push_cursor_input_absolute(e),
+                    Err(e) => warn!("usb_hid_tablet: skipping a bad report {report:?}: {e:?}"),
+                }
             }
             Err(e) => {
                 error!("e: {:?}", e);
             }
         }
-        if !portsc.ccs() {
-            return Err(Error::FailedString(format!("port {} disconnected", port)));
-        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    #[test_case]
+    fn decode_report_with_wheel() {
+        // left button, center of the screen, wheel scrolled down by one notch
+        let report = [0x01, 0x00, 0x40, 0x00, 0x40, 0xff];
+        let e = decode_report(&report, 640.0, 480.0).unwrap();
+        assert!(e.button.l());
+        assert_eq!((e.position.x, e.position.y), (320, 240));
+        assert_eq!(e.wheel, -1);
+        // wheel scrolled up, position clamped to the screen
+        let report = [0x00, 0xff, 0x7f, 0x00, 0x00, 0x02];
+        let e = decode_report(&report, 640.0, 480.0).unwrap();
+        assert!(!e.button.l());
+        assert_eq!((e.position.x, e.position.y), (639, 0));
+        assert_eq!(e.wheel, 2);
+    }
+    #[test_case]
+    fn decode_report_without_wheel() {
+        let report = [0x00, 0x00, 0x00, 0x00, 0x00];
+        assert_eq!(decode_report(&report, 640.0, 480.0).unwrap().wheel, 0);
+        assert!(decode_report(&report[..4], 640.0, 480.0).is_err());
     }
 }
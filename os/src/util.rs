@@ -2,6 +2,13 @@ extern crate alloc;
 
 use crate::error::Error;
 use crate::error::Result;
+#[cfg(test)]
+use crate::test_assert;
+#[cfg(test)]
+use crate::test_assert_eq;
+use crate::x86_64::busy_loop_hint;
+#[cfg(test)]
+use core::cell::Cell;
 use core::cmp::min;
 use core::convert::From;
 use core::convert::TryInto;
@@ -70,6 +77,39 @@ fn extract_bits_tests() {
     );
 }
 
+/// Polls `done` until it returns true, up to `max_polls` times.
+pub fn poll_until(mut done: impl FnMut() -> bool, max_polls: usize) -> Result<()> {
+    for _ in 0..max_polls {
+        if done() {
+            return Ok(());
+        }
+        busy_loop_hint();
+    }
+    Err(Error::Failed("poll_until: timed out"))
+}
+
+#[test_case]
+fn poll_until_completes() {
+    let count = Cell::new(0);
+    let done = || {
+        count.set(count.get() + 1);
+        count.get() >= 3
+    };
+    test_assert_eq!(poll_until(done, 10), Ok(()));
+    test_assert_eq!(count.get(), 3);
+}
+
+#[test_case]
+fn poll_until_times_out() {
+    let count = Cell::new(0);
+    let done = || {
+        count.set(count.get() + 1);
+        false
+    };
+    test_assert!(poll_until(done, 10).is_err());
+    test_assert_eq!(count.get(), 10);
+}
+
 pub fn size_in_pages_from_bytes(size_in_bytes: usize) -> usize {
     (size_in_bytes + PAGE_SIZE - 1) >> PAGE_OFFSET_BITS
 }
@@ -81,17 +121,17 @@ pub fn round_up_to_nearest_pow2(v: usize) -> Result<usize> {
 }
 #[test_case]
 fn round_up_to_nearest_pow2_tests() {
-    assert_eq!(round_up_to_nearest_pow2(0), Err(Error::CalcOutOfRange));
-    assert_eq!(round_up_to_nearest_pow2(1), Ok(1));
-    assert_eq!(round_up_to_nearest_pow2(2), Ok(2));
-    assert_eq!(round_up_to_nearest_pow2(3), Ok(4));
-    assert_eq!(round_up_to_nearest_pow2(4), Ok(4));
-    assert_eq!(round_up_to_nearest_pow2(5), Ok(8));
-    assert_eq!(round_up_to_nearest_pow2(6), Ok(8));
-    assert_eq!(round_up_to_nearest_pow2(7), Ok(8));
-    assert_eq!(round_up_to_nearest_pow2(8), Ok(8));
-    assert_eq!(round_up_to_nearest_pow2(9), Ok(16));
-    assert_eq!(round_up_to_nearest_pow2(9), Ok(16));
+    test_assert_eq!(round_up_to_nearest_pow2(0), Err(Error::CalcOutOfRange));
+    test_assert_eq!(round_up_to_nearest_pow2(1), Ok(1));
+    test_assert_eq!(round_up_to_nearest_pow2(2), Ok(2));
+    test_assert_eq!(round_up_to_nearest_pow2(3), Ok(4));
+    test_assert_eq!(round_up_to_nearest_pow2(4), Ok(4));
+    test_assert_eq!(round_up_to_nearest_pow2(5), Ok(8));
+    test_assert_eq!(round_up_to_nearest_pow2(6), Ok(8));
+    test_assert_eq!(round_up_to_nearest_pow2(7), Ok(8));
+    test_assert_eq!(round_up_to_nearest_pow2(8), Ok(8));
+    test_assert_eq!(round_up_to_nearest_pow2(9), Ok(16));
+    test_assert_eq!(round_up_to_nearest_pow2(9), Ok(16));
 }
 
 pub fn read_le_u16(data: &[u8], ofs: usize) -> Result<u16> {
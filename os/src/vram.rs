@@ -1,8 +1,50 @@
 use crate::efi::locate_graphic_protocol;
 use crate::efi::EfiSystemTable;
 use crate::error::Result;
+use crate::mutex::Mutex;
+use crate::x86_64::clflush;
+use crate::x86_64::sfence;
 use core::pin::Pin;
 use noli::bitmap::Bitmap;
+use noli::rect::Rect;
+
+const CACHE_LINE_SIZE: usize = 64;
+
+// Region of VRAM that is drawn but not flushed yet
+static VRAM_DIRTY_RECT: Mutex<Option<Rect>> = Mutex::new(None);
+
+/// c.f. UEFI Spec 2.10 12.9.1 EFI_GRAPHICS_PIXEL_FORMAT
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum PixelFormat {
+    RedGreenBlueReserved8BitPerColor,
+    BlueGreenRedReserved8BitPerColor,
+    BitMask,
+    BltOnly,
+    Unknown(u32),
+}
+impl From<u32> for PixelFormat {
+    fn from(value: u32) -> Self {
+        match value {
+            0 => PixelFormat::RedGreenBlueReserved8BitPerColor,
+            1 => PixelFormat::BlueGreenRedReserved8BitPerColor,
+            2 => PixelFormat::BitMask,
+            3 => PixelFormat::BltOnly,
+            v => PixelFormat::Unknown(v),
+        }
+    }
+}
+
+/// Geometry of the framebuffer provided by the GOP
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct FramebufferInfo {
+    /// Physical address of the framebuffer
+    pub base: usize,
+    pub width: usize,
+    pub height: usize,
+    /// Number of pixels in a line, including the invisible ones
+    pub stride: usize,
+    pub format: PixelFormat,
+}
 
 #[derive(Clone, Copy)]
 pub struct VRAMBufferInfo {
@@ -10,6 +52,26 @@ pub struct VRAMBufferInfo {
     width: usize,
     height: usize,
     pixels_per_line: usize,
+    format: PixelFormat,
+}
+impl VRAMBufferInfo {
+    pub fn framebuffer(&self) -> FramebufferInfo {
+        FramebufferInfo {
+            base: self.buf as usize,
+            width: self.width,
+            height: self.height,
+            stride: self.pixels_per_line,
+            format: self.format,
+        }
+    }
+    /// Marks the rect as drawn so that it will be flushed by the next flush() call.
+    pub fn mark_dirty(&self, rect: Rect) {
+        let mut dirty = VRAM_DIRTY_RECT.lock();
+        *dirty = Some(match dirty.take() {
+            Some(dirty) => dirty.union(&rect),
+            None => rect,
+        });
+    }
 }
 
 impl Bitmap for VRAMBufferInfo {
@@ -31,6 +93,29 @@ impl Bitmap for VRAMBufferInfo {
     fn buf_mut(&mut self) -> *mut u8 {
         self.buf
     }
+    fn is_rgb_order(&self) -> bool {
+        self.format == PixelFormat::RedGreenBlueReserved8BitPerColor
+    }
+    fn flush(&mut self) {
+        let Some(dirty) = VRAM_DIRTY_RECT.lock().take() else {
+            return;
+        };
+        let Some(rect) = Rect::new(0, 0, self.width(), self.height())
+            .and_then(|screen| screen.intersection(&dirty))
+        else {
+            return;
+        };
+        for y in rect.y()..rect.y() + rect.h() {
+            // SAFETY: (x, y) is in the screen since the rect is clipped above
+            let start = unsafe { self.unchecked_pixel_at(rect.x(), y) } as usize;
+            let end = start + (rect.w() * self.bytes_per_pixel()) as usize;
+            for addr in (start & !(CACHE_LINE_SIZE - 1)..end).step_by(CACHE_LINE_SIZE) {
+                clflush(addr);
+            }
+        }
+        // Drain the write-combining buffers as well
+        sfence();
+    }
 }
 
 pub fn init_vram(efi_system_table: Pin<&EfiSystemTable>) -> Result<VRAMBufferInfo> {
@@ -40,5 +125,33 @@ pub fn init_vram(efi_system_table: Pin<&EfiSystemTable>) -> Result<VRAMBufferInf
         width: gp.mode.info.horizontal_resolution as usize,
         height: gp.mode.info.vertical_resolution as usize,
         pixels_per_line: gp.mode.info.pixels_per_scan_line as usize,
+        format: PixelFormat::from(gp.mode.info.pixel_format),
     })
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test_case]
+    fn framebuffer_info_matches_vram() {
+        let vram = VRAMBufferInfo {
+            buf: 0x8000_0000 as *mut u8,
+            width: 1024,
+            height: 768,
+            pixels_per_line: 1280,
+            format: PixelFormat::from(1),
+        };
+        assert_eq!(
+            vram.framebuffer(),
+            FramebufferInfo {
+                base: 0x8000_0000,
+                width: 1024,
+                height: 768,
+                stride: 1280,
+                format: PixelFormat::BlueGreenRedReserved8BitPerColor,
+            }
+        );
+        assert_eq!(PixelFormat::from(7), PixelFormat::Unknown(7));
+    }
+}
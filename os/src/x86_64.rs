@@ -56,6 +56,7 @@ Comparison:
 
 pub mod apic;
 pub mod context;
+pub mod cpuid;
 pub mod gdt;
 pub mod idt;
 pub mod paging;
@@ -63,7 +64,11 @@ pub mod syscall;
 
 extern crate alloc;
 
+use crate::error::Error;
+use crate::error::Result;
 use crate::serial::SerialPort;
+use crate::x86_64::cpuid::has_feature;
+use crate::x86_64::cpuid::Feature;
 use core::arch::asm;
 use core::fmt;
 use core::fmt::Write;
@@ -99,6 +104,10 @@ pub fn read_rsp() -> u64 {
     value
 }
 
+pub fn sfence() {
+    unsafe { asm!("sfence") }
+}
+
 pub fn clflush(ptr: usize) {
     unsafe {
         asm!("clflush [rax]",
@@ -192,6 +201,17 @@ pub unsafe fn write_gs(selector: u16) {
                 in("ax") selector)
 }
 
+pub fn rdtsc() -> u64 {
+    let mut high: u32;
+    let mut low: u32;
+    unsafe {
+        asm!("rdtsc",
+            out("edx") high,
+            out("eax") low);
+    }
+    ((high as u64) << 32) | low as u64
+}
+
 pub fn busy_loop_hint() {
     unsafe { asm!("pause") }
 }
@@ -290,6 +310,69 @@ pub fn write_io_port_u32(port: u16, data: u32) {
     }
 }
 
+// Control register bits needed to use x87 FPU / SSE instructions
+// c.f. SDM Vol.3 13.1.3 Initialization of the SSE Extensions
+pub const CR0_MP: u64 = 1 << 1;
+pub const CR0_EM: u64 = 1 << 2;
+pub const CR0_TS: u64 = 1 << 3;
+pub const CR4_OSFXSR: u64 = 1 << 9;
+pub const CR4_OSXMMEXCPT: u64 = 1 << 10;
+
+/// Returns the CR0 value that allows FPU / SSE instructions without raising #UD or #NM.
+pub fn cr0_with_fpu_enabled(cr0: u64) -> u64 {
+    (cr0 & !(CR0_EM | CR0_TS)) | CR0_MP
+}
+/// Returns the CR4 value that enables FXSAVE / FXRSTOR and SSE exceptions.
+pub fn cr4_with_sse_enabled(cr4: u64) -> u64 {
+    cr4 | CR4_OSFXSR | CR4_OSXMMEXCPT
+}
+
+pub fn read_cr0() -> u64 {
+    let mut cr0: u64;
+    unsafe {
+        asm!("mov rax, cr0",
+            out("rax") cr0)
+    }
+    cr0
+}
+/// # Safety
+/// Anything can happen if the given value is invalid.
+pub unsafe fn write_cr0(cr0: u64) {
+    asm!("mov cr0, rax",
+            in("rax") cr0)
+}
+pub fn read_cr4() -> u64 {
+    let mut cr4: u64;
+    unsafe {
+        asm!("mov rax, cr4",
+            out("rax") cr4)
+    }
+    cr4
+}
+/// # Safety
+/// Anything can happen if the given value is invalid.
+pub unsafe fn write_cr4(cr4: u64) {
+    asm!("mov cr4, rax",
+            in("rax") cr4)
+}
+
+pub fn init_fpu() -> Result<()> {
+    if !has_feature(Feature::Fxsr) || !has_feature(Feature::Sse) {
+        return Err(Error::Failed("FXSR / SSE is not supported by the CPU"));
+    }
+    // SAFETY: This is safe since the new values only differ in the FPU / SSE related bits.
+    unsafe {
+        write_cr0(cr0_with_fpu_enabled(read_cr0()));
+        write_cr4(cr4_with_sse_enabled(read_cr4()));
+    }
+    Ok(())
+}
+pub fn is_fpu_enabled() -> bool {
+    let cr0 = read_cr0();
+    let cr4 = read_cr4();
+    cr0_with_fpu_enabled(cr0) == cr0 && cr4_with_sse_enabled(cr4) == cr4
+}
+
 pub fn read_cr2() -> u64 {
     let mut cr2: u64;
     unsafe {
@@ -348,6 +431,24 @@ pub fn trigger_debug_interrupt() {
     unsafe { asm!("int3") }
 }
 
+/// Vectors whose handlers expect no error code and no LAPIC EOI, so `int n` reaches them with a
+/// correct stack frame. The exceptions with an error code (8, 13, 14) and the timer (32) are
+/// excluded.
+pub const SOFTWARE_INTERRUPT_VECTORS: [u8; 2] = [3, 6];
+
+/// Executes `int n` for the given vector.
+pub fn trigger_software_interrupt(vector: u8) -> Result<()> {
+    // The vector of `int` should be an immediate value, so list all of them here.
+    unsafe {
+        match vector {
+            3 => asm!("int 3"),
+            6 => asm!("int 6"),
+            _ => return Err(Error::Failed("Unsupported interrupt vector")),
+        }
+    }
+    Ok(())
+}
+
 pub fn stihlt() {
     unsafe { asm!("sti; hlt") }
 }
@@ -358,6 +459,16 @@ pub fn rest_in_peace() -> ! {
     }
 }
 
+/// Resets the machine. Tries the keyboard controller first, then the Reset Control Register
+/// which is found on the chipsets like Q35. Halts if both of them did not work.
+pub fn reboot() -> ! {
+    // Pulse the CPU reset line via the 8042 keyboard controller
+    write_io_port_u8(0x64, 0xFE);
+    // Reset Control Register: full reset (bit 2: reset CPU, bit 1: system reset)
+    write_io_port_u8(0xCF9, 0x06);
+    rest_in_peace()
+}
+
 #[no_mangle]
 pub fn dump_stack() {
     let mut serial_writer = SerialPort::default();
@@ -404,3 +515,28 @@ pub fn dump_stack() {
     let stack = unsafe { slice::from_raw_parts(rbp as *const u8, 64) };
     crate::print::hexdump(stack);
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test_case]
+    fn cr0_with_fpu_enabled_sets_mp_and_clears_em_ts() {
+        assert_eq!(cr0_with_fpu_enabled(0), CR0_MP);
+        assert_eq!(cr0_with_fpu_enabled(CR0_EM | CR0_TS), CR0_MP);
+        // Other bits (PG, PE) are preserved
+        let cr0 = (1 << 31) | 1;
+        assert_eq!(cr0_with_fpu_enabled(cr0 | CR0_EM), cr0 | CR0_MP);
+        assert_eq!(cr0_with_fpu_enabled(cr0 | CR0_MP), cr0 | CR0_MP);
+    }
+    #[test_case]
+    fn cr4_with_sse_enabled_sets_osfxsr_and_osxmmexcpt() {
+        assert_eq!(cr4_with_sse_enabled(0), CR4_OSFXSR | CR4_OSXMMEXCPT);
+        // Other bits (PAE) are preserved
+        let cr4 = 1 << 5;
+        assert_eq!(
+            cr4_with_sse_enabled(cr4 | CR4_OSFXSR),
+            cr4 | CR4_OSFXSR | CR4_OSXMMEXCPT
+        );
+    }
+}
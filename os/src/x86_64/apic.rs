@@ -1,8 +1,13 @@
+use crate::clock::ClockSource;
 use crate::error::Error;
 use crate::error::Result;
+use crate::pit;
 use crate::x86_64;
 use crate::x86_64::CpuidRequest;
+use core::ptr::read_volatile;
 use core::ptr::write_volatile;
+use core::sync::atomic::AtomicU64;
+use core::sync::atomic::Ordering;
 
 #[derive(Debug)]
 #[allow(dead_code)]
@@ -41,6 +46,90 @@ impl Default for LocalApic {
     }
 }
 
+const LAPIC_REG_LVT_TIMER: u64 = 0x320;
+const LAPIC_REG_TIMER_INITIAL_COUNT: u64 = 0x380;
+const LAPIC_REG_TIMER_CURRENT_COUNT: u64 = 0x390;
+const LAPIC_REG_TIMER_DIVIDE_CONFIG: u64 = 0x3E0;
+const LVT_TIMER_VECTOR_UNUSED: u32 = 0xff;
+const LVT_MASKED: u32 = 1 << 16;
+const LVT_TIMER_MODE_PERIODIC: u32 = 1 << 17;
+const TIMER_DIVIDE_BY_128: u32 = 0b1010;
+
+/// The timer of the Local APIC, used as a clock source if neither invariant TSC nor HPET is
+/// available. The 32-bit down counter runs in the periodic mode with the interrupt masked, and
+/// is extended to 64 bits on every read. So it should be read at least once per wraparound,
+/// which takes minutes with the divider of 128.
+pub struct ApicTimer {
+    base_addr: u64,
+    freq: u64,
+    last_counter: AtomicU64,
+}
+static mut APIC_TIMER: Option<ApicTimer> = None;
+impl ApicTimer {
+    /// Returns None if the timer is not calibrated (e.g. other clocks are available).
+    pub fn try_take() -> Option<&'static Self> {
+        unsafe { APIC_TIMER.as_ref() }
+    }
+    /// # Safety
+    /// This is safe if it is called only once.
+    pub unsafe fn set(timer: ApicTimer) {
+        assert!(APIC_TIMER.is_none());
+        APIC_TIMER = Some(timer);
+    }
+    /// Starts the timer of `lapic` and measures its frequency with the PIT for `duration_ms`.
+    pub fn calibrate_with_pit(lapic: &LocalApic, duration_ms: u64) -> Result<Self> {
+        let mut timer = Self {
+            base_addr: lapic.base_addr,
+            freq: 0,
+            last_counter: AtomicU64::new(0),
+        };
+        timer.start();
+        let freq = pit::measure_freq(|| timer.counter(), duration_ms)?;
+        timer.freq = freq;
+        Ok(timer)
+    }
+    fn start(&mut self) {
+        // This is safe as far as the base_addr is taken from a LocalApic.
+        unsafe {
+            self.write_register(LAPIC_REG_TIMER_DIVIDE_CONFIG, TIMER_DIVIDE_BY_128);
+            self.write_register(
+                LAPIC_REG_LVT_TIMER,
+                LVT_TIMER_MODE_PERIODIC | LVT_MASKED | LVT_TIMER_VECTOR_UNUSED,
+            );
+            self.write_register(LAPIC_REG_TIMER_INITIAL_COUNT, u32::MAX);
+        }
+    }
+    unsafe fn write_register(&mut self, offset: u64, value: u32) {
+        write_volatile((self.base_addr + offset) as *mut u32, value)
+    }
+    fn current_count(&self) -> u32 {
+        // This is safe as far as the base_addr is taken from a LocalApic.
+        unsafe { read_volatile((self.base_addr + LAPIC_REG_TIMER_CURRENT_COUNT) as *const u32) }
+    }
+}
+impl ClockSource for ApicTimer {
+    fn counter(&self) -> u64 {
+        let last = self.last_counter.load(Ordering::SeqCst);
+        let counter = extend_counter(last, u32::MAX - self.current_count());
+        self.last_counter.store(counter, Ordering::SeqCst);
+        counter
+    }
+    fn freq(&self) -> u64 {
+        self.freq
+    }
+}
+
+/// Extends `now`, the lower 32 bits of a counter, to 64 bits, assuming that the counter wrapped
+/// around at most once since it was `last`.
+fn extend_counter(last: u64, now: u32) -> u64 {
+    let counter = (last & !(u32::MAX as u64)) | now as u64;
+    if counter < last {
+        counter + (1 << 32)
+    } else {
+        counter
+    }
+}
+
 #[derive(Debug)]
 #[allow(dead_code)]
 struct LocalApicStatus {
@@ -119,3 +208,17 @@ impl IoApic {
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test_case]
+    fn counter_is_extended_across_wraparound() {
+        assert_eq!(extend_counter(0, 0), 0);
+        assert_eq!(extend_counter(0, 100), 100);
+        assert_eq!(extend_counter(u32::MAX as u64 - 1, 3), (1 << 32) + 3);
+        assert_eq!(extend_counter((5 << 32) + 10, 20), (5 << 32) + 20);
+        assert_eq!(extend_counter((5 << 32) + 10, 5), (6 << 32) + 5);
+    }
+}
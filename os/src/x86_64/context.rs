@@ -7,6 +7,7 @@ use crate::mutex::Mutex;
 use crate::process::ProcessContext;
 use crate::process::Scheduler;
 use crate::process::CURRENT_PROCESS;
+use crate::x86_64::is_fpu_enabled;
 use alloc::boxed::Box;
 use core::arch::asm;
 use core::arch::global_asm;
@@ -221,6 +222,10 @@ pub extern "sysv64" fn exec_app_context_proc_func(proc_ctx_ptr: u64) {
 }
 
 pub async fn exec_app_context(proc_context: Box<ProcessContext>) -> Result<i64> {
+    assert!(
+        is_fpu_enabled(),
+        "FPU / SSE should be enabled before running apps"
+    );
     let mut proc_context = Some(proc_context);
     let mut retcode: i64;
     loop {
@@ -0,0 +1,113 @@
+extern crate alloc;
+
+use crate::x86_64::read_cpuid;
+use crate::x86_64::CpuidRequest;
+use alloc::string::String;
+use alloc::vec::Vec;
+
+/// CPU features reported in CPUID leaf 01H
+/// c.f. SDM Vol.2A CPUID Table 3-10, 3-11
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Feature {
+    Tsc,
+    Apic,
+    Fxsr,
+    Sse,
+    Sse2,
+    X2Apic,
+}
+impl Feature {
+    pub const ALL: [Feature; 6] = [
+        Feature::Tsc,
+        Feature::Apic,
+        Feature::Fxsr,
+        Feature::Sse,
+        Feature::Sse2,
+        Feature::X2Apic,
+    ];
+    pub fn name(&self) -> &'static str {
+        match self {
+            Feature::Tsc => "TSC",
+            Feature::Apic => "APIC",
+            Feature::Fxsr => "FXSR",
+            Feature::Sse => "SSE",
+            Feature::Sse2 => "SSE2",
+            Feature::X2Apic => "x2APIC",
+        }
+    }
+}
+
+/// Feature flags of CPUID leaf 01H
+#[derive(Debug, Clone, Copy)]
+pub struct Leaf01Features {
+    ecx: u32,
+    edx: u32,
+}
+impl Leaf01Features {
+    pub fn new(ecx: u32, edx: u32) -> Self {
+        Self { ecx, edx }
+    }
+    pub fn read() -> Self {
+        let r = read_cpuid(CpuidRequest { eax: 1, ecx: 0 });
+        Self::new(r.ecx(), r.edx())
+    }
+    pub fn has(&self, feature: Feature) -> bool {
+        let (reg, bit) = match feature {
+            Feature::Tsc => (self.edx, 4),
+            Feature::Apic => (self.edx, 9),
+            Feature::Fxsr => (self.edx, 24),
+            Feature::Sse => (self.edx, 25),
+            Feature::Sse2 => (self.edx, 26),
+            Feature::X2Apic => (self.ecx, 21),
+        };
+        (reg >> bit) & 1 != 0
+    }
+    /// Returns a one-line summary like "+TSC +APIC -x2APIC"
+    pub fn summary(&self) -> String {
+        Feature::ALL
+            .iter()
+            .map(|f| {
+                let sign = if self.has(*f) { "+" } else { "-" };
+                String::from(sign) + f.name()
+            })
+            .collect::<Vec<String>>()
+            .join(" ")
+    }
+}
+
+pub fn has_feature(feature: Feature) -> bool {
+    Leaf01Features::read().has(feature)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test_case]
+    fn leaf01_features_are_extracted_from_edx() {
+        let f = Leaf01Features::new(0, (1 << 4) | (1 << 24) | (1 << 26));
+        assert!(f.has(Feature::Tsc));
+        assert!(!f.has(Feature::Apic));
+        assert!(f.has(Feature::Fxsr));
+        assert!(!f.has(Feature::Sse));
+        assert!(f.has(Feature::Sse2));
+        assert!(!f.has(Feature::X2Apic));
+    }
+    #[test_case]
+    fn leaf01_features_are_extracted_from_ecx() {
+        let f = Leaf01Features::new(1 << 21, 0);
+        assert!(f.has(Feature::X2Apic));
+        for feature in Feature::ALL {
+            if feature != Feature::X2Apic {
+                assert!(!f.has(feature));
+            }
+        }
+        // Bits of EDX should not be confused with ECX
+        assert!(!Leaf01Features::new(1 << 9, 1 << 21).has(Feature::Apic));
+    }
+    #[test_case]
+    fn leaf01_features_summary() {
+        let f = Leaf01Features::new(0, (1 << 9) | (1 << 25));
+        assert_eq!(f.summary(), "-TSC +APIC -FXSR +SSE -SSE2 -x2APIC");
+    }
+}
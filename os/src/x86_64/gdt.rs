@@ -1,12 +1,19 @@
 extern crate alloc;
 
+use crate::error::Error;
 use crate::error::Result;
 use crate::info;
 use crate::x86_64::idt::TaskStateSegment64;
+use crate::x86_64::KERNEL_CS;
+use crate::x86_64::KERNEL_DS;
 use crate::x86_64::TSS64_SEL;
+use crate::x86_64::USER32_CS;
+use crate::x86_64::USER64_CS;
+use crate::x86_64::USER_DS;
 use alloc::boxed::Box;
 use core::arch::asm;
 use core::fmt;
+use core::mem::offset_of;
 use core::mem::size_of;
 use core::pin::Pin;
 
@@ -15,7 +22,7 @@ pub const BIT_TYPE_CODE: u64 = 0b11u64 << 43;
 
 pub const BIT_PRESENT: u64 = 1u64 << 47;
 pub const BIT_CS_LONG_MODE: u64 = 1u64 << 53;
-pub const BIT_CS_READABLE: u64 = 1u64 << 53;
+pub const BIT_CS_READABLE: u64 = 1u64 << 41;
 pub const BIT_DS_WRITABLE: u64 = 1u64 << 41;
 pub const BIT_DPL0: u64 = 0u64 << 45;
 pub const BIT_DPL3: u64 = 3u64 << 45;
@@ -47,19 +54,97 @@ pub struct Gdt {
     task_state_segment: TaskStateSegment64Descriptor,
 }
 const _: () = assert!(size_of::<Gdt>() == 64);
+// Selectors defined in x86_64.rs should match with the layout of Gdt
+const _: () = assert!(Selectors::LAYOUT.kernel_cs == KERNEL_CS);
+const _: () = assert!(Selectors::LAYOUT.kernel_ds == KERNEL_DS);
+const _: () = assert!(offset_of!(Gdt, user_code_segment_32) == (USER32_CS & !0b11) as usize);
+const _: () = assert!(Selectors::LAYOUT.user_ds == USER_DS);
+const _: () = assert!(Selectors::LAYOUT.user_cs == USER64_CS);
+const _: () = assert!(Selectors::LAYOUT.tss == TSS64_SEL);
 
-impl Gdt {
-    pub fn new(tss64: &Pin<Box<TaskStateSegment64>>) -> Result<Pin<Box<Gdt>>> {
-        let gdt = Self {
+/// Segment selectors of the entries in a Gdt loaded by GdtBuilder
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Selectors {
+    pub kernel_cs: u16,
+    pub kernel_ds: u16,
+    pub user_cs: u16,
+    pub user_ds: u16,
+    pub tss: u16,
+}
+impl Selectors {
+    const RPL3: u16 = 0b11;
+    const LAYOUT: Self = Self {
+        kernel_cs: offset_of!(Gdt, kernel_code_segment) as u16,
+        kernel_ds: offset_of!(Gdt, kernel_data_segment) as u16,
+        user_cs: offset_of!(Gdt, user_code_segment_64) as u16 | Self::RPL3,
+        user_ds: offset_of!(Gdt, user_data_segment) as u16 | Self::RPL3,
+        tss: offset_of!(Gdt, task_state_segment) as u16,
+    };
+}
+
+/// Builds a Gdt with explicit kernel / user segments, e.g.
+/// `GdtBuilder::new().kernel_code().kernel_data().user_code().user_data().tss(&tss64).load()`.
+///
+/// The 32-bit user code segment is always left null: it is only referenced by sysret through
+/// IA32_STAR, which requires the entries to be placed in the order of Gdt.
+#[derive(Default)]
+pub struct GdtBuilder {
+    kernel_code_segment: Option<GdtSegmentDescriptor>,
+    kernel_data_segment: Option<GdtSegmentDescriptor>,
+    user_data_segment: Option<GdtSegmentDescriptor>,
+    user_code_segment_64: Option<GdtSegmentDescriptor>,
+    task_state_segment: Option<TaskStateSegment64Descriptor>,
+}
+impl GdtBuilder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+    pub fn kernel_code(mut self) -> Self {
+        self.kernel_code_segment = Some(GdtSegmentDescriptor::new(GdtAttr::KernelCode));
+        self
+    }
+    pub fn kernel_data(mut self) -> Self {
+        self.kernel_data_segment = Some(GdtSegmentDescriptor::new(GdtAttr::KernelData));
+        self
+    }
+    pub fn user_code(mut self) -> Self {
+        self.user_code_segment_64 = Some(GdtSegmentDescriptor::new(GdtAttr::User64Code));
+        self
+    }
+    pub fn user_data(mut self) -> Self {
+        self.user_data_segment = Some(GdtSegmentDescriptor::new(GdtAttr::UserData));
+        self
+    }
+    pub fn tss(mut self, tss64: &Pin<Box<TaskStateSegment64>>) -> Self {
+        self.task_state_segment = Some(TaskStateSegment64Descriptor::new(tss64.phys_addr()));
+        self
+    }
+    fn build(self) -> Result<Gdt> {
+        Ok(Gdt {
             null_segment: GdtSegmentDescriptor::null(),
-            kernel_code_segment: GdtSegmentDescriptor::new(GdtAttr::KernelCode),
-            kernel_data_segment: GdtSegmentDescriptor::new(GdtAttr::KernelData),
+            kernel_code_segment: self
+                .kernel_code_segment
+                .ok_or(Error::Failed("GdtBuilder: kernel code segment is missing"))?,
+            kernel_data_segment: self
+                .kernel_data_segment
+                .ok_or(Error::Failed("GdtBuilder: kernel data segment is missing"))?,
             user_code_segment_32: GdtSegmentDescriptor::null(),
-            user_data_segment: GdtSegmentDescriptor::new(GdtAttr::UserData),
-            user_code_segment_64: GdtSegmentDescriptor::new(GdtAttr::User64Code),
-            task_state_segment: TaskStateSegment64Descriptor::new(tss64.phys_addr()),
-        };
-        let gdt = Box::pin(gdt);
+            user_data_segment: self
+                .user_data_segment
+                .ok_or(Error::Failed("GdtBuilder: user data segment is missing"))?,
+            user_code_segment_64: self
+                .user_code_segment_64
+                .ok_or(Error::Failed("GdtBuilder: user code segment is missing"))?,
+            task_state_segment: self
+                .task_state_segment
+                .ok_or(Error::Failed("GdtBuilder: TSS is missing"))?,
+        })
+    }
+    /// Builds the Gdt and loads it with lgdt / ltr. The returned Gdt should be kept alive while
+    /// it is in use.
+    pub fn load(self) -> Result<(Pin<Box<Gdt>>, Selectors)> {
+        let gdt = Box::pin(self.build()?);
+        let selectors = Selectors::LAYOUT;
         let params = GdtrParameters {
             limit: (size_of::<Gdt>() - 1) as u16,
             base: gdt.as_ref().get_ref() as *const Gdt,
@@ -70,15 +155,16 @@ impl Gdt {
             asm!("lgdt [rcx]",
                 in("rcx") &params);
         }
-        info!("Loading TSS ( selector = {:#X} )", TSS64_SEL);
+        info!("Loading TSS ( selector = {:#X} )", selectors.tss);
         unsafe {
             asm!("ltr cx",
-                in("cx") TSS64_SEL);
+                in("cx") selectors.tss);
         }
-        Ok(gdt)
+        Ok((gdt, selectors))
     }
 }
 
+#[derive(Clone, Copy)]
 pub struct GdtSegmentDescriptor {
     value: u64,
 }
@@ -90,12 +176,28 @@ impl GdtSegmentDescriptor {
         Self { value: attr as u64 }
     }
 }
+#[cfg(test)]
+impl GdtSegmentDescriptor {
+    fn is_present(&self) -> bool {
+        self.value & BIT_PRESENT != 0
+    }
+    fn is_code(&self) -> bool {
+        self.value & BIT_TYPE_CODE == BIT_TYPE_CODE
+    }
+    fn is_long_mode(&self) -> bool {
+        self.value & BIT_CS_LONG_MODE != 0
+    }
+    fn dpl(&self) -> u64 {
+        (self.value >> 45) & 0b11
+    }
+}
 impl fmt::Display for GdtSegmentDescriptor {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         write!(f, "{:#18X}", self.value)
     }
 }
 
+#[derive(Clone, Copy)]
 #[repr(packed)]
 #[allow(dead_code)]
 struct TaskStateSegment64Descriptor {
@@ -121,3 +223,86 @@ impl TaskStateSegment64Descriptor {
     }
 }
 const _: () = assert!(size_of::<TaskStateSegment64Descriptor>() == 16);
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test_case]
+    fn kernel_code_segment_encoding() {
+        let d = GdtSegmentDescriptor::new(GdtAttr::KernelCode);
+        assert!(d.is_present());
+        assert!(d.is_code());
+        assert!(d.is_long_mode());
+        assert_eq!(d.dpl(), 0);
+        assert!(d.value & BIT_CS_READABLE != 0);
+    }
+    #[test_case]
+    fn kernel_data_segment_encoding() {
+        let d = GdtSegmentDescriptor::new(GdtAttr::KernelData);
+        assert!(d.is_present());
+        assert!(!d.is_code());
+        assert!(!d.is_long_mode());
+        assert_eq!(d.dpl(), 0);
+        assert!(d.value & BIT_DS_WRITABLE != 0);
+    }
+    #[test_case]
+    fn user_code_segment_encoding() {
+        let d = GdtSegmentDescriptor::new(GdtAttr::User64Code);
+        assert!(d.is_present());
+        assert!(d.is_code());
+        assert!(d.is_long_mode());
+        assert_eq!(d.dpl(), 3);
+    }
+    #[test_case]
+    fn user_data_segment_encoding() {
+        let d = GdtSegmentDescriptor::new(GdtAttr::UserData);
+        assert!(d.is_present());
+        assert!(!d.is_code());
+        assert!(!d.is_long_mode());
+        assert_eq!(d.dpl(), 3);
+        assert!(d.value & BIT_DS_WRITABLE != 0);
+    }
+    #[test_case]
+    fn builder_requires_every_segment() {
+        assert!(GdtBuilder::new()
+            .kernel_code()
+            .kernel_data()
+            .user_code()
+            .user_data()
+            .build()
+            .is_err());
+        let tss64 = TaskStateSegment64::new().unwrap();
+        let gdt = GdtBuilder::new()
+            .kernel_code()
+            .kernel_data()
+            .user_code()
+            .user_data()
+            .tss(&tss64)
+            .build()
+            .unwrap();
+        let (kernel_code_segment, user_code_segment_32, user_code_segment_64) = (
+            gdt.kernel_code_segment,
+            gdt.user_code_segment_32,
+            gdt.user_code_segment_64,
+        );
+        assert!(kernel_code_segment.is_code());
+        assert_eq!(kernel_code_segment.dpl(), 0);
+        assert!(!user_code_segment_32.is_present());
+        assert!(user_code_segment_64.is_code());
+        assert_eq!(user_code_segment_64.dpl(), 3);
+    }
+    #[test_case]
+    fn selectors_have_rpl_of_the_segment_dpl() {
+        let selectors = Selectors::LAYOUT;
+        assert_eq!(selectors.kernel_cs & 0b11, 0);
+        assert_eq!(selectors.kernel_ds & 0b11, 0);
+        assert_eq!(selectors.user_cs & 0b11, 3);
+        assert_eq!(selectors.user_ds & 0b11, 3);
+        assert_eq!(selectors.tss & 0b11, 0);
+    }
+    #[test_case]
+    fn null_segment_is_not_present() {
+        assert!(!GdtSegmentDescriptor::null().is_present());
+    }
+}
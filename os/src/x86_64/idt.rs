@@ -5,6 +5,7 @@ use crate::error;
 use crate::error::Result;
 use crate::info;
 use crate::memory::alloc_pages;
+use crate::syscall::exit_to_os;
 use crate::util::PAGE_SIZE;
 use crate::x86_64::read_cr2;
 use alloc::boxed::Box;
@@ -239,19 +240,49 @@ inthandler_common:
 "#
 );
 
+// Page fault error code bits (SDM Vol.3 4.7 Page-Fault Exceptions)
+const PF_ERROR_USER: u64 = 0b0000_0100;
+
+/// Returns true if the exception happened while the CPU was executing an app (ring 3). This
+/// should only be used for exceptions that report the U/S bit in the error code (e.g. #PF).
+pub fn is_user_fault(cs: u64, error_code: u64) -> bool {
+    cs & 0b11 == 0b11 && error_code & PF_ERROR_USER != 0
+}
+
+#[derive(Debug, PartialEq, Eq)]
+enum InterruptKind {
+    // Interrupts that are handled and resumed
+    Timer,
+    Breakpoint,
+    // Exceptions that can't be recovered (except for the ones caused by apps)
+    Fatal,
+}
+fn interrupt_kind(index: usize) -> InterruptKind {
+    match index {
+        3 => InterruptKind::Breakpoint,
+        32 => InterruptKind::Timer,
+        _ => InterruptKind::Fatal,
+    }
+}
+
 #[no_mangle]
 extern "sysv64" fn inthandler(info: &InterruptInfo, index: usize) {
-    if index == 32 {
-        let bsp_local_apic = BootInfo::take().bsp_local_apic();
-        bsp_local_apic.notify_end_of_interrupt();
-        return;
+    match interrupt_kind(index) {
+        InterruptKind::Timer => {
+            let bsp_local_apic = BootInfo::take().bsp_local_apic();
+            bsp_local_apic.notify_end_of_interrupt();
+            return;
+        }
+        InterruptKind::Breakpoint => {
+            // RIP points to the next instruction of int3, so just return to resume.
+            info!("Breakpoint: {:?}", info);
+            return;
+        }
+        InterruptKind::Fatal => {}
     }
     error!("Interrupt Info: {:?}", info);
     error!("Exception {index:#04X}: ");
     match index {
-        3 => {
-            error!("Breakpoint");
-        }
         6 => {
             error!("Invalid Opcode");
         }
@@ -271,7 +302,7 @@ extern "sysv64" fn inthandler(info: &InterruptInfo, index: usize) {
             error!("CR2={:#018X}", read_cr2());
             error!(
                 "Caused by: A {} mode {} on a {} page, page structures are {}",
-                if info.error_code & 0b0000_0100 != 0 {
+                if info.error_code & PF_ERROR_USER != 0 {
                     "user"
                 } else {
                     "supervisor"
@@ -294,6 +325,11 @@ extern "sysv64" fn inthandler(info: &InterruptInfo, index: usize) {
                     "valid"
                 },
             );
+            if is_user_fault(info.ctx.cs, info.error_code) {
+                // Only the app is broken. Terminate it and return to the OS.
+                error!("Terminating the app due to the page fault");
+                exit_to_os(-1i64 as u64);
+            }
         }
         _ => {
             error!("Not handled");
@@ -302,6 +338,37 @@ extern "sysv64" fn inthandler(info: &InterruptInfo, index: usize) {
     panic!("fatal exception");
 }
 
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::x86_64::KERNEL_CS;
+    use crate::x86_64::USER64_CS;
+
+    #[test_case]
+    fn breakpoint_is_resumed() {
+        assert_eq!(interrupt_kind(3), InterruptKind::Breakpoint);
+        assert_eq!(interrupt_kind(32), InterruptKind::Timer);
+    }
+    #[test_case]
+    fn other_exceptions_are_fatal() {
+        for index in [6, 8, 13, 14] {
+            assert_eq!(interrupt_kind(index), InterruptKind::Fatal);
+        }
+    }
+    #[test_case]
+    fn is_user_fault_detects_ring3_faults() {
+        assert!(is_user_fault(USER64_CS as u64, 0b0100));
+        assert!(is_user_fault(USER64_CS as u64, 0b0111));
+    }
+    #[test_case]
+    fn is_user_fault_rejects_ring0_faults() {
+        assert!(!is_user_fault(KERNEL_CS as u64, 0b0000));
+        assert!(!is_user_fault(KERNEL_CS as u64, 0b0011));
+        // The U/S bit alone is not enough to blame the app
+        assert!(!is_user_fault(KERNEL_CS as u64, 0b0100));
+    }
+}
+
 #[no_mangle]
 extern "sysv64" fn int_handler_unimplemented() {
     panic!("unexpected interrupt!");
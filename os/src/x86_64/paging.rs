@@ -324,6 +324,22 @@ impl PML4 {
 
         Ok(TranslationResult::PageMapped4K { phys: page })
     }
+    /// Returns true if `virt` is accessible from the user mode, i.e. the entries of all levels
+    /// have the user bit since the access rights are ANDed over them.
+    pub fn is_user_accessible(&self, virt: u64) -> Result<bool> {
+        let entry = &self.entry[self.calc_index(virt)];
+        let mut user = entry.is_user();
+        let table = entry.table()?;
+        let entry = &table.entry[table.calc_index(virt)];
+        user &= entry.is_user();
+        let table = entry.table()?;
+        let entry = &table.entry[table.calc_index(virt)];
+        user &= entry.is_user();
+        let table = entry.table()?;
+        let entry = &table.entry[table.calc_index(virt)];
+        entry.page()?;
+        Ok(user && entry.is_user())
+    }
 }
 
 #[test_case]
@@ -344,3 +360,39 @@ fn page_translation() {
     assert_eq!(table.translate(0x0000), Ok(PageMapped4K { phys: 0x1000 }));
     assert_eq!(table.translate(0x1000), Err(Error::PageNotFound));
 }
+#[test_case]
+fn user_bit_is_set_and_revoked_over_range() {
+    let mut table = PML4::new();
+    let start = 0x4000_0000;
+    let end = start + 4 * PAGE_SIZE as u64;
+    table
+        .create_mapping(start, end, 0x10_0000, PageAttr::ReadWriteUser)
+        .expect("Failed to create mapping");
+    for addr in (start..end).step_by(PAGE_SIZE) {
+        assert_eq!(table.is_user_accessible(addr), Ok(true));
+    }
+    assert_eq!(table.is_user_accessible(end), Err(Error::PageNotFound));
+    // Revoke the access to the middle pages, as done on app exit
+    table
+        .create_mapping(
+            start + PAGE_SIZE as u64,
+            end - PAGE_SIZE as u64,
+            0x10_1000,
+            PageAttr::ReadWriteKernel,
+        )
+        .expect("Failed to create mapping");
+    assert_eq!(table.is_user_accessible(start), Ok(true));
+    assert_eq!(
+        table.is_user_accessible(start + PAGE_SIZE as u64),
+        Ok(false)
+    );
+    assert_eq!(
+        table.is_user_accessible(end - 2 * PAGE_SIZE as u64),
+        Ok(false)
+    );
+    assert_eq!(table.is_user_accessible(end - PAGE_SIZE as u64), Ok(true));
+    assert_eq!(
+        table.translate(start + PAGE_SIZE as u64),
+        Ok(TranslationResult::PageMapped4K { phys: 0x10_1000 })
+    );
+}
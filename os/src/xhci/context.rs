@@ -13,6 +13,33 @@ use core::mem::size_of;
 use core::mem::MaybeUninit;
 use core::pin::Pin;
 
+/// Converts bInterval of an interrupt endpoint into the Interval field of its Endpoint Context,
+/// which is the exponent of the service period in 125us units (period = 2^Interval * 125us).
+/// See [xhci] Table 6-12.
+pub fn interrupt_ep_interval(mode: UsbMode, b_interval: u8) -> Result<u8> {
+    match mode {
+        UsbMode::LowSpeed | UsbMode::FullSpeed => {
+            // bInterval is the period in frames (1ms = 8 * 125us) in 1..=255. Round it down to a
+            // power of two: 2^Interval * 125us <= bInterval * 1ms, which gives 3..=10.
+            if b_interval == 0 {
+                return Err(Error::Failed("bInterval should not be 0 for FS/LS"));
+            }
+            Ok(b_interval.ilog2() as u8 + 3)
+        }
+        UsbMode::HighSpeed | UsbMode::SuperSpeed => {
+            // bInterval is already an exponent: period = 2^(bInterval - 1) microframes.
+            if !(1..=16).contains(&b_interval) {
+                return Err(Error::Failed("bInterval should be in 1..=16 for HS/SS"));
+            }
+            Ok(b_interval - 1)
+        }
+        mode => Err(Error::FailedString(format!(
+            "Failed to calc interval for {:?}",
+            mode
+        ))),
+    }
+}
+
 #[repr(C, align(32))]
 #[derive(Default, Debug)]
 pub struct EndpointContext {
@@ -45,20 +72,7 @@ impl EndpointContext {
         interval_from_ep_desc: u8,
         average_trb_length: u16,
     ) -> Result<Self> {
-        // See [xhci] Table 6-12
-        let interval = match mode {
-            UsbMode::LowSpeed | UsbMode::FullSpeed => {
-                // interval_ms == 2^interval / 8 == interval_from_ep_desc
-                interval_from_ep_desc.ilog2() as u8 + 3
-            }
-            UsbMode::HighSpeed | UsbMode::SuperSpeed => interval_from_ep_desc - 1,
-            mode => {
-                return Err(Error::FailedString(format!(
-                    "Failed to calc interval for {:?}",
-                    mode
-                )))
-            }
-        };
+        let interval = interrupt_ep_interval(mode, interval_from_ep_desc)?;
         let mut ep = unsafe { Self::new() };
         ep.set_ep_type(EndpointType::InterruptIn)?;
         ep.set_dequeue_cycle_state(true)?;
@@ -317,26 +331,44 @@ impl RawDeviceContextBaseAddressArray {
 
 pub struct DeviceContextBaseAddressArray {
     inner: Pin<Box<RawDeviceContextBaseAddressArray>>,
-    context: [Option<Pin<Box<OutputContext>>>; 255],
-    _scratchpad_buffers: Pin<Box<[*mut u8]>>,
+    context: [Option<Pin<Box<OutputContext>>>; 256],
+    num_slots: usize,
+    _scratchpad_buffers: Option<Pin<Box<[*mut u8]>>>,
 }
 impl DeviceContextBaseAddressArray {
-    pub fn new(scratchpad_buffers: Pin<Box<[*mut u8]>>) -> Self {
+    /// # Arguments
+    /// * `scratchpad_buffers` - Scratchpad Buffer Array to be installed to the entry 0.
+    /// * `num_slots` - number of device slots enabled (= valid slot ids are 1..=num_slots)
+    pub fn new(scratchpad_buffers: Option<Pin<Box<[*mut u8]>>>, num_slots: usize) -> Self {
         let mut inner = RawDeviceContextBaseAddressArray::new();
-        inner.context[0] = scratchpad_buffers.as_ptr() as u64;
+        if let Some(scratchpad_buffers) = &scratchpad_buffers {
+            inner.context[0] = scratchpad_buffers.as_ptr() as u64;
+        }
         Self {
             inner: Box::pin(inner),
             context: unsafe { MaybeUninit::zeroed().assume_init() },
+            num_slots,
             _scratchpad_buffers: scratchpad_buffers,
         }
     }
+    pub fn num_slots(&self) -> usize {
+        self.num_slots
+    }
     /// # Safety
     /// This should only be called from set_dcbaa_ptr during the initialization
     pub unsafe fn inner_mut_ptr(&mut self) -> *mut RawDeviceContextBaseAddressArray {
         self.inner.as_mut().get_unchecked_mut() as *mut RawDeviceContextBaseAddressArray
     }
-    pub fn set_output_context(&mut self, slot: u8, output_context: Pin<Box<OutputContext>>) {
+    pub fn set_output_context(
+        &mut self,
+        slot: u8,
+        output_context: Pin<Box<OutputContext>>,
+    ) -> Result<()> {
         let slot = slot as usize;
+        // Slot 0 is reserved for the scratchpad buffer array
+        if slot == 0 || slot > self.num_slots {
+            return Err(Error::Failed("slot out of range"));
+        }
         // Own the output context here
         self.context[slot] = Some(output_context);
         // ...and set it in the actual pointer array
@@ -348,5 +380,87 @@ impl DeviceContextBaseAddressArray {
                     .as_ref()
                     .get_ref() as *const OutputContext as u64;
         }
+        Ok(())
+    }
+    /// Removes the Output Device Context of `slot`. This should be called only after the slot
+    /// is disabled, since the xHC owns the context until then.
+    pub fn clear_output_context(&mut self, slot: u8) -> Result<()> {
+        let slot = slot as usize;
+        if slot == 0 || slot > self.num_slots {
+            return Err(Error::Failed("slot out of range"));
+        }
+        unsafe {
+            self.inner.as_mut().get_unchecked_mut().context[slot] = 0;
+        }
+        self.context[slot] = None;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    #[test_case]
+    fn dcbaa_rejects_out_of_range_slots() {
+        const NUM_SLOTS: usize = 4;
+        let mut dcbaa = DeviceContextBaseAddressArray::new(None, NUM_SLOTS);
+        assert_eq!(dcbaa.num_slots(), NUM_SLOTS);
+        assert!(dcbaa
+            .set_output_context(NUM_SLOTS as u8, Box::pin(OutputContext::default()))
+            .is_ok());
+        assert_eq!(
+            dcbaa.set_output_context(NUM_SLOTS as u8 + 1, Box::pin(OutputContext::default())),
+            Err(Error::Failed("slot out of range"))
+        );
+        assert_eq!(
+            dcbaa.set_output_context(0, Box::pin(OutputContext::default())),
+            Err(Error::Failed("slot out of range"))
+        );
+        assert!(dcbaa.inner.context[NUM_SLOTS] != 0);
+        assert_eq!(dcbaa.inner.context[NUM_SLOTS + 1], 0);
+    }
+    #[test_case]
+    fn dcbaa_clears_output_context() {
+        let mut dcbaa = DeviceContextBaseAddressArray::new(None, 2);
+        assert!(dcbaa
+            .set_output_context(2, Box::pin(OutputContext::default()))
+            .is_ok());
+        assert_eq!(dcbaa.clear_output_context(2), Ok(()));
+        assert_eq!(dcbaa.inner.context[2], 0);
+        assert!(dcbaa.context[2].is_none());
+        assert_eq!(
+            dcbaa.clear_output_context(3),
+            Err(Error::Failed("slot out of range"))
+        );
+    }
+    #[test_case]
+    fn dcbaa_installs_scratchpad_buffer_array() {
+        let dcbaa = DeviceContextBaseAddressArray::new(None, 1);
+        assert_eq!(dcbaa.inner.context[0], 0);
+        let bufs = [0x1000usize, 0x2000, 0x3000].map(|e| e as *mut u8);
+        let array = Box::into_pin(Box::<[*mut u8]>::from(bufs.as_slice()));
+        let array_ptr = array.as_ptr() as u64;
+        let dcbaa = DeviceContextBaseAddressArray::new(Some(array), 1);
+        assert_eq!(dcbaa.inner.context[0], array_ptr);
+    }
+    #[test_case]
+    fn interrupt_ep_interval_across_speeds() {
+        let period_us = |interval: u8| 125u32 << interval;
+        // FS/LS: bInterval in ms, rounded down to a power of two
+        assert_eq!(interrupt_ep_interval(UsbMode::FullSpeed, 1), Ok(3));
+        assert_eq!(period_us(3), 1000);
+        assert_eq!(interrupt_ep_interval(UsbMode::LowSpeed, 8), Ok(6));
+        assert_eq!(interrupt_ep_interval(UsbMode::FullSpeed, 10), Ok(6));
+        assert_eq!(period_us(6), 8000);
+        assert_eq!(interrupt_ep_interval(UsbMode::FullSpeed, 255), Ok(10));
+        assert!(interrupt_ep_interval(UsbMode::FullSpeed, 0).is_err());
+        // HS/SS: bInterval is an exponent in microframes
+        assert_eq!(interrupt_ep_interval(UsbMode::HighSpeed, 1), Ok(0));
+        assert_eq!(period_us(0), 125);
+        assert_eq!(interrupt_ep_interval(UsbMode::HighSpeed, 4), Ok(3));
+        assert_eq!(interrupt_ep_interval(UsbMode::SuperSpeed, 16), Ok(15));
+        assert!(interrupt_ep_interval(UsbMode::HighSpeed, 0).is_err());
+        assert!(interrupt_ep_interval(UsbMode::SuperSpeed, 17).is_err());
+        assert!(interrupt_ep_interval(UsbMode::Unknown(7), 1).is_err());
     }
 }
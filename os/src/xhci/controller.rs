@@ -1,10 +1,10 @@
 extern crate alloc;
 
 use crate::error;
-use crate::error::Error;
 use crate::error::Result;
 use crate::memory::Mmio;
 use crate::mutex::Mutex;
+use crate::usb::descriptor::decode_string_descriptor;
 use crate::usb::descriptor::ConfigDescriptor;
 use crate::usb::descriptor::DescriptorIterator;
 use crate::usb::descriptor::DescriptorType;
@@ -17,9 +17,10 @@ use crate::xhci::context::EndpointContext;
 use crate::xhci::context::InputContext;
 use crate::xhci::context::InputControlContext;
 use crate::xhci::context::OutputContext;
+use crate::xhci::device::NUM_DEVICE_CONTEXTS;
 use crate::xhci::future::EventFuture;
 use crate::xhci::registers::CapabilityRegisters;
-use crate::xhci::registers::Doorbell;
+use crate::xhci::registers::DoorbellRegisters;
 use crate::xhci::registers::OperationalRegisters;
 use crate::xhci::registers::PortSc;
 use crate::xhci::registers::PortScIterator;
@@ -35,10 +36,8 @@ use crate::xhci::trb::StatusStageTrb;
 use alloc::boxed::Box;
 use alloc::collections::LinkedList;
 use alloc::fmt::Debug;
-use alloc::rc::Rc;
 use alloc::rc::Weak;
 use alloc::string::String;
-use alloc::string::ToString;
 use alloc::vec;
 use alloc::vec::Vec;
 use core::cmp::max;
@@ -47,6 +46,9 @@ use core::future::Future;
 use core::mem::size_of;
 use core::pin::Pin;
 
+/// How long send_command() waits for a free slot while the Command Ring is full
+const COMMAND_RING_RETRY_TIMEOUT_MS: u64 = 1000;
+
 #[derive(Debug, Copy, Clone)]
 #[repr(u8)]
 #[derive(PartialEq, Eq)]
@@ -82,7 +84,7 @@ pub struct Controller {
     op_regs: Mmio<OperationalRegisters>,
     rt_regs: Mmio<RuntimeRegisters>,
     portsc: PortSc,
-    doorbell_regs: Vec<Rc<Doorbell>>,
+    doorbell_regs: DoorbellRegisters,
     command_ring: Mutex<CommandRing>,
     primary_event_ring: Mutex<EventRing>,
     device_context_base_array: Mutex<DeviceContextBaseAddressArray>,
@@ -94,7 +96,7 @@ impl Controller {
         op_regs: Mmio<OperationalRegisters>,
         rt_regs: Mmio<RuntimeRegisters>,
         portsc: PortSc,
-        doorbell_regs: Vec<Rc<Doorbell>>,
+        doorbell_regs: DoorbellRegisters,
         device_context_base_array: Mutex<DeviceContextBaseAddressArray>,
     ) -> Result<Self> {
         let mut xhc = Self {
@@ -111,7 +113,7 @@ impl Controller {
         xhc.init_primary_event_ring()?;
         xhc.init_slots_and_contexts()?;
         xhc.init_command_ring();
-        unsafe { xhc.op_regs.get_unchecked_mut() }.start_xhc();
+        unsafe { xhc.op_regs.get_unchecked_mut() }.start_xhc()?;
         Ok(xhc)
     }
     pub fn device_futures(&self) -> &Mutex<LinkedList<DeviceFuture>> {
@@ -136,30 +138,60 @@ impl Controller {
         unsafe { self.op_regs.get_unchecked_mut() }
             .set_dcbaa_ptr(&mut self.device_context_base_array.lock())
     }
-    pub fn set_output_context_for_slot(&self, slot: u8, output_context: Pin<Box<OutputContext>>) {
+    pub fn set_output_context_for_slot(
+        &self,
+        slot: u8,
+        output_context: Pin<Box<OutputContext>>,
+    ) -> Result<()> {
         self.device_context_base_array
             .lock()
-            .set_output_context(slot, output_context);
+            .set_output_context(slot, output_context)
+    }
+    /// Issues a Disable Slot Command for `slot` and releases its Output Device Context.
+    pub async fn disable_slot(&self, slot: u8) -> Result<()> {
+        self.send_command(GenericTrbEntry::cmd_disable_slot(slot))
+            .await?
+            .completed()?;
+        self.device_context_base_array
+            .lock()
+            .clear_output_context(slot)
     }
     fn init_command_ring(&mut self) {
         unsafe { self.op_regs.get_unchecked_mut() }.set_cmd_ring_ctrl(&self.command_ring.lock());
     }
-    fn notify_xhc(&self) {
-        self.doorbell_regs[0].notify(0, 0);
+    fn notify_xhc(&self) -> Result<()> {
+        self.doorbell_regs.ring(0, 0)
     }
     pub fn notify_ep(&self, slot: u8, dci: usize) -> Result<()> {
-        let db = self
-            .doorbell_regs
-            .get(slot as usize)
-            .ok_or(Error::Failed("invalid slot"))?;
         let dci = u8::try_from(dci)?;
-        db.notify(dci, 0);
-        Ok(())
+        self.doorbell_regs.ring(slot, dci)
     }
     pub async fn send_command(&self, cmd: GenericTrbEntry) -> Result<GenericTrbEntry> {
-        let cmd_ptr = self.command_ring.lock().push(cmd)?;
-        self.notify_xhc();
-        EventFuture::new_on_trb(&self.primary_event_ring, cmd_ptr).await
+        let cmd_ptr = CommandRing::push_with_retry(
+            &self.command_ring,
+            cmd,
+            &self.primary_event_ring,
+            COMMAND_RING_RETRY_TIMEOUT_MS,
+        )
+        .await?;
+        self.notify_xhc()?;
+        let event = EventFuture::new_on_trb(&self.primary_event_ring, cmd_ptr).await?;
+        self.command_ring.lock().dequeue_trb(cmd_ptr)?;
+        Ok(event)
+    }
+    /// Rings the doorbell of the Default Control Endpoint and waits for the event on
+    /// `trb_ptr_waiting`. Control transfers on a slot are issued one at a time, so all the TRBs
+    /// pushed to `ctrl_ep_ring` so far are retired once the event arrives.
+    async fn wait_for_control_transfer(
+        &self,
+        slot: u8,
+        ctrl_ep_ring: &mut CommandRing,
+        trb_ptr_waiting: u64,
+    ) -> Result<()> {
+        self.notify_ep(slot, 1)?;
+        let event = EventFuture::new_on_trb(&self.primary_event_ring, trb_ptr_waiting).await?;
+        ctrl_ep_ring.dequeue_all();
+        event.completed()
     }
     pub async fn request_initial_device_descriptor(
         &self,
@@ -212,10 +244,8 @@ impl Controller {
             .into(),
         )?;
         let trb_ptr_waiting = ctrl_ep_ring.push(StatusStageTrb::new_in().into())?;
-        self.notify_ep(slot, 1)?;
-        EventFuture::new_on_trb(&self.primary_event_ring, trb_ptr_waiting)
-            .await?
-            .completed()
+        self.wait_for_control_transfer(slot, ctrl_ep_ring, trb_ptr_waiting)
+            .await
     }
     pub async fn request_set_interface(
         &self,
@@ -235,10 +265,8 @@ impl Controller {
             .into(),
         )?;
         let trb_ptr_waiting = ctrl_ep_ring.push(StatusStageTrb::new_in().into())?;
-        self.notify_ep(slot, 1)?;
-        EventFuture::new_on_trb(&self.primary_event_ring, trb_ptr_waiting)
-            .await?
-            .completed()
+        self.wait_for_control_transfer(slot, ctrl_ep_ring, trb_ptr_waiting)
+            .await
     }
     pub async fn request_set_protocol(
         &self,
@@ -261,10 +289,8 @@ impl Controller {
             .into(),
         )?;
         let trb_ptr_waiting = ctrl_ep_ring.push(StatusStageTrb::new_in().into())?;
-        self.notify_ep(slot, 1)?;
-        EventFuture::new_on_trb(&self.primary_event_ring, trb_ptr_waiting)
-            .await?
-            .completed()
+        self.wait_for_control_transfer(slot, ctrl_ep_ring, trb_ptr_waiting)
+            .await
     }
     pub async fn request_report_bytes(
         &self,
@@ -287,10 +313,33 @@ impl Controller {
         )?;
         let trb_ptr_waiting = ctrl_ep_ring.push(DataStageTrb::new_in(buf).into())?;
         ctrl_ep_ring.push(StatusStageTrb::new_out().into())?;
-        self.notify_ep(slot, 1)?;
-        EventFuture::new_on_trb(&self.primary_event_ring, trb_ptr_waiting)
-            .await?
-            .completed()
+        self.wait_for_control_transfer(slot, ctrl_ep_ring, trb_ptr_waiting)
+            .await
+    }
+    pub async fn request_set_report(
+        &self,
+        slot: u8,
+        ctrl_ep_ring: &mut CommandRing,
+        interface_number: u8,
+        buf: Pin<&mut [u8]>,
+    ) -> Result<()> {
+        // [HID] 7.2.2 Set_Report Request
+        ctrl_ep_ring.push(
+            SetupStageTrb::new(
+                SetupStageTrb::REQ_TYPE_DIR_HOST_TO_DEVICE
+                    | SetupStageTrb::REQ_TYPE_TYPE_CLASS
+                    | SetupStageTrb::REQ_TYPE_TO_INTERFACE,
+                SetupStageTrb::REQ_SET_REPORT,
+                0x0200, /* Report Type (Output) | Report ID */
+                interface_number as u16,
+                buf.len() as u16,
+            )
+            .into(),
+        )?;
+        ctrl_ep_ring.push(DataStageTrb::new_out(buf).into())?;
+        let trb_ptr_waiting = ctrl_ep_ring.push(StatusStageTrb::new_in().into())?;
+        self.wait_for_control_transfer(slot, ctrl_ep_ring, trb_ptr_waiting)
+            .await
     }
     async fn request_descriptor<T: Sized>(
         &self,
@@ -313,10 +362,8 @@ impl Controller {
         )?;
         let trb_ptr_waiting = ctrl_ep_ring.push(DataStageTrb::new_in(buf).into())?;
         ctrl_ep_ring.push(StatusStageTrb::new_out().into())?;
-        self.notify_ep(slot, 1)?;
-        EventFuture::new_on_trb(&self.primary_event_ring, trb_ptr_waiting)
-            .await?
-            .completed()
+        self.wait_for_control_transfer(slot, ctrl_ep_ring, trb_ptr_waiting)
+            .await
     }
     pub async fn request_config_descriptor_and_rest(
         &self,
@@ -355,7 +402,7 @@ impl Controller {
         lang_id: u16,
         index: u8,
     ) -> Result<String> {
-        let buf = vec![0; 128];
+        let buf = vec![0u8; 128];
         let mut buf = Box::into_pin(buf.into_boxed_slice());
         self.request_descriptor(
             slot,
@@ -366,9 +413,7 @@ impl Controller {
             buf.as_mut(),
         )
         .await?;
-        Ok(String::from_utf8_lossy(&buf[2..])
-            .to_string()
-            .replace('\0', ""))
+        decode_string_descriptor(&buf)
     }
     pub async fn request_string_descriptor_zero(
         &self,
@@ -394,7 +439,7 @@ impl Controller {
         slot: u8,
         input_context: &mut Pin<&mut InputContext>,
         ep_desc_list: &Vec<EndpointDescriptor>,
-    ) -> Result<[Option<TransferRing>; 32]> {
+    ) -> Result<[Option<TransferRing>; NUM_DEVICE_CONTEXTS]> {
         // 4.6.6 Configure Endpoint
         // When configuring or deconfiguring a device, only after completing a successful
         // Configure Endpoint Command and a successful USB SET_CONFIGURATION
@@ -408,15 +453,16 @@ impl Controller {
         let mut input_ctrl_ctx = InputControlContext::default();
         input_ctrl_ctx.add_context(0)?;
         const EP_RING_NONE: Option<TransferRing> = None;
-        let mut ep_rings = [EP_RING_NONE; 32];
+        let mut ep_rings = [EP_RING_NONE; NUM_DEVICE_CONTEXTS];
         let mut last_dci = 1;
         for ep_desc in ep_desc_list {
+            let dci = ep_desc.dci()?;
             match EndpointType::from(ep_desc) {
                 EndpointType::InterruptIn => {
                     let tring = TransferRing::new(4096)?;
-                    input_ctrl_ctx.add_context(ep_desc.dci())?;
+                    input_ctrl_ctx.add_context(dci)?;
                     input_context.set_ep_ctx(
-                        ep_desc.dci(),
+                        dci,
                         EndpointContext::new_interrupt_in_endpoint(
                             portsc.max_packet_size()?,
                             tring.ring_phys_addr(),
@@ -425,14 +471,14 @@ impl Controller {
                             8,
                         )?,
                     )?;
-                    last_dci = max(last_dci, ep_desc.dci());
-                    ep_rings[ep_desc.dci()] = Some(tring);
+                    last_dci = max(last_dci, dci);
+                    ep_rings[dci] = Some(tring);
                 }
                 EndpointType::BulkIn => {
                     let tring = TransferRing::new(4096)?;
-                    input_ctrl_ctx.add_context(ep_desc.dci())?;
+                    input_ctrl_ctx.add_context(dci)?;
                     input_context.set_ep_ctx(
-                        ep_desc.dci(),
+                        dci,
                         EndpointContext::new_bulk_in_endpoint(
                             portsc.max_packet_size()?,
                             tring.ring_phys_addr(),
@@ -441,14 +487,14 @@ impl Controller {
                             8,
                         )?,
                     )?;
-                    last_dci = max(last_dci, ep_desc.dci());
-                    ep_rings[ep_desc.dci()] = Some(tring);
+                    last_dci = max(last_dci, dci);
+                    ep_rings[dci] = Some(tring);
                 }
                 EndpointType::BulkOut => {
                     let tring = TransferRing::new(4096)?;
-                    input_ctrl_ctx.add_context(ep_desc.dci())?;
+                    input_ctrl_ctx.add_context(dci)?;
                     input_context.set_ep_ctx(
-                        ep_desc.dci(),
+                        dci,
                         EndpointContext::new_bulk_out_endpoint(
                             portsc.max_packet_size()?,
                             tring.ring_phys_addr(),
@@ -457,8 +503,8 @@ impl Controller {
                             8,
                         )?,
                     )?;
-                    last_dci = max(last_dci, ep_desc.dci());
-                    ep_rings[ep_desc.dci()] = Some(tring);
+                    last_dci = max(last_dci, dci);
+                    ep_rings[dci] = Some(tring);
                 }
                 _ => {
                     error!("Ignoring unimplemented ep type {:?}", ep_desc);
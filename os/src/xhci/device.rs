@@ -1,20 +1,30 @@
 extern crate alloc;
 
+use crate::error::Error;
 use crate::error::Result;
+use crate::mutex::Mutex;
+#[cfg(test)]
+use crate::usb::descriptor::dci_from_endpoint;
 use crate::usb::descriptor::EndpointDescriptor;
 use crate::usb::descriptor::InterfaceDescriptor;
 use crate::usb::descriptor::UsbDescriptor;
+use crate::usb::descriptor::DCI_EP0;
 use crate::warn;
 use crate::xhci::context::InputContext;
 use crate::xhci::controller::Controller;
 use crate::xhci::future::EventFuture;
+use crate::xhci::registers::UsbMode;
 use crate::xhci::ring::CommandRing;
 use crate::xhci::ring::TransferRing;
 use crate::xhci::trb::GenericTrbEntry;
 use alloc::boxed::Box;
+use alloc::collections::BTreeMap;
 use alloc::collections::VecDeque;
+use alloc::format;
 use alloc::rc::Rc;
+use alloc::string::String;
 use alloc::vec::Vec;
+use core::fmt::Write;
 use core::pin::Pin;
 
 #[derive(Debug, Clone)]
@@ -53,22 +63,155 @@ pub enum UsbHidProtocol {
     ReportProtocol = 1,
 }
 
-pub struct UsbDeviceDriverContext {
-    port: usize,
+/// Summary of an enumerated USB device, listed by the `usb` command.
+#[derive(Debug, Clone)]
+pub struct UsbDeviceInfo {
+    pub slot: u8,
+    pub port: usize,
+    pub speed: UsbMode,
+    /// (class, subclass, protocol) of each interface
+    pub interfaces: Vec<(u8, u8, u8)>,
+    pub product: Option<String>,
+}
+impl UsbDeviceInfo {
+    pub fn new(ddc: &UsbDeviceDriverContext, speed: UsbMode, product: Option<String>) -> Self {
+        let interfaces = ddc
+            .descriptors()
+            .iter()
+            .filter_map(|d| {
+                if let UsbDescriptor::Interface(e) = d {
+                    Some(e.triple())
+                } else {
+                    None
+                }
+            })
+            .collect();
+        Self {
+            slot: ddc.slot(),
+            port: ddc.port(),
+            speed,
+            interfaces,
+            product,
+        }
+    }
+    pub fn summary(&self) -> String {
+        let mut s = format!("slot {:3} port {:2} {:?}", self.slot, self.port, self.speed);
+        for (class, subclass, protocol) in &self.interfaces {
+            let _ = write!(s, " {class:02X}/{subclass:02X}/{protocol:02X}");
+        }
+        if let Some(product) = &self.product {
+            let _ = write!(s, " {product:?}");
+        }
+        s
+    }
+}
+
+static USB_DEVICES: Mutex<BTreeMap<u8, UsbDeviceInfo>> = Mutex::new(BTreeMap::new());
+
+pub fn register_usb_device(info: UsbDeviceInfo) {
+    USB_DEVICES.lock().insert(info.slot, info);
+}
+/// Forgets the devices on `port`, which has been disconnected.
+pub fn unregister_usb_devices_on_port(port: usize) {
+    USB_DEVICES.lock().retain(|_, info| info.port != port);
+}
+pub fn usb_device_list() -> Vec<UsbDeviceInfo> {
+    USB_DEVICES.lock().values().cloned().collect()
+}
+
+/// Number of Device Context entries: the Slot Context (DCI 0) and 31 Endpoint Contexts.
+pub const NUM_DEVICE_CONTEXTS: usize = 32;
+
+fn ep_ring_at(ep_rings: &[Option<TransferRing>], dci: usize) -> Result<&Option<TransferRing>> {
+    ep_rings
+        .get(dci)
+        .ok_or("dci out of range for ep_rings".into())
+}
+
+/// A Device Slot enabled on the xHC, with the rings of its endpoints and the port it is on.
+/// The Output Device Context is owned by the Device Context Base Address Array of the xHC.
+pub struct DeviceSlot {
     slot: u8,
+    port: usize,
     xhci: Rc<Controller>,
-    descriptors: Vec<UsbDescriptor>,
-    _input_context: Pin<Box<InputContext>>,
+    input_context: Pin<Box<InputContext>>,
     ctrl_ep_ring: Pin<Box<CommandRing>>,
+    ep_rings: [Option<TransferRing>; NUM_DEVICE_CONTEXTS],
+}
+impl DeviceSlot {
+    pub fn new(
+        slot: u8,
+        port: usize,
+        xhci: Rc<Controller>,
+        input_context: Pin<Box<InputContext>>,
+        ctrl_ep_ring: Pin<Box<CommandRing>>,
+    ) -> Self {
+        const EP_RING_NONE: Option<TransferRing> = None;
+        Self {
+            slot,
+            port,
+            xhci,
+            input_context,
+            ctrl_ep_ring,
+            ep_rings: [EP_RING_NONE; NUM_DEVICE_CONTEXTS],
+        }
+    }
+    pub fn slot(&self) -> u8 {
+        self.slot
+    }
+    pub fn port(&self) -> usize {
+        self.port
+    }
+    pub fn xhci(&self) -> Rc<Controller> {
+        self.xhci.clone()
+    }
+    /// Sets up the Transfer Rings for the endpoints in `ep_desc_list` and issues a Configure
+    /// Endpoint Command for them.
+    pub async fn configure_endpoint(
+        &mut self,
+        ep_desc_list: &Vec<EndpointDescriptor>,
+    ) -> Result<()> {
+        self.ep_rings = self
+            .xhci
+            .setup_endpoints(
+                self.port,
+                self.slot,
+                &mut self.input_context.as_mut(),
+                ep_desc_list,
+            )
+            .await?;
+        Ok(())
+    }
+    pub fn ep_ring(&self, dci: usize) -> Result<&Option<TransferRing>> {
+        ep_ring_at(&self.ep_rings, dci)
+    }
+    pub fn ctrl_ep_ring(&mut self) -> &mut Pin<Box<CommandRing>> {
+        &mut self.ctrl_ep_ring
+    }
+    /// Rings the doorbell of the endpoint at `dci` of this slot.
+    pub fn notify_ep(&self, dci: usize) -> Result<()> {
+        self.xhci.notify_ep(self.slot, dci)
+    }
+    /// Disables the slot on the xHC, e.g. after the device is disconnected. The rings of the
+    /// slot are dropped once the xHC stops using them.
+    pub async fn disable(self) -> Result<()> {
+        self.xhci.disable_slot(self.slot).await
+    }
+}
+
+pub struct UsbDeviceDriverContext {
+    device_slot: DeviceSlot,
+    descriptors: Vec<UsbDescriptor>,
     ep_desc_list: Vec<EndpointDescriptor>,
-    ep_rings: [Option<TransferRing>; 32],
+    // LANGID used to read String Descriptors, fetched on the first read_string()
+    lang_id: Option<u16>,
 }
 impl UsbDeviceDriverContext {
     pub async fn new(
         port: usize,
         slot: u8,
         xhci: Rc<Controller>,
-        mut input_context: Pin<Box<InputContext>>,
+        input_context: Pin<Box<InputContext>>,
         ctrl_ep_ring: Pin<Box<CommandRing>>,
         descriptors: Vec<UsbDescriptor>,
     ) -> Result<Self> {
@@ -78,28 +221,30 @@ impl UsbDeviceDriverContext {
                 ep_desc_list.push(*e);
             }
         }
-        let ep_rings = xhci
-            .setup_endpoints(port, slot, &mut input_context.as_mut(), &ep_desc_list)
-            .await?;
+        let mut device_slot = DeviceSlot::new(slot, port, xhci, input_context, ctrl_ep_ring);
+        device_slot.configure_endpoint(&ep_desc_list).await?;
         Ok(Self {
-            port,
-            slot,
-            xhci,
+            device_slot,
             descriptors,
-            _input_context: input_context,
-            ctrl_ep_ring,
             ep_desc_list,
-            ep_rings,
+            lang_id: None,
         })
     }
+    pub fn device_slot(&self) -> &DeviceSlot {
+        &self.device_slot
+    }
+    /// Disables the Device Slot and drops everything of the device.
+    pub async fn disable_slot(self) -> Result<()> {
+        self.device_slot.disable().await
+    }
     pub fn port(&self) -> usize {
-        self.port
+        self.device_slot.port()
     }
     pub fn slot(&self) -> u8 {
-        self.slot
+        self.device_slot.slot()
     }
     pub fn xhci(&self) -> Rc<Controller> {
-        self.xhci.clone()
+        self.device_slot.xhci()
     }
     pub fn descriptors(&self) -> &Vec<UsbDescriptor> {
         &self.descriptors
@@ -108,20 +253,48 @@ impl UsbDeviceDriverContext {
         &self.ep_desc_list
     }
     pub fn ep_ring(&self, dci: usize) -> Result<&Option<TransferRing>> {
-        self.ep_rings
-            .get(dci)
-            .ok_or("dci out of range for ep_rings".into())
+        self.device_slot.ep_ring(dci)
+    }
+    /// Returns the first language the device supports. String Descriptor 0 is requested only
+    /// for the first time.
+    async fn lang_id(&mut self) -> Result<u16> {
+        if let Some(lang_id) = self.lang_id {
+            return Ok(lang_id);
+        }
+        let slot = self.slot();
+        let lang_ids = self
+            .xhci()
+            .request_string_descriptor_zero(slot, self.device_slot.ctrl_ep_ring())
+            .await?;
+        let lang_id = *lang_ids
+            .get(1)
+            .ok_or(Error::Failed("No LANGID is supported"))?;
+        self.lang_id = Some(lang_id);
+        Ok(lang_id)
+    }
+    /// Reads the String Descriptor at `index` in the first language the device supports.
+    pub async fn read_string(&mut self, index: u8) -> Result<String> {
+        if index == 0 {
+            return Err(Error::Failed("String Descriptor index 0 is reserved"));
+        }
+        let lang_id = self.lang_id().await?;
+        let slot = self.slot();
+        self.xhci()
+            .request_string_descriptor(slot, self.device_slot.ctrl_ep_ring(), lang_id, index)
+            .await
     }
     pub async fn set_config(&mut self, config_value: u8) -> Result<()> {
+        let slot = self.slot();
         self.xhci()
-            .request_set_config(self.slot, &mut self.ctrl_ep_ring, config_value)
+            .request_set_config(slot, self.device_slot.ctrl_ep_ring(), config_value)
             .await
     }
     pub async fn set_interface(&mut self, interface_desc: &InterfaceDescriptor) -> Result<()> {
-        self.xhci
+        let slot = self.slot();
+        self.xhci()
             .request_set_interface(
-                self.slot,
-                &mut self.ctrl_ep_ring,
+                slot,
+                self.device_slot.ctrl_ep_ring(),
                 interface_desc.interface_number(),
                 interface_desc.alt_setting(),
             )
@@ -133,27 +306,110 @@ impl UsbDeviceDriverContext {
         interface_desc: &InterfaceDescriptor,
         protocol: UsbHidProtocol,
     ) -> Result<()> {
-        self.xhci
+        let slot = self.slot();
+        self.xhci()
             .request_set_protocol(
-                self.slot,
-                &mut self.ctrl_ep_ring,
+                slot,
+                self.device_slot.ctrl_ep_ring(),
                 interface_desc.interface_number(),
                 protocol as u8,
             )
             .await
     }
+    /// USB HID specific request. Sends an Output report, e.g. the LED states of a keyboard.
+    pub async fn set_report(
+        &mut self,
+        interface_desc: &InterfaceDescriptor,
+        report: &[u8],
+    ) -> Result<()> {
+        let slot = self.slot();
+        let mut buf = Box::into_pin(Box::<[u8]>::from(report));
+        self.xhci()
+            .request_set_report(
+                slot,
+                self.device_slot.ctrl_ep_ring(),
+                interface_desc.interface_number(),
+                buf.as_mut(),
+            )
+            .await
+    }
     pub fn push_trb_to_ctrl_ep(&mut self, trb: GenericTrbEntry) -> Result<u64> {
-        self.ctrl_ep_ring.push(trb)
+        self.device_slot.ctrl_ep_ring().push(trb)
     }
     pub fn notify_ctrl_ep(&mut self) -> Result<()> {
-        self.xhci.notify_ep(self.slot, 1)
+        self.device_slot.notify_ep(DCI_EP0)
     }
     pub fn notify_ep(&mut self, ep: &EndpointDescriptor) -> Result<()> {
-        self.xhci.notify_ep(self.slot, ep.dci())
+        self.device_slot.notify_ep(ep.dci()?)
     }
+    /// Waits for a transfer event on the slot, which completes the control transfer pushed with
+    /// push_trb_to_ctrl_ep().
     pub async fn wait_transfer_event(&mut self) -> Result<()> {
-        EventFuture::new_on_slot(self.xhci.primary_event_ring(), self.slot)
-            .await?
-            .completed()
+        let event = EventFuture::new_on_slot(self.xhci().primary_event_ring(), self.slot()).await?;
+        self.device_slot.ctrl_ep_ring().dequeue_all();
+        event.completed()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    #[test_case]
+    fn usb_device_info_summary() {
+        let info = UsbDeviceInfo {
+            slot: 2,
+            port: 5,
+            speed: UsbMode::HighSpeed,
+            interfaces: alloc::vec![(3, 1, 1)],
+            product: Some("QEMU USB Keyboard".into()),
+        };
+        assert_eq!(
+            info.summary(),
+            "slot   2 port  5 HighSpeed 03/01/01 \"QEMU USB Keyboard\""
+        );
+        let info = UsbDeviceInfo {
+            slot: 1,
+            port: 1,
+            speed: UsbMode::Unknown(7),
+            interfaces: alloc::vec![(0xff, 0xff, 0), (3, 0, 0)],
+            product: None,
+        };
+        assert_eq!(
+            info.summary(),
+            "slot   1 port  1 Unknown(7) FF/FF/00 03/00/00"
+        );
+    }
+    #[test_case]
+    fn usb_devices_are_unregistered_on_disconnect() {
+        let info = |slot, port| UsbDeviceInfo {
+            slot,
+            port,
+            speed: UsbMode::HighSpeed,
+            interfaces: Vec::new(),
+            product: None,
+        };
+        let is_listed = |slot| usb_device_list().iter().any(|d| d.slot == slot);
+        register_usb_device(info(250, 200));
+        register_usb_device(info(251, 201));
+        unregister_usb_devices_on_port(200);
+        assert!(!is_listed(250));
+        assert!(is_listed(251));
+        unregister_usb_devices_on_port(201);
+        assert!(!is_listed(251));
+    }
+    #[test_case]
+    fn ep_ring_is_indexed_by_dci() {
+        const EP_RING_NONE: Option<TransferRing> = None;
+        let mut ep_rings = [EP_RING_NONE; NUM_DEVICE_CONTEXTS];
+        let tring = TransferRing::new(8).expect("Failed to create a TransferRing");
+        let ring_addr = tring.ring_phys_addr();
+        ep_rings[dci_from_endpoint(1, true).unwrap()] = Some(tring);
+        let tring = ep_ring_at(&ep_rings, 3).unwrap().as_ref();
+        assert_eq!(tring.map(|t| t.ring_phys_addr()), Some(ring_addr));
+        assert!(ep_ring_at(&ep_rings, dci_from_endpoint(1, false).unwrap())
+            .unwrap()
+            .is_none());
+        assert!(ep_ring_at(&ep_rings, DCI_EP0).unwrap().is_none());
+        assert!(ep_ring_at(&ep_rings, NUM_DEVICE_CONTEXTS).is_err());
     }
 }
@@ -21,7 +21,11 @@ use crate::xhci::context::InputContext;
 use crate::xhci::context::InputControlContext;
 use crate::xhci::context::OutputContext;
 use crate::xhci::controller::Controller;
+use crate::xhci::device::register_usb_device;
+use crate::xhci::device::unregister_usb_devices_on_port;
 use crate::xhci::device::UsbDeviceDriverContext;
+use crate::xhci::device::UsbDeviceInfo;
+use crate::xhci::future::EventFuture;
 use crate::xhci::init::create_host_controller;
 use crate::xhci::registers::PortLinkState;
 use crate::xhci::registers::PortScIteratorItem;
@@ -41,6 +45,31 @@ use core::task::Context;
 #[derive(Default)]
 pub struct XhciDriverForPci {}
 impl XhciDriverForPci {
+    const VDI_LIST: [VendorDeviceId; 3] = [
+        VendorDeviceId {
+            vendor: 0x1b36,
+            device: 0x000d,
+        },
+        VendorDeviceId {
+            vendor: 0x8086,
+            device: 0x31a8,
+        },
+        VendorDeviceId {
+            vendor: 0x8086,
+            device: 0x02ed,
+        },
+    ];
+    /// Returns the first supported xHC in the given device list.
+    /// Err is returned if there is no such controller so that the caller
+    /// can continue booting without USB.
+    pub fn probe(devices: &[(BusDeviceFunction, VendorDeviceId)]) -> Result<BusDeviceFunction> {
+        let driver = Self::default();
+        devices
+            .iter()
+            .find(|(_, vd)| driver.supports(*vd))
+            .map(|(bdf, _)| *bdf)
+            .ok_or(Error::Failed("No supported xHC found"))
+    }
     async fn update_max_packet_size(
         xhc: &Rc<Controller>,
         port: usize,
@@ -95,59 +124,23 @@ impl XhciDriverForPci {
             .await?;
         let device_vendor_id = device_descriptor.vendor_id;
         let device_product_id = device_descriptor.product_id;
-        if let Ok(e) = xhc
-            .request_string_descriptor_zero(slot, &mut ctrl_ep_ring)
-            .await
-        {
-            let lang_id = e[1];
-            let vendor = if device_descriptor.manufacturer_idx != 0 {
-                Some(
-                    xhc.request_string_descriptor(
-                        slot,
-                        &mut ctrl_ep_ring,
-                        lang_id,
-                        device_descriptor.manufacturer_idx,
-                    )
-                    .await?,
-                )
-            } else {
-                None
-            };
-            let product = if device_descriptor.product_idx != 0 {
-                Some(
-                    xhc.request_string_descriptor(
-                        slot,
-                        &mut ctrl_ep_ring,
-                        lang_id,
-                        device_descriptor.product_idx,
-                    )
-                    .await?,
-                )
-            } else {
-                None
-            };
-            let serial = if device_descriptor.serial_idx != 0 {
-                Some(
-                    xhc.request_string_descriptor(
-                        slot,
-                        &mut ctrl_ep_ring,
-                        lang_id,
-                        device_descriptor.serial_idx,
-                    )
-                    .await?,
-                )
-            } else {
-                None
-            };
-            info!("USB device detected: vendor/product/serial =  {vendor:?}/{product:?}/{serial:?} (vid:pid = {device_vendor_id:#06X}:{device_product_id:#06X})");
-        } else {
-            info!(
-                "USB device detected: vid:pid = {device_vendor_id:#06X}:{device_product_id:#06X}",
-            );
-        }
-        let ddc =
+        let mut ddc =
             UsbDeviceDriverContext::new(port, slot, xhc, input_context, ctrl_ep_ring, descriptors)
                 .await?;
+        let vendor = ddc
+            .read_string(device_descriptor.manufacturer_idx)
+            .await
+            .ok();
+        let product = ddc.read_string(device_descriptor.product_idx).await.ok();
+        let serial = ddc.read_string(device_descriptor.serial_idx).await.ok();
+        info!("USB device detected: vendor/product/serial = {vendor:?}/{product:?}/{serial:?} (vid:pid = {device_vendor_id:#06X}:{device_product_id:#06X})");
+        let speed = ddc
+            .xhci()
+            .portsc(port)?
+            .upgrade()
+            .ok_or("PORTSC was invalid")?
+            .port_speed();
+        register_usb_device(UsbDeviceInfo::new(&ddc, speed, product));
         if device_vendor_id == 2965 && device_product_id == 6032 {
             ax88179::attach_usb_device(ddc).await?;
         } else if device_vendor_id == 0x0bda
@@ -185,7 +178,7 @@ impl XhciDriverForPci {
         // Setup an input context and send AddressDevice command.
         // 4.3.3 Device Slot Initialization
         let output_context = Box::pin(OutputContext::default());
-        xhc.set_output_context_for_slot(slot, output_context);
+        xhc.set_output_context_for_slot(slot, output_context)?;
         let mut input_ctrl_ctx = InputControlContext::default();
         input_ctrl_ctx.add_context(0)?;
         input_ctrl_ctx.add_context(1)?;
@@ -252,7 +245,12 @@ impl XhciDriverForPci {
         }
         Self::enable_slot(xhc.clone(), port).await
     }
-    async fn poll(xhc: Rc<Controller>) -> Result<()> {
+    async fn poll(xhc: Rc<Controller>, port_status_change: &EventFuture) -> Result<()> {
+        // Port Status Change Events are also delivered to the device drivers waiting on the port
+        // to detect disconnects. Changes are picked up via CSC below, so just drain them here.
+        while let Some(trb) = port_status_change.try_pop() {
+            info!("Port Status Change Event: {trb:?}");
+        }
         // 4.3 USB Device Initialization
         // USB3: Disconnected -> Polling -> Enabled
         // USB2: Disconnected -> Disabled
@@ -288,6 +286,7 @@ impl XhciDriverForPci {
                 }
             } else {
                 info!("Port {}: Device detached: {:?}", port, portsc);
+                unregister_usb_devices_on_port(port);
             }
         }
         let waker = dummy_waker();
@@ -304,7 +303,7 @@ impl XhciDriverForPci {
         }
         Ok(())
     }
-    fn spawn(bdf: BusDeviceFunction) -> Result<Self> {
+    fn spawn(bdf: BusDeviceFunction) -> Result<XhciDriverInstance> {
         spawn_global(async move {
             info!("Initializing the xHC");
             let xhc = create_host_controller(bdf)?;
@@ -318,42 +317,36 @@ impl XhciDriverForPci {
                     }
                 })
             }
+            let port_status_change =
+                EventFuture::new_port_status_change(xhc.primary_event_ring(), None);
             info!("Checking if the ring works");
             Self::ensure_ring_is_working(xhc.clone()).await?;
             info!("Entering the main loop");
             loop {
-                if let Err(e) = Self::poll(xhc.clone()).await {
+                if let Err(e) = Self::poll(xhc.clone(), &port_status_change).await {
                     break Err(e);
                 } else {
                     yield_execution().await;
                 }
             }
         });
-        Ok(Self::default())
+        Ok(XhciDriverInstance { bdf })
     }
 }
-impl PciDeviceDriverInstance for XhciDriverForPci {
+pub struct XhciDriverInstance {
+    bdf: BusDeviceFunction,
+}
+impl PciDeviceDriverInstance for XhciDriverInstance {
     fn name(&self) -> &str {
         "XhciDriverInstance"
     }
+    fn bdf(&self) -> BusDeviceFunction {
+        self.bdf
+    }
 }
 impl PciDeviceDriver for XhciDriverForPci {
     fn supports(&self, vp: VendorDeviceId) -> bool {
-        const VDI_LIST: [VendorDeviceId; 3] = [
-            VendorDeviceId {
-                vendor: 0x1b36,
-                device: 0x000d,
-            },
-            VendorDeviceId {
-                vendor: 0x8086,
-                device: 0x31a8,
-            },
-            VendorDeviceId {
-                vendor: 0x8086,
-                device: 0x02ed,
-            },
-        ];
-        VDI_LIST.contains(&vp)
+        Self::VDI_LIST.contains(&vp)
     }
     fn attach(&self, bdf: BusDeviceFunction) -> Result<Box<dyn PciDeviceDriverInstance>> {
         Ok(Box::new(Self::spawn(bdf)?) as Box<dyn PciDeviceDriverInstance>)
@@ -362,3 +355,50 @@ impl PciDeviceDriver for XhciDriverForPci {
         "XhciDriver"
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    #[test_case]
+    fn probe_returns_err_if_no_xhc_found() {
+        let devices = [
+            (
+                BusDeviceFunction::new(0, 1, 0).unwrap(),
+                VendorDeviceId {
+                    vendor: 0x8086,
+                    device: 0x29c0,
+                },
+            ),
+            (
+                BusDeviceFunction::new(0, 2, 0).unwrap(),
+                VendorDeviceId {
+                    vendor: 0x10ec,
+                    device: 0x8139,
+                },
+            ),
+        ];
+        assert!(XhciDriverForPci::probe(&devices).is_err());
+        assert!(XhciDriverForPci::probe(&[]).is_err());
+    }
+    #[test_case]
+    fn probe_finds_xhc() {
+        let xhc_bdf = BusDeviceFunction::new(0, 3, 0).unwrap();
+        let devices = [
+            (
+                BusDeviceFunction::new(0, 1, 0).unwrap(),
+                VendorDeviceId {
+                    vendor: 0x8086,
+                    device: 0x29c0,
+                },
+            ),
+            (
+                xhc_bdf,
+                VendorDeviceId {
+                    vendor: 0x1b36,
+                    device: 0x000d,
+                },
+            ),
+        ];
+        assert_eq!(XhciDriverForPci::probe(&devices), Ok(xhc_bdf));
+    }
+}
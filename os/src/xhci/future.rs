@@ -18,6 +18,7 @@ pub struct EventWaitCond {
     trb_type: Option<TrbType>,
     trb_addr: Option<u64>,
     slot: Option<u8>,
+    port: Option<usize>,
 }
 
 #[derive(Debug)]
@@ -37,6 +38,11 @@ impl EventWaitInfo {
                 return false;
             }
         }
+        if let Some(port) = self.cond.port {
+            if trb.port_id() != Some(port) {
+                return false;
+            }
+        }
         if let Some(trb_addr) = self.cond.trb_addr {
             if trb.data() != trb_addr {
                 return false;
@@ -82,6 +88,7 @@ impl EventFuture {
                 trb_type: None,
                 trb_addr: None,
                 slot: Some(slot),
+                port: None,
             },
         )
     }
@@ -92,6 +99,20 @@ impl EventFuture {
                 trb_type: Some(TrbType::CommandCompletionEvent),
                 trb_addr: None,
                 slot: Some(slot),
+                port: None,
+            },
+        )
+    }
+    /// Waits for any Command Completion Event, which also means that a slot in the Command Ring
+    /// has been consumed by the controller.
+    pub fn new_command_completion(event_ring: &Mutex<EventRing>) -> Self {
+        Self::new(
+            event_ring,
+            EventWaitCond {
+                trb_type: Some(TrbType::CommandCompletionEvent),
+                trb_addr: None,
+                slot: None,
+                port: None,
             },
         )
     }
@@ -102,9 +123,26 @@ impl EventFuture {
                 trb_type: Some(TrbType::TransferEvent),
                 trb_addr: None,
                 slot: Some(slot),
+                port: None,
+            },
+        )
+    }
+    /// Waits for Port Status Change Events on `port`, or on any port if `port` is None.
+    pub fn new_port_status_change(event_ring: &Mutex<EventRing>, port: Option<usize>) -> Self {
+        Self::new(
+            event_ring,
+            EventWaitCond {
+                trb_type: Some(TrbType::PortStatusChangeEvent),
+                trb_addr: None,
+                slot: None,
+                port,
             },
         )
     }
+    /// Non-blocking
+    pub fn try_pop(&self) -> Option<GenericTrbEntry> {
+        self.wait_on.trbs.lock().pop_front()
+    }
     pub fn new_on_trb(event_ring: &Mutex<EventRing>, trb_addr: u64) -> Self {
         Self::new(
             event_ring,
@@ -112,6 +150,7 @@ impl EventFuture {
                 trb_type: None,
                 trb_addr: Some(trb_addr),
                 slot: None,
+                port: None,
             },
         )
     }
@@ -7,23 +7,26 @@ use crate::memory::Mmio;
 use crate::mutex::Mutex;
 use crate::pci::BusDeviceFunction;
 use crate::pci::Pci;
-use crate::util::PAGE_SIZE;
 use crate::xhci::context::DeviceContextBaseAddressArray;
 use crate::xhci::controller::Controller;
 use crate::xhci::registers::CapabilityRegisters;
-use crate::xhci::registers::Doorbell;
+use crate::xhci::registers::DoorbellRegisters;
 use crate::xhci::registers::OperationalRegisters;
 use crate::xhci::registers::PortSc;
 use crate::xhci::registers::RuntimeRegisters;
 use alloc::alloc::Layout;
 use alloc::boxed::Box;
-use alloc::rc::Rc;
-use alloc::vec::Vec;
+use core::alloc::GlobalAlloc;
 use core::convert::AsRef;
 use core::mem::size_of;
 use core::pin::Pin;
 use core::slice;
 
+fn scratch_pad_buffer_layout(page_size: usize) -> Result<Layout> {
+    Layout::from_size_align(page_size, page_size)
+        .map_err(|_| Error::Failed("Invalid page size for scratchpad buffers"))
+}
+
 // Allocate scratchpad buffers as xHCI spec says:
 // > 4.20 Scratchpad Buffers
 // > This should be done before xHC starts.
@@ -32,21 +35,58 @@ use core::slice;
 // > which is sized PAGESIZE and aligned on
 // > PAGESIZE. (PAGESIZE can be retrieved from
 // > op_regs.PAGESIZE)
-fn alloc_scratch_pad_buffers(num_scratch_pad_bufs: usize) -> Result<Pin<Box<[*mut u8]>>> {
-    let scratchpad_buffers = ALLOCATOR.alloc_with_options(
-        Layout::from_size_align(size_of::<usize>() * num_scratch_pad_bufs, PAGE_SIZE)
-            .map_err(|_| Error::Failed("could not allocated scratchpad buffers"))?,
-    );
-    let scratchpad_buffers =
-        unsafe { slice::from_raw_parts(scratchpad_buffers as *mut *mut u8, num_scratch_pad_bufs) };
-    let mut scratchpad_buffers = Pin::new(Box::<[*mut u8]>::from(scratchpad_buffers));
-    for sb in scratchpad_buffers.iter_mut() {
-        *sb = ALLOCATOR.alloc_with_options(
-            Layout::from_size_align(PAGE_SIZE, PAGE_SIZE)
-                .map_err(|_| Error::Failed("could not allocated scratchpad buffers"))?,
-        );
+// Returns None if the xHC does not require any scratchpad buffers.
+// If an allocation fails, the buffers allocated so far are released with free_buffer.
+fn build_scratch_pad_buffer_array(
+    num_scratch_pad_bufs: usize,
+    alloc_buffer: &mut dyn FnMut() -> Result<*mut u8>,
+    free_buffer: &mut dyn FnMut(*mut u8),
+) -> Result<Option<Pin<Box<[*mut u8]>>>> {
+    if num_scratch_pad_bufs == 0 {
+        return Ok(None);
+    }
+    // Table 6-1: Scratchpad Buffer Array should be 64-byte aligned
+    let array_layout = Layout::from_size_align(size_of::<*mut u8>() * num_scratch_pad_bufs, 64)
+        .map_err(|_| Error::Failed("could not allocated scratchpad buffer array"))?;
+    let array = ALLOCATOR.alloc_with_options(array_layout);
+    if array.is_null() {
+        return Err(Error::Failed("could not allocated scratchpad buffer array"));
     }
-    Ok(scratchpad_buffers)
+    // SAFETY: array is allocated with the size of num_scratch_pad_bufs pointers above
+    let array = unsafe { slice::from_raw_parts_mut(array as *mut *mut u8, num_scratch_pad_bufs) };
+    for i in 0..array.len() {
+        match alloc_buffer() {
+            Ok(buf) => array[i] = buf,
+            Err(e) => {
+                array[..i].iter().for_each(|&buf| free_buffer(buf));
+                // SAFETY: array is allocated by ALLOCATOR with array_layout above
+                unsafe { ALLOCATOR.dealloc(array.as_mut_ptr() as *mut u8, array_layout) };
+                return Err(e);
+            }
+        }
+    }
+    // SAFETY: array is allocated by the global allocator and fully initialized above
+    Ok(Some(Box::into_pin(unsafe { Box::from_raw(array) })))
+}
+
+fn alloc_scratch_pad_buffers(
+    num_scratch_pad_bufs: usize,
+    page_size: usize,
+) -> Result<Option<Pin<Box<[*mut u8]>>>> {
+    let layout = scratch_pad_buffer_layout(page_size)?;
+    build_scratch_pad_buffer_array(
+        num_scratch_pad_bufs,
+        &mut || {
+            let buf = ALLOCATOR.alloc_with_options(layout);
+            if buf.is_null() {
+                Err(Error::Failed("could not allocated scratchpad buffers"))
+            } else {
+                Ok(buf)
+            }
+        },
+        // SAFETY: buf is allocated by ALLOCATOR with layout above
+        &mut |buf| unsafe { ALLOCATOR.dealloc(buf, layout) },
+    )
 }
 
 pub fn create_host_controller(bdf: BusDeviceFunction) -> Result<Controller> {
@@ -54,6 +94,7 @@ pub fn create_host_controller(bdf: BusDeviceFunction) -> Result<Controller> {
     pci.disable_interrupt(bdf)?;
     pci.enable_bus_master(bdf)?;
     let bar0 = pci.try_bar0_mem64(bdf)?;
+    pci.enable_memory_space(bdf)?;
     bar0.disable_cache();
 
     let cap_regs = unsafe { Mmio::from_raw(bar0.addr() as *mut CapabilityRegisters) };
@@ -62,25 +103,21 @@ pub fn create_host_controller(bdf: BusDeviceFunction) -> Result<Controller> {
     let mut op_regs = unsafe {
         Mmio::from_raw(bar0.addr().add(cap_regs.as_ref().length()) as *mut OperationalRegisters)
     };
-    unsafe { op_regs.get_unchecked_mut() }.reset_xhc();
-    op_regs.as_ref().assert_params()?;
+    unsafe { op_regs.get_unchecked_mut() }.reset_xhc()?;
+    let page_size = op_regs.as_ref().page_size()?;
 
     let rt_regs = unsafe {
         Mmio::from_raw(bar0.addr().add(cap_regs.as_ref().rtsoff()) as *mut RuntimeRegisters)
     };
 
     let num_slots = cap_regs.as_ref().num_of_device_slots();
-    let mut doorbell_regs = Vec::new();
-    for i in 0..=num_slots {
-        let ptr = unsafe { bar0.addr().add(cap_regs.as_ref().dboff()).add(4 * i) as *mut u32 };
-        doorbell_regs.push(Rc::new(Doorbell::new(ptr)))
-    }
-    // number of doorbells will be 1 + num_slots since doorbell[] is for the host controller.
-    assert!(doorbell_regs.len() == 1 + num_slots);
+    let doorbell_regs = DoorbellRegisters::new(&bar0, cap_regs.as_ref());
 
     let portsc = PortSc::new(&bar0, cap_regs.as_ref());
-    let scratchpad_buffers = alloc_scratch_pad_buffers(cap_regs.as_ref().num_scratch_pad_bufs())?;
-    let device_context_base_array = DeviceContextBaseAddressArray::new(scratchpad_buffers);
+    let scratchpad_buffers =
+        alloc_scratch_pad_buffers(cap_regs.as_ref().num_scratch_pad_bufs(), page_size)?;
+    let device_context_base_array =
+        DeviceContextBaseAddressArray::new(scratchpad_buffers, num_slots);
     let device_context_base_array = Mutex::new(device_context_base_array);
     Controller::new(
         cap_regs,
@@ -91,3 +128,78 @@ pub fn create_host_controller(bdf: BusDeviceFunction) -> Result<Controller> {
         device_context_base_array,
     )
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::util::PAGE_SIZE;
+    #[test_case]
+    fn scratch_pad_buffer_layout_follows_page_size() {
+        let layout = scratch_pad_buffer_layout(8192).expect("8192 should be a valid page size");
+        assert_eq!(layout.size(), 8192);
+        assert_eq!(layout.align(), 8192);
+        let layout = scratch_pad_buffer_layout(PAGE_SIZE).expect("PAGE_SIZE should be valid");
+        assert_eq!(layout.align(), PAGE_SIZE);
+        assert!(scratch_pad_buffer_layout(3000).is_err());
+    }
+    #[test_case]
+    fn scratch_pad_buffer_array_construction() {
+        let mut next_buf = 0x1000;
+        let array = build_scratch_pad_buffer_array(
+            3,
+            &mut || {
+                let buf = next_buf as *mut u8;
+                next_buf += 0x1000;
+                Ok(buf)
+            },
+            &mut |_| panic!("no buffers should be freed"),
+        )
+        .expect("allocation should succeed")
+        .expect("array should be created for non-zero scratchpad count");
+        assert_eq!(array.len(), 3);
+        assert_eq!(array.as_ptr() as usize % 64, 0);
+        assert_eq!(array[0] as usize, 0x1000);
+        assert_eq!(array[1] as usize, 0x2000);
+        assert_eq!(array[2] as usize, 0x3000);
+    }
+    #[test_case]
+    fn scratch_pad_buffer_array_is_none_for_zero_bufs() {
+        let array = build_scratch_pad_buffer_array(
+            0,
+            &mut || {
+                panic!("no buffers should be allocated");
+            },
+            &mut |_| panic!("no buffers should be freed"),
+        )
+        .expect("should succeed");
+        assert!(array.is_none());
+    }
+    #[test_case]
+    fn scratch_pad_buffer_array_propagates_alloc_failure() {
+        let array = build_scratch_pad_buffer_array(
+            3,
+            &mut || Err(Error::Failed("out of memory")),
+            &mut |_| panic!("no buffers should be freed"),
+        );
+        assert_eq!(array, Err(Error::Failed("out of memory")));
+    }
+    #[test_case]
+    fn scratch_pad_buffer_array_frees_buffers_on_failure() {
+        let mut next_buf = 0x1000;
+        let mut freed = alloc::vec::Vec::new();
+        let array = build_scratch_pad_buffer_array(
+            3,
+            &mut || {
+                if next_buf == 0x3000 {
+                    return Err(Error::Failed("out of memory"));
+                }
+                let buf = next_buf as *mut u8;
+                next_buf += 0x1000;
+                Ok(buf)
+            },
+            &mut |buf| freed.push(buf as usize),
+        );
+        assert_eq!(array, Err(Error::Failed("out of memory")));
+        assert_eq!(freed, [0x1000, 0x2000]);
+    }
+}
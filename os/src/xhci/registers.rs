@@ -5,7 +5,7 @@ use crate::error::Result;
 use crate::mutex::Mutex;
 use crate::pci::BarMem64;
 use crate::util::extract_bits;
-use crate::util::PAGE_SIZE;
+use crate::util::poll_until;
 use crate::volatile::Volatile;
 use crate::x86_64::busy_loop_hint;
 use crate::xhci::context::DeviceContextBaseAddressArray;
@@ -22,6 +22,23 @@ use core::mem::size_of;
 use core::mem::transmute;
 use core::ptr::read_volatile;
 use core::ptr::write_volatile;
+use core::sync::atomic::fence;
+use core::sync::atomic::Ordering;
+
+#[cfg(test)]
+use core::sync::atomic::AtomicUsize;
+
+#[cfg(test)]
+static NUM_REGISTER_WRITE_BARRIERS: AtomicUsize = AtomicUsize::new(0);
+
+/// Orders the preceding writes to the memory shared with the xHC (TRBs, contexts and so on)
+/// before the register write that follows, so that the xHC never reads stale data after it is
+/// notified via a doorbell or given a pointer to the structure.
+fn barrier_before_register_write() {
+    #[cfg(test)]
+    NUM_REGISTER_WRITE_BARRIERS.fetch_add(1, Ordering::SeqCst);
+    fence(Ordering::SeqCst);
+}
 
 #[derive(PartialEq, Eq, Debug, Copy, Clone)]
 pub enum UsbMode {
@@ -223,6 +240,12 @@ impl<'a> Iterator for PortScIterator<'a> {
         }
     }
 }
+impl<'a> PortScIterator<'a> {
+    /// Yields only the ports that have a device connected (CCS=1).
+    pub fn filter_connected(self) -> impl Iterator<Item = PortScIteratorItem> + 'a {
+        self.filter(|item| item.portsc.upgrade().map(|p| p.ccs()).unwrap_or(false))
+    }
+}
 impl<'a> DoubleEndedIterator for PortScIterator<'a> {
     fn next_back(&mut self) -> Option<Self::Item> {
         if self.next_port <= self.next_port_back {
@@ -247,6 +270,13 @@ impl PortSc {
     pub fn new(bar: &BarMem64, cap_regs: &CapabilityRegisters) -> Self {
         let base = unsafe { bar.addr().add(cap_regs.length()).add(0x400) } as *mut u32;
         let num_ports = cap_regs.num_of_ports();
+        // SAFETY: base points the PORTSC of the port 1 and the xHC has num_ports ports.
+        unsafe { Self::from_raw(base, num_ports) }
+    }
+    /// # Safety
+    /// `base` should point the PORTSC register for the port 1,
+    /// followed by the registers for the rest of `num_ports` ports with the stride of 0x10 bytes.
+    unsafe fn from_raw(base: *mut u32, num_ports: usize) -> Self {
         let mut entries = Vec::new();
         for port in 1..=num_ports {
             // SAFETY: This is safe since the result of ptr calculation
@@ -271,6 +301,9 @@ impl PortSc {
             next_port_back: self.entries.len(),
         }
     }
+    pub fn connected_ports(&self) -> impl Iterator<Item = PortScIteratorItem> + '_ {
+        self.iter().filter_connected()
+    }
 }
 
 #[repr(C)]
@@ -378,6 +411,7 @@ impl OperationalRegisters {
         Ok(())
     }
     pub fn set_dcbaa_ptr(&mut self, dcbaa: &mut DeviceContextBaseAddressArray) -> Result<()> {
+        barrier_before_register_write();
         unsafe {
             write_volatile(
                 &mut self.device_ctx_base_addr_array_ptr,
@@ -387,27 +421,35 @@ impl OperationalRegisters {
         Ok(())
     }
     pub fn set_cmd_ring_ctrl(&mut self, ring: &CommandRing) {
-        self.cmd_ring_ctrl = ring.ring_phys_addr() | 1 /* Consumer Ring Cycle State */
+        barrier_before_register_write();
+        unsafe {
+            write_volatile(
+                &mut self.cmd_ring_ctrl,
+                ring.ring_phys_addr() | 1, /* Consumer Ring Cycle State */
+            );
+        }
     }
-    pub fn assert_params(&self) -> Result<()> {
-        assert_eq!(self.page_size()?, PAGE_SIZE);
-        Ok(())
+    /// Upper bound of the register polls while waiting for the xHC to change its state.
+    /// Generous enough for real hardware; only a dead controller should hit this.
+    pub const POLL_LIMIT: usize = 100_000_000;
+    pub fn reset_xhc(&mut self) -> Result<()> {
+        self.reset_xhc_with_limit(Self::POLL_LIMIT)
     }
-    pub fn reset_xhc(&mut self) {
+    pub fn reset_xhc_with_limit(&mut self, max_polls: usize) -> Result<()> {
         self.clear_command_bits(Self::CMD_RUN_STOP);
-        while self.status() & Self::STATUS_HC_HALTED == 0 {
-            busy_loop_hint();
-        }
+        poll_until(|| self.status() & Self::STATUS_HC_HALTED != 0, max_polls)
+            .or(Err(Error::Failed("xHC did not halt")))?;
         self.set_command_bits(Self::CMD_HC_RESET);
-        while self.command() & Self::CMD_HC_RESET != 0 {
-            busy_loop_hint();
-        }
+        poll_until(|| self.command() & Self::CMD_HC_RESET == 0, max_polls)
+            .or(Err(Error::Failed("xHC did not complete the reset")))
     }
-    pub fn start_xhc(&mut self) {
+    pub fn start_xhc(&mut self) -> Result<()> {
+        self.start_xhc_with_limit(Self::POLL_LIMIT)
+    }
+    pub fn start_xhc_with_limit(&mut self, max_polls: usize) -> Result<()> {
         self.set_command_bits(Self::CMD_RUN_STOP);
-        while self.status() & Self::STATUS_HC_HALTED != 0 {
-            busy_loop_hint();
-        }
+        poll_until(|| self.status() & Self::STATUS_HC_HALTED == 0, max_polls)
+            .or(Err(Error::Failed("xHC did not start running")))
     }
 }
 
@@ -435,6 +477,7 @@ impl RuntimeRegisters {
             .irs
             .get_mut(index)
             .ok_or(Error::Failed("Index out of range"))?;
+        barrier_before_register_write();
         irs.erst_size = 1;
         irs.erdp = ring.ring_phys_addr();
         irs.erst_base = ring.erst_phys_addr();
@@ -466,9 +509,125 @@ impl Doorbell {
     // index 1-255: for device contexts (index by a Slot ID)
     pub fn notify(&self, target: u8, task: u16) {
         let value = (target as u32) | (task as u32) << 16;
+        barrier_before_register_write();
         // SAFETY: This is safe as long as the ptr is valid
         unsafe {
             write_volatile(*self.ptr.lock(), value);
         }
     }
 }
+// Interface to ring the doorbells
+//
+// [xhci] 5.6 Doorbell Registers
+// Base + DBOFF + 4 * n
+// where n = 0 (host controller) or Slot ID (1, 2, ..., MaxSlots)
+pub struct DoorbellRegisters {
+    doorbells: Vec<Doorbell>,
+}
+impl DoorbellRegisters {
+    pub fn new(bar: &BarMem64, cap_regs: &CapabilityRegisters) -> Self {
+        let base = unsafe { bar.addr().add(cap_regs.dboff()) } as *mut u32;
+        let num_slots = cap_regs.num_of_device_slots();
+        // SAFETY: base points the doorbell for the host controller
+        // and the xHC has num_slots device slots.
+        unsafe { Self::from_raw(base, num_slots) }
+    }
+    /// # Safety
+    /// `base` should point the doorbell register for the host controller,
+    /// followed by the registers for `num_slots` device slots with the stride of 4 bytes.
+    unsafe fn from_raw(base: *mut u32, num_slots: usize) -> Self {
+        let mut doorbells = Vec::new();
+        for i in 0..=num_slots {
+            doorbells.push(Doorbell::new(base.add(i)));
+        }
+        // number of doorbells will be 1 + num_slots since doorbell[0] is for the host controller.
+        assert!(doorbells.len() == 1 + num_slots);
+        Self { doorbells }
+    }
+    pub fn num_slots(&self) -> usize {
+        self.doorbells.len() - 1
+    }
+    /// Rings the doorbell for the `slot` (0 for the host controller) with the DB Target
+    /// `target`, i.e. 0 for the command ring or a DCI for a transfer ring.
+    pub fn ring(&self, slot: u8, target: u8) -> Result<()> {
+        self.ring_stream(slot, target, 0)
+    }
+    /// Same as ring(), but with the DB Stream ID for the endpoints which use streams.
+    pub fn ring_stream(&self, slot: u8, target: u8, stream_id: u16) -> Result<()> {
+        self.doorbells
+            .get(slot as usize)
+            .ok_or(Error::Failed("xHC: Slot ID Out of Range"))?
+            .notify(target, stream_id);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    #[test_case]
+    fn portsc_filter_connected() {
+        const NUM_PORTS: usize = 4;
+        // Each PORTSC register is placed with the stride of 0x10 bytes
+        let mut regs = [0u32; NUM_PORTS * 4];
+        let pp_ccs = PortScWrapper::BIT_PORT_POWER | PortScWrapper::BIT_CURRENT_CONNECT_STATUS;
+        regs[0] = PortScWrapper::BIT_PORT_POWER;
+        regs[4] = pp_ccs;
+        regs[8] = PortScWrapper::BIT_PORT_POWER;
+        regs[12] = pp_ccs;
+        let portsc = unsafe { PortSc::from_raw(regs.as_mut_ptr(), NUM_PORTS) };
+        assert_eq!(portsc.iter().count(), NUM_PORTS);
+        let connected: Vec<usize> = portsc.connected_ports().map(|e| e.port).collect();
+        assert_eq!(connected, [2, 4]);
+        let connected: Vec<usize> = portsc.iter().filter_connected().map(|e| e.port).collect();
+        assert_eq!(connected, [2, 4]);
+    }
+    #[test_case]
+    fn doorbell_is_rung_after_barrier() {
+        let mut reg = 0u32;
+        let db = Doorbell::new(&mut reg as *mut u32);
+        let before = NUM_REGISTER_WRITE_BARRIERS.load(Ordering::SeqCst);
+        db.notify(3, 0x1234);
+        assert_eq!(
+            NUM_REGISTER_WRITE_BARRIERS.load(Ordering::SeqCst),
+            before + 1
+        );
+        assert_eq!(reg, 0x1234_0003);
+    }
+    #[test_case]
+    fn doorbell_registers_ring_per_slot() {
+        const NUM_SLOTS: usize = 3;
+        let mut regs = [0u32; NUM_SLOTS + 1];
+        let doorbells = unsafe { DoorbellRegisters::from_raw(regs.as_mut_ptr(), NUM_SLOTS) };
+        assert_eq!(doorbells.num_slots(), NUM_SLOTS);
+        doorbells.ring(0, 0).unwrap();
+        doorbells.ring(2, 5).unwrap();
+        doorbells.ring_stream(3, 1, 0xABCD).unwrap();
+        assert_eq!(
+            doorbells.ring(4, 1),
+            Err(Error::Failed("xHC: Slot ID Out of Range"))
+        );
+        assert_eq!(regs, [0, 0, 0x0000_0005, 0xABCD_0001]);
+    }
+    #[test_case]
+    fn stuck_xhc_is_reported() {
+        // No controller behind these registers, so no bits change by themselves.
+        let mut regs: OperationalRegisters = unsafe { core::mem::zeroed() };
+        assert_eq!(
+            regs.reset_xhc_with_limit(100),
+            Err(Error::Failed("xHC did not halt"))
+        );
+        regs.status = OperationalRegisters::STATUS_HC_HALTED;
+        assert_eq!(
+            regs.reset_xhc_with_limit(100),
+            Err(Error::Failed("xHC did not complete the reset"))
+        );
+        regs.command = 0;
+        assert_eq!(
+            regs.start_xhc_with_limit(100),
+            Err(Error::Failed("xHC did not start running"))
+        );
+        regs.status = 0;
+        assert_eq!(regs.start_xhc_with_limit(100), Ok(()));
+    }
+}
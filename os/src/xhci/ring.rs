@@ -3,11 +3,14 @@ extern crate alloc;
 use crate::allocator::ALLOCATOR;
 use crate::error::Error;
 use crate::error::Result;
+use crate::executor::SelectFuture;
+use crate::executor::TimeoutFuture;
 use crate::info;
 use crate::mutex::Mutex;
 use crate::warn;
 use crate::x86_64::paging::disable_cache;
 use crate::x86_64::paging::IoBox;
+use crate::xhci::future::EventFuture;
 use crate::xhci::future::EventWaitInfo;
 use crate::xhci::trb::GenericTrbEntry;
 use crate::xhci::trb::NormalTrb;
@@ -20,7 +23,10 @@ use alloc::fmt::Debug;
 use alloc::format;
 use alloc::rc::Rc;
 use alloc::rc::Weak;
+use alloc::string::String;
 use alloc::vec::Vec;
+use core::alloc::GlobalAlloc;
+use core::future::Future;
 use core::marker::PhantomPinned;
 use core::mem::size_of;
 use core::ptr::null_mut;
@@ -53,6 +59,27 @@ impl TrbRing {
     pub const fn num_trbs(&self) -> usize {
         Self::NUM_TRB
     }
+    /// Number of TRBs which can hold entries, i.e. excluding the Link TRB at the end.
+    const NUM_USABLE_TRBS: usize = Self::NUM_TRB - 1;
+    /// Returns the index after `index`, wrapping around before the Link TRB.
+    fn next_usable_index(index: usize) -> usize {
+        (index + 1) % Self::NUM_USABLE_TRBS
+    }
+    /// Number of steps to advance from `from` to reach `to`, skipping the Link TRB.
+    fn usable_distance(from: usize, to: usize) -> usize {
+        (to + Self::NUM_USABLE_TRBS - from) % Self::NUM_USABLE_TRBS
+    }
+    /// Number of TRBs between `dequeue_index` and the Enqueue Pointer (current_index).
+    fn num_pending_trbs(&self, dequeue_index: usize) -> usize {
+        Self::usable_distance(dequeue_index, self.current_index)
+    }
+    /// 4.9.2.2 Pointer Advancement
+    /// To prevent overruns, software shall determine when the Ring is full. The ring is
+    /// defined as “full” if advancing the Enqueue Pointer will make it equal to the
+    /// Dequeue Pointer.
+    fn is_full(&self, dequeue_index: usize) -> bool {
+        Self::next_usable_index(self.current_index) == dequeue_index
+    }
     fn advance_index(&mut self, new_cycle: bool) -> Result<()> {
         if self.current().cycle_state() == new_cycle {
             return Err(Error::Failed("cycle state does not change"));
@@ -97,6 +124,33 @@ impl TrbRing {
         self.write(self.current_index, trb)
             .expect("writing to the current index shall not fail")
     }
+    /// Returns a message naming the ring and the index if the cycle bit of the TRB at `index`
+    /// is not `expected`.
+    fn cycle_state_mismatch(
+        &self,
+        ring_name: &str,
+        index: usize,
+        expected: bool,
+    ) -> Option<String> {
+        let actual = self.trb(index).cycle_state();
+        if actual == expected {
+            None
+        } else {
+            Some(format!(
+                "{ring_name}: cycle state of TRB[{index}] is {} but expected {}",
+                actual as u8, expected as u8
+            ))
+        }
+    }
+    /// Panics on a cycle state mismatch in debug builds. A mismatch here means a bug in the
+    /// ring logic, which would otherwise end up in a silent hang of the xHC or the driver.
+    fn debug_assert_cycle_state(&self, ring_name: &str, index: usize, expected: bool) {
+        if cfg!(debug_assertions) {
+            if let Some(msg) = self.cycle_state_mismatch(ring_name, index, expected) {
+                panic!("{msg}");
+            }
+        }
+    }
 }
 impl Debug for TrbRing {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
@@ -111,12 +165,17 @@ impl Debug for TrbRing {
 pub struct CommandRing {
     ring: IoBox<TrbRing>,
     cycle_state_ours: bool,
+    // Index of the oldest TRB which is not completed by the xHC yet.
+    // The xHC does not write back the cycle bits, so this is maintained with the TRB pointers
+    // reported in the completion events. See dequeue_trb().
+    dequeue_index: usize,
 }
 impl Default for CommandRing {
     fn default() -> Self {
         let mut this = Self {
             ring: TrbRing::new(),
             cycle_state_ours: false,
+            dequeue_index: 0,
         };
         let link_trb = GenericTrbEntry::trb_link(this.ring.as_ref());
         unsafe { this.ring.get_unchecked_mut() }
@@ -128,21 +187,52 @@ impl Default for CommandRing {
 impl CommandRing {
     pub fn reset(&mut self) {
         self.cycle_state_ours = false;
+        self.dequeue_index = 0;
         let ring = unsafe { self.ring.get_unchecked_mut() };
         ring.reset();
     }
     pub fn ring_phys_addr(&self) -> u64 {
         self.ring.as_ref() as *const TrbRing as u64
     }
+    fn enqueue_index(&self) -> usize {
+        self.ring.as_ref().current_index()
+    }
+    /// Number of TRBs pushed and not completed by the xHC yet.
+    fn num_pending_trbs(&self) -> usize {
+        self.ring.as_ref().num_pending_trbs(self.dequeue_index)
+    }
+    pub fn is_full(&self) -> bool {
+        self.ring.as_ref().is_full(self.dequeue_index)
+    }
+    /// Retires the TRBs up to and including the one at `trb_ptr`, which is the TRB pointer
+    /// reported by a completion event for this ring.
+    /// Completion events can be observed out of order by the tasks waiting on them, so a TRB
+    /// which is retired already is silently ignored.
+    pub fn dequeue_trb(&mut self, trb_ptr: u64) -> Result<()> {
+        let ring = self.ring.as_ref();
+        let index = (0..TrbRing::NUM_USABLE_TRBS)
+            .find(|i| ring.trb_ptr(*i) as u64 == trb_ptr)
+            .ok_or(Error::Failed("CommandRing: TRB pointer out of range"))?;
+        if TrbRing::usable_distance(self.dequeue_index, index) < self.num_pending_trbs() {
+            self.dequeue_index = TrbRing::next_usable_index(index);
+        }
+        Ok(())
+    }
+    /// Retires all the TRBs pushed so far.
+    pub fn dequeue_all(&mut self) {
+        self.dequeue_index = self.enqueue_index();
+    }
     pub fn push(&mut self, mut src: GenericTrbEntry) -> Result<u64> {
+        if self.is_full() {
+            return Err(Error::CommandRingFull);
+        }
         // Calling get_unchecked_mut() here is safe
         // as far as this function does not move the ring out.
         let ring = unsafe { self.ring.get_unchecked_mut() };
-        if ring.current().cycle_state() != self.cycle_state_ours {
-            return Err(Error::Failed("Command Ring is Full"));
-        }
         src.set_cycle_state(self.cycle_state_ours);
         let dst_ptr = ring.current_ptr();
+        // The TRB to be overwritten should be still owned by us
+        ring.debug_assert_cycle_state("CommandRing", ring.current_index(), self.cycle_state_ours);
         ring.write_current(src);
         ring.advance_index(!self.cycle_state_ours)?;
         if ring.current().trb_type() == TrbType::Link as u32 {
@@ -153,6 +243,55 @@ impl CommandRing {
         // The returned ptr will be used for waiting on command completion events.
         Ok(dst_ptr as u64)
     }
+    /// Pushes `trb` like push(), but if the ring is full, waits for a command completion
+    /// event on `event_ring` to free a slot and retries, until `timeout_ms` elapses.
+    pub async fn push_with_retry(
+        this: &Mutex<Self>,
+        trb: GenericTrbEntry,
+        event_ring: &Mutex<EventRing>,
+        timeout_ms: u64,
+    ) -> Result<u64> {
+        retry_push_on_full(
+            || this.lock().push(trb.clone()),
+            || {
+                let completion = EventFuture::new_command_completion(event_ring);
+                async move {
+                    let event = completion.await?;
+                    // The Command TRB Pointer of the event tells how far the xHC has consumed
+                    this.lock().dequeue_trb(event.data())?;
+                    Ok(event)
+                }
+            },
+            timeout_ms,
+        )
+        .await
+    }
+}
+
+async fn retry_push_on_full<F: Future<Output = Result<GenericTrbEntry>>>(
+    mut push: impl FnMut() -> Result<u64>,
+    mut wait_for_completion: impl FnMut() -> F,
+    timeout_ms: u64,
+) -> Result<u64> {
+    let mut timeout = TimeoutFuture::new_ms(timeout_ms);
+    loop {
+        // Start waiting before pushing so that a completion in between is not missed.
+        let completion = wait_for_completion();
+        match push() {
+            Err(Error::CommandRingFull) => {}
+            result => return result,
+        }
+        match SelectFuture::new(completion, &mut timeout).await {
+            (Some(completion), _) => {
+                completion?;
+            }
+            (None, _) => {
+                return Err(Error::Failed(
+                    "Timed out waiting for a free slot in the Command Ring",
+                ))
+            }
+        }
+    }
 }
 
 // Producer: Software
@@ -184,50 +323,46 @@ impl TransferRingInner {
             .write(num_trbs - 1, link_trb)
             .expect("failed to write a link trb");
         for (i, v) in this.buffers.iter_mut().enumerate() {
-            *v = ALLOCATOR.alloc_with_options(
-                Layout::from_size_align(Self::BUF_SIZE, Self::BUF_ALIGN)
-                    .map_err(|_| Error::Failed("TransferRing buffer allocation failed"))?,
-            );
+            // Buffers allocated so far will be freed by drop() on failure
+            *v = ALLOCATOR.alloc_with_options(Self::buf_layout()?);
+            if v.is_null() {
+                return Err(Error::Failed("TransferRing buffer allocation failed"));
+            }
             mut_ring
                 .write(i, NormalTrb::new(*v, transfer_size as u16).into())
                 .expect("failed to write a link trb");
         }
         Ok(this)
     }
-    pub fn fill_ring(&mut self) -> Result<()> {
-        // 4.9.2.2 Pointer Advancement
-        // To prevent overruns, software shall determine when the Ring is full. The ring is
-        // defined as “full” if advancing the Enqueue Pointer will make it equal to the
-        // Dequeue Pointer.
-        //
-        // Note: without taking care of this, QEMU will work without errors but not on the real
-        // hardwares...
-        loop {
-            // Wrap with num_trbs() - 1 to ignore LinkTrb
-            let next_enqueue_index =
-                (self.ring.as_ref().current_index() + 1) % (self.ring.as_ref().num_trbs() - 1);
-            if next_enqueue_index == self.ring.as_ref().num_trbs() - 4 {
-                // Ring is full. stop filling.
-                break;
-            }
-            let mut_ring = unsafe { self.ring.get_unchecked_mut() };
-            mut_ring.advance_index(!self.cycle_state_ours)?;
-        }
-        Ok(())
+    fn buf_layout() -> Result<Layout> {
+        Layout::from_size_align(Self::BUF_SIZE, Self::BUF_ALIGN)
+            .map_err(|_| Error::Failed("TransferRing buffer allocation failed"))
     }
-    pub fn dequeue_trb(&mut self, trb_ptr: usize) -> Result<()> {
-        let trb_ptr_expected = self.ring.as_ref().trb_ptr(self.dequeue_index);
-        if trb_ptr_expected != trb_ptr {
-            return Err(Error::FailedString(format!("expected trb ptr {trb_ptr_expected:#018X} but got {trb_ptr:#018X}. dequeue_index = {}", self.dequeue_index)));
+    fn enqueue_index(&self) -> usize {
+        self.ring.as_ref().current_index()
+    }
+    fn is_full(&self) -> bool {
+        self.ring.as_ref().is_full(self.dequeue_index)
+    }
+    fn is_empty(&self) -> bool {
+        self.num_pending_trbs() == 0
+    }
+    /// Number of TRBs handed to the xHC and not dequeued yet.
+    fn num_pending_trbs(&self) -> usize {
+        self.ring.as_ref().num_pending_trbs(self.dequeue_index)
+    }
+    /// Hands the TRB at the Enqueue Pointer to the xHC.
+    fn enqueue_trb(&mut self) -> Result<()> {
+        if self.is_full() {
+            return Err(Error::Failed("TransferRing is full"));
         }
         let mut_ring = unsafe { self.ring.get_unchecked_mut() };
-        // Dequeue the trb
-        self.dequeue_index += 1;
-        if self.dequeue_index == mut_ring.num_trbs() - 1 {
-            // Dequeue the link trb
-            self.dequeue_index = 0;
-        }
-        // Enqueue the next trb
+        // The TRB to be enqueued should be still owned by us
+        mut_ring.debug_assert_cycle_state(
+            "TransferRing",
+            mut_ring.current_index(),
+            self.cycle_state_ours,
+        );
         mut_ring.advance_index(!self.cycle_state_ours)?;
         if mut_ring.current().trb_type() == TrbType::Link as u32 {
             // Reached to Link TRB. Let's skip it and toggle the cycle.
@@ -236,6 +371,27 @@ impl TransferRingInner {
         }
         Ok(())
     }
+    pub fn fill_ring(&mut self) -> Result<()> {
+        // Note: without taking care of the fullness, QEMU will work without errors but not on
+        // the real hardwares...
+        while !self.is_full() {
+            self.enqueue_trb()?;
+        }
+        Ok(())
+    }
+    pub fn dequeue_trb(&mut self, trb_ptr: usize) -> Result<()> {
+        if self.is_empty() {
+            return Err(Error::Failed("TransferRing is empty"));
+        }
+        let trb_ptr_expected = self.ring.as_ref().trb_ptr(self.dequeue_index);
+        if trb_ptr_expected != trb_ptr {
+            return Err(Error::FailedString(format!("expected trb ptr {trb_ptr_expected:#018X} but got {trb_ptr:#018X}. dequeue_index = {}", self.dequeue_index)));
+        }
+        // Dequeue the trb (skipping the link trb)
+        self.dequeue_index = TrbRing::next_usable_index(self.dequeue_index);
+        // Enqueue the next trb
+        self.enqueue_trb()
+    }
     pub fn current(&self) -> GenericTrbEntry {
         self.ring.as_ref().current()
     }
@@ -250,12 +406,23 @@ impl Debug for TransferRingInner {
             "TransferRing @ {:#018X}: di: {}, ei: {}, {:?}",
             self.ring_phys_addr(),
             self.dequeue_index,
-            self.ring.as_ref().current_index,
+            self.enqueue_index(),
             self.ring.as_ref(),
         )?;
         Ok(())
     }
 }
+impl Drop for TransferRingInner {
+    fn drop(&mut self) {
+        let layout = Self::buf_layout().expect("TransferRing buffer layout should be valid");
+        for v in self.buffers.iter_mut() {
+            if !v.is_null() {
+                unsafe { ALLOCATOR.dealloc(*v, layout) }
+                *v = null_mut();
+            }
+        }
+    }
+}
 pub struct TransferRing {
     inner: Mutex<TransferRingInner>,
 }
@@ -450,3 +617,184 @@ impl EventRingSegmentTableEntry {
         Ok(erst)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::executor::block_on;
+    use core::cell::Cell;
+
+    fn fill_command_ring(ring: &Mutex<CommandRing>) -> Vec<u64> {
+        let mut ptrs = Vec::new();
+        while !ring.lock().is_full() {
+            ptrs.push(
+                ring.lock()
+                    .push(GenericTrbEntry::cmd_no_op())
+                    .expect("push shall succeed until the ring gets full"),
+            );
+        }
+        ptrs
+    }
+    #[test_case]
+    fn usable_indices_skip_the_link_trb() {
+        let last = TrbRing::NUM_USABLE_TRBS - 1;
+        assert_eq!(TrbRing::next_usable_index(0), 1);
+        assert_eq!(TrbRing::next_usable_index(last), 0);
+        assert_eq!(TrbRing::usable_distance(3, 3), 0);
+        assert_eq!(TrbRing::usable_distance(3, 5), 2);
+        assert_eq!(TrbRing::usable_distance(last, 1), 2);
+    }
+    #[test_case]
+    fn command_ring_push_fails_when_full() {
+        let ring = Mutex::new(CommandRing::default());
+        let ptrs = fill_command_ring(&ring);
+        // One slot is kept empty to distinguish a full ring from an empty one
+        assert_eq!(ptrs.len(), TrbRing::NUM_USABLE_TRBS - 1);
+        assert_eq!(
+            ring.lock().push(GenericTrbEntry::cmd_no_op()),
+            Err(Error::CommandRingFull)
+        );
+        // The xHC reports the completion of the first command
+        ring.lock().dequeue_trb(ptrs[0]).unwrap();
+        assert!(ring.lock().push(GenericTrbEntry::cmd_no_op()).is_ok());
+        assert_eq!(
+            ring.lock().push(GenericTrbEntry::cmd_no_op()),
+            Err(Error::CommandRingFull)
+        );
+    }
+    #[test_case]
+    fn command_ring_dequeue_wraps_around() {
+        let ring = Mutex::new(CommandRing::default());
+        // Go around the ring several times, across the Link TRB
+        for _ in 0..(TrbRing::NUM_TRB * 3) {
+            let ptr = ring.lock().push(GenericTrbEntry::cmd_no_op()).unwrap();
+            assert_eq!(ring.lock().num_pending_trbs(), 1);
+            ring.lock().dequeue_trb(ptr).unwrap();
+            assert_eq!(ring.lock().num_pending_trbs(), 0);
+        }
+        let ptrs = fill_command_ring(&ring);
+        // Completions observed out of order only move the Dequeue Pointer forward
+        ring.lock().dequeue_trb(ptrs[2]).unwrap();
+        ring.lock().dequeue_trb(ptrs[1]).unwrap();
+        assert_eq!(ring.lock().num_pending_trbs(), ptrs.len() - 3);
+        // Pointers outside of the ring are rejected
+        assert!(ring.lock().dequeue_trb(0).is_err());
+        ring.lock().dequeue_all();
+        assert_eq!(ring.lock().num_pending_trbs(), 0);
+    }
+    #[test_case]
+    fn command_ring_enqueue_trb_is_owned_by_us() {
+        let mut ring = CommandRing::default();
+        // Go around the ring several times, across the Link TRB which toggles the cycle
+        for _ in 0..(TrbRing::NUM_TRB * 3) {
+            let index = ring.enqueue_index();
+            assert_eq!(
+                ring.ring.as_ref().cycle_state_mismatch(
+                    "CommandRing",
+                    index,
+                    ring.cycle_state_ours
+                ),
+                None
+            );
+            let ptr = ring.push(GenericTrbEntry::cmd_no_op()).unwrap();
+            ring.dequeue_trb(ptr).unwrap();
+        }
+    }
+    #[test_case]
+    fn command_ring_push_retries_after_completion() {
+        let result = block_on(async {
+            let ring = Mutex::new(CommandRing::default());
+            let ptrs = fill_command_ring(&ring);
+            let num_pushes = Cell::new(0);
+            let ptr = retry_push_on_full(
+                || {
+                    num_pushes.set(num_pushes.get() + 1);
+                    ring.lock().push(GenericTrbEntry::cmd_no_op())
+                },
+                || async {
+                    // The controller completed the first command, which frees a slot
+                    ring.lock().dequeue_trb(ptrs[0])?;
+                    Ok(GenericTrbEntry::default())
+                },
+                1000,
+            )
+            .await?;
+            assert_eq!(num_pushes.get(), 2);
+            Ok(ptr)
+        });
+        assert!(result.is_ok());
+    }
+    #[test_case]
+    fn command_ring_push_retry_times_out() {
+        let result = block_on(async {
+            let ring = Mutex::new(CommandRing::default());
+            fill_command_ring(&ring);
+            retry_push_on_full(
+                || ring.lock().push(GenericTrbEntry::cmd_no_op()),
+                core::future::pending,
+                10,
+            )
+            .await
+        });
+        assert!(result.is_err());
+    }
+    #[test_case]
+    fn transfer_ring_fill_and_dequeue_keep_occupancy_in_range() {
+        let mut ring = TransferRingInner::new(8).expect("Failed to create a TransferRing");
+        assert_eq!(ring.num_pending_trbs(), 0);
+        assert!(ring.dequeue_trb(ring.ring.as_ref().trb_ptr(0)).is_err());
+        ring.fill_ring().unwrap();
+        // One slot is kept empty to distinguish a full ring from an empty one
+        assert_eq!(ring.num_pending_trbs(), TrbRing::NUM_USABLE_TRBS - 1);
+        assert!(ring.is_full());
+        assert!(ring.enqueue_trb().is_err());
+        // Go around the ring several times, across the Link TRB
+        for _ in 0..(TrbRing::NUM_TRB * 3) {
+            let trb_ptr = ring.ring.as_ref().trb_ptr(ring.dequeue_index);
+            ring.dequeue_trb(trb_ptr).unwrap();
+            assert_eq!(ring.num_pending_trbs(), TrbRing::NUM_USABLE_TRBS - 1);
+            assert!(ring.num_pending_trbs() < TrbRing::NUM_TRB);
+            assert!(ring.enqueue_index() < TrbRing::NUM_USABLE_TRBS);
+            ring.fill_ring().unwrap();
+            assert_eq!(ring.num_pending_trbs(), TrbRing::NUM_USABLE_TRBS - 1);
+        }
+        // Dequeuing out of order is rejected
+        let wrong_ptr = ring.ring.as_ref().trb_ptr(ring.enqueue_index());
+        assert!(ring.dequeue_trb(wrong_ptr).is_err());
+    }
+    #[test_case]
+    fn transfer_ring_cycle_state_mismatch_is_detected() {
+        let mut ring = TransferRingInner::new(8).expect("Failed to create a TransferRing");
+        ring.fill_ring().unwrap();
+        let index = ring.enqueue_index();
+        let trb_ring = ring.ring.as_ref();
+        assert_eq!(
+            trb_ring.cycle_state_mismatch("TransferRing", index, ring.cycle_state_ours),
+            None
+        );
+        // Make the TRB at the Enqueue Pointer look like it is owned by the xHC
+        let trb_ring = unsafe { ring.ring.get_unchecked_mut() };
+        let mut trb = trb_ring.current();
+        trb.set_cycle_state(!ring.cycle_state_ours);
+        trb_ring.write_current(trb);
+        assert_eq!(
+            ring.ring
+                .as_ref()
+                .cycle_state_mismatch("TransferRing", index, ring.cycle_state_ours),
+            Some(format!(
+                "TransferRing: cycle state of TRB[{index}] is 1 but expected 0"
+            ))
+        );
+    }
+    #[test_case]
+    fn transfer_ring_frees_buffers_on_drop() {
+        let buffers_size = TransferRingInner::BUF_SIZE * (TrbRing::NUM_TRB - 1);
+        // Mapping the ring as IO memory can allocate page tables which are kept after the drop
+        let tolerance = 4 * 4096;
+        let before = ALLOCATOR.free_bytes();
+        let ring = TransferRing::new(8).expect("Failed to create a TransferRing");
+        assert!(ALLOCATOR.free_bytes() + buffers_size <= before);
+        drop(ring);
+        assert!(ALLOCATOR.free_bytes() + tolerance >= before);
+    }
+}
@@ -25,6 +25,7 @@ pub enum TrbType {
     StatusStage = 4,
     Link = 6,
     EnableSlotCommand = 9,
+    DisableSlotCommand = 10,
     AddressDeviceCommand = 11,
     ConfigureEndpointCommand = 12,
     EvaluateContextCommand = 13,
@@ -117,6 +118,14 @@ impl GenericTrbEntry {
     pub fn set_slot_id(&mut self, slot: u8) {
         self.control.write_bits(24, 8, slot as u32).unwrap()
     }
+    /// Returns the Port ID of a Port Status Change Event TRB. See 6.4.2.3 of xhci spec.
+    pub fn port_id(&self) -> Option<usize> {
+        if self.trb_type() == TrbType::PortStatusChangeEvent as u32 {
+            Some(((self.data.read() >> 24) & 0xFF) as usize)
+        } else {
+            None
+        }
+    }
     pub fn completed(&self) -> Result<()> {
         if self.trb_type() != TrbType::CommandCompletionEvent as u32
             && self.trb_type() != TrbType::TransferEvent as u32
@@ -147,6 +156,12 @@ impl GenericTrbEntry {
         trb.set_trb_type(TrbType::EnableSlotCommand);
         trb
     }
+    pub fn cmd_disable_slot(slot_id: u8) -> Self {
+        let mut trb = Self::default();
+        trb.set_trb_type(TrbType::DisableSlotCommand);
+        trb.set_slot_id(slot_id);
+        trb
+    }
     pub fn cmd_address_device(input_context: Pin<&InputContext>, slot_id: u8) -> Self {
         let mut trb = Self::default();
         trb.set_trb_type(TrbType::AddressDeviceCommand);
@@ -211,7 +226,7 @@ impl Debug for GenericTrbEntry {
                 )
             }
             e if e == (TrbType::PortStatusChangeEvent as u32) => {
-                write!(f, "PortStatusChangeEvent Port = {}", self.data.read() >> 24,)
+                write!(f, "PortStatusChangeEvent Port = {:?}", self.port_id())
             }
             _ => {
                 write!(f, "TRB type={:?}", self.trb_type())
@@ -305,6 +320,8 @@ impl SetupStageTrb {
     pub const REQ_GET_REPORT: u8 = 1;
     pub const REQ_GET_DESCRIPTOR: u8 = 6;
     pub const REQ_SET_CONFIGURATION: u8 = 9;
+    // [HID] 7.2.2 Set_Report Request (class specific, so it shares the value with SET_CONFIGURATION)
+    pub const REQ_SET_REPORT: u8 = 9;
     pub const REQ_SET_INTERFACE: u8 = 11;
     pub const REQ_SET_PROTOCOL: u8 = 0x0b;
     pub fn new_vendor_device_in(request: u8, value: u16, index: u16, length: u16) -> Self {
@@ -336,7 +353,7 @@ impl SetupStageTrb {
         const TRT_IN_DATA_STAGE: u32 = 3;
         let transfer_type = if length == 0 {
             TRT_NO_DATA_STAGE
-        } else if request & Self::REQ_TYPE_DIR_DEVICE_TO_HOST != 0 {
+        } else if request_type & Self::REQ_TYPE_DIR_DEVICE_TO_HOST != 0 {
             TRT_IN_DATA_STAGE
         } else {
             TRT_OUT_DATA_STAGE
@@ -416,3 +433,81 @@ impl StatusStageTrb {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    #[test_case]
+    fn setup_stage_trb_packs_request() {
+        let trb = SetupStageTrb::new(
+            SetupStageTrb::REQ_TYPE_DIR_DEVICE_TO_HOST,
+            SetupStageTrb::REQ_GET_DESCRIPTOR,
+            0x0100,
+            0x0409,
+            18,
+        );
+        let trb = GenericTrbEntry::from(trb);
+        assert_eq!(trb.trb_type(), TrbType::SetupStage as u32);
+        assert_eq!(trb.data(), 0x0012_0409_0100_0680);
+        assert_eq!(trb.transfer_length(), 8);
+        // TRT: IN Data Stage
+        assert_eq!(extract_bits(trb.control.read(), 16, 2), 3);
+        assert!(trb.control.read() & GenericTrbEntry::CTRL_BIT_IMMEDIATE_DATA != 0);
+    }
+    #[test_case]
+    fn setup_stage_trb_transfer_type() {
+        let trb = GenericTrbEntry::from(SetupStageTrb::new(
+            0,
+            SetupStageTrb::REQ_SET_CONFIGURATION,
+            1,
+            0,
+            0,
+        ));
+        // TRT: No Data Stage
+        assert_eq!(extract_bits(trb.control.read(), 16, 2), 0);
+        let trb = GenericTrbEntry::from(SetupStageTrb::new_vendor_device_out(1, 0, 0, 4));
+        // TRT: OUT Data Stage
+        assert_eq!(extract_bits(trb.control.read(), 16, 2), 2);
+        let trb = GenericTrbEntry::from(SetupStageTrb::new_vendor_device_in(1, 0, 0, 4));
+        // TRT: IN Data Stage
+        assert_eq!(extract_bits(trb.control.read(), 16, 2), 3);
+    }
+    #[test_case]
+    fn data_stage_trb_encodes_direction() {
+        let mut buf = [0u8; 18];
+        let buf_addr = buf.as_ptr() as u64;
+        let trb = GenericTrbEntry::from(DataStageTrb::new_in(Pin::new(&mut buf[..])));
+        assert_eq!(trb.trb_type(), TrbType::DataStage as u32);
+        assert_eq!(trb.data(), buf_addr);
+        assert_eq!(trb.transfer_length(), 18);
+        assert!(trb.control.read() & GenericTrbEntry::CTRL_BIT_DATA_DIR_IN != 0);
+        let trb = GenericTrbEntry::from(DataStageTrb::new_out(Pin::new(&mut buf[..])));
+        assert_eq!(trb.trb_type(), TrbType::DataStage as u32);
+        assert!(trb.control.read() & GenericTrbEntry::CTRL_BIT_DATA_DIR_IN == 0);
+    }
+    #[test_case]
+    fn status_stage_trb_encodes_direction() {
+        let trb = GenericTrbEntry::from(StatusStageTrb::new_in());
+        assert_eq!(trb.trb_type(), TrbType::StatusStage as u32);
+        assert!(trb.control.read() & GenericTrbEntry::CTRL_BIT_DATA_DIR_IN != 0);
+        let trb = GenericTrbEntry::from(StatusStageTrb::new_out());
+        assert_eq!(trb.trb_type(), TrbType::StatusStage as u32);
+        assert!(trb.control.read() & GenericTrbEntry::CTRL_BIT_DATA_DIR_IN == 0);
+    }
+    #[test_case]
+    fn port_status_change_event_port_id() {
+        let mut trb = GenericTrbEntry::default();
+        trb.set_trb_type(TrbType::PortStatusChangeEvent);
+        // Bits above the Port ID field are reserved and should be ignored
+        trb.data.write(0xFFFF_FFFF_0300_0000);
+        assert_eq!(trb.port_id(), Some(3));
+        trb.set_trb_type(TrbType::TransferEvent);
+        assert_eq!(trb.port_id(), None);
+    }
+    #[test_case]
+    fn disable_slot_command_has_slot_id() {
+        let trb = GenericTrbEntry::cmd_disable_slot(5);
+        assert_eq!(trb.trb_type(), TrbType::DisableSlotCommand as u32);
+        assert_eq!(trb.slot_id(), 5);
+    }
+}
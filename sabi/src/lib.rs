@@ -48,6 +48,203 @@ impl PointerPosition {
 pub struct MouseEvent {
     pub button: MouseButtonState,
     pub position: PointerPosition,
+    // Scroll wheel delta. Positive values mean scrolling up (away from the user).
+    pub wheel: i8,
+}
+impl MouseEvent {
+    pub fn new(button: MouseButtonState, position: PointerPosition) -> Self {
+        Self {
+            button,
+            position,
+            wheel: 0,
+        }
+    }
+}
+
+// Same bit layout as the modifier byte of the USB HID keyboard boot report.
+#[repr(C)]
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq)]
+pub struct Modifiers(pub u8);
+pub const MODIFIER_LEFT_CTRL: u8 = 1 << 0;
+pub const MODIFIER_LEFT_SHIFT: u8 = 1 << 1;
+pub const MODIFIER_LEFT_ALT: u8 = 1 << 2;
+pub const MODIFIER_LEFT_GUI: u8 = 1 << 3;
+pub const MODIFIER_RIGHT_CTRL: u8 = 1 << 4;
+pub const MODIFIER_RIGHT_SHIFT: u8 = 1 << 5;
+pub const MODIFIER_RIGHT_ALT: u8 = 1 << 6;
+pub const MODIFIER_RIGHT_GUI: u8 = 1 << 7;
+impl Modifiers {
+    pub fn ctrl(self) -> bool {
+        self.0 & (MODIFIER_LEFT_CTRL | MODIFIER_RIGHT_CTRL) != 0
+    }
+    pub fn shift(self) -> bool {
+        self.0 & (MODIFIER_LEFT_SHIFT | MODIFIER_RIGHT_SHIFT) != 0
+    }
+    pub fn alt(self) -> bool {
+        self.0 & (MODIFIER_LEFT_ALT | MODIFIER_RIGHT_ALT) != 0
+    }
+    pub fn gui(self) -> bool {
+        self.0 & (MODIFIER_LEFT_GUI | MODIFIER_RIGHT_GUI) != 0
+    }
+}
+
+#[repr(C)]
+#[derive(Debug, Default, Copy, Clone)]
+pub struct KeyboardEvent {
+    pub key: char,
+    pub modifiers: Modifiers,
+}
+
+// An entry of the buffer passed to the draw_pixels syscall.
+#[repr(C)]
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq)]
+pub struct RawPixel {
+    pub x: i64,
+    pub y: i64,
+    pub color: u32,
 }
 
 pub type RawIpV4Addr = [u8; 4];
+
+// Flags for the open_file syscall.
+pub const OPEN_FLAG_CREATE: u64 = 1 << 0;
+
+// Colors of the draw_string syscall are packed into a u64: fg in bits 0..32, bg in bits 32..64.
+// The bg is transparent if it is NO_BG_COLOR.
+pub const NO_BG_COLOR: u32 = u32::MAX;
+pub fn pack_text_colors(fg: u32, bg: Option<u32>) -> u64 {
+    (fg as u64) | (bg.unwrap_or(NO_BG_COLOR) as u64) << 32
+}
+pub fn unpack_text_colors(colors: u64) -> (u32, Option<u32>) {
+    let bg = (colors >> 32) as u32;
+    (
+        colors as u32,
+        if bg == NO_BG_COLOR { None } else { Some(bg) },
+    )
+}
+
+// Written by the map_framebuffer syscall. The app draws a 0xRRGGBB pixel at (x, y) by writing
+// it to the u32 at base + (y * stride + x) * 4, and calls the present syscall to show them.
+#[repr(C)]
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq)]
+pub struct FramebufferInfo {
+    pub base: u64,
+    pub stride: u64,
+    pub width: u64,
+    pub height: u64,
+}
+
+// Numbers of the syscalls, passed in rdx. See os/src/x86_64.rs for the calling conventions.
+// Do not renumber the existing ones since apps built before would call a wrong syscall.
+#[repr(u64)]
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Syscall {
+    Exit = 0,
+    Print = 1,
+    DrawPoint = 2,
+    Noop = 3,
+    ReadKey = 4,
+    GetMouseCursorInfo = 5,
+    GetArgsRegion = 6,
+    Nslookup = 7,
+    TcpConnect = 8,
+    TcpWrite = 9,
+    TcpRead = 10,
+    ReadKeyEvent = 11,
+    DrawRect = 12,
+    DrawPixels = 13,
+    UptimeMs = 14,
+    OpenFile = 15,
+    Read = 16,
+    Write = 17,
+    Close = 18,
+    Yield = 19,
+    DrawString = 20,
+    MapFramebuffer = 21,
+    Present = 22,
+    ReadKeyTimeout = 23,
+}
+impl Syscall {
+    pub const ALL: [Syscall; 24] = [
+        Syscall::Exit,
+        Syscall::Print,
+        Syscall::DrawPoint,
+        Syscall::Noop,
+        Syscall::ReadKey,
+        Syscall::GetMouseCursorInfo,
+        Syscall::GetArgsRegion,
+        Syscall::Nslookup,
+        Syscall::TcpConnect,
+        Syscall::TcpWrite,
+        Syscall::TcpRead,
+        Syscall::ReadKeyEvent,
+        Syscall::DrawRect,
+        Syscall::DrawPixels,
+        Syscall::UptimeMs,
+        Syscall::OpenFile,
+        Syscall::Read,
+        Syscall::Write,
+        Syscall::Close,
+        Syscall::Yield,
+        Syscall::DrawString,
+        Syscall::MapFramebuffer,
+        Syscall::Present,
+        Syscall::ReadKeyTimeout,
+    ];
+    pub fn from_u64(op: u64) -> Option<Self> {
+        Self::ALL.iter().find(|s| **s as u64 == op).copied()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    #[test]
+    fn syscall_numbers_are_stable() {
+        assert_eq!(Syscall::Exit as u64, 0);
+        assert_eq!(Syscall::Print as u64, 1);
+        assert_eq!(Syscall::DrawPoint as u64, 2);
+        assert_eq!(Syscall::Noop as u64, 3);
+        assert_eq!(Syscall::ReadKey as u64, 4);
+        assert_eq!(Syscall::GetMouseCursorInfo as u64, 5);
+        assert_eq!(Syscall::GetArgsRegion as u64, 6);
+        assert_eq!(Syscall::Nslookup as u64, 7);
+        assert_eq!(Syscall::TcpConnect as u64, 8);
+        assert_eq!(Syscall::TcpWrite as u64, 9);
+        assert_eq!(Syscall::TcpRead as u64, 10);
+        assert_eq!(Syscall::ReadKeyEvent as u64, 11);
+        assert_eq!(Syscall::DrawRect as u64, 12);
+        assert_eq!(Syscall::DrawPixels as u64, 13);
+        assert_eq!(Syscall::UptimeMs as u64, 14);
+        assert_eq!(Syscall::OpenFile as u64, 15);
+        assert_eq!(Syscall::Read as u64, 16);
+        assert_eq!(Syscall::Write as u64, 17);
+        assert_eq!(Syscall::Close as u64, 18);
+        assert_eq!(Syscall::Yield as u64, 19);
+        assert_eq!(Syscall::DrawString as u64, 20);
+        assert_eq!(Syscall::MapFramebuffer as u64, 21);
+        assert_eq!(Syscall::Present as u64, 22);
+        assert_eq!(Syscall::ReadKeyTimeout as u64, 23);
+    }
+    #[test]
+    fn syscall_numbers_are_unique_and_round_trip() {
+        for (i, a) in Syscall::ALL.iter().enumerate() {
+            for b in &Syscall::ALL[i + 1..] {
+                assert_ne!(*a as u64, *b as u64, "{a:?} and {b:?} have the same number");
+            }
+            assert_eq!(Syscall::from_u64(*a as u64), Some(*a));
+        }
+        assert_eq!(Syscall::from_u64(Syscall::ALL.len() as u64), None);
+        assert_eq!(Syscall::from_u64(u64::MAX), None);
+    }
+    #[test]
+    fn text_colors_round_trip() {
+        let packed = pack_text_colors(0xFFFFFF, Some(0x123456));
+        assert_eq!(packed, 0x0012_3456_00FF_FFFF);
+        assert_eq!(unpack_text_colors(packed), (0xFFFFFF, Some(0x123456)));
+        assert_eq!(
+            unpack_text_colors(pack_text_colors(0x000000, None)),
+            (0x000000, None)
+        );
+    }
+}